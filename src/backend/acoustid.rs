@@ -0,0 +1,284 @@
+use std::{
+    ffi::OsStr,
+    fs::File,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use super::{artists::link_song_artists, config::Config, model::library, utils::cache_dir};
+use miette::{miette, IntoDiagnostic, Result};
+use paris::info;
+use reqwest::Client;
+use rusty_chromaprint::{Configuration, FingerprintCompressor, Fingerprinter};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set, TransactionTrait};
+use serde::{Deserialize, Serialize};
+use symphonia::{
+    core::{audio::SampleBuffer, io::MediaSourceStream, probe::Hint},
+    default::{get_codecs, get_probe},
+};
+
+/// Only this many seconds of audio are fingerprinted, same as `fpcalc`
+const FINGERPRINT_SECONDS: u64 = 120;
+
+/// Matches with a lower score are discarded
+const MIN_SCORE: f32 = 0.5;
+
+/// Time between lookups, since the API allows at most 3 requests per second
+const LOOKUP_INTERVAL: Duration = Duration::from_millis(334);
+
+/// When the next lookup may be sent
+static NEXT_LOOKUP: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// Metadata of a recording identified by AcoustID
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Identification {
+    pub artist: Option<String>,
+    pub title: Option<String>,
+    pub album: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct LookupResponse {
+    status: String,
+    #[serde(default)]
+    results: Vec<LookupResult>,
+}
+
+#[derive(Deserialize, Debug)]
+struct LookupResult {
+    score: f32,
+    #[serde(default)]
+    recordings: Vec<Recording>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Recording {
+    title: Option<String>,
+    #[serde(default)]
+    artists: Vec<Name>,
+    #[serde(default)]
+    releasegroups: Vec<ReleaseGroup>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Name {
+    name: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ReleaseGroup {
+    title: Option<String>,
+}
+
+/// Result of an earlier lookup of a song, including misses
+pub fn cached(hash: i64) -> Option<Option<Identification>> {
+    let cached = std::fs::read(cache_file(hash)?).ok()?;
+
+    rmp_serde::from_slice(&cached).ok()
+}
+
+/// Identifies a file by its fingerprint and duration, see `fingerprint`.
+/// Results (including misses) are cached by song hash, so files are only looked up once.
+pub async fn lookup(
+    client: &Client,
+    fingerprint: &str,
+    duration: u64,
+    hash: i64,
+    api_key: &str,
+) -> Result<Option<Identification>> {
+    // Lookups can run concurrently, so each one reserves its own slot
+    let wait = {
+        let mut next = NEXT_LOOKUP.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let slot = next.map_or(now, |v| v.max(now));
+        *next = Some(slot + LOOKUP_INTERVAL);
+
+        slot - now
+    };
+    tokio::time::sleep(wait).await;
+
+    let response: LookupResponse = client
+        .get("https://api.acoustid.org/v2/lookup")
+        .query(&[
+            ("client", api_key),
+            ("meta", "recordings releasegroups"),
+            ("duration", &duration.to_string()),
+            ("fingerprint", fingerprint),
+        ])
+        .send()
+        .await
+        .into_diagnostic()?
+        .json()
+        .await
+        .into_diagnostic()?;
+
+    let identification = best_match(response)?;
+
+    if let Some(ref found) = identification {
+        info!(
+            "Identified song {hash} as {:?} by {:?}",
+            found.title, found.artist
+        );
+    }
+
+    let cache_file = cache_file(hash).ok_or(miette!("Cache directory does not exist"))?;
+    std::fs::create_dir_all(cache_file.parent().unwrap_or(&cache_file)).into_diagnostic()?;
+    std::fs::write(
+        cache_file,
+        rmp_serde::to_vec(&identification).into_diagnostic()?,
+    )
+    .into_diagnostic()?;
+
+    Ok(identification)
+}
+
+/// Fills in the artist, title and album of a song from an identification,
+/// where its tags didn't have them
+pub async fn apply(hash: i64, found: Identification, db: &DatabaseConnection) -> Result<()> {
+    let Some(song) = library::Entity::find()
+        .filter(library::Column::Hash.eq(hash))
+        .one(db)
+        .await
+        .into_diagnostic()?
+    else {
+        // The song was removed while it was looked up
+        return Ok(());
+    };
+
+    let artist = song.artist.clone().or(found.artist);
+
+    let txn = db.begin().await.into_diagnostic()?;
+
+    library::Entity::update(library::ActiveModel {
+        id: Set(song.id),
+        artist: Set(artist.clone()),
+        name: Set(song.name.or(found.title)),
+        album: Set(song.album.or(found.album)),
+        ..Default::default()
+    })
+    .exec(&txn)
+    .await
+    .into_diagnostic()?;
+
+    if song.artist.is_none() && artist.is_some() {
+        let separators = Config::read_config()?.artist_separators;
+        link_song_artists(hash, artist.as_deref(), &separators, &txn).await?;
+    }
+
+    txn.commit().await.into_diagnostic()
+}
+
+fn cache_file(hash: i64) -> Option<PathBuf> {
+    Some(cache_dir()?.join("acoustid").join(format!("{hash}.mp")))
+}
+
+/// Pick the highest scoring recording out of a lookup response
+fn best_match(response: LookupResponse) -> Result<Option<Identification>> {
+    miette::ensure!(
+        response.status == "ok",
+        "AcoustID lookup failed with status {}",
+        response.status
+    );
+
+    Ok(response
+        .results
+        .into_iter()
+        .filter(|v| v.score >= MIN_SCORE)
+        .max_by(|a, b| a.score.total_cmp(&b.score))
+        .and_then(|v| v.recordings.into_iter().next())
+        .map(|recording| Identification {
+            artist: (!recording.artists.is_empty()).then(|| {
+                recording
+                    .artists
+                    .into_iter()
+                    .map(|v| v.name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            }),
+            title: recording.title,
+            album: recording.releasegroups.into_iter().find_map(|v| v.title),
+        }))
+}
+
+/// Compute a compressed Chromaprint fingerprint and the duration of a file in seconds
+pub fn fingerprint(path: &Path) -> Result<(String, u64)> {
+    let file = Box::new(File::open(path).into_diagnostic()?);
+
+    let ext = path.extension().and_then(OsStr::to_str).unwrap_or("");
+
+    let source = MediaSourceStream::new(file, Default::default());
+    let mut format = get_probe()
+        .format(
+            Hint::new().with_extension(ext),
+            source,
+            &Default::default(),
+            &Default::default(),
+        )
+        .into_diagnostic()?
+        .format;
+
+    let track = format
+        .default_track()
+        .ok_or(miette!("No audio track found in {}", path.display()))?;
+    let track_id = track.id;
+
+    let sample_rate = track
+        .codec_params
+        .sample_rate
+        .ok_or(miette!("Unknown sample rate for {}", path.display()))?;
+    let channels = track
+        .codec_params
+        .channels
+        .ok_or(miette!("Unknown channel layout for {}", path.display()))?
+        .count() as u32;
+    let total_frames = track.codec_params.n_frames;
+
+    let mut decoder = get_codecs()
+        .make(&track.codec_params, &Default::default())
+        .into_diagnostic()?;
+
+    let config = Configuration::preset_test2();
+    let mut printer = Fingerprinter::new(&config);
+    printer.start(sample_rate, channels).into_diagnostic()?;
+
+    let limit = FINGERPRINT_SECONDS * sample_rate as u64;
+    let mut frames = 0;
+    let mut buffer: Option<SampleBuffer<i16>> = None;
+
+    while let Ok(packet) = format.next_packet() {
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(v) => v,
+            // Skip over corrupted packets
+            Err(symphonia::core::errors::Error::DecodeError(_)) => continue,
+            Err(e) => return Err(e).into_diagnostic(),
+        };
+
+        let buffer = buffer
+            .get_or_insert_with(|| SampleBuffer::new(decoded.capacity() as u64, *decoded.spec()));
+        frames += decoded.frames() as u64;
+        buffer.copy_interleaved_ref(decoded);
+
+        if frames < limit {
+            printer.consume(buffer.samples());
+        } else if total_frames.is_some() {
+            // The rest of the file is only needed to find its duration
+            break;
+        }
+    }
+
+    printer.finish();
+
+    let duration = total_frames.unwrap_or(frames) / sample_rate as u64;
+
+    let fingerprint = FingerprintCompressor::from(&config).compress(printer.fingerprint());
+
+    Ok((
+        base64::encode_config(fingerprint, base64::URL_SAFE_NO_PAD),
+        duration,
+    ))
+}
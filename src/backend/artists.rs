@@ -0,0 +1,237 @@
+use std::collections::HashSet;
+
+use super::model::{artists, library, song_artists};
+use miette::{IntoDiagnostic, Result};
+use sea_orm::{
+    sea_query::{Expr, Query},
+    ColumnTrait, ConnectionTrait, DatabaseConnection, DeriveColumn, EntityTrait, EnumIter,
+    IdenStatic, ModelTrait, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, Select, Set,
+    TransactionTrait,
+};
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveColumn)]
+enum QueryAs {
+    SongHash,
+}
+
+/// Splits a raw artist tag like "A feat. B" into the names of every contributing artist,
+/// without duplicates. Separators are matched case insensitively.
+pub fn split_artists(raw: &str, separators: &[String]) -> Vec<String> {
+    let mut parts = vec![raw.to_string()];
+
+    for separator in separators.iter().filter(|v| !v.is_empty()) {
+        parts = parts
+            .iter()
+            .flat_map(|v| split_ignore_case(v, separator))
+            .collect();
+    }
+
+    let mut seen = HashSet::new();
+
+    parts
+        .into_iter()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .filter(|v| seen.insert(v.to_lowercase()))
+        .collect()
+}
+
+fn split_ignore_case(text: &str, separator: &str) -> Vec<String> {
+    let mut parts = vec![];
+    let mut start = 0;
+    let mut i = 0;
+
+    while i < text.len() {
+        let matches = text
+            .get(i..i + separator.len())
+            .is_some_and(|v| v.eq_ignore_ascii_case(separator));
+
+        if matches {
+            parts.push(close_parenthesis(&text[start..i], separator, start > 0));
+            i += separator.len();
+            start = i;
+        } else {
+            i += text[i..].chars().next().map_or(1, char::len_utf8);
+        }
+    }
+
+    parts.push(close_parenthesis(&text[start..], separator, start > 0));
+    parts
+}
+
+/// Removes the closing parenthesis from the part after a separator like "(feat. "
+fn close_parenthesis(part: &str, separator: &str, after_separator: bool) -> String {
+    let opens = separator.trim_start().starts_with('(');
+
+    match part.trim_end().strip_suffix(')') {
+        Some(stripped) if opens && after_separator => stripped.to_string(),
+        _ => part.to_string(),
+    }
+}
+
+/// Replaces the artists a song is linked to with the ones in its raw artist tag.
+/// Either every link is replaced or none are.
+pub async fn link_song_artists(
+    hash: i64,
+    raw: Option<&str>,
+    separators: &[String],
+    db: &impl TransactionTrait,
+) -> Result<()> {
+    let txn = db.begin().await.into_diagnostic()?;
+
+    song_artists::Entity::delete_many()
+        .filter(song_artists::Column::SongHash.eq(hash))
+        .exec(&txn)
+        .await
+        .into_diagnostic()?;
+
+    for name in raw
+        .map(|v| split_artists(v, separators))
+        .unwrap_or_default()
+    {
+        let artist_id = artist_id(&name, &txn).await?;
+
+        song_artists::Entity::insert(song_artists::ActiveModel {
+            song_hash: Set(hash),
+            artist_id: Set(artist_id),
+            ..Default::default()
+        })
+        .exec(&txn)
+        .await
+        .into_diagnostic()?;
+    }
+
+    txn.commit().await.into_diagnostic()
+}
+
+/// Finds an artist by name, creating it if it doesn't exist yet
+async fn artist_id(name: &str, db: &impl ConnectionTrait) -> Result<i32> {
+    // Names are compared case insensitively by the database
+    if let Some(artist) = artists::Entity::find()
+        .filter(artists::Column::Name.eq(name))
+        .one(db)
+        .await
+        .into_diagnostic()?
+    {
+        return Ok(artist.id);
+    }
+
+    let inserted = artists::Entity::insert(artists::ActiveModel {
+        name: Set(name.to_string()),
+        ..Default::default()
+    })
+    .exec(db)
+    .await
+    .into_diagnostic()?;
+
+    Ok(inserted.last_insert_id)
+}
+
+/// Links every song that has an artist tag but no linked artists, and removes unused artists
+pub async fn link_unlinked_artists(separators: &[String], db: &DatabaseConnection) -> Result<()> {
+    let linked: HashSet<i64> = song_artists::Entity::find()
+        .select_only()
+        .column_as(song_artists::Column::SongHash, QueryAs::SongHash)
+        .group_by(song_artists::Column::SongHash)
+        .into_values::<_, QueryAs>()
+        .all(db)
+        .await
+        .into_diagnostic()?
+        .into_iter()
+        .collect();
+
+    let songs = library::Entity::find()
+        .filter(library::Column::Artist.is_not_null())
+        .all(db)
+        .await
+        .into_diagnostic()?;
+
+    for song in songs.iter().filter(|v| !linked.contains(&v.hash)) {
+        link_song_artists(song.hash, song.artist.as_deref(), separators, db).await?;
+    }
+
+    remove_unused(db).await
+}
+
+/// Splits every song's artists again, e.g. after the separators were changed
+pub async fn relink_all_artists(separators: &[String], db: &DatabaseConnection) -> Result<()> {
+    song_artists::Entity::delete_many()
+        .exec(db)
+        .await
+        .into_diagnostic()?;
+
+    link_unlinked_artists(separators, db).await
+}
+
+async fn remove_unused(db: &DatabaseConnection) -> Result<()> {
+    artists::Entity::delete_many()
+        .filter(
+            artists::Column::Id.not_in_subquery(
+                Query::select()
+                    .column(song_artists::Column::ArtistId)
+                    .from(song_artists::Entity)
+                    .to_owned(),
+            ),
+        )
+        .exec(db)
+        .await
+        .into_diagnostic()?;
+
+    Ok(())
+}
+
+/// Every artist, sorted by name
+pub async fn list_artists(db: &DatabaseConnection) -> Result<Vec<artists::Model>> {
+    artists::Entity::find()
+        .order_by_asc(artists::Column::Name)
+        .all(db)
+        .await
+        .into_diagnostic()
+}
+
+/// A slice of the sorted artist list, for views that load it as they're scrolled
+pub async fn artists_page(
+    offset: u64,
+    limit: u64,
+    db: &DatabaseConnection,
+) -> Result<Vec<artists::Model>> {
+    artists::Entity::find()
+        .order_by_asc(artists::Column::Name)
+        .offset(offset)
+        .limit(limit)
+        .all(db)
+        .await
+        .into_diagnostic()
+}
+
+pub async fn count_artists(db: &DatabaseConnection) -> Result<usize> {
+    artists::Entity::find().count(db).await.into_diagnostic()
+}
+
+/// Songs an artist contributed to, including ones where they're only featured
+pub async fn songs_by_artist(
+    artist: &artists::Model,
+    db: &DatabaseConnection,
+) -> Result<Vec<library::Model>> {
+    artist
+        .find_related(library::Entity)
+        .order_by_asc(library::Column::Album)
+        .order_by_asc(library::Column::Disc)
+        .order_by_asc(library::Column::Track)
+        .all(db)
+        .await
+        .into_diagnostic()
+}
+
+/// Restricts a library query to songs an artist contributed to
+pub fn filter_by_artist(query: Select<library::Entity>, artist_id: i32) -> Select<library::Entity> {
+    query.filter(
+        library::Column::Hash.in_subquery(
+            Query::select()
+                .column(song_artists::Column::SongHash)
+                .from(song_artists::Entity)
+                .and_where(Expr::col(song_artists::Column::ArtistId).eq(artist_id))
+                .to_owned(),
+        ),
+    )
+}
@@ -0,0 +1,230 @@
+use std::{
+    fs::{create_dir_all, remove_file, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    config::Config,
+    details::cached_details,
+    model::library,
+    utils::{cache_dir, song_path},
+};
+use lofty::{read_from_path, PictureType};
+use miette::{miette, IntoDiagnostic, Result};
+use serde::{Deserialize, Serialize};
+
+/// The Okabe-Ito palette, which stays distinguishable with every common form of color blindness
+const PALETTE: [(u8, u8, u8); 8] = [
+    (0xE6, 0x9F, 0x00),
+    (0x56, 0xB4, 0xE9),
+    (0x00, 0x9E, 0x73),
+    (0xF0, 0xE4, 0x42),
+    (0x00, 0x72, 0xB2),
+    (0xD5, 0x5E, 0x00),
+    (0xCC, 0x79, 0xA7),
+    (0x00, 0x00, 0x00),
+];
+
+/// Image data of an album's cover
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Artwork {
+    pub data: Vec<u8>,
+    pub mime: String,
+}
+
+/// Returns the cover of a song's album, generating a placeholder if the song has none.
+/// Both are cached, so files are only read once per album.
+pub fn album_art(song: &library::Model) -> Result<Artwork> {
+    let (album, artist) = album_names(song);
+
+    let dir = art_dir("art")?;
+    let key = album_key(song);
+
+    if let Some(artwork) = cached(&dir, key)? {
+        return Ok(artwork);
+    }
+
+    let local = Config::read_config()?
+        .local_source_ids()
+        .contains(&song.source_id);
+
+    // Remote songs only have art once their details were fetched
+    let found = if local {
+        embedded_art(song)
+    } else {
+        cached_details(song.hash).and_then(|v| v.art)
+    };
+
+    let artwork = match found {
+        Some(artwork) => artwork,
+        None => Artwork {
+            data: placeholder(album, artist).into_bytes(),
+            mime: "image/svg+xml".to_string(),
+        },
+    };
+
+    store(&dir, key, &artwork)?;
+
+    Ok(artwork)
+}
+
+/// Whether a song's album has a cover of its own, rather than a placeholder
+pub fn has_album_art(song: &library::Model) -> Result<bool> {
+    Ok(album_art(song)?.mime != "image/svg+xml")
+}
+
+/// Caches a cover found elsewhere for a song's album, replacing its placeholder
+pub fn save_album_art(song: &library::Model, artwork: &Artwork) -> Result<()> {
+    store(&art_dir("art")?, album_key(song), artwork)
+}
+
+/// A cached picture of an artist, see `save_artist_image`
+pub fn artist_image(name: &str) -> Result<Option<Artwork>> {
+    cached(&art_dir("artists")?, artist_key(name))
+}
+
+pub fn save_artist_image(name: &str, artwork: &Artwork) -> Result<()> {
+    store(&art_dir("artists")?, artist_key(name), artwork)
+}
+
+/// Album and artist name a song's cover is stored under
+fn album_names(song: &library::Model) -> (&str, &str) {
+    let album = song.album.as_deref().unwrap_or(&song.filename);
+    let artist = song
+        .album_artist
+        .as_deref()
+        .or(song.artist.as_deref())
+        .unwrap_or_default();
+
+    (album, artist)
+}
+
+fn album_key(song: &library::Model) -> u32 {
+    let (album, artist) = album_names(song);
+
+    adler::adler32_slice(format!("{artist}\0{album}").as_bytes())
+}
+
+/// Artists are compared case insensitively, like in the `artists` table
+fn artist_key(name: &str) -> u32 {
+    adler::adler32_slice(name.to_lowercase().as_bytes())
+}
+
+fn art_dir(name: &str) -> Result<PathBuf> {
+    Ok(cache_dir()
+        .ok_or(miette!("Cache directory does not exist"))?
+        .join(name))
+}
+
+/// Writes an image to the cache, removing any other image cached under the same key
+fn store(dir: &Path, key: u32, artwork: &Artwork) -> Result<()> {
+    create_dir_all(dir).into_diagnostic()?;
+
+    for mime in ["image/jpeg", "image/png", "image/svg+xml"] {
+        let path = dir.join(file_name(key, mime));
+
+        if mime != artwork.mime && path.exists() {
+            remove_file(path).into_diagnostic()?;
+        }
+    }
+
+    File::create(dir.join(file_name(key, &artwork.mime)))
+        .and_then(|mut v| v.write_all(&artwork.data))
+        .into_diagnostic()
+}
+
+fn cached(dir: &Path, key: u32) -> Result<Option<Artwork>> {
+    for mime in ["image/jpeg", "image/png", "image/svg+xml"] {
+        let path = dir.join(file_name(key, mime));
+
+        if path.exists() {
+            return Ok(Some(Artwork {
+                data: std::fs::read(path).into_diagnostic()?,
+                mime: mime.to_string(),
+            }));
+        }
+    }
+
+    Ok(None)
+}
+
+fn file_name(key: u32, mime: &str) -> String {
+    let ext = match mime {
+        "image/png" => "png",
+        "image/svg+xml" => "svg",
+        _ => "jpg",
+    };
+
+    format!("{key:08x}.{ext}")
+}
+
+/// Reads the front cover from a local song's tags, or any picture if there is no front cover
+pub fn embedded_art(song: &library::Model) -> Option<Artwork> {
+    let file = read_from_path(song_path(song), false).ok()?;
+    let tag = file.primary_tag().or(file.first_tag())?;
+
+    let picture = tag
+        .pictures()
+        .iter()
+        .find(|v| v.pic_type() == PictureType::CoverFront)
+        .or(tag.pictures().first())?;
+
+    Some(Artwork {
+        data: picture.data().to_vec(),
+        mime: picture.mime_type().as_str().to_string(),
+    })
+}
+
+/// Generates an SVG cover showing the album's initials.
+/// Colors are derived from the album and artist names, so the same album always looks the same.
+pub fn placeholder(album: &str, artist: &str) -> String {
+    let hash = adler::adler32_slice(format!("{artist}\0{album}").as_bytes());
+
+    let index = hash as usize % PALETTE.len();
+    let background = PALETTE[index];
+    // Offset by at least one so the accent stripe never matches the background
+    let accent = PALETTE[(index + 1 + (hash >> 8) as usize % (PALETTE.len() - 1)) % PALETTE.len()];
+
+    // Relative luminance decides whether text is more readable in black or white
+    let (r, g, b) = background;
+    let luminance = 0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32;
+    let text = if luminance > 140.0 {
+        "#000000"
+    } else {
+        "#FFFFFF"
+    };
+
+    format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 256 256" width="256" height="256"><rect width="256" height="256" fill="{}"/><path d="M0 232 L256 200 L256 256 L0 256 Z" fill="{}"/><text x="128" y="128" font-family="sans-serif" font-size="96" font-weight="bold" fill="{text}" text-anchor="middle" dominant-baseline="central">{}</text></svg>"##,
+        hex(background),
+        hex(accent),
+        escape(&initials(album)),
+    )
+}
+
+/// First letters of the first two words of a name
+fn initials(name: &str) -> String {
+    let initials: String = name
+        .split_whitespace()
+        .filter_map(|v| v.chars().find(|v| v.is_alphanumeric()))
+        .take(2)
+        .flat_map(char::to_uppercase)
+        .collect();
+
+    if initials.is_empty() {
+        "?".to_string()
+    } else {
+        initials
+    }
+}
+
+fn hex((r, g, b): (u8, u8, u8)) -> String {
+    format!("#{r:02X}{g:02X}{b:02X}")
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
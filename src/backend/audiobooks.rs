@@ -0,0 +1,194 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use super::{
+    browse::{album_tracks, Album},
+    chapters::{read_chapters, Chapter},
+    config::Config,
+    events::{subscribe, Event},
+    model::{library, resume_points},
+    playback::PlaybackState,
+    tempo::clamp_speed,
+    utils::song_path,
+};
+use miette::{IntoDiagnostic, Result};
+use paris::warn;
+use sea_orm::{
+    sea_query::OnConflict, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder,
+    Set,
+};
+use tokio::{sync::broadcast::error::RecvError, task::JoinHandle};
+
+/// How often the position of a playing audiobook is saved
+const SAVE_INTERVAL: Duration = Duration::from_secs(10);
+/// Resume points this close to the start aren't worth keeping
+const MIN_RESUME: Duration = Duration::from_secs(5);
+
+/// Whether a song is from a source marked as containing audiobooks
+pub fn is_audiobook(song: &library::Model, config: &Config) -> bool {
+    config.audiobook_source_ids().contains(&song.source_id)
+}
+
+/// Speed a song should be played at. Audiobooks have their own speed, other songs
+/// play at the speed set for everything.
+pub fn playback_speed(song: &library::Model, config: &Config) -> f32 {
+    let speed = match config.audiobook_speed {
+        Some(speed) if is_audiobook(song, config) => speed,
+        _ => config.playback.speed.unwrap_or(1.0),
+    };
+
+    clamp_speed(speed)
+}
+
+/// Chapters of a local audiobook track, e.g. from an M4B file. Remote tracks have none.
+pub fn chapters(song: &library::Model, config: &Config) -> Result<Vec<Chapter>> {
+    if !config.local_source_ids().contains(&song.source_id) {
+        return Ok(vec![]);
+    }
+
+    read_chapters(&song_path(song))
+}
+
+/// Where playback of a track should start, `None` if it wasn't listened to before
+pub async fn resume_point(hash: i64, db: &DatabaseConnection) -> Result<Option<Duration>> {
+    Ok(resume_points::Entity::find()
+        .filter(resume_points::Column::SongHash.eq(hash))
+        .one(db)
+        .await
+        .into_diagnostic()?
+        .map(|v| Duration::from_millis(v.position_ms.max(0) as u64)))
+}
+
+/// The track of a book that was listened to last, and where in it playback stopped.
+/// `None` if the book wasn't started, or was finished.
+pub async fn resume_book(
+    book: &Album,
+    db: &DatabaseConnection,
+) -> Result<Option<(library::Model, Duration)>> {
+    let tracks = album_tracks(book, db).await?;
+
+    let latest = resume_points::Entity::find()
+        .filter(resume_points::Column::SongHash.is_in(tracks.iter().map(|v| v.hash)))
+        .order_by_desc(resume_points::Column::UpdatedAt)
+        .one(db)
+        .await
+        .into_diagnostic()?;
+
+    Ok(latest.and_then(|point| {
+        let track = tracks.into_iter().find(|v| v.hash == point.song_hash)?;
+        Some((
+            track,
+            Duration::from_millis(point.position_ms.max(0) as u64),
+        ))
+    }))
+}
+
+pub async fn save_resume_point(
+    hash: i64,
+    position: Duration,
+    db: &DatabaseConnection,
+) -> Result<()> {
+    if position < MIN_RESUME {
+        return Ok(());
+    }
+
+    resume_points::Entity::insert(resume_points::ActiveModel {
+        song_hash: Set(hash),
+        position_ms: Set(position.as_millis() as i64),
+        updated_at: Set(SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .into_diagnostic()?
+            .as_secs() as i64),
+        ..Default::default()
+    })
+    .on_conflict(
+        OnConflict::column(resume_points::Column::SongHash)
+            .update_columns([
+                resume_points::Column::PositionMs,
+                resume_points::Column::UpdatedAt,
+            ])
+            .to_owned(),
+    )
+    .exec(db)
+    .await
+    .into_diagnostic()?;
+
+    Ok(())
+}
+
+/// Forgets where a track stopped, e.g. once it was listened to the end
+pub async fn clear_resume_point(hash: i64, db: &DatabaseConnection) -> Result<()> {
+    resume_points::Entity::delete_many()
+        .filter(resume_points::Column::SongHash.eq(hash))
+        .exec(db)
+        .await
+        .into_diagnostic()?;
+
+    Ok(())
+}
+
+/// Remembers where audiobook tracks stop playing, until the event bus closes.
+/// A finished track's position is forgotten, so the book continues with the next one.
+pub fn track(db: DatabaseConnection) -> JoinHandle<()> {
+    let mut events = subscribe();
+
+    tokio::spawn(async move {
+        let mut playing: Option<i64> = None;
+        let mut last_saved = Instant::now();
+
+        loop {
+            let result = match events.recv().await {
+                Ok(Event::TrackStarted { hash }) => {
+                    last_saved = Instant::now();
+
+                    audiobook_hash(hash, &db).await.map(|v| playing = v)
+                }
+                Ok(Event::Position { position, state }) => match playing {
+                    // Pausing saves right away, in case the app is closed next
+                    Some(hash)
+                        if state != PlaybackState::Playing
+                            || last_saved.elapsed() >= SAVE_INTERVAL =>
+                    {
+                        last_saved = Instant::now();
+                        save_resume_point(hash, position, &db).await
+                    }
+                    _ => Ok(()),
+                },
+                Ok(Event::TrackEnded { hash, finished }) if playing == Some(hash) => {
+                    playing = None;
+
+                    if finished {
+                        clear_resume_point(hash, &db).await
+                    } else {
+                        Ok(())
+                    }
+                }
+                Ok(_) => Ok(()),
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Audiobook tracking missed {skipped} events");
+                    Ok(())
+                }
+                Err(RecvError::Closed) => break,
+            };
+
+            if let Err(e) = result {
+                warn!("Couldn't save the position of an audiobook: {e}");
+            }
+        }
+    })
+}
+
+/// The hash back if it's an audiobook track
+async fn audiobook_hash(hash: i64, db: &DatabaseConnection) -> Result<Option<i64>> {
+    let ids = Config::read_config()?.audiobook_source_ids();
+    if ids.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(library::Entity::find()
+        .filter(library::Column::Hash.eq(hash))
+        .filter(library::Column::SourceId.is_in(ids))
+        .one(db)
+        .await
+        .into_diagnostic()?
+        .map(|v| v.hash))
+}
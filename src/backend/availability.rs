@@ -0,0 +1,164 @@
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+    config::Config,
+    model::{downloads, library, remote_files, sea_orm_active_enums::DownloadStatus},
+    utils::song_path,
+};
+use miette::{IntoDiagnostic, Result};
+use paris::warn;
+use sea_orm::{
+    sea_query::OnConflict, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+/// Where a song can be played from
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Availability {
+    /// Part of a local source
+    Local,
+    /// Remote, with a downloaded copy for offline playback
+    Downloaded,
+    /// Remote, and streamed completely before, so its size is known
+    Streamed,
+    /// Remote, and never streamed
+    Remote,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrackAvailability {
+    pub availability: Availability,
+    /// Size of the file in bytes, if known
+    pub size: Option<u64>,
+}
+
+impl TrackAvailability {
+    /// Whether playing the song costs traffic
+    pub fn is_remote(&self) -> bool {
+        matches!(
+            self.availability,
+            Availability::Streamed | Availability::Remote
+        )
+    }
+
+    /// Whether to ask before playing the song on a metered connection,
+    /// because it's larger than `Config::large_stream_mb`
+    pub fn needs_confirmation(&self, config: &Config, metered: bool) -> bool {
+        let limit = config.large_stream_mb * 1024 * 1024;
+
+        metered && limit > 0 && self.is_remote() && self.size.is_some_and(|v| v >= limit)
+    }
+}
+
+/// Remembers that a remote song was streamed completely, and how large it is
+pub async fn record_streamed(hash: i64, size: u64, db: &DatabaseConnection) -> Result<()> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .into_diagnostic()?
+        .as_secs() as i64;
+
+    remote_files::Entity::insert(remote_files::ActiveModel {
+        song_hash: Set(hash),
+        size: Set(size as i64),
+        streamed_at: Set(now),
+        ..Default::default()
+    })
+    .on_conflict(
+        OnConflict::column(remote_files::Column::SongHash)
+            .update_columns([remote_files::Column::Size, remote_files::Column::StreamedAt])
+            .to_owned(),
+    )
+    .exec(db)
+    .await
+    .into_diagnostic()?;
+
+    Ok(())
+}
+
+/// Records a song once it was streamed completely, see `StreamingReader::completed`
+pub fn track_completion(
+    hash: i64,
+    mut completed: watch::Receiver<Option<u64>>,
+    db: DatabaseConnection,
+) {
+    tokio::spawn(async move {
+        let size = loop {
+            if let Some(size) = *completed.borrow_and_update() {
+                break size;
+            }
+
+            if completed.changed().await.is_err() {
+                return;
+            }
+        };
+
+        if let Err(e) = record_streamed(hash, size, &db).await {
+            warn!("Couldn't record streamed song {hash}: {e}");
+        }
+    });
+}
+
+/// Availability of songs, by their hashes
+pub async fn availability(
+    songs: &[library::Model],
+    db: &DatabaseConnection,
+) -> Result<HashMap<i64, TrackAvailability>> {
+    let local = Config::read_config()?.local_source_ids();
+    let remote: Vec<i64> = songs
+        .iter()
+        .filter(|v| !local.contains(&v.source_id))
+        .map(|v| v.hash)
+        .collect();
+
+    let downloaded: HashMap<i64, Option<i64>> = downloads::Entity::find()
+        .filter(downloads::Column::SongHash.is_in(remote.clone()))
+        .filter(downloads::Column::Status.eq(DownloadStatus::Completed))
+        .all(db)
+        .await
+        .into_diagnostic()?
+        .into_iter()
+        .map(|v| (v.song_hash, v.size))
+        .collect();
+
+    let streamed: HashMap<i64, i64> = remote_files::Entity::find()
+        .filter(remote_files::Column::SongHash.is_in(remote))
+        .all(db)
+        .await
+        .into_diagnostic()?
+        .into_iter()
+        .map(|v| (v.song_hash, v.size))
+        .collect();
+
+    Ok(songs
+        .iter()
+        .map(|song| {
+            let track = if local.contains(&song.source_id) {
+                TrackAvailability {
+                    availability: Availability::Local,
+                    size: std::fs::metadata(song_path(song)).ok().map(|v| v.len()),
+                }
+            } else if let Some(size) = downloaded.get(&song.hash) {
+                TrackAvailability {
+                    availability: Availability::Downloaded,
+                    size: size.map(|v| v as u64),
+                }
+            } else if let Some(size) = streamed.get(&song.hash) {
+                TrackAvailability {
+                    availability: Availability::Streamed,
+                    size: Some(*size as u64),
+                }
+            } else {
+                TrackAvailability {
+                    availability: Availability::Remote,
+                    size: None,
+                }
+            };
+
+            (song.hash, track)
+        })
+        .collect())
+}
@@ -0,0 +1,351 @@
+use std::{
+    cmp::Reverse,
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+    config::Config,
+    migrator::Migrator,
+    utils::{cache_dir, config_dir},
+};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use miette::{ensure, miette, IntoDiagnostic, Result};
+use paris::{info, success};
+use sea_orm::{ConnectionTrait, DatabaseConnection, Statement};
+use sea_orm_migration::MigratorTrait;
+use serde::{Deserialize, Serialize};
+
+/// Version of the archive layout, increased whenever it changes incompatibly
+const FORMAT: u32 = 1;
+const SQLITE_HEADER: &[u8] = b"SQLite format 3\0";
+
+/// Everything needed to move the library to another machine: the database with playlists,
+/// ratings and play counts, and the settings. Stored credentials are left out, so sources
+/// that need them have to be signed into again after restoring.
+#[derive(Serialize, Deserialize)]
+struct Archive {
+    format: u32,
+    /// Seconds since the Unix epoch
+    created: u64,
+    /// Migrations applied to the database, so restoring can tell whether it understands it
+    migrations: Vec<String>,
+    /// Contents of `settings.toml`
+    settings: String,
+    /// The database file
+    database: Vec<u8>,
+}
+
+/// Writes a backup of the database and the settings to `path`, as compressed messagepack.
+/// The database can be in use while this runs.
+pub async fn export(path: &Path, db: &DatabaseConnection) -> Result<()> {
+    let snapshot = cache_dir()
+        .ok_or(miette!("Cache directory does not exist"))?
+        .join("backup.db");
+
+    vacuum_into(&snapshot, db).await?;
+
+    let database = std::fs::read(&snapshot).into_diagnostic();
+    remove_if_exists(&snapshot)?;
+
+    let archive = Archive {
+        format: FORMAT,
+        created: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .into_diagnostic()?
+            .as_secs(),
+        migrations: applied_migrations(db).await?,
+        settings: std::fs::read_to_string(settings_path()?).into_diagnostic()?,
+        database: database?,
+    };
+
+    let mut encoder = GzEncoder::new(
+        BufWriter::new(File::create(path).into_diagnostic()?),
+        Compression::default(),
+    );
+    rmp_serde::encode::write(&mut encoder, &archive).into_diagnostic()?;
+    encoder
+        .finish()
+        .and_then(|mut v| v.flush())
+        .into_diagnostic()?;
+
+    success!("Backed up the library to {}", path.display());
+
+    Ok(())
+}
+
+/// Replaces the database and the settings with the ones in a backup.
+/// Has to run before the database is opened. Backups from older versions are migrated
+/// by `prepare_db` as usual, and backups from newer versions are refused.
+/// The replaced files are kept next to the new ones with an `.old` extension.
+pub fn restore(path: &Path) -> Result<()> {
+    let file = File::open(path).into_diagnostic()?;
+    let archive: Archive =
+        rmp_serde::from_read(GzDecoder::new(BufReader::new(file))).into_diagnostic()?;
+
+    ensure!(
+        archive.format <= FORMAT,
+        "This backup was made by a newer version of Eleanor"
+    );
+
+    let known: Vec<String> = Migrator::migrations()
+        .iter()
+        .map(|v| v.name().to_string())
+        .collect();
+
+    if let Some(unknown) = archive.migrations.iter().find(|v| !known.contains(v)) {
+        return Err(miette!(
+            "This backup was made by a newer version of Eleanor, its database has migration {} applied",
+            unknown
+        ));
+    }
+
+    ensure!(
+        archive.database.starts_with(SQLITE_HEADER),
+        "The backup doesn't contain a valid database"
+    );
+
+    // Checked before anything is replaced, so a broken backup leaves everything as it was
+    toml::from_str::<Config>(&archive.settings)
+        .into_diagnostic()?
+        .validate()?;
+
+    replace_database(&archive.database)?;
+
+    let settings = settings_path()?;
+    if settings.exists() {
+        std::fs::copy(&settings, with_suffix(&settings, ".old")).into_diagnostic()?;
+    }
+
+    std::fs::write(&settings, &archive.settings).into_diagnostic()?;
+
+    info!(
+        "Restored a backup with {} migrations applied, made at {} (Unix time)",
+        archive.migrations.len(),
+        archive.created
+    );
+    success!("Restored the library from {}", path.display());
+
+    Ok(())
+}
+
+/// A copy of the database taken automatically, see `snapshot`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub path: PathBuf,
+    /// Milliseconds since the Unix epoch
+    pub created: u128,
+    /// Why it was taken, e.g. `migration` or `purge`
+    pub reason: String,
+}
+
+/// Where snapshots are kept. Cleaning the cache leaves them alone.
+pub fn snapshots_dir() -> Result<PathBuf> {
+    cache_dir()
+        .map(|v| v.join("snapshots"))
+        .ok_or(miette!("Cache directory does not exist"))
+}
+
+/// Copies the database into the snapshot directory, before something that can't be undone
+/// or periodically. Only the newest `snapshot_retention` snapshots are kept.
+/// Returns `None` if snapshots are turned off.
+pub async fn snapshot(reason: &str, db: &DatabaseConnection) -> Result<Option<PathBuf>> {
+    let retention = Config::read_config()?.snapshot_retention;
+    if retention == 0 {
+        return Ok(None);
+    }
+
+    let dir = snapshots_dir()?;
+    std::fs::create_dir_all(&dir).into_diagnostic()?;
+
+    let path = free_path(&dir, reason)?;
+
+    vacuum_into(&path, db).await?;
+    info!("Saved a snapshot of the database to {}", path.display());
+
+    rotate(&dir, retention)?;
+
+    Ok(Some(path))
+}
+
+/// Snapshots that were taken, newest first
+pub fn snapshots() -> Result<Vec<Snapshot>> {
+    snapshots_in(&snapshots_dir()?)
+}
+
+fn snapshots_in(dir: &Path) -> Result<Vec<Snapshot>> {
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+
+    let mut snapshots: Vec<Snapshot> = std::fs::read_dir(dir)
+        .into_diagnostic()?
+        .filter_map(|v| v.ok())
+        .filter_map(|entry| {
+            let path = entry.path();
+            let name = path.file_name()?.to_str()?;
+            let (created, reason) = name
+                .strip_prefix("eleanor-")?
+                .strip_suffix(".db")?
+                .split_once('-')?;
+
+            Some(Snapshot {
+                created: created.parse().ok()?,
+                reason: reason.to_string(),
+                path,
+            })
+        })
+        .collect();
+
+    snapshots.sort_by_key(|v| Reverse(v.created));
+
+    Ok(snapshots)
+}
+
+/// Where a new snapshot is written. Names are unique to the millisecond, and a snapshot
+/// taken in the same millisecond as the newest one moves on to the next, so they keep their order.
+fn free_path(dir: &Path, reason: &str) -> Result<PathBuf> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .into_diagnostic()?
+        .as_millis();
+
+    let created = match snapshots_in(dir)?.first() {
+        Some(newest) => now.max(newest.created + 1),
+        None => now,
+    };
+
+    Ok(dir.join(format!("eleanor-{created}-{reason}.db")))
+}
+
+/// Removes all but the newest `retention` snapshots
+fn rotate(dir: &Path, retention: usize) -> Result<()> {
+    for old in snapshots_in(dir)?.iter().skip(retention) {
+        remove_if_exists(&old.path)?;
+    }
+
+    Ok(())
+}
+
+/// Puts a snapshot back in place of the database. Like `restore`, this has to run before
+/// the database is opened, and migrations newer than the snapshot are applied on startup.
+pub fn restore_snapshot(snapshot: &Snapshot) -> Result<()> {
+    let database = std::fs::read(&snapshot.path).into_diagnostic()?;
+
+    ensure!(
+        database.starts_with(SQLITE_HEADER),
+        "The snapshot isn't a valid database"
+    );
+
+    replace_database(&database)?;
+    success!(
+        "Restored the snapshot taken at {} (Unix time)",
+        snapshot.created / 1000
+    );
+
+    Ok(())
+}
+
+/// Replaces `eleanor.db`, keeping the previous file with an `.old` extension
+fn replace_database(contents: &[u8]) -> Result<()> {
+    let dir = config_dir().ok_or(miette!("Configuration directory not found"))?;
+    std::fs::create_dir_all(&dir).into_diagnostic()?;
+
+    let database = dir.join("eleanor.db");
+
+    // Write-ahead log files of the old database would be applied to the restored one
+    for suffix in ["", "-wal", "-shm"] {
+        let path = with_suffix(&database, suffix);
+
+        if path.exists() {
+            std::fs::rename(&path, with_suffix(&path, ".old")).into_diagnostic()?;
+        }
+    }
+
+    std::fs::write(&database, contents).into_diagnostic()
+}
+
+/// Writes a consistent copy of the database to `path`. Copying the file directly
+/// could catch it halfway through a write.
+async fn vacuum_into(path: &Path, db: &DatabaseConnection) -> Result<()> {
+    // `VACUUM INTO` refuses to overwrite files
+    remove_if_exists(path)?;
+
+    db.execute(Statement::from_string(
+        db.get_database_backend(),
+        format!(
+            "VACUUM INTO '{}'",
+            path.display().to_string().replace('\'', "''")
+        ),
+    ))
+    .await
+    .into_diagnostic()?;
+
+    Ok(())
+}
+
+async fn applied_migrations(db: &DatabaseConnection) -> Result<Vec<String>> {
+    db.query_all(Statement::from_string(
+        db.get_database_backend(),
+        "SELECT version FROM seaql_migrations ORDER BY version".into(),
+    ))
+    .await
+    .into_diagnostic()?
+    .iter()
+    .map(|v| v.try_get("", "version").into_diagnostic())
+    .collect()
+}
+
+fn settings_path() -> Result<PathBuf> {
+    config_dir()
+        .map(|v| v.join("settings.toml"))
+        .ok_or(miette!("Configuration file not found"))
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+
+    path.into()
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e).into_diagnostic(),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, remove_dir_all};
+
+    #[test]
+    fn rotation_keeps_newest() {
+        let dir = std::env::temp_dir().join(format!("eleanor-snapshots-{}", std::process::id()));
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+
+        let mut taken = vec![];
+        for reason in ["periodic", "purge", "purge", "migration"] {
+            let path = free_path(&dir, reason).unwrap();
+            File::create(&path).unwrap();
+            taken.push(path);
+        }
+
+        rotate(&dir, 2).unwrap();
+
+        let kept: Vec<PathBuf> = snapshots_in(&dir)
+            .unwrap()
+            .into_iter()
+            .map(|v| v.path)
+            .collect();
+
+        assert_eq!(kept, [taken[3].clone(), taken[2].clone()]);
+
+        remove_dir_all(dir).unwrap();
+    }
+}
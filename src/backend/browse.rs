@@ -0,0 +1,394 @@
+use std::{
+    cmp::Reverse,
+    collections::{hash_map::Entry, BTreeSet, HashMap},
+    path::{Component, Path, PathBuf},
+};
+
+use super::{
+    artists::songs_by_artist,
+    compilations::album_artist,
+    config::{Config, SourceKind},
+    model::{artists, library},
+};
+use miette::{miette, IntoDiagnostic, Result};
+use paris::warn;
+use sea_orm::{
+    ColumnTrait, Condition, ConnectionTrait, DatabaseConnection, DeriveColumn, EntityTrait,
+    EnumIter, IdenStatic, QueryFilter, QueryOrder, QuerySelect,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveColumn)]
+enum QueryAs {
+    Path,
+}
+
+/// Contents of a directory in a source
+#[derive(Debug, Clone, Default)]
+pub struct Listing {
+    /// Names of subdirectories that contain indexed songs
+    pub directories: Vec<String>,
+    /// Songs directly inside the directory
+    pub tracks: Vec<library::Model>,
+}
+
+/// An album as it's listed in the library
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Album {
+    /// Album artist, falling back to the artist. "Various Artists" for compilations.
+    pub artist: Option<String>,
+    pub name: String,
+    pub compilation: bool,
+    /// Directory of an album without album tags, named after the directory
+    pub folder: Option<String>,
+}
+
+/// Every album in the library, sorted by artist.
+/// Compilations are listed once instead of once for each of their artists,
+/// and albums available in several formats or sources are listed once.
+/// Songs without album tags are grouped by directory, see `folder_album`.
+pub async fn albums(db: &DatabaseConnection) -> Result<Vec<Album>> {
+    let songs = library::Entity::find().all(db).await.into_diagnostic()?;
+
+    let mut albums: BTreeSet<Album> = songs
+        .iter()
+        .filter_map(|v| {
+            Some(Album {
+                artist: album_artist(v).map(str::to_string),
+                name: v.album.clone()?,
+                compilation: v.compilation,
+                folder: None,
+            })
+        })
+        .collect();
+
+    let sources = Config::read_config()?.sources;
+    // `None` for sources that are gone from the config, whose songs are left out
+    let mut roots: HashMap<i32, Option<PathBuf>> = HashMap::new();
+    let mut folders: HashMap<&str, (&str, BTreeSet<Option<&str>>)> = HashMap::new();
+
+    for song in songs.iter().filter(|v| v.album.is_none()) {
+        if let Entry::Vacant(entry) = roots.entry(song.source_id) {
+            let root = match sources.iter().find(|v| i32::from(v.id) == song.source_id) {
+                Some(source) => Some(source_root(source.id, db).await?),
+                None => {
+                    warn!(
+                        "Skipped songs of source {}, which doesn't exist anymore",
+                        song.source_id
+                    );
+                    None
+                }
+            };
+
+            entry.insert(root);
+        }
+
+        let Some(root) = &roots[&song.source_id] else {
+            continue;
+        };
+
+        if let Some(name) = folder_album(song, root) {
+            folders
+                .entry(&song.path)
+                .or_insert((name, BTreeSet::new()))
+                .1
+                .insert(album_artist(song));
+        }
+    }
+
+    for (folder, (name, artists)) in folders {
+        // Bootlegs of a single artist are listed under them
+        let artist = match artists.into_iter().collect::<Vec<_>>()[..] {
+            [artist] => artist.map(str::to_string),
+            _ => None,
+        };
+
+        albums.insert(Album {
+            artist,
+            name: name.to_string(),
+            compilation: false,
+            folder: Some(folder.to_string()),
+        });
+    }
+
+    Ok(albums.into_iter().collect())
+}
+
+/// Albums an artist has songs on, including compilations and albums they're only featured on.
+/// Sorted by name, songs without album tags are left out.
+pub async fn artist_albums(artist: &artists::Model, db: &DatabaseConnection) -> Result<Vec<Album>> {
+    let albums: BTreeSet<_> = songs_by_artist(artist, db)
+        .await?
+        .iter()
+        .filter_map(|v| {
+            Some((
+                v.album.clone()?,
+                album_artist(v).map(str::to_string),
+                v.compilation,
+            ))
+        })
+        .collect();
+
+    Ok(albums
+        .into_iter()
+        .map(|(name, artist, compilation)| Album {
+            artist,
+            name,
+            compilation,
+            folder: None,
+        })
+        .collect())
+}
+
+/// Songs added to the library most recently, newest first.
+/// Songs indexed before added dates were recorded are left out.
+pub async fn recently_added(limit: u64, db: &DatabaseConnection) -> Result<Vec<library::Model>> {
+    library::Entity::find()
+        .filter(library::Column::AddedDate.is_not_null())
+        .order_by_desc(library::Column::AddedDate)
+        .order_by_asc(library::Column::Path)
+        .order_by_asc(library::Column::Track)
+        .limit(limit)
+        .all(db)
+        .await
+        .into_diagnostic()
+}
+
+/// Albums with the songs added most recently, newest first, e.g. for a "new in the library"
+/// row on the home screen. Songs without album tags are left out.
+pub async fn recently_added_albums(limit: usize, db: &DatabaseConnection) -> Result<Vec<Album>> {
+    let songs = library::Entity::find()
+        .filter(library::Column::AddedDate.is_not_null())
+        .filter(library::Column::Album.is_not_null())
+        .order_by_desc(library::Column::AddedDate)
+        .all(db)
+        .await
+        .into_diagnostic()?;
+
+    let mut albums: Vec<Album> = vec![];
+
+    for song in &songs {
+        let album = Album {
+            artist: album_artist(song).map(str::to_string),
+            name: song.album.clone().unwrap_or_default(),
+            compilation: song.compilation,
+            folder: None,
+        };
+
+        if !albums.contains(&album) {
+            albums.push(album);
+        }
+
+        if albums.len() >= limit {
+            break;
+        }
+    }
+
+    Ok(albums)
+}
+
+/// Name of the album a song without an album tag belongs to, which is the directory it's in,
+/// matching how untagged bootlegs are usually organized.
+/// Loose files in the root of a source don't belong to an album.
+pub fn folder_album<'a>(song: &'a library::Model, root: &Path) -> Option<&'a str> {
+    if song.album.is_some() {
+        return None;
+    }
+
+    let path = Path::new(&song.path);
+
+    if path.components().eq(root.components()) {
+        return None;
+    }
+
+    path.file_name().and_then(|v| v.to_str())
+}
+
+/// One copy of an album, e.g. the FLAC files in a local source
+#[derive(Debug, Clone)]
+pub struct AlbumVersion {
+    pub source_id: i32,
+    /// Lowercase file extension of the tracks
+    pub format: String,
+    /// Tracks in track order
+    pub tracks: Vec<library::Model>,
+}
+
+/// Songs of an album, in track order. If the album exists in several formats or sources,
+/// only the tracks of the preferred version are returned.
+pub async fn album_tracks(album: &Album, db: &DatabaseConnection) -> Result<Vec<library::Model>> {
+    Ok(album_versions(album, db)
+        .await?
+        .into_iter()
+        .next()
+        .map(|v| v.tracks)
+        .unwrap_or_default())
+}
+
+/// Every copy of an album, ordered by preference: by `Config::preferred_formats`,
+/// then by `Config::source_rank`, then more complete copies first
+pub async fn album_versions(album: &Album, db: &DatabaseConnection) -> Result<Vec<AlbumVersion>> {
+    let config = Config::read_config()?;
+
+    let mut versions: Vec<AlbumVersion> = vec![];
+
+    for track in all_album_tracks(album, db).await? {
+        let format = Path::new(&track.filename)
+            .extension()
+            .map(|v| v.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+
+        match versions
+            .iter_mut()
+            .find(|v| v.source_id == track.source_id && v.format == format)
+        {
+            Some(version) => version.tracks.push(track),
+            None => versions.push(AlbumVersion {
+                source_id: track.source_id,
+                format,
+                tracks: vec![track],
+            }),
+        }
+    }
+
+    versions.sort_by_key(|v| {
+        let (listed, remote, _) = config.source_rank(v.source_id);
+
+        (
+            config
+                .preferred_formats
+                .iter()
+                .position(|f| f.eq_ignore_ascii_case(&v.format))
+                .unwrap_or(usize::MAX),
+            listed,
+            remote,
+            Reverse(v.tracks.len()),
+            v.source_id,
+        )
+    });
+
+    Ok(versions)
+}
+
+async fn all_album_tracks(album: &Album, db: &DatabaseConnection) -> Result<Vec<library::Model>> {
+    let mut query = library::Entity::find();
+
+    if let Some(folder) = &album.folder {
+        query = query
+            .filter(library::Column::Album.is_null())
+            .filter(library::Column::Path.eq(folder.as_str()));
+    } else {
+        query = query
+            .filter(library::Column::Album.eq(album.name.as_str()))
+            .filter(library::Column::Compilation.eq(album.compilation));
+    }
+
+    if !album.compilation && album.folder.is_none() {
+        query = query.filter(match &album.artist {
+            Some(artist) => Condition::any()
+                .add(library::Column::AlbumArtist.eq(artist.as_str()))
+                .add(
+                    Condition::all()
+                        .add(library::Column::AlbumArtist.is_null())
+                        .add(library::Column::Artist.eq(artist.as_str())),
+                ),
+            None => Condition::all()
+                .add(library::Column::AlbumArtist.is_null())
+                .add(library::Column::Artist.is_null()),
+        });
+    }
+
+    query
+        .order_by_asc(library::Column::Disc)
+        .order_by_asc(library::Column::Track)
+        .order_by_asc(library::Column::Filename)
+        .all(db)
+        .await
+        .into_diagnostic()
+}
+
+/// Lists directories and indexed songs under a path relative to the root of a source.
+/// Only directories containing indexed songs are listed, so this works for remote sources too.
+pub async fn by_path(source_id: u8, subpath: &Path, db: &DatabaseConnection) -> Result<Listing> {
+    miette::ensure!(
+        subpath
+            .components()
+            .all(|v| matches!(v, Component::Normal(_) | Component::CurDir)),
+        "Invalid path {}",
+        subpath.display()
+    );
+
+    let root = source_root(source_id, db).await?;
+    // Normalize away trailing slashes, which would break prefix matching
+    let directory: PathBuf = root.join(subpath).components().collect();
+
+    let songs = library::Entity::find()
+        .filter(library::Column::SourceId.eq(source_id))
+        .filter(library::Column::Path.starts_with(&directory.to_string_lossy()))
+        .order_by_asc(library::Column::Disc)
+        .order_by_asc(library::Column::Track)
+        .order_by_asc(library::Column::Filename)
+        .all(db)
+        .await
+        .into_diagnostic()?;
+
+    let mut directories = BTreeSet::new();
+    let mut tracks = vec![];
+
+    for song in songs {
+        // `starts_with` also matches sibling directories sharing a prefix
+        let Ok(relative) = Path::new(&song.path).strip_prefix(&directory) else {
+            continue;
+        };
+
+        match relative.components().next() {
+            Some(Component::Normal(name)) => {
+                directories.insert(name.to_string_lossy().to_string());
+            }
+            _ => tracks.push(song),
+        }
+    }
+
+    Ok(Listing {
+        directories: directories.into_iter().collect(),
+        tracks,
+    })
+}
+
+/// The directory paths in a source are relative to.
+/// For remote sources this is the deepest directory containing every song.
+pub async fn source_root(source_id: u8, db: &impl ConnectionTrait) -> Result<PathBuf> {
+    let source = Config::read_config()?
+        .sources
+        .into_iter()
+        .find(|v| v.id == source_id)
+        .ok_or(miette!("Source {} does not exist", source_id))?;
+
+    if let SourceKind::Local { path } = source.source {
+        return Ok(path.into());
+    }
+
+    let paths: Vec<String> = library::Entity::find()
+        .select_only()
+        .column_as(library::Column::Path, QueryAs::Path)
+        .filter(library::Column::SourceId.eq(source_id))
+        .group_by(library::Column::Path)
+        .into_values::<_, QueryAs>()
+        .all(db)
+        .await
+        .into_diagnostic()?;
+
+    let mut paths = paths.iter().map(Path::new);
+
+    let Some(first) = paths.next() else {
+        return Ok(PathBuf::new());
+    };
+
+    Ok(paths.fold(first.to_path_buf(), |root, path| {
+        root.components()
+            .zip(path.components())
+            .take_while(|(a, b)| a == b)
+            .map(|(a, _)| a)
+            .collect()
+    }))
+}
@@ -0,0 +1,486 @@
+use std::{
+    io::ErrorKind,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use super::{
+    config::Playback,
+    errors::{report, Category, Severity},
+    events::{publish, Event},
+    playback::{Chain, StreamFormat},
+    replaygain::Gain,
+};
+use miette::{ensure, miette, IntoDiagnostic, Result};
+use paris::warn;
+use serde::{Deserialize, Serialize};
+use symphonia::{
+    core::{
+        audio::SampleBuffer,
+        codecs::Decoder,
+        errors::Error,
+        formats::{FormatOptions, FormatReader},
+        io::{MediaSource, MediaSourceStream},
+        probe::Hint,
+    },
+    default::{get_codecs, get_probe},
+};
+use thread_priority::{set_current_thread_priority, ThreadPriority};
+
+/// Seconds of audio the decoder keeps ready ahead of the output device
+const BUFFER_SECONDS: usize = 2;
+
+/// How long the decoder waits for the output to make room once the buffer is full
+const FULL_WAIT: Duration = Duration::from_millis(10);
+
+static UNDERRUNS: AtomicU64 = AtomicU64::new(0);
+static MISSED_SAMPLES: AtomicU64 = AtomicU64::new(0);
+
+/// Counters of how well the decoder has kept up with the output since the last reset
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Telemetry {
+    /// Times the output device asked for audio the decoder hadn't produced yet
+    pub underruns: u64,
+    /// Samples that were replaced by silence during underruns
+    pub missed_samples: u64,
+}
+
+pub fn telemetry() -> Telemetry {
+    Telemetry {
+        underruns: UNDERRUNS.load(Ordering::Relaxed),
+        missed_samples: MISSED_SAMPLES.load(Ordering::Relaxed),
+    }
+}
+
+pub fn reset_telemetry() {
+    UNDERRUNS.store(0, Ordering::Relaxed);
+    MISSED_SAMPLES.store(0, Ordering::Relaxed);
+}
+
+/// Interleaved samples shared by one producer and one consumer without locking.
+/// `read` and `write` only ever grow, their difference is how much is buffered.
+struct Ring {
+    samples: Box<[AtomicU32]>,
+    read: AtomicUsize,
+    write: AtomicUsize,
+    /// Set once the producer won't write anything else
+    finished: AtomicBool,
+    /// Set once the producer wrote the whole song, instead of being stopped
+    ended: AtomicBool,
+    /// Set once the consumer read the first samples
+    started: AtomicBool,
+}
+
+impl Ring {
+    fn available(&self) -> usize {
+        self.write.load(Ordering::Acquire) - self.read.load(Ordering::Acquire)
+    }
+}
+
+/// Creates a ring buffer holding up to `capacity` samples
+pub fn ring_buffer(capacity: usize) -> (Producer, Consumer) {
+    let ring = Arc::new(Ring {
+        samples: (0..capacity.max(1)).map(|_| AtomicU32::new(0)).collect(),
+        read: AtomicUsize::new(0),
+        write: AtomicUsize::new(0),
+        finished: AtomicBool::new(false),
+        ended: AtomicBool::new(false),
+        started: AtomicBool::new(false),
+    });
+
+    (Producer(ring.clone()), Consumer(ring))
+}
+
+/// The writing side of a ring buffer, owned by the decoder thread
+pub struct Producer(Arc<Ring>);
+
+impl Producer {
+    /// Writes as many samples as fit, returning how many were written
+    pub fn push(&mut self, samples: &[f32]) -> usize {
+        let ring = &self.0;
+        let capacity = ring.samples.len();
+        let write = ring.write.load(Ordering::Relaxed);
+        let read = ring.read.load(Ordering::Acquire);
+
+        let count = samples.len().min(capacity - (write - read));
+
+        for (offset, sample) in samples[..count].iter().enumerate() {
+            ring.samples[(write + offset) % capacity].store(sample.to_bits(), Ordering::Relaxed);
+        }
+
+        ring.write.store(write + count, Ordering::Release);
+
+        count
+    }
+
+    /// Marks the end of the audio, so running out of it isn't counted as an underrun
+    pub fn finish(&mut self) {
+        self.0.finished.store(true, Ordering::Release);
+    }
+
+    /// Marks that everything up to the end of the song was written, so it can be played to its end
+    pub fn end(&mut self) {
+        self.0.ended.store(true, Ordering::Release);
+        self.finish();
+    }
+}
+
+impl Drop for Producer {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// The reading side of a ring buffer, owned by the audio callback
+pub struct Consumer(Arc<Ring>);
+
+impl Consumer {
+    /// Reads up to `out.len()` samples, returning how many were read
+    pub fn pop(&mut self, out: &mut [f32]) -> usize {
+        let ring = &self.0;
+        let capacity = ring.samples.len();
+        let read = ring.read.load(Ordering::Relaxed);
+        let write = ring.write.load(Ordering::Acquire);
+
+        let count = out.len().min(write - read);
+
+        for (offset, sample) in out[..count].iter_mut().enumerate() {
+            *sample =
+                f32::from_bits(ring.samples[(read + offset) % capacity].load(Ordering::Relaxed));
+        }
+
+        ring.read.store(read + count, Ordering::Release);
+
+        if count > 0 && !ring.started.load(Ordering::Relaxed) {
+            ring.started.store(true, Ordering::Release);
+        }
+
+        count
+    }
+
+    /// Fills the output device's buffer. If the decoder fell behind, the rest is silence and
+    /// the underrun is counted. Never blocks or allocates, so it's safe in the audio callback.
+    pub fn fill(&mut self, out: &mut [f32]) {
+        let count = self.pop(out);
+
+        if count == out.len() {
+            return;
+        }
+
+        out[count..].fill(0.0);
+
+        if !self.0.finished.load(Ordering::Acquire) {
+            UNDERRUNS.fetch_add(1, Ordering::Relaxed);
+            MISSED_SAMPLES.fetch_add((out.len() - count) as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Samples ready to be played
+    pub fn available(&self) -> usize {
+        self.0.available()
+    }
+
+    /// Whether the decoder is done and everything it produced has been played
+    pub fn is_finished(&self) -> bool {
+        self.0.finished.load(Ordering::Acquire) && self.available() == 0
+    }
+}
+
+/// Decodes a song on a dedicated high priority thread, so the output keeps getting audio
+/// while indexing or the GUI are busy. Decoding stops when this is dropped.
+///
+/// The encoder delay and padding of MP3 and AAC files, from their LAME or iTunes gapless info,
+/// are trimmed off, so albums meant to be gapless play without silence between tracks.
+///
+/// Publishes `Event::TrackStarted` once audio of the song comes out of the buffer, see `played`,
+/// and `Event::TrackEnded` when it's dropped after that. The track counts as finished if all of
+/// it was played, and as skipped if it was dropped earlier.
+pub struct DecoderThread {
+    hash: i64,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<Result<()>>>,
+    /// Frames of the song decoded so far, not counting the trimmed delay and padding
+    frames: Arc<AtomicU64>,
+    sample_rate: u32,
+    /// Frames in the song without the delay and padding, if the file says
+    total: Option<u64>,
+    /// Format and speed of what's in the buffer, to tell how much of the song it holds
+    output: StreamFormat,
+    speed: f32,
+    /// The buffer the consumer plays from, to tell whether the song started and finished
+    ring: Arc<Ring>,
+    /// Whether `Event::TrackStarted` was published
+    announced: AtomicBool,
+}
+
+impl DecoderThread {
+    /// Starts decoding a song's file through a processing chain with the song's gain.
+    /// Returns the thread along with the buffer to play from, whose format is the chain's output.
+    pub fn spawn(
+        hash: i64,
+        source: Box<dyn MediaSource>,
+        ext: &str,
+        gain: Option<Gain>,
+        settings: &Playback,
+    ) -> Result<(Self, Consumer)> {
+        let source = MediaSourceStream::new(source, Default::default());
+        let format = get_probe()
+            .format(
+                Hint::new().with_extension(ext),
+                source,
+                &FormatOptions {
+                    enable_gapless: true,
+                    ..Default::default()
+                },
+                &Default::default(),
+            )
+            .into_diagnostic()?
+            .format;
+
+        let track = format
+            .default_track()
+            .ok_or(miette!("No audio track found"))?;
+        let track_id = track.id;
+
+        let source_rate = track
+            .codec_params
+            .sample_rate
+            .ok_or(miette!("Unknown sample rate"))?;
+        let layout = track
+            .codec_params
+            .channels
+            .ok_or(miette!("Unknown channel layout"))?;
+        // With gapless enabled, this leaves out the delay and padding
+        let total = track.codec_params.n_frames;
+
+        let decoder = get_codecs()
+            .make(&track.codec_params, &Default::default())
+            .into_diagnostic()?;
+
+        let mut chain = Chain::new(source_rate, layout, settings)?;
+        chain.set_gain(gain);
+
+        let output = chain.format();
+        let speed = chain.speed();
+        let (producer, consumer) =
+            ring_buffer(output.sample_rate as usize * output.channels * BUFFER_SECONDS);
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let frames = Arc::new(AtomicU64::new(0));
+        let thread_frames = frames.clone();
+
+        let handle = thread::Builder::new()
+            .name("eleanor-decoder".into())
+            .spawn(move || {
+                if let Err(e) = set_current_thread_priority(ThreadPriority::Max) {
+                    warn!("Couldn't raise the decoder's priority: {e:?}");
+                }
+
+                decode(
+                    format,
+                    decoder,
+                    track_id,
+                    chain,
+                    producer,
+                    &thread_frames,
+                    &thread_stop,
+                )
+            })
+            .into_diagnostic()?;
+
+        Ok((
+            DecoderThread {
+                hash,
+                ring: consumer.0.clone(),
+                announced: AtomicBool::new(false),
+                stop,
+                handle: Some(handle),
+                frames,
+                sample_rate: source_rate,
+                total,
+                output,
+                speed,
+            },
+            consumer,
+        ))
+    }
+
+    /// How much of the song has been decoded, to the sample. Playback is behind this
+    /// by what's waiting in the buffer.
+    pub fn decoded(&self) -> Duration {
+        frames_duration(self.frames.load(Ordering::Relaxed), self.sample_rate)
+    }
+
+    /// Position of the audio coming out of the buffer, to the sample.
+    /// This is where playback is, give or take the output device's latency.
+    /// Called by the player as the song plays, which is when its start is announced.
+    pub fn played(&self, buffer: &Consumer) -> Duration {
+        self.announce();
+
+        let frames = buffer.available() / self.output.channels;
+        let buffered = frames_duration(frames as u64, self.output.sample_rate).mul_f32(self.speed);
+
+        self.decoded().saturating_sub(buffered)
+    }
+
+    /// Exact length of the song without the encoder delay and padding, if the file says
+    pub fn duration(&self) -> Option<Duration> {
+        self.total.map(|v| frames_duration(v, self.sample_rate))
+    }
+
+    /// Whether the decoder has reached the end of the file or failed
+    pub fn is_finished(&self) -> bool {
+        self.handle.as_ref().is_none_or(|v| v.is_finished())
+    }
+
+    /// Publishes `Event::TrackStarted` the first time this is called after the song started playing
+    fn announce(&self) {
+        if self.ring.started.load(Ordering::Acquire)
+            && !self.announced.swap(true, Ordering::Relaxed)
+        {
+            publish(Event::TrackStarted { hash: self.hash });
+        }
+    }
+
+    /// Stops decoding, returning the error decoding ended with, if any
+    pub fn stop(mut self) -> Result<()> {
+        self.join()
+    }
+
+    fn join(&mut self) -> Result<()> {
+        self.stop.store(true, Ordering::Relaxed);
+
+        match self.handle.take() {
+            Some(handle) => handle
+                .join()
+                .map_err(|_| miette!("The decoder thread panicked"))?,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for DecoderThread {
+    fn drop(&mut self) {
+        if let Err(e) = self.join() {
+            report(
+                Severity::Error,
+                Category::Decoding,
+                None,
+                format!("Decoding failed: {e}"),
+            );
+        }
+
+        // Songs that were only buffered, like prefetched ones, never started
+        if self.ring.started.load(Ordering::Acquire) {
+            self.announce();
+
+            publish(Event::TrackEnded {
+                hash: self.hash,
+                finished: self.ring.ended.load(Ordering::Acquire) && self.ring.available() == 0,
+            });
+        }
+    }
+}
+
+fn frames_duration(frames: u64, sample_rate: u32) -> Duration {
+    Duration::from_secs_f64(frames as f64 / sample_rate as f64)
+}
+
+fn decode(
+    mut format: Box<dyn FormatReader>,
+    mut decoder: Box<dyn Decoder>,
+    mut track_id: u32,
+    mut chain: Chain,
+    mut producer: Producer,
+    frames: &AtomicU64,
+    stop: &AtomicBool,
+) -> Result<()> {
+    let mut buffer: Option<SampleBuffer<f32>> = None;
+    let params = decoder.codec_params();
+    let layout = (params.sample_rate, params.channels);
+
+    loop {
+        let packet = match format.next_packet() {
+            Ok(v) => v,
+            // The end of the stream comes out as an error too
+            Err(Error::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => break,
+            // Chained streams, like Ogg recordings of a radio, start a new track
+            Err(Error::ResetRequired) => {
+                let track = format
+                    .default_track()
+                    .ok_or(miette!("No audio track found"))?;
+
+                // The processing chain only takes the format it was made for
+                ensure!(
+                    (track.codec_params.sample_rate, track.codec_params.channels) == layout,
+                    "The audio format changed partway through the file"
+                );
+
+                track_id = track.id;
+                decoder = get_codecs()
+                    .make(&track.codec_params, &Default::default())
+                    .into_diagnostic()?;
+                continue;
+            }
+            Err(e) => return Err(e).into_diagnostic(),
+        };
+
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        // Decoders trim the delay and padding off the packets that have them,
+        // so packets entirely made of them come out empty
+        let decoded = match decoder.decode(&packet) {
+            Ok(v) => v,
+            // Skip over corrupted packets
+            Err(Error::DecodeError(_)) => continue,
+            Err(Error::ResetRequired) => {
+                decoder.reset();
+                continue;
+            }
+            Err(e) => return Err(e).into_diagnostic(),
+        };
+        frames.fetch_add(decoded.frames() as u64, Ordering::Relaxed);
+
+        let buffer = buffer
+            .get_or_insert_with(|| SampleBuffer::new(decoded.capacity() as u64, *decoded.spec()));
+        buffer.copy_interleaved_ref(decoded);
+
+        let samples = chain.process(buffer.samples())?;
+
+        if !write_all(&mut producer, &samples, stop) {
+            return Ok(());
+        }
+    }
+
+    let samples = chain.finish()?;
+    if write_all(&mut producer, &samples, stop) {
+        producer.end();
+    }
+
+    Ok(())
+}
+
+/// Waits for the output to make room until every sample is written.
+/// Returns false if decoding was stopped first.
+fn write_all(producer: &mut Producer, mut samples: &[f32], stop: &AtomicBool) -> bool {
+    loop {
+        if stop.load(Ordering::Relaxed) {
+            return false;
+        }
+
+        samples = &samples[producer.push(samples)..];
+
+        if samples.is_empty() {
+            return true;
+        }
+
+        thread::sleep(FULL_WAIT);
+    }
+}
@@ -0,0 +1,46 @@
+use std::sync::Arc;
+
+use tokio::sync::watch;
+
+/// Lets long-running work, like indexing a source, be stopped from elsewhere.
+/// Work checks the token between steps and winds down on its own, so nothing is left half-written.
+/// Clones share the same state, cancelling one cancels all of them.
+#[derive(Clone, Debug)]
+pub struct CancellationToken {
+    sender: Arc<watch::Sender<bool>>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        CancellationToken {
+            sender: Arc::new(watch::channel(false).0),
+        }
+    }
+
+    /// Asks everything holding this token to stop
+    pub fn cancel(&self) {
+        // Unlike `send`, this also works while nobody is waiting on `cancelled`
+        self.sender.send_replace(true);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.sender.borrow()
+    }
+
+    /// Resolves once the token is cancelled
+    pub async fn cancelled(&self) {
+        let mut cancelled = self.sender.subscribe();
+
+        while !*cancelled.borrow_and_update() {
+            if cancelled.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+impl Default for CancellationToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
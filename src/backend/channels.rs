@@ -0,0 +1,101 @@
+use symphonia::core::audio::Channels;
+
+/// Gain of channels that don't belong to the front pair, -3 dB
+const SURROUND_GAIN: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+/// Short description of a channel layout, like "mono" or "5.1", as stored in the library
+pub fn layout_name(channels: usize) -> String {
+    match channels {
+        1 => "mono".into(),
+        2 => "stereo".into(),
+        4 => "quad".into(),
+        6 => "5.1".into(),
+        8 => "7.1".into(),
+        n => format!("{n} channels"),
+    }
+}
+
+/// Turns interleaved audio of any layout into interleaved stereo.
+/// Mono is copied to both sides, and multichannel audio is mixed down with the
+/// center and surround channels at -3 dB and the LFE left out.
+pub struct Downmix {
+    /// Left and right gain of every input channel
+    gains: Vec<[f32; 2]>,
+}
+
+impl Downmix {
+    /// Returns `None` for stereo audio, which doesn't need mixing
+    pub fn new(layout: Channels) -> Option<Self> {
+        let gains: Vec<_> = match layout.count() {
+            0 | 2 => return None,
+            1 => vec![[1.0, 1.0]],
+            _ => layout.iter().map(channel_gain).collect(),
+        };
+
+        // Scale everything down so a full scale signal on every channel can't clip
+        let left: f32 = gains.iter().map(|v| v[0]).sum();
+        let right: f32 = gains.iter().map(|v| v[1]).sum();
+        let scale = 1.0 / left.max(right).max(1.0);
+
+        Some(Downmix {
+            gains: gains
+                .into_iter()
+                .map(|[l, r]| [l * scale, r * scale])
+                .collect(),
+        })
+    }
+
+    /// Mixes a block of interleaved samples down to stereo
+    pub fn process(&self, samples: &[f32]) -> Vec<f32> {
+        let mut output = Vec::with_capacity(samples.len() / self.gains.len() * 2);
+
+        for frame in samples.chunks_exact(self.gains.len()) {
+            let (left, right) = frame
+                .iter()
+                .zip(&self.gains)
+                .fold((0.0, 0.0), |(l, r), (sample, [gl, gr])| {
+                    (l + sample * gl, r + sample * gr)
+                });
+
+            output.push(left);
+            output.push(right);
+        }
+
+        output
+    }
+}
+
+fn channel_gain(channel: Channels) -> [f32; 2] {
+    const LEFT: Channels = Channels::REAR_LEFT
+        .union(Channels::SIDE_LEFT)
+        .union(Channels::FRONT_LEFT_CENTRE)
+        .union(Channels::REAR_LEFT_CENTRE)
+        .union(Channels::FRONT_LEFT_WIDE)
+        .union(Channels::FRONT_LEFT_HIGH)
+        .union(Channels::TOP_FRONT_LEFT)
+        .union(Channels::TOP_REAR_LEFT);
+    const RIGHT: Channels = Channels::REAR_RIGHT
+        .union(Channels::SIDE_RIGHT)
+        .union(Channels::FRONT_RIGHT_CENTRE)
+        .union(Channels::REAR_RIGHT_CENTRE)
+        .union(Channels::FRONT_RIGHT_WIDE)
+        .union(Channels::FRONT_RIGHT_HIGH)
+        .union(Channels::TOP_FRONT_RIGHT)
+        .union(Channels::TOP_REAR_RIGHT);
+    const LFE: Channels = Channels::LFE1.union(Channels::LFE2);
+
+    if channel == Channels::FRONT_LEFT {
+        [1.0, 0.0]
+    } else if channel == Channels::FRONT_RIGHT {
+        [0.0, 1.0]
+    } else if LEFT.contains(channel) {
+        [SURROUND_GAIN, 0.0]
+    } else if RIGHT.contains(channel) {
+        [0.0, SURROUND_GAIN]
+    } else if LFE.contains(channel) {
+        [0.0, 0.0]
+    } else {
+        // Centre channels, and anything without a side
+        [SURROUND_GAIN, SURROUND_GAIN]
+    }
+}
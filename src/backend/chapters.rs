@@ -0,0 +1,532 @@
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+    time::Duration,
+};
+
+use super::fetching::open_format;
+use lofty::{read_from_path, ItemKey, ItemValue, Tag};
+use miette::{IntoDiagnostic, Result};
+use serde::{Deserialize, Serialize};
+use symphonia::core::meta::StandardTagKey;
+
+/// Files at least this long get evenly spaced chapters if they don't have any
+pub const LONG_FILE: Duration = Duration::from_secs(60 * 60);
+
+/// Going back further into a chapter than this restarts it instead of going to the previous one
+const RESTART_THRESHOLD: Duration = Duration::from_secs(3);
+
+/// Larger `moov` atoms aren't read, they can't be from a sane file
+const MAX_MOOV_SIZE: u64 = 64 * 1024 * 1024;
+
+/// A section of a long file, like a chapter of an audiobook or a track of a mix
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Chapter {
+    /// Position in the file at which the chapter starts
+    pub start: Duration,
+    pub title: Option<String>,
+}
+
+/// Reads the chapters of a file, sorted by their start.
+/// Vorbis style `CHAPTERxxx` tags are checked first, then the chapter list of MP4 files like
+/// M4B audiobooks, then cue sheets embedded in the file, and finally a cue sheet next to the file.
+pub fn read_chapters(path: &Path) -> Result<Vec<Chapter>> {
+    let audio = read_from_path(path, false).into_diagnostic()?;
+
+    for tag in audio.tags() {
+        let chapters = tag_chapters(tag);
+        if !chapters.is_empty() {
+            return Ok(chapters);
+        }
+    }
+
+    let extension = path
+        .extension()
+        .map(|v| v.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    let embedded = match extension.as_str() {
+        "m4b" | "m4a" | "mp4" => mp4_chapters(path)?,
+        // Formats the decoder can't open don't have embedded cue sheets it could read anyway
+        _ => cue_chapters(path).unwrap_or_default(),
+    };
+
+    if !embedded.is_empty() {
+        return Ok(embedded);
+    }
+
+    let filename = path
+        .file_name()
+        .map(|v| v.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    // Both `mix.cue` and `mix.flac.cue` are common
+    let cue_sheets = [
+        path.with_extension("cue"),
+        path.with_file_name(format!("{filename}.cue")),
+    ];
+
+    for cue_sheet in cue_sheets {
+        if let Ok(contents) = std::fs::read_to_string(cue_sheet) {
+            let chapters = parse_cue(&contents, &filename);
+            if !chapters.is_empty() {
+                return Ok(chapters);
+            }
+        }
+    }
+
+    Ok(vec![])
+}
+
+/// Reads `CHAPTER001=00:00:00.000` and `CHAPTER001NAME=Title` pairs
+fn tag_chapters(tag: &Tag) -> Vec<Chapter> {
+    let mut numbered = vec![];
+
+    for item in tag.items() {
+        let (ItemKey::Unknown(key), ItemValue::Text(value)) = (item.key(), item.value()) else {
+            continue;
+        };
+
+        let key = key.to_ascii_uppercase();
+        let Some(number) = key.strip_prefix("CHAPTER") else {
+            continue;
+        };
+
+        if number.chars().all(|v| v.is_ascii_digit()) {
+            if let Some(start) = parse_clock(value) {
+                let name = format!("{key}NAME");
+                let title = tag
+                    .items()
+                    .iter()
+                    .find(
+                        |v| matches!(v.key(), ItemKey::Unknown(k) if k.eq_ignore_ascii_case(&name)),
+                    )
+                    .and_then(|v| v.value().text())
+                    .map(|v| v.trim().to_string())
+                    .filter(|v| !v.is_empty());
+
+                numbered.push(Chapter { start, title });
+            }
+        }
+    }
+
+    numbered.sort_by_key(|v| v.start);
+    numbered.dedup_by_key(|v| v.start);
+    numbered
+}
+
+/// Reads the Nero `chpl` chapter list in `moov/udta`, which most M4B audiobooks have
+fn mp4_chapters(path: &Path) -> Result<Vec<Chapter>> {
+    let mut file = File::open(path).into_diagnostic()?;
+
+    let Some(moov) = read_top_level_atom(&mut file, b"moov")? else {
+        return Ok(vec![]);
+    };
+
+    Ok(find_atom(&moov, b"udta")
+        .and_then(|v| find_atom(v, b"chpl"))
+        .map(parse_chpl)
+        .unwrap_or_default())
+}
+
+/// Reads the contents of a top level atom, skipping over the others, which include the audio
+fn read_top_level_atom(file: &mut File, kind: &[u8; 4]) -> Result<Option<Vec<u8>>> {
+    loop {
+        let mut header = [0; 8];
+        if file.read_exact(&mut header).is_err() {
+            return Ok(None);
+        }
+
+        let (size, header_size) =
+            match u32::from_be_bytes([header[0], header[1], header[2], header[3]]) {
+                // The size follows the type as a 64 bit number
+                1 => {
+                    let mut size = [0; 8];
+                    file.read_exact(&mut size).into_diagnostic()?;
+                    (u64::from_be_bytes(size), 16)
+                }
+                // The atom goes on until the end of the file
+                0 => (
+                    file.metadata().into_diagnostic()?.len()
+                        - file.stream_position().into_diagnostic()?
+                        + 8,
+                    8,
+                ),
+                size => (u64::from(size), 8),
+            };
+
+        let Some(contents) = size.checked_sub(header_size) else {
+            return Ok(None);
+        };
+
+        if &header[4..] == kind {
+            if contents > MAX_MOOV_SIZE {
+                return Ok(None);
+            }
+
+            let mut data = vec![0; contents as usize];
+            file.read_exact(&mut data).into_diagnostic()?;
+
+            return Ok(Some(data));
+        }
+
+        file.seek(SeekFrom::Current(contents as i64))
+            .into_diagnostic()?;
+    }
+}
+
+/// Contents of the first atom of a kind among atoms read into memory
+fn find_atom<'a>(mut data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    while data.len() >= 8 {
+        let size = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+        let atom = data.get(8..size)?;
+
+        if &data[4..8] == kind {
+            return Some(atom);
+        }
+
+        data = &data[size..];
+    }
+
+    None
+}
+
+/// Chapters are stored as their start in units of 100 ns, followed by a length prefixed title
+fn parse_chpl(data: &[u8]) -> Vec<Chapter> {
+    let mut chapters = vec![];
+
+    // Version 1 has 4 more bytes after the version and flags
+    let header = if data.first().is_some_and(|&v| v > 0) {
+        8
+    } else {
+        4
+    };
+    let Some((&count, mut rest)) = data.get(header..).and_then(<[u8]>::split_first) else {
+        return chapters;
+    };
+
+    for _ in 0..count {
+        let Some(start) = rest.get(..8) else {
+            break;
+        };
+        let start = u64::from_be_bytes(start.try_into().unwrap_or_default());
+
+        let Some(&length) = rest.get(8) else {
+            break;
+        };
+        let Some(title) = rest.get(9..9 + length as usize) else {
+            break;
+        };
+
+        chapters.push(Chapter {
+            start: Duration::from_nanos(start.saturating_mul(100)),
+            title: Some(String::from_utf8_lossy(title).trim().to_string())
+                .filter(|v| !v.is_empty()),
+        });
+
+        rest = &rest[9 + length as usize..];
+    }
+
+    chapters.sort_by_key(|v| v.start);
+    chapters
+}
+
+/// Reads cue sheets the decoder finds in the file, like FLAC's `CUESHEET` block
+fn cue_chapters(path: &Path) -> Result<Vec<Chapter>> {
+    let format = open_format(path)?;
+
+    let Some(params) = format.default_track().map(|v| &v.codec_params) else {
+        return Ok(vec![]);
+    };
+    let Some(time_base) = params.time_base else {
+        return Ok(vec![]);
+    };
+
+    let mut chapters: Vec<Chapter> = format
+        .cues()
+        .iter()
+        // The lead-out of a FLAC cue sheet is a track starting at the end
+        .filter(|v| params.n_frames.is_none_or(|end| v.start_ts < end))
+        // Cues with a start that doesn't fit in a duration are skipped
+        .filter_map(|cue| {
+            let time = time_base.calc_time(cue.start_ts);
+
+            Some(Chapter {
+                start: Duration::from_secs(time.seconds)
+                    .checked_add(Duration::try_from_secs_f64(time.frac).ok()?)?,
+                title: cue
+                    .tags
+                    .iter()
+                    .find(|v| v.std_key == Some(StandardTagKey::TrackTitle))
+                    .map(|v| v.value.to_string()),
+            })
+        })
+        .collect();
+
+    chapters.sort_by_key(|v| v.start);
+    chapters.dedup_by_key(|v| v.start);
+
+    Ok(chapters)
+}
+
+/// Parses an `hh:mm:ss.sss` timestamp. Hours may be left out.
+fn parse_clock(text: &str) -> Option<Duration> {
+    let mut parts = text.trim().rsplit(':');
+
+    let seconds: f64 = parts.next()?.parse().ok()?;
+    let minutes: u64 = parts.next().map_or(Ok(0), str::parse).ok()?;
+    let hours: u64 = parts.next().map_or(Ok(0), str::parse).ok()?;
+
+    if parts.next().is_some() {
+        return None;
+    }
+
+    let whole = hours
+        .checked_mul(60)?
+        .checked_add(minutes)?
+        .checked_mul(60)?;
+
+    // Negative, infinite and overly large seconds don't fit in a duration
+    Duration::from_secs(whole).checked_add(Duration::try_from_secs_f64(seconds).ok()?)
+}
+
+/// Reads the tracks of a cue sheet that belong to a file.
+/// Sheets that describe a single file are used even if the file was renamed.
+pub fn parse_cue(contents: &str, filename: &str) -> Vec<Chapter> {
+    let mut tracks = vec![];
+    let mut file = None;
+    let mut title = None;
+    let mut in_track = false;
+
+    for line in contents.lines() {
+        let line = line.trim();
+        let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+
+        match command.to_ascii_uppercase().as_str() {
+            "FILE" => {
+                file = Some(cue_string(rest.rsplit_once(' ').map_or(rest, |v| v.0)));
+                in_track = false;
+            }
+            "TRACK" => {
+                title = None;
+                in_track = true;
+            }
+            // A title before the first track is the album's
+            "TITLE" if in_track => title = Some(cue_string(rest)),
+            "INDEX" => {
+                let Some(("01", time)) = rest.trim().split_once(' ') else {
+                    continue;
+                };
+
+                if let Some(start) = parse_cue_time(time) {
+                    tracks.push((
+                        file.clone(),
+                        Chapter {
+                            start,
+                            title: title.clone().filter(|v: &String| !v.is_empty()),
+                        },
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let single_file = tracks.windows(2).all(|v| v[0].0 == v[1].0);
+
+    let mut chapters: Vec<Chapter> = tracks
+        .into_iter()
+        .filter(|(file, _)| {
+            single_file
+                || file.as_deref().is_some_and(|v| {
+                    // Paths in cue sheets are relative to the sheet
+                    Path::new(&v.replace('\\', "/"))
+                        .file_name()
+                        .is_some_and(|v| v.to_string_lossy() == filename)
+                })
+        })
+        .map(|(_, chapter)| chapter)
+        .collect();
+
+    chapters.sort_by_key(|v| v.start);
+    chapters
+}
+
+fn cue_string(text: &str) -> String {
+    text.trim().trim_matches('"').to_string()
+}
+
+/// Parses a cue sheet `mm:ss:ff` timestamp, with 75 frames per second
+fn parse_cue_time(text: &str) -> Option<Duration> {
+    let mut parts = text.trim().split(':').map(str::parse::<u64>);
+
+    let minutes = parts.next()?.ok()?;
+    let seconds = parts.next()?.ok()?;
+    let frames = parts.next()?.ok()?;
+
+    if parts.next().is_some() || frames >= 75 {
+        return None;
+    }
+
+    Some(
+        Duration::from_secs(minutes.checked_mul(60)?.checked_add(seconds)?)
+            + Duration::from_millis(frames * 1000 / 75),
+    )
+}
+
+/// Splits a long file without chapters into sections `interval` long, so it can still be
+/// navigated. Shorter files, and files that have chapters, are left as they are.
+pub fn with_fallback(
+    chapters: Vec<Chapter>,
+    duration: Duration,
+    interval: Duration,
+) -> Vec<Chapter> {
+    if !chapters.is_empty() || duration < LONG_FILE || interval.is_zero() {
+        return chapters;
+    }
+
+    let mut start = Duration::ZERO;
+    let mut generated = vec![];
+
+    while start < duration {
+        generated.push(Chapter { start, title: None });
+        start += interval;
+    }
+
+    generated
+}
+
+/// Index of the chapter playing at a position
+pub fn chapter_at(chapters: &[Chapter], position: Duration) -> Option<usize> {
+    chapters
+        .partition_point(|v| v.start <= position)
+        .checked_sub(1)
+}
+
+/// Start of the chapter after the one playing, if there is one
+pub fn next_chapter(chapters: &[Chapter], position: Duration) -> Option<Duration> {
+    chapters.iter().map(|v| v.start).find(|&v| v > position)
+}
+
+/// Where to seek to when going back, which is the start of the current chapter
+/// unless playback just entered it
+pub fn previous_chapter(chapters: &[Chapter], position: Duration) -> Duration {
+    let Some(current) = chapter_at(chapters, position) else {
+        return Duration::ZERO;
+    };
+
+    let start = chapters[current].start;
+
+    if position - start > RESTART_THRESHOLD || current == 0 {
+        start
+    } else {
+        chapters[current - 1].start
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chapter(seconds: u64, title: Option<&str>) -> Chapter {
+        Chapter {
+            start: Duration::from_secs(seconds),
+            title: title.map(String::from),
+        }
+    }
+
+    #[test]
+    fn clock_timestamps() {
+        assert_eq!(
+            parse_clock("01:02:03.500"),
+            Some(Duration::from_millis(3_723_500))
+        );
+        assert_eq!(parse_clock(" 02:03 "), Some(Duration::from_secs(123)));
+        assert_eq!(parse_clock("7.25"), Some(Duration::from_millis(7250)));
+
+        for malformed in [
+            "", "1:2:3:4", "aa:00", "00:-1", "00:00:-5", "00:inf", "00:NaN",
+        ] {
+            assert_eq!(parse_clock(malformed), None, "{malformed}");
+        }
+
+        // Overflows are rejected instead of panicking
+        assert_eq!(parse_clock(&format!("{}:00:00", u64::MAX)), None);
+        assert_eq!(parse_clock("1e300"), None);
+    }
+
+    #[test]
+    fn cue_sheet_tracks() {
+        let sheet = r#"
+            TITLE "The Mix"
+            FILE "mix.flac" WAVE
+              TRACK 01 AUDIO
+                TITLE "Intro"
+                INDEX 00 00:00:00
+                INDEX 01 00:00:00
+              TRACK 02 AUDIO
+                TITLE "Second"
+                INDEX 01 03:15:37
+              TRACK 03 AUDIO
+                INDEX 01 04:00:75
+              TRACK 04 AUDIO
+                INDEX 01 nonsense
+        "#;
+
+        assert_eq!(
+            parse_cue(sheet, "renamed.flac"),
+            [
+                chapter(0, Some("Intro")),
+                Chapter {
+                    start: Duration::from_millis(195_493),
+                    title: Some("Second".into()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn cue_sheet_with_several_files() {
+        let sheet = r#"
+            FILE "disc 1\a.flac" WAVE
+              TRACK 01 AUDIO
+                INDEX 01 00:00:00
+            FILE "b.flac" WAVE
+              TRACK 02 AUDIO
+                TITLE "Only"
+                INDEX 01 00:10:00
+        "#;
+
+        assert_eq!(parse_cue(sheet, "b.flac"), [chapter(10, Some("Only"))]);
+        assert_eq!(parse_cue(sheet, "a.flac"), [chapter(0, None)]);
+        assert!(parse_cue(sheet, "c.flac").is_empty());
+        assert!(parse_cue("INDEX\nTRACK\nFILE", "c.flac").is_empty());
+    }
+
+    #[test]
+    fn going_back() {
+        let chapters = [chapter(0, None), chapter(60, None), chapter(120, None)];
+
+        assert_eq!(
+            previous_chapter(&chapters, Duration::from_secs(90)),
+            Duration::from_secs(60)
+        );
+        assert_eq!(
+            previous_chapter(&chapters, Duration::from_secs(61)),
+            Duration::ZERO
+        );
+        assert_eq!(
+            previous_chapter(&chapters, Duration::from_secs(2)),
+            Duration::ZERO
+        );
+        assert_eq!(
+            previous_chapter(&[], Duration::from_secs(30)),
+            Duration::ZERO
+        );
+        assert_eq!(
+            previous_chapter(&chapters[1..], Duration::from_secs(30)),
+            Duration::ZERO
+        );
+    }
+}
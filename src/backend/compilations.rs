@@ -0,0 +1,64 @@
+use std::collections::{HashMap, HashSet};
+
+use super::model::library;
+use miette::{IntoDiagnostic, Result};
+use sea_orm::{sea_query::Expr, ColumnTrait, ConnectionTrait, EntityTrait, QueryFilter};
+
+/// Shown as the album artist of compilations
+pub const VARIOUS_ARTISTS: &str = "Various Artists";
+
+/// Flags albums as compilations when songs in the same directory share an album name
+/// but have different artists and no album artist to tie them together.
+/// Songs of an album that is already flagged, e.g. by the iTunes compilation tag, are flagged too.
+pub async fn detect_compilations(source_id: i32, db: &impl ConnectionTrait) -> Result<()> {
+    let songs = library::Entity::find()
+        .filter(library::Column::SourceId.eq(source_id))
+        .filter(library::Column::Album.is_not_null())
+        .all(db)
+        .await
+        .into_diagnostic()?;
+
+    let mut albums: HashMap<(Option<String>, String), Vec<library::Model>> = HashMap::new();
+
+    for song in songs {
+        albums
+            .entry((song.album.clone(), song.path.clone()))
+            .or_default()
+            .push(song);
+    }
+
+    for songs in albums.into_values() {
+        let artists: HashSet<_> = songs.iter().filter_map(|v| v.artist.as_ref()).collect();
+
+        let various = songs.iter().any(|v| {
+            v.album_artist
+                .as_ref()
+                .is_some_and(|v| v.eq_ignore_ascii_case(VARIOUS_ARTISTS))
+        });
+        let untied = songs.iter().all(|v| v.album_artist.is_none()) && artists.len() > 1;
+
+        if !(various || untied || songs.iter().any(|v| v.compilation)) {
+            continue;
+        }
+
+        library::Entity::update_many()
+            .col_expr(library::Column::Compilation, Expr::value(true))
+            .filter(
+                library::Column::Id.is_in(songs.iter().filter(|v| !v.compilation).map(|v| v.id)),
+            )
+            .exec(db)
+            .await
+            .into_diagnostic()?;
+    }
+
+    Ok(())
+}
+
+/// The artist an album is listed under
+pub fn album_artist(song: &library::Model) -> Option<&str> {
+    if song.compilation {
+        Some(VARIOUS_ARTISTS)
+    } else {
+        song.album_artist.as_deref().or(song.artist.as_deref())
+    }
+}
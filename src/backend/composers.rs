@@ -0,0 +1,40 @@
+use super::model::library;
+use miette::{IntoDiagnostic, Result};
+use sea_orm::{
+    ColumnTrait, DatabaseConnection, DeriveColumn, EntityTrait, EnumIter, IdenStatic, QueryFilter,
+    QueryOrder, QuerySelect,
+};
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveColumn)]
+enum QueryAs {
+    Composer,
+}
+
+/// Every composer in the library, sorted by name
+pub async fn list_composers(db: &DatabaseConnection) -> Result<Vec<String>> {
+    library::Entity::find()
+        .select_only()
+        .column_as(library::Column::Composer, QueryAs::Composer)
+        .filter(library::Column::Composer.is_not_null())
+        .group_by(library::Column::Composer)
+        .order_by_asc(library::Column::Composer)
+        .into_values::<_, QueryAs>()
+        .all(db)
+        .await
+        .into_diagnostic()
+}
+
+/// Songs by a composer, grouped by album since works are usually spread across tracks
+pub async fn songs_by_composer(
+    composer: &str,
+    db: &DatabaseConnection,
+) -> Result<Vec<library::Model>> {
+    library::Entity::find()
+        .filter(library::Column::Composer.eq(composer))
+        .order_by_asc(library::Column::Album)
+        .order_by_asc(library::Column::Disc)
+        .order_by_asc(library::Column::Track)
+        .all(db)
+        .await
+        .into_diagnostic()
+}
@@ -1,25 +1,121 @@
-use std::{fs::File, io::Write};
+use std::{collections::HashMap, fs::File, io::Write};
 
-use super::utils::config_dir;
+use super::{
+    events::{publish, Event},
+    hashes::HashScope,
+    loudness::{LoudnessAnalysis, Normalization},
+    network::MeteredMode,
+    output::Output,
+    playback::BitDepth,
+    presets::Preset,
+    queue::EndOfQueue,
+    utils::config_dir,
+};
 use miette::{miette, IntoDiagnostic, Result};
 use serde::{Deserialize, Serialize};
 
 /// Determines if the files will be loaded from a local path or remotely
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(untagged)]
 pub enum SourceKind {
     /// Path to a directory
     Local { path: String },
     /// Remote server address
     Remote { address: String },
+    /// Address of a Subsonic or OpenSubsonic server, like Navidrome or Airsonic
+    Subsonic { url: String },
+    /// Address of a WebDAV share, e.g. a Nextcloud folder
+    WebDav { share: String },
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Source {
     pub id: u8,
     pub name: String,
     #[serde(flatten)]
     pub source: SourceKind,
+    /// Overrides the global proxy for this source
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    /// Extra headers sent with every request to a remote source,
+    /// e.g. Cloudflare Access tokens or a custom `User-Agent`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+    /// Sent as a bearer token instead of the stored username and password,
+    /// for servers behind an auth proxy. Subsonic servers still need a username and password.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(default, skip_serializing_if = "Tls::is_default")]
+    pub tls: Tls,
+    #[serde(flatten)]
+    pub settings: SourceSettings,
+}
+
+/// How the certificates of a source served over HTTPS are checked
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct Tls {
+    /// PEM file of a certificate authority to trust besides the system ones,
+    /// e.g. the one a home server's certificate was made with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ca_certificate: Option<String>,
+    /// Accept any certificate, including self-signed and expired ones.
+    /// Anyone on the network can then pretend to be the server, so `ca_certificate` is safer.
+    pub insecure: bool,
+}
+
+impl Tls {
+    fn is_default(&self) -> bool {
+        *self == Tls::default()
+    }
+}
+
+/// How a source is indexed, set alongside its other fields
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct SourceSettings {
+    /// Index new songs when the app starts
+    pub scan_on_startup: bool,
+    /// Keep the source up to date in the background with the scheduled rescan or remote refresh
+    pub watch: bool,
+    /// Analyze the ReplayGain values of local and WebDAV songs that aren't tagged with them
+    pub analyze_replaygain: bool,
+    /// Only index files with these extensions, like `flac` or `opus`. Empty indexes every file.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub extensions: Vec<String>,
+    /// The source contains audiobooks: their positions are remembered, and they're left out
+    /// of radio and station mode
+    pub audiobooks: bool,
+    /// Compute the seek bar waveforms of local and WebDAV songs while indexing,
+    /// instead of the first time they're played
+    pub waveforms: bool,
+    /// Overrides `Config::hash_megabytes` for this source, 0 hashes all of the audio
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash_megabytes: Option<u64>,
+    /// Limits songs streamed from a remote source to this many kbit/s, e.g. over a mobile hotspot.
+    /// Subsonic and Eleanor servers transcode songs above it, from other servers
+    /// they're received no faster than that once playback can start. 0 doesn't limit them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_bitrate: Option<u32>,
+    /// Look up covers of albums that have none, and pictures of artists, on Cover Art Archive
+    /// and Deezer. Needs the `online_artwork` feature.
+    pub fetch_artwork: bool,
+}
+
+impl Default for SourceSettings {
+    fn default() -> Self {
+        SourceSettings {
+            scan_on_startup: true,
+            watch: true,
+            analyze_replaygain: true,
+            extensions: vec![],
+            audiobooks: false,
+            waveforms: false,
+            hash_megabytes: None,
+            max_bitrate: None,
+            fetch_artwork: true,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -28,9 +124,318 @@ pub struct Config {
     pub cache_expire_days: usize,
     pub crossfade: bool,
     pub crossfade_duration: u8,
+    /// Crossfade between consecutive tracks of the same album too. Turning this off keeps
+    /// concept albums and live records gapless, while still crossfading across albums.
+    pub crossfade_within_albums: bool,
+    /// Remote tracks start buffering this many seconds before the track before them ends,
+    /// so crossfades and gapless transitions aren't held up by the network
+    pub prefetch_seconds: u64,
     pub song_change_notification: bool,
     pub volume: f32,
+    /// Files over an hour long without chapters are split into sections this many minutes long
+    /// for navigation, 0 disables this
+    pub chapter_interval_minutes: u64,
+    /// How remote songs without ReplayGain values are leveled
+    pub loudness_analysis: LoudnessAnalysis,
+    /// Length of the beginning of a song measured in the `preview` analysis mode
+    pub loudness_preview_seconds: u64,
+    /// Write ReplayGain values to the tags of local files after indexing them
+    pub write_replaygain: bool,
+    /// File extensions in order of preference, used to pick between copies of the same album
+    pub preferred_formats: Vec<String>,
+    /// Ids of sources in the order songs that are in several of them are taken from,
+    /// see `Config::source_rank`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub source_priority: Vec<u8>,
+    /// Whether to save traffic by treating the connection as metered
+    pub metered: MeteredMode,
+    /// Playing remote songs at least this many megabytes large on a metered connection
+    /// asks for confirmation first, 0 disables this
+    pub large_stream_mb: u64,
+    /// Streams remote songs at no more than this many kbit/s on a metered connection,
+    /// like `SourceSettings::max_bitrate`. Downloaded copies are still played first. 0 disables this
+    pub metered_bitrate: u32,
+    /// Strings separating the names in artist tags like "A feat. B", matched case insensitively
+    pub artist_separators: Vec<String>,
+    pub end_of_queue: EndOfQueue,
+    /// Id of the playlist played when `end_of_queue` is `playlist`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fallback_playlist: Option<i32>,
+    /// Client key used to identify untagged files through AcoustID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub acoustid_key: Option<String>,
+    /// Proxy used for all HTTP traffic, e.g. `socks5://127.0.0.1:9050`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    /// Log database queries taking at least this many milliseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slow_query_ms: Option<u64>,
+    /// Remove listening history older than this many days
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub history_retention_days: Option<u64>,
+    /// Speed audiobooks are played at, e.g. 0.9 to follow them more easily
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audiobook_speed: Option<f32>,
+    /// Database snapshots kept in the cache, see `backup::snapshot`. 0 turns snapshots off.
+    pub snapshot_retention: usize,
+    /// Serve the HTTP API on this port of localhost, see `http_api::HttpApi`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub http_api_port: Option<u16>,
+    /// Names of the plugin scripts to run, see `plugins::PluginHost`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub plugins: Vec<String>,
+    /// Identify local and WebDAV songs by the first this many megabytes of their audio,
+    /// along with its length and the size of the file, instead of all of it.
+    /// Makes indexing large libraries faster, but retagging a file changes its size,
+    /// so its song is moved to a new hash when it's read again.
+    /// Unset or 0 hashes all of the audio.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash_megabytes: Option<u64>,
+    // Tables have to come after plain values in TOML
     pub sources: Vec<Source>,
+    /// Intervals of background jobs
+    pub schedule: Schedule,
+    pub playback: Playback,
+    /// Where the audio is played, e.g. a Snapcast server for several rooms
+    pub output: Output,
+    /// Fixes `tag_cleanup` suggests
+    pub tag_cleanup: TagCleanup,
+    /// Files left out when indexing
+    pub exclusions: Exclusions,
+    /// Limits on fetching from third-party services like AcoustID, see `external::spawn`
+    pub external_metadata: ExternalMetadata,
+    /// How much of the machine indexing takes up
+    pub indexing: Indexing,
+    /// Keyboard shortcuts that work while the window isn't focused, see `hotkeys::Hotkeys`
+    pub hotkeys: HotkeyBindings,
+    /// Songs other devices on the network can add to the queue, see `party::Party`
+    pub party: PartyMode,
+    /// Playback settings for particular genres or artists
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub presets: Vec<Preset>,
+}
+
+/// How often background jobs run, in hours. Jobs without an interval don't run.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Schedule {
+    /// Index new files in local sources
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rescan: Option<u64>,
+    /// Fetch the indices of remote sources again
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_remote: Option<u64>,
+    /// Remove cached files older than `cache_expire_days`,
+    /// and history older than `history_retention_days`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clean_cache: Option<u64>,
+    /// Take a snapshot of the database
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<u64>,
+    /// Look for new podcast episodes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_podcasts: Option<u64>,
+}
+
+/// Format of the audio sent to the output device
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Playback {
+    /// Resample everything to this rate, e.g. when the device is fixed at 48 kHz,
+    /// instead of leaving it to the OS
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample_rate: Option<u32>,
+    pub bit_depth: BitDepth,
+    /// Added to ReplayGain adjustments in dB. ReplayGain leaves a lot of headroom,
+    /// so raising this makes normalized songs louder at the risk of clipping.
+    pub preamp: f32,
+    /// Turn the volume down briefly during peaks instead of lowering the gain of songs that would clip
+    pub limiter: bool,
+    /// Lower the gain of songs whose ReplayGain peak it would push above full scale.
+    /// Off always applies the full gain, so peaks clip unless the limiter rounds them off.
+    pub prevent_clipping: bool,
+    /// Which ReplayGain values songs are leveled with
+    pub normalization: Normalization,
+    /// Gains of the equalizer bands in dB, see `equalizer::BANDS`. Empty for a flat curve.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub eq: Vec<f32>,
+    /// Speed everything plays at, from 0.5 to 3. Unset for normal speed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed: Option<f32>,
+    /// Let the pitch change with the speed, like a record played faster,
+    /// instead of keeping voices at their natural pitch
+    pub varispeed: bool,
+    /// Publish spectrum and level data while playing, for the visualizer
+    pub visualization: bool,
+    /// Fade out when pausing and in when resuming, in milliseconds, so the sound doesn't stop
+    /// with a click. 0 turns the fade off.
+    pub pause_fade_ms: u64,
+    /// Fade out before stopping, in milliseconds
+    pub stop_fade_ms: u64,
+    /// Fade out before and in after moving to another position, in milliseconds
+    pub seek_fade_ms: u64,
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Playback {
+            sample_rate: None,
+            bit_depth: BitDepth::default(),
+            preamp: 0.0,
+            limiter: false,
+            prevent_clipping: true,
+            normalization: Normalization::default(),
+            eq: vec![],
+            speed: None,
+            varispeed: false,
+            visualization: false,
+            pause_fade_ms: 40,
+            stop_fade_ms: 40,
+            seek_fade_ms: 15,
+        }
+    }
+}
+
+/// Rules for cleaning up tags
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TagCleanup {
+    /// Remove leading and trailing whitespace, and collapse repeated spaces
+    pub trim_whitespace: bool,
+    /// Capitalize titles and albums written entirely in lower or upper case
+    pub title_case: bool,
+    /// Write "ft.", "feat" and "featuring" as "feat."
+    pub normalize_featuring: bool,
+    /// Remove "[Explicit]" and "(Explicit)" from titles and albums
+    pub strip_explicit: bool,
+}
+
+impl Default for TagCleanup {
+    fn default() -> Self {
+        TagCleanup {
+            trim_whitespace: true,
+            title_case: false,
+            normalize_featuring: true,
+            strip_explicit: true,
+        }
+    }
+}
+
+/// Rules for leaving files out of the library, e.g. ringtones or sample packs.
+/// Patterns are matched against paths relative to the source's directory.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Exclusions {
+    /// Glob patterns like `**/Samples/**` or `*.m4r`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub globs: Vec<String>,
+    /// Regular expressions, for rules globs can't express
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub regexes: Vec<String>,
+    /// Skip files and directories whose name starts with a dot
+    pub skip_hidden: bool,
+    /// Skip files smaller than this many kilobytes, 0 disables this
+    pub min_size_kb: u64,
+}
+
+/// Global keyboard shortcuts, written like `ctrl+alt+Space` or `super+shift+ArrowRight`.
+/// Shortcuts that aren't set aren't registered, since they'd take the keys from every other app.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct HotkeyBindings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub play_pause: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volume_up: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volume_down: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed_up: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed_down: Option<String>,
+}
+
+/// Limits on fetches from third-party metadata services
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ExternalMetadata {
+    /// Turning this off stops every fetch from third-party services, e.g. while one misbehaves
+    pub enabled: bool,
+    /// Fetches taking longer than this are cancelled
+    pub timeout_seconds: u64,
+    /// Fetches running at the same time
+    pub max_concurrent: usize,
+}
+
+impl Default for ExternalMetadata {
+    fn default() -> Self {
+        ExternalMetadata {
+            enabled: true,
+            timeout_seconds: 15,
+            max_concurrent: 2,
+        }
+    }
+}
+
+/// How much of the machine indexing takes up
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Indexing {
+    /// Files read and decoded at the same time, 0 for one per CPU core. Songs are still written
+    /// to the library one at a time, since it only takes one writer at once.
+    pub threads: usize,
+    /// While a track plays, read one file at a time and pause between files,
+    /// so playback from the same slow disk doesn't drop out
+    pub low_priority: bool,
+}
+
+impl Default for Indexing {
+    fn default() -> Self {
+        Indexing {
+            threads: 0,
+            low_priority: true,
+        }
+    }
+}
+
+impl Indexing {
+    /// Number of files read at the same time, with `threads` resolved
+    pub fn threads(&self) -> usize {
+        match self.threads {
+            0 => std::thread::available_parallelism().map_or(1, |v| v.get()),
+            threads => threads,
+        }
+    }
+}
+
+/// Lets guests on the local network add songs to the queue through the HTTP API
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct PartyMode {
+    pub enabled: bool,
+    /// Guests send this in the `X-Party-Pin` header. Without one, anyone on the network can
+    /// add songs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pin: Option<String>,
+    /// Requested songs wait for the host to approve them before they're queued
+    pub approval: bool,
+    /// Songs each guest can request per minute
+    pub requests_per_minute: usize,
+}
+
+impl Default for PartyMode {
+    fn default() -> Self {
+        PartyMode {
+            enabled: false,
+            pin: None,
+            approval: true,
+            requests_per_minute: 3,
+        }
+    }
 }
 
 impl Config {
@@ -41,10 +446,15 @@ impl Config {
 
         let contents = std::fs::read_to_string(file).into_diagnostic()?;
 
-        toml::from_str(&contents).into_diagnostic()
+        let config: Config = toml::from_str(&contents).into_diagnostic()?;
+        config.validate()?;
+
+        Ok(config)
     }
 
     pub fn write_config(config: &Config) -> Result<()> {
+        config.validate()?;
+
         let contents = toml::to_string(config).into_diagnostic()?;
 
         let path = config_dir()
@@ -53,7 +463,115 @@ impl Config {
 
         File::create(path)
             .and_then(|mut v| v.write_all(contents.as_bytes()))
-            .into_diagnostic()
+            .into_diagnostic()?;
+
+        publish(Event::ConfigChanged);
+        Ok(())
+    }
+
+    /// Checks for mistakes the TOML format doesn't catch, like two sources with the same id.
+    /// Songs and credentials are stored by source id, so those sources would mix them up.
+    pub fn validate(&self) -> Result<()> {
+        let mut names: HashMap<u8, &str> = HashMap::new();
+
+        for source in &self.sources {
+            if let Some(other) = names.insert(source.id, &source.name) {
+                let free = self
+                    .next_source_id(&[])
+                    .map(|v| format!(", like {v}"))
+                    .unwrap_or_default();
+
+                return Err(miette!(
+                    "Sources \"{}\" and \"{}\" both have the id {} in settings.toml, \
+                     give one of them an unused id{}",
+                    other,
+                    source.name,
+                    source.id,
+                    free
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// An id no source has. It's above every id in use or in `reserved`, e.g. ids of removed
+    /// sources whose songs are still in the library, so they aren't given to the new source.
+    /// Once the highest id is taken, the lowest free one is used instead.
+    pub fn next_source_id(&self, reserved: &[u8]) -> Result<u8> {
+        let taken: Vec<u8> = self
+            .sources
+            .iter()
+            .map(|v| v.id)
+            .chain(reserved.iter().copied())
+            .collect();
+
+        match taken.iter().max() {
+            None => Ok(0),
+            Some(&max) => max
+                .checked_add(1)
+                .or_else(|| (0..=u8::MAX).find(|v| !taken.contains(v)))
+                .ok_or(miette!("There are no source ids left")),
+        }
+    }
+
+    /// Adds a source with default settings under the next free id, returning the id.
+    /// `reserved` is passed on to `next_source_id`.
+    /// Credentials of remote sources are stored separately, see `utils::store_auth_source`.
+    pub fn add_source(&mut self, name: &str, source: SourceKind, reserved: &[u8]) -> Result<u8> {
+        let id = self.next_source_id(reserved)?;
+
+        self.sources.push(Source {
+            id,
+            name: name.to_string(),
+            source,
+            proxy: None,
+            headers: HashMap::new(),
+            token: None,
+            tls: Default::default(),
+            settings: Default::default(),
+        });
+
+        Ok(id)
+    }
+
+    /// Ids of sources whose files are stored locally
+    pub fn audiobook_source_ids(&self) -> Vec<i32> {
+        self.sources
+            .iter()
+            .filter(|v| v.settings.audiobooks)
+            .map(|v| v.id.into())
+            .collect()
+    }
+
+    /// How much of the audio of a source's songs is hashed
+    pub fn hash_scope(&self, source: &Source) -> HashScope {
+        match source.settings.hash_megabytes.or(self.hash_megabytes) {
+            Some(megabytes) if megabytes > 0 => HashScope::Prefix(megabytes * 1024 * 1024),
+            _ => HashScope::Full,
+        }
+    }
+
+    /// Where a source comes when picking which copy of a song to list and play, lower first:
+    /// sources in `source_priority` in that order, then local sources, so songs are played from
+    /// disk instead of streamed, then remote ones
+    pub fn source_rank(&self, source_id: i32) -> (usize, bool, i32) {
+        let listed = self
+            .source_priority
+            .iter()
+            .position(|v| i32::from(*v) == source_id)
+            .unwrap_or(usize::MAX);
+        let remote = !self.local_source_ids().contains(&source_id);
+
+        (listed, remote, source_id)
+    }
+
+    pub fn local_source_ids(&self) -> Vec<i32> {
+        self.sources
+            .iter()
+            .filter(|v| matches!(v.source, SourceKind::Local { .. }))
+            .map(|v| v.id.into())
+            .collect()
     }
 }
 
@@ -64,15 +582,145 @@ impl Default for Config {
             cache_expire_days: 30,
             crossfade: false,
             crossfade_duration: 5,
+            crossfade_within_albums: true,
+            prefetch_seconds: 20,
             song_change_notification: false,
             volume: 0.5,
+            chapter_interval_minutes: 10,
+            loudness_analysis: LoudnessAnalysis::Preview,
+            loudness_preview_seconds: 30,
+            write_replaygain: false,
+            preferred_formats: ["flac", "wav", "opus", "ogg", "m4a", "mp3"]
+                .map(String::from)
+                .to_vec(),
+            source_priority: vec![],
+            metered: MeteredMode::Auto,
+            large_stream_mb: 100,
+            metered_bitrate: 192,
+            artist_separators: [
+                " feat. ",
+                " ft. ",
+                " featuring ",
+                "(feat. ",
+                "(ft. ",
+                ";",
+                "/",
+                "\0",
+            ]
+            .map(String::from)
+            .to_vec(),
+            end_of_queue: EndOfQueue::Stop,
+            fallback_playlist: None,
+            acoustid_key: None,
+            proxy: None,
+            slow_query_ms: None,
+            history_retention_days: None,
+            audiobook_speed: None,
+            snapshot_retention: 5,
+            http_api_port: None,
+            plugins: vec![],
+            hash_megabytes: None,
             sources: vec![Source {
                 id: 0,
                 name: "Music".into(),
                 source: SourceKind::Local {
                     path: "/home/agatha/Music/local".into(),
                 },
+                proxy: None,
+                headers: HashMap::new(),
+                token: None,
+                tls: Default::default(),
+                settings: SourceSettings::default(),
             }],
+            schedule: Schedule {
+                clean_cache: Some(24),
+                ..Default::default()
+            },
+            playback: Playback::default(),
+            output: Output::default(),
+            tag_cleanup: TagCleanup::default(),
+            exclusions: Exclusions::default(),
+            external_metadata: ExternalMetadata::default(),
+            indexing: Indexing::default(),
+            hotkeys: HotkeyBindings::default(),
+            party: PartyMode::default(),
+            presets: vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Config with a local source under each of `ids`, and no others
+    fn with_ids(ids: impl IntoIterator<Item = u8>) -> Config {
+        let mut config = Config {
+            sources: vec![],
+            ..Default::default()
+        };
+
+        for id in ids {
+            config
+                .add_source(&format!("Source {id}"), local(), &[])
+                .unwrap();
+            config.sources.last_mut().unwrap().id = id;
+        }
+
+        config
+    }
+
+    fn local() -> SourceKind {
+        SourceKind::Local {
+            path: "/music".into(),
         }
     }
+
+    #[test]
+    fn first_source_id_is_0() {
+        assert_eq!(with_ids([]).next_source_id(&[]).unwrap(), 0);
+    }
+
+    #[test]
+    fn source_ids_skip_reserved_ones() {
+        let config = with_ids([0, 1]);
+
+        assert_eq!(config.next_source_id(&[]).unwrap(), 2);
+        assert_eq!(config.next_source_id(&[2, 5]).unwrap(), 6);
+        assert_eq!(with_ids([]).next_source_id(&[3]).unwrap(), 4);
+    }
+
+    #[test]
+    fn source_ids_wrap_around_past_255() {
+        let config = with_ids([0, 1, 255]);
+        assert_eq!(config.next_source_id(&[]).unwrap(), 2);
+        assert_eq!(config.next_source_id(&[2]).unwrap(), 3);
+
+        let full = with_ids(0..=u8::MAX);
+        assert_eq!(
+            full.next_source_id(&[]).unwrap_err().to_string(),
+            "There are no source ids left"
+        );
+    }
+
+    #[test]
+    fn added_sources_get_the_next_id() {
+        let mut config = with_ids([4]);
+
+        assert_eq!(config.add_source("New", local(), &[7]).unwrap(), 8);
+        assert_eq!(config.sources[1].name, "New");
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn duplicate_source_ids_are_rejected() {
+        let mut config = with_ids([0, 1]);
+        config.sources[1].id = 0;
+
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "Sources \"Source 0\" and \"Source 1\" both have the id 0 in settings.toml, give one \
+             of them an unused id, like 1"
+        );
+    }
 }
@@ -0,0 +1,130 @@
+use std::{
+    fs::{create_dir_all, File},
+    io::Write,
+    path::PathBuf,
+};
+
+use super::{
+    artwork::{embedded_art, Artwork},
+    chapters::{read_chapters, Chapter},
+    config::{Config, SourceKind},
+    errors::{report, Category, Severity},
+    lyrics::{read_lyrics, Lyrics},
+    model::library,
+    network::is_metered,
+    utils::{basic_auth, cache_dir, http_client, song_path},
+};
+use miette::{miette, IntoDiagnostic, Result};
+use serde::{Deserialize, Serialize};
+
+/// Metadata that's too large to be part of a source's index.
+/// For remote songs it's only fetched once a song is viewed or queued.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Details {
+    pub art: Option<Artwork>,
+    pub lyrics: Option<Lyrics>,
+    /// Chapters from tags or a cue sheet. Use `chapters::with_fallback` to navigate long files.
+    #[serde(default)]
+    pub chapters: Vec<Chapter>,
+}
+
+/// Returns a song's details, reading them from its file for local songs.
+/// Remote songs' details are fetched from the server the first time and cached.
+pub async fn details(song: &library::Model) -> Result<Details> {
+    let config = Config::read_config()?;
+
+    let source = config
+        .sources
+        .iter()
+        .find(|v| i32::from(v.id) == song.source_id)
+        .ok_or(miette!("Source {} does not exist", song.source_id))?;
+
+    let address = match &source.source {
+        SourceKind::Local { .. } => {
+            let path = song_path(song);
+
+            return Ok(Details {
+                art: embedded_art(song),
+                lyrics: read_lyrics(&path)?,
+                chapters: read_chapters(&path)?,
+            });
+        }
+        SourceKind::Remote { address } => address,
+        // The Subsonic API has nothing to fill them with
+        SourceKind::Subsonic { .. } => return Ok(Details::default()),
+        // Reading them would mean downloading the whole file
+        SourceKind::WebDav { .. } => return Ok(Details::default()),
+    };
+
+    if let Some(details) = cached_details(song.hash) {
+        return Ok(details);
+    }
+
+    let mut request =
+        http_client(&config, Some(source))?.get(format!("{address}/{}/details", song.hash));
+
+    if let Some((username, password)) = basic_auth(source)? {
+        request = request.basic_auth(username, Some(password));
+    }
+
+    let response = request
+        .send()
+        .await
+        .into_diagnostic()?
+        .error_for_status()
+        .into_diagnostic()?
+        .bytes()
+        .await
+        .into_diagnostic()?;
+
+    let details: Details = rmp_serde::from_slice(&response).into_diagnostic()?;
+
+    let path = details_path(song.hash).ok_or(miette!("Cache directory does not exist"))?;
+    create_dir_all(path.parent().unwrap_or(&path)).into_diagnostic()?;
+
+    File::create(path)
+        .and_then(|mut v| v.write_all(&response))
+        .into_diagnostic()?;
+
+    Ok(details)
+}
+
+/// Fetches the details of queued songs in the background, so they're ready once the songs play
+pub fn prefetch(songs: Vec<library::Model>) {
+    tokio::spawn(async move {
+        let local = Config::read_config()
+            .map(|v| v.local_source_ids())
+            .unwrap_or_default();
+
+        // Details are still fetched when a song is viewed
+        if is_metered() {
+            return;
+        }
+
+        for song in songs
+            .into_iter()
+            .filter(|v| !local.contains(&v.source_id))
+            .filter(|v| details_path(v.hash).is_some_and(|v| !v.exists()))
+        {
+            if let Err(e) = details(&song).await {
+                report(
+                    Severity::Warning,
+                    Category::Network,
+                    Some(song.filename.clone()),
+                    format!("Couldn't fetch details of {}: {e}", song.filename),
+                );
+            }
+        }
+    });
+}
+
+/// Details of a remote song that were fetched before
+pub fn cached_details(hash: i64) -> Option<Details> {
+    let file = std::fs::read(details_path(hash)?).ok()?;
+
+    rmp_serde::from_slice(&file).ok()
+}
+
+fn details_path(hash: i64) -> Option<PathBuf> {
+    cache_dir().map(|v| v.join("details").join(format!("{hash}.mp")))
+}
@@ -0,0 +1,193 @@
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex, OnceLock,
+    },
+    time::Duration,
+};
+
+use paris::{info, warn};
+use sea_orm::{metric::Info, DatabaseConnection};
+
+/// Queries are logged in full up to this many characters
+const MAX_LOGGED_LENGTH: usize = 300;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static THRESHOLD_MS: AtomicU64 = AtomicU64::new(100);
+
+/// Timings of a slow query, grouped by its SQL with parameters left as placeholders
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SlowQuery {
+    pub sql: String,
+    pub count: u32,
+    pub total: Duration,
+    pub max: Duration,
+    pub failed: u32,
+}
+
+/// Rows changed by an operation like indexing a source, summed over its runs
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OperationRows {
+    pub operation: String,
+    pub runs: u32,
+    pub rows: u64,
+    pub total: Duration,
+}
+
+fn slow_queries() -> &'static Mutex<HashMap<String, SlowQuery>> {
+    static SLOW: OnceLock<Mutex<HashMap<String, SlowQuery>>> = OnceLock::new();
+
+    SLOW.get_or_init(Default::default)
+}
+
+fn operations() -> &'static Mutex<HashMap<String, OperationRows>> {
+    static OPERATIONS: OnceLock<Mutex<HashMap<String, OperationRows>>> = OnceLock::new();
+
+    OPERATIONS.get_or_init(Default::default)
+}
+
+/// Starts timing every query run through a connection.
+/// Nothing is logged until diagnostics are enabled, which can be done at any time.
+pub fn install(db: &mut DatabaseConnection) {
+    db.set_metric_callback(record);
+}
+
+/// Turns slow query logging on or off. Queries taking at least `threshold` are logged.
+pub fn set_enabled(enabled: bool, threshold: Duration) {
+    THRESHOLD_MS.store(threshold.as_millis() as u64, Ordering::Relaxed);
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+fn record(info: &Info<'_>) {
+    if !is_enabled() || info.elapsed < Duration::from_millis(THRESHOLD_MS.load(Ordering::Relaxed)) {
+        return;
+    }
+
+    let sql = &info.statement.sql;
+
+    warn!(
+        "Slow query took {:.1?}{}: {}",
+        info.elapsed,
+        if info.failed { " and failed" } else { "" },
+        truncate(sql)
+    );
+
+    if let Ok(mut slow) = slow_queries().lock() {
+        let entry = slow.entry(sql.clone()).or_insert_with(|| SlowQuery {
+            sql: sql.clone(),
+            ..Default::default()
+        });
+
+        entry.count += 1;
+        entry.total += info.elapsed;
+        entry.max = entry.max.max(info.elapsed);
+        entry.failed += info.failed as u32;
+    }
+}
+
+/// Records how many rows an operation changed and how long it took, while diagnostics are
+/// enabled. sea-orm doesn't pass row counts to the metric callback, so they're counted by the
+/// operations themselves. Operations slower than the threshold are logged like slow queries.
+pub fn record_rows(operation: &str, rows: u64, elapsed: Duration) {
+    if !is_enabled() {
+        return;
+    }
+
+    if elapsed >= Duration::from_millis(THRESHOLD_MS.load(Ordering::Relaxed)) {
+        warn!("Slow {operation} took {elapsed:.1?} and changed {rows} rows");
+    }
+
+    if let Ok(mut operations) = operations().lock() {
+        let entry = operations
+            .entry(operation.to_string())
+            .or_insert_with(|| OperationRows {
+                operation: operation.to_string(),
+                ..Default::default()
+            });
+
+        entry.runs += 1;
+        entry.rows += rows;
+        entry.total += elapsed;
+    }
+}
+
+/// Row counts of the operations that took the most time in total, slowest first
+pub fn operation_rows(limit: usize) -> Vec<OperationRows> {
+    let mut operations: Vec<_> = operations()
+        .lock()
+        .map(|v| v.values().cloned().collect())
+        .unwrap_or_default();
+
+    operations.sort_by_key(|v| Reverse(v.total));
+    operations.truncate(limit);
+
+    operations
+}
+
+/// The slow queries that took the most time in total, worst first
+pub fn worst_offenders(limit: usize) -> Vec<SlowQuery> {
+    let mut queries: Vec<_> = slow_queries()
+        .lock()
+        .map(|v| v.values().cloned().collect())
+        .unwrap_or_default();
+
+    queries.sort_by_key(|v| Reverse(v.total));
+    queries.truncate(limit);
+
+    queries
+}
+
+/// Logs the worst offenders, if any queries were slow, and the rows each operation changed
+pub fn log_summary(limit: usize) {
+    let queries = worst_offenders(limit);
+
+    if !queries.is_empty() {
+        info!("Slowest queries:");
+    }
+
+    for query in queries {
+        info!(
+            "{:.1?} total, {} runs, {:.1?} max: {}",
+            query.total,
+            query.count,
+            query.max,
+            truncate(&query.sql)
+        );
+    }
+
+    let operations = operation_rows(limit);
+
+    if !operations.is_empty() {
+        info!("Rows changed per operation:");
+    }
+
+    for operation in operations {
+        info!(
+            "{}: {} rows in {} runs, {:.1?} total",
+            operation.operation, operation.rows, operation.runs, operation.total
+        );
+    }
+}
+
+pub fn clear() {
+    if let Ok(mut slow) = slow_queries().lock() {
+        slow.clear();
+    }
+
+    if let Ok(mut operations) = operations().lock() {
+        operations.clear();
+    }
+}
+
+fn truncate(sql: &str) -> String {
+    match sql.char_indices().nth(MAX_LOGGED_LENGTH) {
+        Some((i, _)) => format!("{}…", &sql[..i]),
+        None => sql.to_string(),
+    }
+}
@@ -0,0 +1,325 @@
+use std::{
+    ffi::OsStr,
+    fs::{create_dir_all, rename, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    browse::{album_versions, Album},
+    config::Config,
+    errors::{report, Category, Severity},
+    model::{downloads, library, playlist_entries, sea_orm_active_enums::DownloadStatus},
+    network::is_metered,
+    utils::{audio_location, cache_dir, http_client, song_path},
+};
+use miette::{miette, IntoDiagnostic, Result};
+use paris::{info, success};
+use reqwest::{header::RANGE, StatusCode};
+use sea_orm::{
+    sea_query::OnConflict, ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait,
+    QueryFilter, Set,
+};
+
+/// Progress is saved to the database every time this many bytes were downloaded
+const SAVE_INTERVAL: u64 = 1024 * 1024;
+
+/// Marks remote songs to be downloaded for offline playback. Local songs are skipped.
+pub async fn mark_songs(songs: &[library::Model], db: &DatabaseConnection) -> Result<()> {
+    let local = Config::read_config()?.local_source_ids();
+
+    let rows: Vec<_> = songs
+        .iter()
+        .filter(|v| !local.contains(&v.source_id))
+        .map(|v| downloads::ActiveModel {
+            song_hash: Set(v.hash),
+            status: Set(DownloadStatus::Pending),
+            ..Default::default()
+        })
+        .collect();
+
+    if rows.is_empty() {
+        return Ok(());
+    }
+
+    downloads::Entity::insert_many(rows)
+        .on_conflict(
+            OnConflict::column(downloads::Column::SongHash)
+                .do_nothing()
+                .to_owned(),
+        )
+        .exec(db)
+        .await
+        .into_diagnostic()?;
+
+    Ok(())
+}
+
+/// Marks the preferred remote copy of an album
+pub async fn mark_album(album: &Album, db: &DatabaseConnection) -> Result<()> {
+    let local = Config::read_config()?.local_source_ids();
+
+    let Some(version) = album_versions(album, db)
+        .await?
+        .into_iter()
+        .find(|v| !local.contains(&v.source_id))
+    else {
+        return Ok(());
+    };
+
+    mark_songs(&version.tracks, db).await
+}
+
+pub async fn mark_playlist(playlist_id: i32, db: &DatabaseConnection) -> Result<()> {
+    let hashes: Vec<i64> = playlist_entries::Entity::find()
+        .filter(playlist_entries::Column::PlaylistId.eq(playlist_id))
+        .all(db)
+        .await
+        .into_diagnostic()?
+        .into_iter()
+        .map(|v| v.song_hash)
+        .collect();
+
+    let songs = library::Entity::find()
+        .filter(library::Column::Hash.is_in(hashes))
+        .all(db)
+        .await
+        .into_diagnostic()?;
+
+    mark_songs(&songs, db).await
+}
+
+/// Stops keeping a song offline and removes its downloaded file
+pub async fn unmark(song: &library::Model, db: &DatabaseConnection) -> Result<()> {
+    downloads::Entity::delete_many()
+        .filter(downloads::Column::SongHash.eq(song.hash))
+        .exec(db)
+        .await
+        .into_diagnostic()?;
+
+    for path in [download_path(song)?, partial_path(song)?] {
+        if path.exists() {
+            std::fs::remove_file(path).into_diagnostic()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Downloads every marked song that isn't downloaded yet, including ones that failed before.
+/// Interrupted downloads continue where they stopped.
+pub async fn download_pending(db: &DatabaseConnection) -> Result<()> {
+    if is_metered() {
+        info!("Not downloading songs on a metered connection");
+        return Ok(());
+    }
+
+    let pending = downloads::Entity::find()
+        .filter(downloads::Column::Status.ne(DownloadStatus::Completed))
+        .find_also_related(library::Entity)
+        .all(db)
+        .await
+        .into_diagnostic()?;
+
+    let mut completed = 0;
+
+    for (download, song) in pending {
+        let Some(song) = song else {
+            continue;
+        };
+
+        match download_song(download.clone(), &song, db).await {
+            Ok(true) => completed += 1,
+            Ok(false) => {
+                info!("Paused downloads on a metered connection");
+                break;
+            }
+            Err(e) => {
+                report(
+                    Severity::Warning,
+                    Category::Network,
+                    Some(song.filename.clone()),
+                    format!("Couldn't download {}: {e}", song.filename),
+                );
+
+                downloads::ActiveModel {
+                    id: Set(download.id),
+                    status: Set(DownloadStatus::Failed),
+                    error: Set(Some(e.to_string())),
+                    ..Default::default()
+                }
+                .update(db)
+                .await
+                .into_diagnostic()?;
+            }
+        }
+    }
+
+    if completed > 0 {
+        success!("Downloaded {completed} songs for offline playback");
+    }
+
+    Ok(())
+}
+
+/// Returns whether the download completed, rather than being paused
+async fn download_song(
+    download: downloads::Model,
+    song: &library::Model,
+    db: &DatabaseConnection,
+) -> Result<bool> {
+    let config = Config::read_config()?;
+
+    let source = config
+        .sources
+        .iter()
+        .find(|v| i32::from(v.id) == song.source_id)
+        .ok_or(miette!("Source {} does not exist", song.source_id))?;
+
+    let partial = partial_path(song)?;
+    create_dir_all(partial.parent().unwrap_or(&partial)).into_diagnostic()?;
+
+    let existing = partial.metadata().map(|v| v.len()).unwrap_or(0);
+
+    let (url, credentials) = audio_location(source, song, None)?;
+
+    let mut request = http_client(&config, Some(source))?.get(url);
+
+    if let Some((username, password)) = credentials {
+        request = request.basic_auth(username, Some(password));
+    }
+
+    if existing > 0 {
+        request = request.header(RANGE, format!("bytes={existing}-"));
+    }
+
+    let mut response = request
+        .send()
+        .await
+        .into_diagnostic()?
+        .error_for_status()
+        .into_diagnostic()?;
+
+    // Servers that don't support ranges send the whole file again
+    let resumed = response.status() == StatusCode::PARTIAL_CONTENT;
+    let mut downloaded = if resumed { existing } else { 0 };
+
+    let size = response.content_length().map(|v| v + downloaded);
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resumed)
+        .truncate(!resumed)
+        .open(&partial)
+        .into_diagnostic()?;
+
+    let mut row = downloads::ActiveModel {
+        id: Set(download.id),
+        status: Set(DownloadStatus::Downloading),
+        downloaded: Set(downloaded as i64),
+        size: Set(size.map(|v| v as i64)),
+        error: Set(None),
+        ..Default::default()
+    };
+    row.clone().update(db).await.into_diagnostic()?;
+
+    let mut last_saved = downloaded;
+
+    while let Some(chunk) = response.chunk().await.into_diagnostic()? {
+        file.write_all(&chunk).into_diagnostic()?;
+        downloaded += chunk.len() as u64;
+
+        // The partial file is resumed once the connection is unmetered again
+        if is_metered() {
+            file.flush().into_diagnostic()?;
+
+            row.status = Set(DownloadStatus::Pending);
+            row.downloaded = Set(downloaded as i64);
+            row.update(db).await.into_diagnostic()?;
+
+            return Ok(false);
+        }
+
+        if downloaded - last_saved >= SAVE_INTERVAL {
+            row.downloaded = Set(downloaded as i64);
+            row.clone().update(db).await.into_diagnostic()?;
+            last_saved = downloaded;
+        }
+    }
+
+    file.flush().into_diagnostic()?;
+    rename(&partial, download_path(song)?).into_diagnostic()?;
+
+    row.status = Set(DownloadStatus::Completed);
+    row.downloaded = Set(downloaded as i64);
+    row.size = Set(Some(downloaded as i64));
+    row.update(db).await.into_diagnostic()?;
+
+    Ok(true)
+}
+
+/// The file a song should be played from. For remote songs this is the downloaded copy,
+/// or `None` if there is none and the song has to be streamed.
+pub async fn playable_path(
+    song: &library::Model,
+    db: &DatabaseConnection,
+) -> Result<Option<PathBuf>> {
+    if Config::read_config()?
+        .local_source_ids()
+        .contains(&song.source_id)
+    {
+        return Ok(Some(song_path(song)));
+    }
+
+    let completed = downloads::Entity::find()
+        .filter(downloads::Column::SongHash.eq(song.hash))
+        .filter(downloads::Column::Status.eq(DownloadStatus::Completed))
+        .one(db)
+        .await
+        .into_diagnostic()?
+        .is_some();
+
+    let path = download_path(song)?;
+
+    Ok((completed && path.exists()).then_some(path))
+}
+
+/// Renames a song's downloaded file after the song moved to another hash
+pub fn move_download(song: &library::Model, hash: i64) -> Result<()> {
+    let from = download_path(song)?;
+
+    if from.exists() {
+        let to = download_path(&library::Model {
+            hash,
+            ..song.clone()
+        })?;
+        rename(from, to).into_diagnostic()?;
+    }
+
+    Ok(())
+}
+
+/// Directory downloaded songs are kept in. It's left alone when the cache is cleaned.
+pub fn downloads_dir() -> Result<PathBuf> {
+    cache_dir()
+        .map(|v| v.join("downloads"))
+        .ok_or(miette!("Cache directory does not exist"))
+}
+
+fn download_path(song: &library::Model) -> Result<PathBuf> {
+    // Keep the extension, so the format can be guessed like for local files
+    let name = match Path::new(&song.filename)
+        .extension()
+        .and_then(OsStr::to_str)
+    {
+        Some(ext) => format!("{}.{ext}", song.hash),
+        None => song.hash.to_string(),
+    };
+
+    Ok(downloads_dir()?.join(name))
+}
+
+fn partial_path(song: &library::Model) -> Result<PathBuf> {
+    Ok(downloads_dir()?.join(format!("{}.part", song.hash)))
+}
@@ -0,0 +1,64 @@
+use super::{config::Config, model::library};
+use miette::{IntoDiagnostic, Result};
+use sea_orm::{ColumnTrait, ConnectionTrait, EntityTrait, QueryFilter};
+
+/// Which source has a song that was found in another one as well
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Owner {
+    /// No other source has the song, so it can be added
+    Nobody,
+    /// Another source has the song, and keeps it since it comes first
+    Other,
+    /// The song moved here from a source that comes after this one
+    Moved,
+}
+
+/// Songs are stored once per hash, even if several sources have a copy of them. The copy that's
+/// listed and played is the one in the source that comes first in `Config::source_rank`,
+/// so a song that's both on disk and on a server plays from disk.
+///
+/// When a source has a copy of a song another source already has, this moves the song to the
+/// copy if the source comes first. Only where the song is changes, its tags, plays and
+/// playlist entries stay as they are.
+pub async fn claim(
+    copy: &library::ActiveModel,
+    hash: i64,
+    source_id: u8,
+    config: &Config,
+    db: &impl ConnectionTrait,
+) -> Result<Owner> {
+    let owner = library::Entity::find()
+        .filter(library::Column::Hash.eq(hash))
+        .one(db)
+        .await
+        .into_diagnostic()?;
+
+    let source_id = i32::from(source_id);
+
+    let Some(owner) = owner.filter(|v| v.source_id != source_id) else {
+        return Ok(Owner::Nobody);
+    };
+
+    if config.source_rank(source_id) > config.source_rank(owner.source_id) {
+        return Ok(Owner::Other);
+    }
+
+    let location = library::ActiveModel {
+        source_id: copy.source_id.clone(),
+        path: copy.path.clone(),
+        filename: copy.filename.clone(),
+        file_size: copy.file_size.clone(),
+        file_modified: copy.file_modified.clone(),
+        hash_bytes: copy.hash_bytes.clone(),
+        ..Default::default()
+    };
+
+    library::Entity::update_many()
+        .set(location)
+        .filter(library::Column::Id.eq(owner.id))
+        .exec(db)
+        .await
+        .into_diagnostic()?;
+
+    Ok(Owner::Moved)
+}
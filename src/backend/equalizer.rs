@@ -0,0 +1,87 @@
+use std::f32::consts::PI;
+
+/// Center frequencies of the bands, in Hz
+pub const BANDS: [f32; 10] = [
+    31.0, 62.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0,
+];
+
+/// Width of each band, about an octave
+const Q: f32 = 1.41;
+
+/// A peaking filter, with coefficients from the Audio EQ Cookbook
+#[derive(Debug, Clone, Copy)]
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl Biquad {
+    fn peaking(frequency: f32, gain: f32, sample_rate: u32) -> Self {
+        let a = 10f32.powf(gain / 40.0);
+        let w0 = 2.0 * PI * frequency / sample_rate as f32;
+        let alpha = w0.sin() / (2.0 * Q);
+        let a0 = 1.0 + alpha / a;
+
+        Biquad {
+            b0: (1.0 + alpha * a) / a0,
+            b1: (-2.0 * w0.cos()) / a0,
+            b2: (1.0 - alpha * a) / a0,
+            a1: (-2.0 * w0.cos()) / a0,
+            a2: (1.0 - alpha / a) / a0,
+        }
+    }
+}
+
+/// A graphic equalizer with one band per entry of `BANDS`
+pub struct Equalizer {
+    filters: Vec<Biquad>,
+    channels: usize,
+    /// Previous two inputs and outputs of every filter for every channel
+    state: Vec<[f32; 4]>,
+}
+
+impl Equalizer {
+    /// Builds an equalizer from the gain of each band in dB. Missing bands are left flat.
+    /// Returns `None` for a flat curve, which wouldn't change anything.
+    pub fn new(gains: &[f32], sample_rate: u32, channels: usize) -> Option<Self> {
+        let filters: Vec<_> = BANDS
+            .iter()
+            .zip(gains)
+            // Bands too close to the Nyquist frequency can't be filtered
+            .filter(|(&frequency, &gain)| gain != 0.0 && frequency < sample_rate as f32 * 0.45)
+            .map(|(&frequency, &gain)| Biquad::peaking(frequency, gain, sample_rate))
+            .collect();
+
+        if filters.is_empty() || channels == 0 {
+            return None;
+        }
+
+        Some(Equalizer {
+            state: vec![[0.0; 4]; filters.len() * channels],
+            filters,
+            channels,
+        })
+    }
+
+    /// Filters a block of interleaved samples in place
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for frame in samples.chunks_mut(self.channels) {
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                for (index, filter) in self.filters.iter().enumerate() {
+                    let [x1, x2, y1, y2] = &mut self.state[index * self.channels + channel];
+
+                    let x = *sample;
+                    let y = filter.b0 * x + filter.b1 * *x1 + filter.b2 * *x2
+                        - filter.a1 * *y1
+                        - filter.a2 * *y2;
+
+                    (*x2, *x1, *y2, *y1) = (*x1, x, *y1, y);
+                    *sample = y;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::SystemTime,
+};
+
+use super::events::{publish, Event};
+use paris::{error, info, warn};
+
+/// Reports older than this many are forgotten
+const CAPACITY: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    /// Something didn't work, but everything else carries on, e.g. one file couldn't be analyzed
+    Warning,
+    /// A whole operation failed, e.g. indexing a source
+    Error,
+}
+
+/// Which part of the app a report comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    Indexing,
+    Network,
+    Decoding,
+    Other,
+}
+
+/// Something that went wrong in the background, kept so the GUI can show it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    /// Increases with every report, so the GUI can tell which ones it has shown
+    pub id: u64,
+    pub time: SystemTime,
+    pub severity: Severity,
+    pub category: Category,
+    /// What was being worked on, like a file name or a source
+    pub context: Option<String>,
+    pub message: String,
+}
+
+static REPORTS: Mutex<VecDeque<Report>> = Mutex::new(VecDeque::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Logs a problem and keeps it with the recent ones. Subscribers to the event bus
+/// get it as `Event::ErrorReported`, e.g. for the GUI to show a toast.
+pub fn report(
+    severity: Severity,
+    category: Category,
+    context: Option<String>,
+    message: impl Display,
+) {
+    let message = message.to_string();
+
+    match severity {
+        Severity::Info => info!("{message}"),
+        Severity::Warning => warn!("{message}"),
+        Severity::Error => error!("{message}"),
+    }
+
+    let report = Report {
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        time: SystemTime::now(),
+        severity,
+        category,
+        context,
+        message,
+    };
+
+    {
+        let mut reports = REPORTS.lock().unwrap_or_else(|e| e.into_inner());
+
+        if reports.len() == CAPACITY {
+            reports.pop_front();
+        }
+        reports.push_back(report.clone());
+    }
+
+    publish(Event::ErrorReported(report));
+}
+
+/// Recent reports, oldest first, for the error log page
+pub fn recent() -> Vec<Report> {
+    REPORTS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .cloned()
+        .collect()
+}
+
+/// Forgets every report, e.g. once the user cleared the error log
+pub fn clear() {
+    REPORTS.lock().unwrap_or_else(|e| e.into_inner()).clear();
+}
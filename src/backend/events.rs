@@ -0,0 +1,91 @@
+use std::{sync::OnceLock, time::Duration};
+
+use super::{
+    errors::Report, party::SongRequest, playback::PlaybackState, queue::Placement,
+    spectrum::SpectrumFrame,
+};
+use tokio::sync::broadcast::{self, Receiver, Sender};
+
+/// Events older than this many are dropped for subscribers that fall behind
+const CAPACITY: usize = 256;
+
+/// Something that happened in the backend that other parts of the app may react to
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    TrackStarted {
+        hash: i64,
+    },
+    /// `finished` is false if the track was skipped before its end
+    TrackEnded {
+        hash: i64,
+        finished: bool,
+    },
+    /// Where playback is in the current track. Published by `position::update` every second
+    /// while a track plays, and whenever it's paused, resumed or moved to another position.
+    /// `position::current` has it in between.
+    Position {
+        position: Duration,
+        state: PlaybackState,
+    },
+    /// A song was indexed. `total` is the number of songs found in the source.
+    IndexProgress {
+        source_id: u8,
+        indexed: usize,
+        total: usize,
+    },
+    IndexFinished {
+        source_id: u8,
+    },
+    /// Indexing was stopped early. Songs indexed up to that point are kept.
+    IndexCancelled {
+        source_id: u8,
+    },
+    /// Songs new to the library appeared while indexing or syncing a source,
+    /// see `browse::recently_added`
+    SongsAdded {
+        source_id: u8,
+        count: usize,
+    },
+    ConfigChanged,
+    /// Something went wrong in the background, see `errors::report`
+    ErrorReported(Report),
+    QueueUpdated,
+    /// Tracks were queued with `Queue::queue` or one of the methods it calls, e.g. to confirm
+    /// "Playing next" in a toast. Published right after the `QueueUpdated` of the change.
+    TracksQueued {
+        hashes: Vec<i64>,
+        placement: Placement,
+    },
+    /// The connection became metered or unmetered, see `network::is_metered`
+    MeteredChanged {
+        metered: bool,
+    },
+    /// The sleep timer ran out, playback should fade out over this long and pause
+    SleepTimerExpired {
+        fade_out: Duration,
+    },
+    /// The app is about to exit, playback should fade out
+    ShuttingDown,
+    /// A guest asked for a song in party mode, and it waits for the host to approve it
+    SongRequested(SongRequest),
+    /// Levels of the audio that's playing, many times a second while `visualization` is on.
+    /// Subscribers that don't draw them should ignore these quickly.
+    Spectrum(SpectrumFrame),
+}
+
+fn sender() -> &'static Sender<Event> {
+    static SENDER: OnceLock<Sender<Event>> = OnceLock::new();
+
+    SENDER.get_or_init(|| broadcast::channel(CAPACITY).0)
+}
+
+/// Sends an event to every current subscriber
+pub fn publish(event: Event) {
+    // Sending only fails if nobody is subscribed, which is fine
+    let _ = sender().send(event);
+}
+
+/// Receives every event published from now on
+pub fn subscribe() -> Receiver<Event> {
+    sender().subscribe()
+}
@@ -0,0 +1,130 @@
+use super::{config::Exclusions, vfs::Entry};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use miette::{miette, Result};
+use regex::Regex;
+
+/// Why a file was left out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    Pattern,
+    Hidden,
+    TooSmall,
+    /// Not one of the extensions the source is limited to
+    Extension,
+}
+
+/// How many files were left out of an indexing run, by reason
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Skipped {
+    pub patterns: usize,
+    pub hidden: usize,
+    pub too_small: usize,
+    pub extension: usize,
+}
+
+impl Skipped {
+    pub fn add(&mut self, reason: Reason) {
+        match reason {
+            Reason::Pattern => self.patterns += 1,
+            Reason::Hidden => self.hidden += 1,
+            Reason::TooSmall => self.too_small += 1,
+            Reason::Extension => self.extension += 1,
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.patterns + self.hidden + self.too_small + self.extension
+    }
+}
+
+/// Exclusion rules compiled for the files of one source
+pub struct Rules {
+    root: String,
+    globs: GlobSet,
+    regexes: Vec<Regex>,
+    skip_hidden: bool,
+    min_size: u64,
+    /// Lowercase extensions without the dot, empty allows every file
+    extensions: Vec<String>,
+}
+
+impl Rules {
+    /// Compiles the configured rules for files listed under `root`.
+    /// With `extensions` set, files with any other extension are left out too.
+    pub fn new(settings: &Exclusions, extensions: &[String], root: &str) -> Result<Self> {
+        let mut globs = GlobSetBuilder::new();
+
+        for pattern in &settings.globs {
+            globs.add(
+                Glob::new(pattern)
+                    .map_err(|e| miette!("Invalid exclusion pattern {}: {}", pattern, e))?,
+            );
+        }
+
+        Ok(Rules {
+            root: root.trim_end_matches('/').to_string(),
+            globs: globs
+                .build()
+                .map_err(|e| miette!("Invalid exclusion patterns: {}", e))?,
+            regexes: settings
+                .regexes
+                .iter()
+                .map(|v| {
+                    Regex::new(v).map_err(|e| miette!("Invalid exclusion pattern {}: {}", v, e))
+                })
+                .collect::<Result<_>>()?,
+            skip_hidden: settings.skip_hidden,
+            min_size: settings.min_size_kb * 1024,
+            extensions: extensions
+                .iter()
+                .map(|v| v.trim_start_matches('.').to_lowercase())
+                .collect(),
+        })
+    }
+
+    /// Whether a file should be left out, and why
+    pub fn check(&self, entry: &Entry) -> Option<Reason> {
+        let path = self.relative_path(entry);
+
+        if self.skip_hidden && path.split('/').any(|v| v.starts_with('.')) {
+            return Some(Reason::Hidden);
+        }
+
+        if self.globs.is_match(&path) || self.regexes.iter().any(|v| v.is_match(&path)) {
+            return Some(Reason::Pattern);
+        }
+
+        // Files whose size isn't known are kept
+        if entry.size.is_some_and(|v| v < self.min_size) {
+            return Some(Reason::TooSmall);
+        }
+
+        if !self.extensions.is_empty() {
+            let extension = entry
+                .filename
+                .rsplit_once('.')
+                .map(|(_, v)| v.to_lowercase());
+
+            if !extension.is_some_and(|v| self.extensions.contains(&v)) {
+                return Some(Reason::Extension);
+            }
+        }
+
+        None
+    }
+
+    /// Path of the file inside the source, like `Artist/Album/01.flac`
+    fn relative_path(&self, entry: &Entry) -> String {
+        let dir = match entry.dir.strip_prefix(&self.root) {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => rest,
+            _ => &entry.dir,
+        }
+        .trim_matches('/');
+
+        if dir.is_empty() {
+            entry.filename.clone()
+        } else {
+            format!("{dir}/{}", entry.filename)
+        }
+    }
+}
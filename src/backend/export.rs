@@ -0,0 +1,300 @@
+use std::{
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    downloads::playable_path,
+    model::{library, playlist_entries, playlists},
+};
+use miette::{miette, IntoDiagnostic, Result};
+use paris::warn;
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder};
+use serde::{Deserialize, Serialize};
+use tokio::{fs, process::Command};
+
+/// Codecs that are worth transcoding to save space, by their short names
+const LOSSLESS: [&str; 3] = ["flac", "alac", "wavpack"];
+
+/// What lossless songs are turned into when they're exported
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    /// Copy every file as it is
+    #[default]
+    Original,
+    Opus,
+    Mp3,
+}
+
+impl ExportFormat {
+    fn extension(self) -> Option<&'static str> {
+        match self {
+            ExportFormat::Original => None,
+            ExportFormat::Opus => Some("opus"),
+            ExportFormat::Mp3 => Some("mp3"),
+        }
+    }
+}
+
+/// Where songs are exported to and in which format
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    /// A folder, or the mount point of a device like a phone or a portable player
+    pub target: PathBuf,
+    pub format: ExportFormat,
+    /// In kbit/s, for transcoded songs
+    pub bitrate: u32,
+}
+
+/// What happened to the exported songs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExportSummary {
+    pub copied: usize,
+    pub transcoded: usize,
+    /// Songs that were already on the target
+    pub skipped: usize,
+    /// Remote songs that aren't downloaded, so there's no file to export
+    pub unavailable: usize,
+}
+
+/// Copies songs to the target as `Artist/Album/Song`, transcoding lossless ones if a format
+/// is set. Transcoding runs `ffmpeg`, which has to be installed.
+/// Songs already on the target aren't exported again, so exports can be resumed.
+pub async fn export_songs(
+    songs: &[library::Model],
+    options: &ExportOptions,
+    mut progress: impl FnMut(usize, usize) + Send,
+    db: &DatabaseConnection,
+) -> Result<ExportSummary> {
+    let mut summary = ExportSummary::default();
+
+    for (i, song) in songs.iter().enumerate() {
+        progress(i, songs.len());
+
+        let Some(source) = playable_path(song, db).await? else {
+            summary.unavailable += 1;
+            continue;
+        };
+
+        let transcode = transcoded_extension(song, options.format);
+        let target = options.target.join(relative_path(song, transcode));
+
+        if is_exported(&source, &target, transcode.is_some()).await {
+            summary.skipped += 1;
+            continue;
+        }
+
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).await.into_diagnostic()?;
+        }
+
+        // Written next to the target first, so an interrupted export doesn't leave half a song
+        let mut partial = target.clone().into_os_string();
+        partial.push(".part");
+        let partial = PathBuf::from(partial);
+
+        let result = match transcode {
+            Some(_) => transcode_file(&source, &partial, options).await,
+            None => fs::copy(&source, &partial)
+                .await
+                .map(|_| ())
+                .into_diagnostic(),
+        };
+
+        if let Err(e) = result {
+            warn!("Couldn't export {}: {e}", song.filename);
+            let _ = fs::remove_file(&partial).await;
+            continue;
+        }
+
+        fs::rename(&partial, &target).await.into_diagnostic()?;
+
+        match transcode {
+            Some(_) => summary.transcoded += 1,
+            None => summary.copied += 1,
+        }
+    }
+
+    progress(songs.len(), songs.len());
+
+    Ok(summary)
+}
+
+/// Exports the songs of a playlist, and writes it to the target's root as an M3U playlist
+/// pointing to them with relative paths, so it works wherever the target is mounted
+pub async fn export_playlist(
+    playlist_id: i32,
+    options: &ExportOptions,
+    progress: impl FnMut(usize, usize) + Send,
+    db: &DatabaseConnection,
+) -> Result<ExportSummary> {
+    let playlist = playlists::Entity::find_by_id(playlist_id)
+        .one(db)
+        .await
+        .into_diagnostic()?
+        .ok_or(miette!("Playlist {} does not exist", playlist_id))?;
+
+    let hashes: Vec<i64> = playlist_entries::Entity::find()
+        .filter(playlist_entries::Column::PlaylistId.eq(playlist_id))
+        .order_by_asc(playlist_entries::Column::Ordinal)
+        .all(db)
+        .await
+        .into_diagnostic()?
+        .into_iter()
+        .map(|v| v.song_hash)
+        .collect();
+
+    let found = library::Entity::find()
+        .filter(library::Column::Hash.is_in(hashes.clone()))
+        .all(db)
+        .await
+        .into_diagnostic()?;
+
+    // Keep the order of the playlist, including songs that are in it more than once
+    let songs: Vec<library::Model> = hashes
+        .iter()
+        .filter_map(|hash| found.iter().find(|v| v.hash == *hash).cloned())
+        .collect();
+
+    let summary = export_songs(&songs, options, progress, db).await?;
+
+    let mut m3u = String::from("#EXTM3U\n");
+
+    for song in &songs {
+        let transcode = transcoded_extension(song, options.format);
+        let path = relative_path(song, transcode);
+
+        if !options.target.join(&path).exists() {
+            continue;
+        }
+
+        let _ = writeln!(m3u, "{}", extinf(song));
+        // Forward slashes work on every device, backslashes only on Windows
+        let _ = writeln!(m3u, "{}", path.to_string_lossy().replace('\\', "/"));
+    }
+
+    let name = clean_component(playlist.name.as_deref().unwrap_or("Playlist"));
+    fs::write(options.target.join(format!("{name}.m3u8")), m3u)
+        .await
+        .into_diagnostic()?;
+
+    Ok(summary)
+}
+
+/// The `#EXTINF` line describing a song in an M3U playlist, with its length in seconds
+fn extinf(song: &library::Model) -> String {
+    format!(
+        "#EXTINF:{},{} - {}",
+        song.duration / 1000,
+        song.artist.as_deref().unwrap_or_default(),
+        song.name.as_deref().unwrap_or(&song.filename)
+    )
+}
+
+/// Extension of the file a song is transcoded to, `None` if it's copied as it is
+fn transcoded_extension(song: &library::Model, format: ExportFormat) -> Option<&'static str> {
+    let lossless = song
+        .codec
+        .as_deref()
+        .is_some_and(|v| LOSSLESS.contains(&v) || v.starts_with("pcm"));
+
+    format.extension().filter(|_| lossless)
+}
+
+/// Where a song goes on the target, relative to its root.
+/// Transcoded songs get the extension of their new format.
+fn relative_path(song: &library::Model, extension: Option<&str>) -> PathBuf {
+    let artist = song
+        .album_artist
+        .as_deref()
+        .or(song.artist.as_deref())
+        .unwrap_or("Unknown Artist");
+    let album = song.album.as_deref().unwrap_or("Unknown Album");
+
+    let mut filename = PathBuf::from(clean_component(&song.filename));
+    if let Some(extension) = extension {
+        filename.set_extension(extension);
+    }
+
+    Path::new(&clean_component(artist))
+        .join(clean_component(album))
+        .join(filename)
+}
+
+/// Replaces what isn't allowed in file names on FAT32, which most devices are formatted with
+fn clean_component(name: &str) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|v| match v {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            v if v.is_control() => '_',
+            v => v,
+        })
+        .collect();
+
+    match cleaned.trim_end_matches(['.', ' ']) {
+        "" => "_".to_string(),
+        v => v.to_string(),
+    }
+}
+
+/// Copies are compared by size. Transcoded files can't be, so any file counts.
+async fn is_exported(source: &Path, target: &Path, transcoded: bool) -> bool {
+    let Ok(existing) = fs::metadata(target).await else {
+        return false;
+    };
+
+    transcoded
+        || fs::metadata(source)
+            .await
+            .is_ok_and(|v| v.len() == existing.len())
+}
+
+async fn transcode_file(source: &Path, target: &Path, options: &ExportOptions) -> Result<()> {
+    let (format, codec) = match options.format {
+        ExportFormat::Opus => ("opus", "libopus"),
+        ExportFormat::Mp3 => ("mp3", "libmp3lame"),
+        ExportFormat::Original => return Err(miette!("Nothing to transcode to")),
+    };
+
+    // Only the audio is kept, since Ogg can't carry cover art as a stream
+    let output = Command::new("ffmpeg")
+        .args(["-nostdin", "-y", "-loglevel", "error", "-i"])
+        .arg(source)
+        .args(["-map", "0:a", "-map_metadata", "0", "-c:a", codec, "-b:a"])
+        .arg(format!("{}k", options.bitrate))
+        .args(["-f", format])
+        .arg(target)
+        .output()
+        .await
+        .map_err(|e| miette!("Couldn't run ffmpeg: {}", e))?;
+
+    if !output.status.success() {
+        return Err(miette!(
+            "ffmpeg failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::test_util::song;
+
+    #[test]
+    fn extinf_lengths_are_in_seconds() {
+        let named = library::Model {
+            artist: Some("Artist".into()),
+            name: Some("Title".into()),
+            duration: 185_750,
+            ..song(1)
+        };
+
+        assert_eq!(extinf(&named), "#EXTINF:185,Artist - Title");
+        assert_eq!(extinf(&song(2)), "#EXTINF:1, - 2.flac");
+    }
+}
@@ -0,0 +1,87 @@
+use std::{
+    future::Future,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+use super::{
+    config::ExternalMetadata,
+    errors::{report, Category, Severity},
+};
+use paris::warn;
+use tokio::{
+    runtime::{Builder, Runtime},
+    sync::Semaphore,
+};
+
+/// Worker threads of the fetcher runtime, fetches spend most of their time waiting anyway
+const WORKER_THREADS: usize = 2;
+
+struct Fetchers {
+    runtime: Runtime,
+    permits: Arc<Semaphore>,
+}
+
+static FETCHERS: OnceLock<Option<Fetchers>> = OnceLock::new();
+
+/// Runs a fetch from a third-party metadata service, like AcoustID or MusicBrainz,
+/// in the background. Fetches get a runtime of their own, so a slow or hanging service can't
+/// hold up indexing or playback, and are cut off once they take longer than the configured
+/// timeout. At most `max_concurrent` run at once, the rest wait their turn.
+///
+/// Returns false without running the fetch if external metadata is turned off in the config.
+pub fn spawn<F>(name: &'static str, settings: &ExternalMetadata, fetch: F) -> bool
+where
+    F: Future<Output = miette::Result<()>> + Send + 'static,
+{
+    if !settings.enabled {
+        return false;
+    }
+
+    let Some(fetchers) = FETCHERS.get_or_init(|| start(settings.max_concurrent)) else {
+        return false;
+    };
+
+    let permits = fetchers.permits.clone();
+    let timeout = Duration::from_secs(settings.timeout_seconds);
+
+    fetchers.runtime.spawn(async move {
+        // The semaphore is never closed
+        let Ok(_permit) = permits.acquire().await else {
+            return;
+        };
+
+        match tokio::time::timeout(timeout, fetch).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => report(
+                Severity::Warning,
+                Category::Network,
+                Some(name.to_string()),
+                format!("Fetching from {name} failed: {e}"),
+            ),
+            Err(_) => report(
+                Severity::Warning,
+                Category::Network,
+                Some(name.to_string()),
+                format!("Fetching from {name} took over {timeout:?}, gave up"),
+            ),
+        }
+    });
+
+    true
+}
+
+fn start(max_concurrent: usize) -> Option<Fetchers> {
+    let runtime = Builder::new_multi_thread()
+        .worker_threads(WORKER_THREADS)
+        .thread_name("eleanor-fetcher")
+        .enable_all()
+        .build()
+        .map_err(|e| warn!("Couldn't start the metadata fetcher runtime: {e}"))
+        .ok()?;
+
+    Some(Fetchers {
+        runtime,
+        permits: Arc::new(Semaphore::new(max_concurrent.max(1))),
+    })
+}
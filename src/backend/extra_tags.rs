@@ -0,0 +1,95 @@
+use std::collections::BTreeMap;
+
+use super::model::library;
+use lofty::{ItemKey, ItemValue, Tag};
+use miette::{IntoDiagnostic, Result};
+use sea_orm::{entity::prelude::Json, DatabaseConnection, EntityTrait};
+
+/// Keys that have columns of their own, or are too large to be worth copying
+const SKIPPED: [ItemKey; 17] = [
+    ItemKey::TrackArtist,
+    ItemKey::AlbumArtist,
+    ItemKey::TrackTitle,
+    ItemKey::AlbumTitle,
+    ItemKey::Genre,
+    ItemKey::TrackNumber,
+    ItemKey::Year,
+    ItemKey::RecordingDate,
+    ItemKey::DiscNumber,
+    ItemKey::ReplayGainTrackGain,
+    ItemKey::ReplayGainTrackPeak,
+    ItemKey::ReplayGainAlbumGain,
+    ItemKey::ReplayGainAlbumPeak,
+    ItemKey::FlagCompilation,
+    ItemKey::Composer,
+    ItemKey::Comment,
+    ItemKey::Lyrics,
+];
+
+/// Collects the tags without a column of their own into a JSON object, keyed by the name
+/// the tag format uses, e.g. `{"CATALOGNUMBER": "ABC-123"}`. Repeated keys are joined with "; ".
+/// Returns `None` if there are no such tags.
+pub fn extra_tags(tag: &Tag) -> Option<Json> {
+    let mut tags: BTreeMap<String, String> = BTreeMap::new();
+
+    for item in tag.items() {
+        if SKIPPED.contains(item.key()) {
+            continue;
+        }
+
+        let value = match item.value() {
+            ItemValue::Text(v) | ItemValue::Locator(v) => v,
+            ItemValue::Binary(_) => continue,
+        };
+
+        let key = match item.key() {
+            ItemKey::Unknown(key) => key.as_str(),
+            key => match key.map_key(tag.tag_type(), true) {
+                Some(key) => key,
+                None => continue,
+            },
+        };
+
+        tags.entry(key.to_string())
+            .and_modify(|v| {
+                v.push_str("; ");
+                v.push_str(value);
+            })
+            .or_insert_with(|| value.to_string());
+    }
+
+    (!tags.is_empty()).then(|| tags.into_iter().collect())
+}
+
+/// Value of an extra tag of a song. Keys are matched case insensitively,
+/// since formats disagree on how to write them.
+pub fn extra_tag<'a>(song: &'a library::Model, key: &str) -> Option<&'a str> {
+    song.extra_tags
+        .as_ref()?
+        .as_object()?
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(key))?
+        .1
+        .as_str()
+}
+
+/// Songs with an extra tag, e.g. a catalog number or a mood.
+/// With `value` set, only songs where the tag contains it, ignoring case.
+pub async fn songs_with_tag(
+    key: &str,
+    value: Option<&str>,
+    db: &DatabaseConnection,
+) -> Result<Vec<library::Model>> {
+    let value = value.map(str::to_lowercase);
+
+    let songs = library::Entity::find().all(db).await.into_diagnostic()?;
+
+    Ok(songs
+        .into_iter()
+        .filter(|song| match (extra_tag(song, key), &value) {
+            (Some(tag), Some(value)) => tag.to_lowercase().contains(value),
+            (found, None) => found.is_some(),
+            (None, Some(_)) => false,
+        })
+        .collect())
+}
@@ -1,220 +1,1136 @@
 use std::{
-    ffi::{OsStr, OsString},
+    collections::{HashMap, HashSet, VecDeque},
+    ffi::OsStr,
     fs::File,
     hash::Hasher,
     path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use crate::backend::utils::get_auth_source;
+use crate::backend::utils::http_client;
 
 use super::{
+    artists::{link_song_artists, link_unlinked_artists},
+    backup::snapshot,
+    cancellation::CancellationToken,
+    channels::layout_name,
+    compilations::detect_compilations,
     config::{Config, Source, SourceKind},
-    model::{library, library::Column},
+    diagnostics::record_rows,
+    duplicates::{claim, Owner},
+    errors::{report, Category, Severity},
+    events::{publish, Event},
+    exclusions::{Rules, Skipped},
+    extra_tags::extra_tags,
+    genres::{link_song, link_unlinked},
+    hashes::{audio_hasher, legacy_songs, remap, HashScope},
+    model::{library, library::Column, sea_orm_active_enums::ScanStatus},
+    playback::PlaybackState,
+    position,
+    replaygain::{from_tag, update_album_gain, write_back, Analyzer, Gain},
+    scan_status::{files_with_issues, record, update_missing},
+    stats::{mark_indexed, mark_partially_indexed},
+    subsonic::sync_subsonic,
+    sync::{preview_remote, sync_remote, SyncReport, SyncStats},
+    vfs::{local_copy, Entry, LocalCopy, LocalFiles, Provider},
+    waveform::{save_waveform, PeakCollector, Waveform},
+    webdav::WebDav,
+};
+use lofty::{read_from_path, Accessor, AudioFile, Tag, TaggedFile};
+use miette::{miette, IntoDiagnostic, Report, Result};
+use paris::{info, success, warn};
+use sea_orm::{
+    ActiveValue, ColumnTrait, ConnectionTrait, DatabaseConnection, DatabaseTransaction,
+    EntityTrait, NotSet, PaginatorTrait, QueryFilter, Set, TransactionTrait,
 };
-use adler::Adler32;
-use lofty::{read_from_path, Accessor, AudioFile};
-use miette::{miette, IntoDiagnostic, Result};
-use paris::{success, warn};
-use reqwest::Client;
-use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QuerySelect, Set};
 use symphonia::{
     core::{
+        codecs::CodecParameters,
+        formats::FormatReader,
         io::MediaSourceStream,
         meta::{Limit, MetadataOptions},
         probe::Hint,
     },
-    default::get_probe,
+    default::{get_codecs, get_probe},
 };
-use walkdir::WalkDir;
+use tokio::task::{spawn_blocking, JoinHandle};
+
+/// Pause between files while indexing at low priority, see `Indexing::low_priority`
+const LOW_PRIORITY_PAUSE: Duration = Duration::from_millis(250);
 
 #[derive(PartialEq, Debug)]
 pub enum IndexMode {
     Purge,
     New,
     Initial,
+    /// Reads the tags of every song again. Files with the same size and modification time
+    /// as when they were last read keep their hash and analysis, so their audio isn't decoded.
+    Metadata,
 }
 
-pub async fn index_source(source: Source, mode: IndexMode, db: &DatabaseConnection) -> Result<()> {
-    let mut existing: Vec<OsString> = vec![];
+/// What the library held for a source before it's indexed again
+#[derive(Default)]
+struct Known {
+    /// Names of the files indexed before, which only indexing new songs skips
+    filenames: Vec<String>,
+    /// Songs still stored under the Adler-32 hash of their audio, by directory and file name
+    legacy: HashMap<(String, String), i64>,
+    /// When songs were first added by hash, kept for songs that are indexed again
+    added_dates: HashMap<i64, i64>,
+    /// Songs by directory and file name, for refreshing their tags
+    /// and moving songs whose file hashes differently now
+    songs: HashMap<(String, String), library::Model>,
+    /// Files with a problem recorded, which is forgotten once they're read fine
+    issues: HashSet<(String, String)>,
+}
 
-    // Force reindex source
-    if mode == IndexMode::Purge {
-        warn!("Overwriting source {}", source.id);
+/// Indexes a source. Cancelling stops it after the song it's on. Every song indexed until then
+/// is kept, and the run is recorded as a partial one rather than as the source being indexed.
+pub async fn index_source(
+    source: Source,
+    mode: IndexMode,
+    cancel: &CancellationToken,
+    db: &DatabaseConnection,
+) -> Result<()> {
+    let config = Config::read_config()?;
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .into_diagnostic()?
+        .as_secs() as i64;
 
-        library::Entity::delete_many()
-            .filter(library::Column::SourceId.eq(source.id))
-            .exec(db)
-            .await
-            .into_diagnostic()?;
-    // Only index new songs
-    } else if mode == IndexMode::New {
-        existing = library::Entity::find()
-            .filter(library::Column::SourceId.eq(source.id))
-            .column(library::Column::Filename)
-            .all(db)
-            .await
-            .into_diagnostic()?
-            .into_iter()
-            .map(|v| v.filename.into())
-            .collect();
-    }
+    // Songs hashed before XXH64 was used get their new hash when they're read again
+    let mut known = Known {
+        legacy: legacy_songs(source.id, db).await?,
+        issues: files_with_issues(source.id, db).await?,
+        ..Default::default()
+    };
 
-    match source.source {
-        SourceKind::Local { path } => {
-            for file in WalkDir::new(path)
+    let songs = library::Entity::find()
+        .filter(library::Column::SourceId.eq(source.id))
+        .all(db)
+        .await
+        .into_diagnostic()?;
+
+    match mode {
+        // Force reindex source
+        IndexMode::Purge => {
+            warn!("Overwriting source {}", source.id);
+            snapshot("purge", db).await?;
+
+            // Reindexed songs keep the date they were first added on
+            known.added_dates = songs
+                .iter()
+                .filter_map(|v| Some((v.hash, v.added_date?)))
+                .collect();
+
+            // Files are purged in the same transaction they're indexed in, see `index_files`
+            if !matches!(
+                source.source,
+                SourceKind::Local { .. } | SourceKind::WebDav { .. }
+            ) {
+                purge(source.id, db).await?;
+            }
+        }
+        // Only index new songs
+        IndexMode::New => {
+            known.filenames = songs
                 .into_iter()
-                .filter_map(Result::ok)
-                .filter(|e| !e.file_type().is_dir())
-                .filter(|e| {
-                    mime_guess::from_path(e.path())
-                        .first()
-                        .map(|v| v.type_() == mime::AUDIO)
-                        .unwrap_or(false)
+                .filter(|v| {
+                    !known
+                        .legacy
+                        .contains_key(&(v.path.clone(), v.filename.clone()))
                 })
-            {
-                if mode == IndexMode::New {
-                    if existing.contains(&file.file_name().into()) {
-                        continue;
-                    };
-                }
+                .map(|v| v.filename)
+                .collect();
+        }
+        IndexMode::Metadata | IndexMode::Initial => {
+            known.songs = songs
+                .into_iter()
+                .map(|v| ((v.path.clone(), v.filename.clone()), v))
+                .collect();
+        }
+    }
 
-                let audio = read_from_path(file.path(), true).into_diagnostic()?;
-
-                let tags = audio.primary_tag().or(audio.first_tag());
-
-                let properties = audio.properties();
-
-                let hash = hash_file(file.path())?;
-
-                let song: library::ActiveModel = library::ActiveModel {
-                    path: Set(file
-                        .path()
-                        .parent()
-                        .and_then(Path::to_str)
-                        .ok_or(miette!("Couldn't get path for file {:?}", file))?
-                        .to_string()),
-                    filename: Set(file
-                        .file_name()
-                        .to_str()
-                        .ok_or(miette!("Couldn't get filename for file {:?}", file))?
-                        .to_string()),
-                    source_id: Set(source.id.into()),
-                    hash: Set(hash.try_into().into_diagnostic()?),
-                    artist: Set(tags.and_then(|t| t.artist()).map(|t| t.to_string())),
-                    album_artist: Set(tags
-                        .and_then(|t| t.get_string(&lofty::ItemKey::AlbumArtist))
-                        .map(|t| t.to_string())),
-                    name: Set(tags.and_then(|t| t.title()).map(|t| t.to_string())),
-                    album: Set(tags.and_then(|t| t.album()).map(|t| t.to_string())),
-                    genres: Set(tags.and_then(|t| t.genre()).map(|t| t.to_string())),
-                    track: Set(tags.and_then(|t| t.track()).map(|t| t as i32)),
-                    year: Set(tags.and_then(|t| t.year()).map(|t| t as i32)),
-                    duration: Set(properties
-                        .duration()
-                        .as_millis()
-                        .try_into()
-                        .into_diagnostic()?),
-                    ..Default::default()
-                };
+    let finished = match &source.source {
+        SourceKind::Local { path } => {
+            let finished = index_files(
+                &source,
+                &LocalFiles::new(path),
+                &mode,
+                &known,
+                &config,
+                cancel,
+                db,
+            )
+            .await?;
 
-                library::Entity::insert(song)
-                    .on_conflict(
-                        sea_query::OnConflict::column(Column::Hash)
-                            .do_nothing()
-                            .to_owned(),
-                    )
-                    .exec(db)
-                    .await
-                    .into_diagnostic()?;
+            if finished && config.write_replaygain {
+                write_back(source.id.into(), false, db).await?;
             }
+
+            finished
+        }
+        SourceKind::WebDav { share } => {
+            let provider = WebDav::new(&source, share, &config)?;
+            index_files(&source, &provider, &mode, &known, &config, cancel, db).await?
         }
+        // Syncs are applied in a single transaction, so dropping one leaves the library as it was
         SourceKind::Remote { address } => {
-            let (username, password) = get_auth_source(source.id)?;
+            let client = http_client(&config, Some(&source))?;
+            let progress = |indexed, total| {
+                publish(Event::IndexProgress {
+                    source_id: source.id,
+                    indexed,
+                    total,
+                })
+            };
+
+            tokio::select! {
+                stats = sync_remote(&source, address, &client, progress, &config, db) => {
+                    report_sync(source.id, stats?);
+                    true
+                }
+                _ = cancel.cancelled() => false,
+            }
+        }
+        SourceKind::Subsonic { url } => {
+            let client = http_client(&config, Some(&source))?;
+
+            tokio::select! {
+                stats = sync_subsonic(&source, url, &client, &config, db) => {
+                    report_sync(source.id, stats?);
+                    true
+                }
+                _ = cancel.cancelled() => false,
+            }
+        }
+    };
 
-            let client = Client::new();
+    link_unlinked(db).await?;
+    link_unlinked_artists(&config.artist_separators, db).await?;
 
-            let index = client
-                .get(format!("{address}/"))
-                .basic_auth(username, Some(password))
-                .send()
-                .await
-                .into_diagnostic()?
-                .bytes()
-                .await
-                .into_diagnostic()?;
+    // Also after a cancelled run, since the songs indexed until then are kept
+    let added = library::Entity::find()
+        .filter(library::Column::SourceId.eq(source.id))
+        .filter(library::Column::AddedDate.gte(started))
+        .count(db)
+        .await
+        .into_diagnostic()? as usize;
 
-            // Deserialize messagepack into a library model
-            let parsed: Vec<library::Model> = rmp_serde::from_slice(&index).into_diagnostic()?;
+    if added > 0 {
+        publish(Event::SongsAdded {
+            source_id: source.id,
+            count: added,
+        });
+    }
 
-            // Use all fields except for id and source_id
-            let songs: Vec<_> = parsed
-                .into_iter()
-                .map(|v| {
-                    return library::ActiveModel {
-                        path: Set(v.path),
-                        filename: Set(v.filename),
-                        source_id: Set(source.id.into()), // Use local source id, not remote
-                        hash: Set(v.hash),
-                        artist: Set(v.artist),
-                        album_artist: Set(v.album_artist),
-                        name: Set(v.name),
-                        album: Set(v.album),
-                        genres: Set(v.genres),
-                        track: Set(v.track),
-                        year: Set(v.year),
-                        duration: Set(v.duration),
-                        ..Default::default()
-                    };
-                })
-                .collect();
+    if !finished {
+        mark_partially_indexed(source.id)?;
+        publish(Event::IndexCancelled {
+            source_id: source.id,
+        });
+        info!("Indexing source {} was cancelled", source.id);
+        return Ok(());
+    }
 
-            library::Entity::insert_many(songs)
-                .on_conflict(
-                    sea_query::OnConflict::column(Column::Hash)
-                        .do_nothing()
-                        .to_owned(),
-                )
-                .exec(db)
-                .await
-                .into_diagnostic()?;
+    // Only reached once the run's transaction is committed, so a failed run isn't recorded
+    mark_indexed(source.id)?;
+    publish(Event::IndexFinished {
+        source_id: source.id,
+    });
+    success!("Indexed source {} in {:?} mode", source.id, mode);
+    Ok(())
+}
+
+/// Indexes every source that's set to be scanned on startup
+pub async fn index_initial(cancel: &CancellationToken, db: &DatabaseConnection) -> Result<()> {
+    let sources = Config::read_config()?.sources;
+
+    for source in sources.into_iter().filter(|v| v.settings.scan_on_startup) {
+        if cancel.is_cancelled() {
+            break;
         }
+
+        index_source(source, IndexMode::Initial, cancel, db).await?;
     }
 
-    success!("Indexed source {} in {:?} mode", source.id, mode);
     Ok(())
 }
 
-pub async fn index_initial(db: &DatabaseConnection) -> Result<()> {
+/// Reads the tags of the songs of every source again, see `IndexMode::Metadata`
+pub async fn index_metadata(cancel: &CancellationToken, db: &DatabaseConnection) -> Result<()> {
     let sources = Config::read_config()?.sources;
 
     for source in sources {
-        index_source(source, IndexMode::Initial, db).await?;
+        if cancel.is_cancelled() {
+            break;
+        }
+
+        index_source(source, IndexMode::Metadata, cancel, db).await?;
     }
 
     Ok(())
 }
 
-pub async fn index_new(db: &DatabaseConnection) -> Result<()> {
+/// Reports what syncing a source would change, without changing anything.
+/// `None` for sources that aren't synced from another Eleanor server.
+pub async fn preview_sync(source: &Source, db: &DatabaseConnection) -> Result<Option<SyncReport>> {
+    let SourceKind::Remote { address } = &source.source else {
+        return Ok(None);
+    };
+
+    let client = http_client(&Config::read_config()?, Some(source))?;
+
+    preview_remote(source, address, &client, db).await.map(Some)
+}
+
+/// Indexes the new songs of every source that's set to be scanned on startup
+pub async fn index_new(cancel: &CancellationToken, db: &DatabaseConnection) -> Result<()> {
     let sources = Config::read_config()?.sources;
 
-    for source in sources {
-        index_source(source, IndexMode::New, db).await?;
+    for source in sources.into_iter().filter(|v| v.settings.scan_on_startup) {
+        if cancel.is_cancelled() {
+            break;
+        }
+
+        index_source(source, IndexMode::New, cancel, db).await?;
     }
 
     Ok(())
 }
 
-fn hash_file(path: &Path) -> Result<u64> {
-    let file = Box::new(File::open(path).into_diagnostic()?);
+/// Removes every song of a source
+async fn purge(source_id: u8, db: &impl ConnectionTrait) -> Result<()> {
+    let started = Instant::now();
+
+    let purged = library::Entity::delete_many()
+        .filter(library::Column::SourceId.eq(source_id))
+        .exec(db)
+        .await
+        .into_diagnostic()?;
+
+    record_rows("purge", purged.rows_affected, started.elapsed());
+    Ok(())
+}
+
+/// Indexes the audio files of a source, wherever they're stored.
+/// Everything is written in one transaction, along with the purge in `IndexMode::Purge`,
+/// so a run that fails partway leaves the library as it was. A cancelled run is committed,
+/// keeping the songs indexed until then.
+/// Files are read and decoded on blocking threads, up to `Indexing::threads` at once ahead of the
+/// file being written to the library, so the async runtime only ever waits on downloads and
+/// the database. Files are still written in the order they're listed.
+/// Returns false if it was cancelled before going through every file.
+async fn index_files(
+    source: &Source,
+    provider: &dyn Provider,
+    mode: &IndexMode,
+    known: &Known,
+    config: &Config,
+    cancel: &CancellationToken,
+    db: &DatabaseConnection,
+) -> Result<bool> {
+    let started = Instant::now();
+    let txn = db.begin().await.into_diagnostic()?;
+
+    if *mode == IndexMode::Purge {
+        purge(source.id, &txn).await?;
+    }
+
+    let scope = config.hash_scope(source);
+    let writer = Writer {
+        source,
+        scope,
+        mode,
+        known,
+        config,
+        db: &txn,
+        rows: AtomicU64::new(0),
+        added_date: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .into_diagnostic()?
+            .as_secs() as i64,
+        #[cfg(feature = "acoustid")]
+        lookups: Default::default(),
+    };
+
+    let rules = Rules::new(
+        &config.exclusions,
+        &source.settings.extensions,
+        &provider.root(),
+    )?;
+    let mut skipped = Skipped::default();
+
+    // Collected first so progress can be reported against the total
+    let files = provider.list().await?;
+    update_missing(source.id, &files, &txn).await?;
+
+    let threads = config.indexing.threads();
+
+    let mut finished = true;
+    let mut pending = VecDeque::new();
+
+    for (i, entry) in files.iter().enumerate() {
+        if cancel.is_cancelled() {
+            finished = false;
+            break;
+        }
+
+        let playing = config.indexing.low_priority
+            && position::current().is_some_and(|v| v.state == PlaybackState::Playing);
+
+        publish(Event::IndexProgress {
+            source_id: source.id,
+            indexed: i,
+            total: files.len(),
+        });
+
+        if *mode == IndexMode::New && known.filenames.contains(&entry.filename) {
+            continue;
+        }
+
+        if let Some(reason) = rules.check(entry) {
+            skipped.add(reason);
+            continue;
+        }
+
+        let unchanged = match mode {
+            IndexMode::Metadata => known
+                .songs
+                .get(&(entry.dir.clone(), entry.filename.clone()))
+                // Songs hashed with another scope are hashed again
+                .filter(|v| is_unchanged(v, entry) && v.hash_bytes == scope.bytes()),
+            _ => None,
+        };
+
+        if playing {
+            tokio::select! {
+                _ = tokio::time::sleep(LOW_PRIORITY_PAUSE) => {}
+                _ = cancel.cancelled() => {
+                    finished = false;
+                    break;
+                }
+            }
+        }
+
+        // Nothing is written until the file is there, so a download can be dropped
+        let copy = tokio::select! {
+            copy = local_copy(provider, entry) => copy?,
+            _ = cancel.cancelled() => {
+                finished = false;
+                break;
+            }
+        };
+
+        let options = ReadOptions {
+            tags_only: unchanged.is_some(),
+            analyze: source.settings.analyze_replaygain,
+            waveform: source.settings.waveforms,
+            scope,
+            #[cfg(feature = "acoustid")]
+            fingerprint: config.acoustid_key.is_some() && config.external_metadata.enabled,
+        };
+        let read = {
+            let cancel = cancel.clone();
+            spawn_blocking(move || read_file(copy, &options, &cancel))
+        };
+
+        pending.push_back(Pending {
+            entry,
+            unchanged,
+            read,
+        });
+
+        // Only the newest file keeps being read while playing at low priority
+        let ahead = if playing { 1 } else { threads };
+
+        while pending.len() > ahead {
+            if let Some(previous) = pending.pop_front() {
+                if !writer.write(previous).await? {
+                    finished = false;
+                    break;
+                }
+            }
+        }
+
+        if !finished {
+            break;
+        }
+    }
+
+    // The last files, or the ones that were being read when indexing was cancelled
+    for last in pending {
+        if !writer.write(last).await? {
+            finished = false;
+        }
+    }
+
+    if skipped.total() > 0 {
+        info!(
+            "Skipped {} files in source {}: {} excluded by patterns, {} hidden, {} too small, {} with other extensions",
+            skipped.total(),
+            source.id,
+            skipped.patterns,
+            skipped.hidden,
+            skipped.too_small,
+            skipped.extension
+        );
+    }
+
+    // Also done after a cancelled run, so the songs that made it in are complete
+    detect_compilations(source.id.into(), &txn).await?;
+    update_album_gain(source.id.into(), &txn).await?;
+
+    let rows = writer.rows.into_inner();
+    #[cfg(feature = "acoustid")]
+    let lookups = writer
+        .lookups
+        .into_inner()
+        .unwrap_or_else(|e| e.into_inner());
+
+    txn.commit().await.into_diagnostic()?;
+    record_rows("indexing", rows, started.elapsed());
+
+    // Lookups fill in rows, so they only start once the rows are committed
+    #[cfg(feature = "acoustid")]
+    look_up(lookups, config, db)?;
+
+    Ok(finished)
+}
+
+/// A file that's being read while the one before it is written to the library
+struct Pending<'a> {
+    entry: &'a Entry,
+    /// The file's song if the file didn't change since it was read, so only its tags are read
+    unchanged: Option<&'a library::Model>,
+    read: JoinHandle<Result<Option<ReadFile>, ReadError>>,
+}
+
+/// What to read from a file besides its tags
+struct ReadOptions {
+    /// Only read the tags, for songs that are refreshed
+    tags_only: bool,
+    /// Analyze the ReplayGain values of files without ReplayGain tags
+    analyze: bool,
+    waveform: bool,
+    scope: HashScope,
+    /// Fingerprint files without a title that weren't looked up before
+    #[cfg(feature = "acoustid")]
+    fingerprint: bool,
+}
+
+/// What reading a file found out about it
+struct ReadFile {
+    audio: TaggedFile,
+    /// `None` if only the tags were read
+    scan: Option<Scan>,
+    /// Chromaprint fingerprint and duration in seconds, if the file was fingerprinted
+    #[cfg(feature = "acoustid")]
+    fingerprint: Option<Result<(String, u64)>>,
+}
+
+/// Why a file couldn't be read
+struct ReadError {
+    /// Either `TagError` or `DecodeError`
+    status: ScanStatus,
+    error: Report,
+}
+
+/// Reads a file's tags, along with what `options` asks for. Decoding takes a while,
+/// so this is run on a blocking thread. Copies of remote files are removed once read.
+/// Returns `None` if cancelled partway through.
+fn read_file(
+    copy: LocalCopy,
+    options: &ReadOptions,
+    cancel: &CancellationToken,
+) -> Result<Option<ReadFile>, ReadError> {
+    let path = copy.path();
+    let audio = read_from_path(path, true)
+        .into_diagnostic()
+        .map_err(|error| ReadError {
+            status: ScanStatus::TagError,
+            error,
+        })?;
+
+    if options.tags_only {
+        return Ok(Some(ReadFile {
+            audio,
+            scan: None,
+            #[cfg(feature = "acoustid")]
+            fingerprint: None,
+        }));
+    }
+
+    let tags = audio.primary_tag().or(audio.first_tag());
+
+    // Files with ReplayGain tags don't need to be analyzed
+    let analyze = options.analyze && tags.and_then(from_tag).is_none();
+
+    let scanned =
+        scan_file(path, analyze, options.waveform, options.scope, cancel).map_err(|error| {
+            ReadError {
+                status: ScanStatus::DecodeError,
+                error,
+            }
+        })?;
+    let Some(scan) = scanned else {
+        return Ok(None);
+    };
+
+    #[cfg(feature = "acoustid")]
+    let fingerprint = (options.fingerprint
+        && tags.and_then(|t| t.title()).is_none()
+        && super::acoustid::cached(scan.hash as i64).is_none())
+    .then(|| super::acoustid::fingerprint(path));
+
+    Ok(Some(ReadFile {
+        audio,
+        scan: Some(scan),
+        #[cfg(feature = "acoustid")]
+        fingerprint,
+    }))
+}
+
+/// Writes the files of a source to the library once they're read
+struct Writer<'a> {
+    source: &'a Source,
+    /// How much of the audio of the source's songs is hashed
+    scope: HashScope,
+    mode: &'a IndexMode,
+    known: &'a Known,
+    config: &'a Config,
+    db: &'a DatabaseTransaction,
+    /// Songs inserted or updated so far, see `diagnostics::record_rows`
+    rows: AtomicU64,
+    /// Date new songs are added on, which is when indexing started
+    added_date: i64,
+    /// Fingerprints of songs without a title to look up on AcoustID, with their duration
+    /// and hash
+    #[cfg(feature = "acoustid")]
+    lookups: std::sync::Mutex<Vec<(String, u64, i64)>>,
+}
+
+impl Writer<'_> {
+    /// Waits for a file to be read and writes it. Returns false if reading it was cancelled.
+    async fn write(&self, pending: Pending<'_>) -> Result<bool> {
+        let Writer {
+            source,
+            scope,
+            mode,
+            known,
+            config,
+            db,
+            added_date,
+            ..
+        } = *self;
+        let entry = pending.entry;
+
+        let key = (entry.dir.clone(), entry.filename.clone());
 
-    let probe = get_probe();
+        let read = match pending.read.await.into_diagnostic()? {
+            Ok(Some(read)) => read,
+            Ok(None) => return Ok(false),
+            Err(ReadError { status, error }) => {
+                let category = match status {
+                    ScanStatus::TagError => Category::Indexing,
+                    _ => Category::Decoding,
+                };
+                report(
+                    Severity::Warning,
+                    category,
+                    Some(entry.to_string()),
+                    format!("Couldn't read {entry}: {error}"),
+                );
+
+                let message = Some(error.to_string());
+                record(source.id, &entry.dir, &entry.filename, status, message, db).await?;
+                return Ok(true);
+            }
+        };
+
+        // Analysis that fails below is recorded again
+        if known.issues.contains(&key) {
+            record(
+                source.id,
+                &entry.dir,
+                &entry.filename,
+                ScanStatus::Ok,
+                None,
+                db,
+            )
+            .await?;
+        }
+
+        let tags = read.audio.primary_tag().or(read.audio.first_tag());
+
+        let properties = read.audio.properties();
+
+        if let Some(song) = pending.unchanged {
+            refresh_tags(song, tags, config, db).await?;
+            self.rows.fetch_add(1, Ordering::Relaxed);
+            return Ok(true);
+        }
+
+        // Only the tags of unchanged files are read
+        let Some(scan) = read.scan else {
+            return Ok(true);
+        };
+
+        let tagged_gain = tags.and_then(from_tag);
+        let hash = scan.hash as i64;
+
+        // A file whose audio changed replaces the song it was before
+        let previous = known
+            .legacy
+            .get(&key)
+            .copied()
+            .or_else(|| known.songs.get(&key).map(|v| v.hash).filter(|v| *v != hash));
+        if let Some(old) = previous {
+            if !remap(old, hash, db).await? {
+                return Ok(true);
+            }
+        }
+
+        // Different files with the same audio are only indexed once
+        let indexed = library::Entity::find()
+            .filter(Column::Hash.eq(hash))
+            .one(db)
+            .await
+            .into_diagnostic()?;
+        if let Some(other) = indexed.as_ref().filter(|v| {
+            v.source_id != i32::from(source.id)
+                || v.path != entry.dir
+                || v.filename != entry.filename
+        }) {
+            report(
+                Severity::Info,
+                Category::Indexing,
+                Some(entry.to_string()),
+                format!(
+                    "Skipped {entry}, its audio is the same as {}/{} in source {}",
+                    other.path, other.filename, other.source_id
+                ),
+            );
+            return Ok(true);
+        }
+
+        let artist = tags.and_then(|t| t.artist()).map(|t| t.to_string());
+        let name = tags.and_then(|t| t.title()).map(|t| t.to_string());
+        let album = tags.and_then(|t| t.album()).map(|t| t.to_string());
+
+        // Fall back to the audio fingerprint for files without a title.
+        // Lookups that weren't cached fill in the row in the background once the run is committed.
+        #[cfg(feature = "acoustid")]
+        let mut fingerprint = None;
+        #[cfg(feature = "acoustid")]
+        let (artist, name, album) = match (&config.acoustid_key, &name) {
+            (Some(_), None) => match super::acoustid::cached(hash) {
+                Some(Some(found)) => (artist.or(found.artist), found.title, album.or(found.album)),
+                Some(None) => (artist, name, album),
+                None => {
+                    match read.fingerprint {
+                        Some(Ok(v)) => fingerprint = Some(v),
+                        Some(Err(e)) => report(
+                            Severity::Warning,
+                            Category::Decoding,
+                            Some(entry.to_string()),
+                            format!("Couldn't fingerprint {entry}: {e}"),
+                        ),
+                        None => {}
+                    }
+
+                    (artist, name, album)
+                }
+            },
+            _ => (artist, name, album),
+        };
+
+        // Problems with the analysis don't keep the song out of the library,
+        // but are recorded as decoding errors of its file
+        let mut problems = vec![];
+
+        let gain = match (tagged_gain, scan.gain) {
+            (Some(gain), _) => Some(gain),
+            (None, Some(Ok(gain))) => Some(gain),
+            (None, Some(Err(e))) => {
+                problems.push(format!("Couldn't analyze {entry}: {e}"));
+                None
+            }
+            (None, None) => None,
+        };
+
+        let waveform = scan
+            .waveform
+            .map(|v| v.and_then(|v| save_waveform(hash, &v)));
+        if let Some(Err(e)) = waveform {
+            problems.push(format!("Couldn't compute the waveform of {entry}: {e}"));
+        }
+
+        for problem in &problems {
+            report(
+                Severity::Warning,
+                Category::Decoding,
+                Some(entry.to_string()),
+                problem,
+            );
+        }
+
+        let mut song: library::ActiveModel = library::ActiveModel {
+            path: Set(entry.dir.clone()),
+            filename: Set(entry.filename.clone()),
+            source_id: Set(source.id.into()),
+            hash: Set(hash),
+            artist: Set(artist),
+            name: Set(name),
+            album: Set(album),
+            rg_track_gain: Set(gain.map(|v| v.gain)),
+            rg_track_peak: Set(gain.map(|v| v.peak)),
+            channel_layout: Set(properties.channels().map(|v| layout_name(v.into()))),
+            codec: Set(scan.codec),
+            bitrate: Set(properties.audio_bitrate().map(|v| v as i32)),
+            sample_rate: Set(properties.sample_rate().map(|v| v as i32)),
+            bit_depth: Set(properties.bit_depth().map(i32::from)),
+            channels: Set(properties.channels().map(i32::from)),
+            // Durations are stored in milliseconds, which overflow after 49 days
+            duration: Set(properties
+                .duration()
+                .as_millis()
+                .try_into()
+                .unwrap_or(u32::MAX)),
+            added_date: Set(Some(
+                known
+                    .added_dates
+                    .get(&hash)
+                    .or_else(|| known.added_dates.get(&previous?))
+                    .copied()
+                    .unwrap_or(added_date),
+            )),
+            file_size: Set(entry.size.map(|v| v as i64)),
+            file_modified: Set(entry.modified),
+            hash_bytes: Set(scope.bytes()),
+            ..tag_columns(tags)
+        };
+
+        match indexed {
+            // What was read replaces the row, except for analysis that wasn't done again
+            Some(before) if *mode == IndexMode::Metadata => {
+                song.id = Set(before.id);
+                song.added_date = NotSet;
+                if gain.is_none() {
+                    song.rg_track_gain = NotSet;
+                    song.rg_track_peak = NotSet;
+                }
+
+                update_song(&before, song, config, db).await?;
+                self.rows.fetch_add(1, Ordering::Relaxed);
+            }
+            // Other modes leave songs that are already there as they are.
+            // Songs another source has only move here if this source comes first.
+            _ => {
+                if claim(&song, hash, source.id, config, db).await? == Owner::Nobody {
+                    library::Entity::insert(song)
+                        .on_conflict(
+                            sea_query::OnConflict::column(Column::Hash)
+                                .update_columns([
+                                    Column::FileSize,
+                                    Column::FileModified,
+                                    Column::HashBytes,
+                                ])
+                                .to_owned(),
+                        )
+                        .exec(db)
+                        .await
+                        .into_diagnostic()?;
+                    self.rows.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+
+        if !problems.is_empty() {
+            let message = Some(problems.join("\n"));
+            let status = ScanStatus::DecodeError;
+            record(source.id, &entry.dir, &entry.filename, status, message, db).await?;
+        }
+
+        #[cfg(feature = "acoustid")]
+        if let Some((fingerprint, duration)) = fingerprint {
+            self.lookups
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push((fingerprint, duration, hash));
+        }
+
+        Ok(true)
+    }
+}
+
+/// Looks up the fingerprints of songs without a title in the background, filling in their rows
+#[cfg(feature = "acoustid")]
+fn look_up(
+    lookups: Vec<(String, u64, i64)>,
+    config: &Config,
+    db: &DatabaseConnection,
+) -> Result<()> {
+    let Some(key) = &config.acoustid_key else {
+        return Ok(());
+    };
+
+    let client = http_client(config, None)?;
+
+    for (fingerprint, duration, hash) in lookups {
+        let client = client.clone();
+        let key = key.clone();
+        let db = db.clone();
+
+        super::external::spawn("AcoustID", &config.external_metadata, async move {
+            let found =
+                super::acoustid::lookup(&client, &fingerprint, duration, hash, &key).await?;
+
+            match found {
+                Some(found) => super::acoustid::apply(hash, found, &db).await,
+                None => Ok(()),
+            }
+        });
+    }
+
+    Ok(())
+}
+
+/// Whether a file has the same size and modification time as when the song was last read
+fn is_unchanged(song: &library::Model, entry: &Entry) -> bool {
+    let size = entry.size.map(|v| v as i64);
+
+    size.is_some()
+        && entry.modified.is_some()
+        && song.file_size == size
+        && song.file_modified == entry.modified
+}
+
+/// Updates the columns of a song that come from its tags, keeping its hash and analysis
+async fn refresh_tags(
+    song: &library::Model,
+    tags: Option<&Tag>,
+    config: &Config,
+    db: &DatabaseTransaction,
+) -> Result<()> {
+    let title = tags.and_then(|t| t.title()).map(|t| t.to_string());
+
+    // Songs without a title may have been named by their audio fingerprint, which is kept
+    let mut update = match title {
+        Some(title) => library::ActiveModel {
+            id: Set(song.id),
+            artist: Set(tags.and_then(|t| t.artist()).map(|t| t.to_string())),
+            name: Set(Some(title)),
+            album: Set(tags.and_then(|t| t.album()).map(|t| t.to_string())),
+            ..tag_columns(tags)
+        },
+        None => library::ActiveModel {
+            id: Set(song.id),
+            ..tag_columns(tags)
+        },
+    };
+
+    if let Some(gain) = tags.and_then(from_tag) {
+        update.rg_track_gain = Set(Some(gain.gain));
+        update.rg_track_peak = Set(Some(gain.peak));
+    }
+
+    update_song(song, update, config, db).await
+}
+
+/// Columns that come straight from a file's tags. Artist, title and album aren't included,
+/// since files without a title get them from their audio fingerprint.
+fn tag_columns(tags: Option<&Tag>) -> library::ActiveModel {
+    library::ActiveModel {
+        album_artist: Set(tags
+            .and_then(|t| t.get_string(&lofty::ItemKey::AlbumArtist))
+            .map(|t| t.to_string())),
+        genres: Set(tags.and_then(|t| t.genre()).map(|t| t.to_string())),
+        track: Set(tags.and_then(|t| t.track()).map(|t| t as i32)),
+        year: Set(tags.and_then(|t| t.year()).map(|t| t as i32)),
+        disc: Set(tags.and_then(|t| t.disk()).map(|t| t as i32)),
+        compilation: Set(tags
+            .and_then(|t| t.get_string(&lofty::ItemKey::FlagCompilation))
+            .is_some_and(|t| matches!(t.trim(), "1" | "true"))),
+        composer: Set(tags
+            .and_then(|t| t.get_string(&lofty::ItemKey::Composer))
+            .map(|t| t.to_string())),
+        comment: Set(tags
+            .and_then(|t| t.get_string(&lofty::ItemKey::Comment))
+            .map(|t| t.to_string())),
+        extra_tags: Set(tags.and_then(extra_tags)),
+        ..Default::default()
+    }
+}
+
+/// Writes changes to a song, linking it to its genres and artists again if they changed
+async fn update_song(
+    before: &library::Model,
+    song: library::ActiveModel,
+    config: &Config,
+    db: &DatabaseTransaction,
+) -> Result<()> {
+    let genres = match &song.genres {
+        ActiveValue::Set(v) if *v != before.genres => Some(v.clone()),
+        _ => None,
+    };
+    let artist = match &song.artist {
+        ActiveValue::Set(v) if *v != before.artist => Some(v.clone()),
+        _ => None,
+    };
+
+    library::Entity::update(song)
+        .exec(db)
+        .await
+        .into_diagnostic()?;
+
+    // The hash is only changed beforehand, with `remap`
+    if let Some(genres) = genres {
+        link_song(before.hash, genres.as_deref(), db).await?;
+    }
+
+    if let Some(artist) = artist {
+        link_song_artists(
+            before.hash,
+            artist.as_deref(),
+            &config.artist_separators,
+            db,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+fn report_sync(source_id: u8, stats: SyncStats) {
+    publish(Event::IndexProgress {
+        source_id,
+        indexed: stats.added + stats.updated,
+        total: stats.added + stats.updated,
+    });
+
+    info!(
+        "Synced source {source_id}: {} added, {} updated, {} removed",
+        stats.added, stats.updated, stats.removed
+    );
+}
+
+/// What a single read of a file's audio found out about it
+struct Scan {
+    /// XXH64 hash of the audio packets, so retagging a file doesn't change it,
+    /// unless the hash covers the file's size too
+    hash: u64,
+    codec: Option<String>,
+    /// ReplayGain values, if they were asked for
+    gain: Option<Result<Gain>>,
+    /// Peaks for the seek bar, if they were asked for
+    waveform: Option<Result<Waveform>>,
+}
+
+/// Hashes as much of a file's audio as `scope` covers, and analyzes its ReplayGain values
+/// while at it with `analyze` set, and its waveform with `waveform` set.
+/// Packets are hashed and decoded as they're read, then dropped, so memory use stays the same
+/// for files of any length. Returns `None` if cancelled partway through.
+fn scan_file(
+    path: &Path,
+    analyze: bool,
+    waveform: bool,
+    scope: HashScope,
+    cancel: &CancellationToken,
+) -> Result<Option<Scan>> {
+    let mut data = open_format(path)?;
+
+    let track = data
+        .default_track()
+        .ok_or(miette!("No audio track found in {}", path.display()))?;
+    let track_id = track.id;
+    let frames = track.codec_params.n_frames;
+
+    let codec = codec_name(&track.codec_params);
+
+    let mut analyzer = analyze.then(|| Analyzer::new(&track.codec_params));
+    let mut peaks = waveform.then(|| PeakCollector::new(&track.codec_params));
+
+    let mut hasher = audio_hasher();
+    let mut hashed = 0;
+
+    while let Ok(packet) = data.next_packet() {
+        // Long files take a while to decode, don't make cancelling wait for them
+        if cancel.is_cancelled() {
+            return Ok(None);
+        }
+
+        match scope {
+            HashScope::Prefix(bytes) if hashed >= bytes => {
+                // Nothing else needs the rest of the audio
+                if analyzer.is_none() && peaks.is_none() {
+                    break;
+                }
+            }
+            _ => {
+                hasher.write(&packet.data);
+                hashed += packet.data.len() as u64;
+            }
+        }
+
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        // A failed analysis doesn't stop the hashing
+        if let Some(Ok(inner)) = &mut analyzer {
+            if let Err(e) = inner.process(&packet) {
+                analyzer = Some(Err(e));
+            }
+        }
+
+        if let Some(Ok(inner)) = &mut peaks {
+            if let Err(e) = inner.process(&packet) {
+                peaks = Some(Err(e));
+            }
+        }
+    }
+
+    if let HashScope::Prefix(_) = scope {
+        hasher.write_u64(frames.unwrap_or(0));
+        hasher.write_u64(std::fs::metadata(path).into_diagnostic()?.len());
+    }
+
+    Ok(Some(Scan {
+        hash: hasher.finish(),
+        codec,
+        gain: analyzer.map(|v| v.map(Analyzer::finish)),
+        waveform: peaks.map(|v| v.and_then(PeakCollector::finish)),
+    }))
+}
+
+/// Name of the codec a file's audio is encoded with, without reading the rest of the file
+pub fn probe_codec(path: &Path) -> Result<Option<String>> {
+    let data = open_format(path)?;
+
+    Ok(data
+        .default_track()
+        .and_then(|v| codec_name(&v.codec_params)))
+}
+
+/// Short name of a codec, like "flac" or "mp3"
+fn codec_name(params: &CodecParameters) -> Option<String> {
+    get_codecs()
+        .get_codec(params.codec)
+        .map(|v| v.short_name.to_string())
+}
+
+pub fn open_format(path: &Path) -> Result<Box<dyn FormatReader>> {
+    let file = Box::new(File::open(path).into_diagnostic()?);
 
     let ext = path.extension().and_then(OsStr::to_str).unwrap_or("");
 
     let source = MediaSourceStream::new(file, Default::default());
-    let mut data = probe
+
+    Ok(get_probe()
         .format(
-            &Hint::new().with_extension(ext),
+            Hint::new().with_extension(ext),
             source,
             &Default::default(),
             &MetadataOptions {
@@ -223,13 +1139,77 @@ fn hash_file(path: &Path) -> Result<u64> {
             },
         )
         .into_diagnostic()?
-        .format;
+        .format)
+}
 
-    let mut adler = Adler32::new();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{test_util::memory_db, vfs::Entry};
+    use async_trait::async_trait;
+    use sea_orm::PaginatorTrait;
+    use std::collections::HashMap;
+    use symphonia::core::io::MediaSource;
 
-    while let Ok(packet) = data.next_packet() {
-        adler.write(&packet.data);
+    /// A source whose files can't be listed
+    struct Unreachable;
+
+    #[async_trait]
+    impl Provider for Unreachable {
+        async fn list(&self) -> Result<Vec<Entry>> {
+            Err(miette::miette!("The source is unreachable"))
+        }
+
+        fn open(&self, _entry: &Entry) -> Result<Box<dyn MediaSource>> {
+            unreachable!()
+        }
+
+        fn root(&self) -> String {
+            String::new()
+        }
     }
 
-    Ok(adler.finish())
+    #[tokio::test]
+    async fn failed_purge_is_rolled_back() {
+        let db = memory_db().await;
+
+        let source = Source {
+            id: 1,
+            name: "Music".into(),
+            source: SourceKind::Local {
+                path: "/nonexistent".into(),
+            },
+            proxy: None,
+            headers: HashMap::new(),
+            token: None,
+            tls: Default::default(),
+            settings: Default::default(),
+        };
+
+        library::Entity::insert(library::ActiveModel {
+            path: Set("Album".into()),
+            filename: Set("1.flac".into()),
+            source_id: Set(1),
+            hash: Set(1),
+            duration: Set(1000),
+            ..Default::default()
+        })
+        .exec(&db)
+        .await
+        .unwrap();
+
+        let indexed = index_files(
+            &source,
+            &Unreachable,
+            &IndexMode::Purge,
+            &Known::default(),
+            &Config::default(),
+            &CancellationToken::new(),
+            &db,
+        )
+        .await;
+
+        assert!(indexed.is_err());
+        assert_eq!(library::Entity::find().count(&db).await.unwrap(), 1);
+    }
 }
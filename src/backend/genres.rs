@@ -0,0 +1,186 @@
+use std::collections::HashSet;
+
+use super::model::{genres, library, song_genres};
+use miette::{IntoDiagnostic, Result};
+use sea_orm::{
+    sea_query::{Expr, Query},
+    ColumnTrait, ConnectionTrait, DatabaseConnection, DeriveColumn, EntityTrait, EnumIter,
+    IdenStatic, ModelTrait, QueryFilter, QueryOrder, QuerySelect, Select, Set, TransactionTrait,
+};
+
+/// Characters genres are commonly separated by in tags
+const SEPARATORS: [char; 4] = [',', ';', '/', '\0'];
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveColumn)]
+enum QueryAs {
+    SongHash,
+}
+
+/// Splits a raw genre tag into title cased genre names, without duplicates
+pub fn normalize_genres(raw: &str) -> Vec<String> {
+    let mut seen = HashSet::new();
+
+    raw.split(SEPARATORS)
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(title_case)
+        .filter(|v| seen.insert(v.to_lowercase()))
+        .collect()
+}
+
+/// Capitalizes every word, keeping words that are entirely uppercase (e.g. "EDM") intact
+fn title_case(genre: &str) -> String {
+    let mut result = String::with_capacity(genre.len());
+
+    for (i, word) in genre.split(' ').filter(|v| !v.is_empty()).enumerate() {
+        if i > 0 {
+            result.push(' ');
+        }
+
+        for (j, part) in word.split('-').enumerate() {
+            if j > 0 {
+                result.push('-');
+            }
+
+            if part.chars().all(|v| !v.is_lowercase()) {
+                result.push_str(part);
+                continue;
+            }
+
+            let mut chars = part.chars();
+            if let Some(first) = chars.next() {
+                result.extend(first.to_uppercase());
+                result.push_str(&chars.as_str().to_lowercase());
+            }
+        }
+    }
+
+    result
+}
+
+/// Replaces the genres a song is linked to with the ones in its raw genre tag.
+/// Either every link is replaced or none are.
+pub async fn link_song(hash: i64, raw: Option<&str>, db: &impl TransactionTrait) -> Result<()> {
+    let txn = db.begin().await.into_diagnostic()?;
+
+    song_genres::Entity::delete_many()
+        .filter(song_genres::Column::SongHash.eq(hash))
+        .exec(&txn)
+        .await
+        .into_diagnostic()?;
+
+    for name in raw.map(normalize_genres).unwrap_or_default() {
+        let genre_id = genre_id(&name, &txn).await?;
+
+        song_genres::Entity::insert(song_genres::ActiveModel {
+            song_hash: Set(hash),
+            genre_id: Set(genre_id),
+            ..Default::default()
+        })
+        .exec(&txn)
+        .await
+        .into_diagnostic()?;
+    }
+
+    txn.commit().await.into_diagnostic()
+}
+
+/// Finds a genre by name, creating it if it doesn't exist yet
+async fn genre_id(name: &str, db: &impl ConnectionTrait) -> Result<i32> {
+    // Names are compared case insensitively by the database
+    if let Some(genre) = genres::Entity::find()
+        .filter(genres::Column::Name.eq(name))
+        .one(db)
+        .await
+        .into_diagnostic()?
+    {
+        return Ok(genre.id);
+    }
+
+    let inserted = genres::Entity::insert(genres::ActiveModel {
+        name: Set(name.to_string()),
+        ..Default::default()
+    })
+    .exec(db)
+    .await
+    .into_diagnostic()?;
+
+    Ok(inserted.last_insert_id)
+}
+
+/// Links every song that has a genre tag but no linked genres, and removes unused genres
+pub async fn link_unlinked(db: &DatabaseConnection) -> Result<()> {
+    let linked: HashSet<i64> = song_genres::Entity::find()
+        .select_only()
+        .column_as(song_genres::Column::SongHash, QueryAs::SongHash)
+        .group_by(song_genres::Column::SongHash)
+        .into_values::<_, QueryAs>()
+        .all(db)
+        .await
+        .into_diagnostic()?
+        .into_iter()
+        .collect();
+
+    let songs = library::Entity::find()
+        .filter(library::Column::Genres.is_not_null())
+        .all(db)
+        .await
+        .into_diagnostic()?;
+
+    for song in songs.iter().filter(|v| !linked.contains(&v.hash)) {
+        link_song(song.hash, song.genres.as_deref(), db).await?;
+    }
+
+    genres::Entity::delete_many()
+        .filter(
+            genres::Column::Id.not_in_subquery(
+                Query::select()
+                    .column(song_genres::Column::GenreId)
+                    .from(song_genres::Entity)
+                    .to_owned(),
+            ),
+        )
+        .exec(db)
+        .await
+        .into_diagnostic()?;
+
+    Ok(())
+}
+
+/// Every genre, sorted by name
+pub async fn list_genres(db: &DatabaseConnection) -> Result<Vec<genres::Model>> {
+    genres::Entity::find()
+        .order_by_asc(genres::Column::Name)
+        .all(db)
+        .await
+        .into_diagnostic()
+}
+
+/// Songs that have a genre
+pub async fn songs_by_genre(
+    genre: &genres::Model,
+    db: &DatabaseConnection,
+) -> Result<Vec<library::Model>> {
+    genre
+        .find_related(library::Entity)
+        .order_by_asc(library::Column::Artist)
+        .order_by_asc(library::Column::Album)
+        .order_by_asc(library::Column::Disc)
+        .order_by_asc(library::Column::Track)
+        .all(db)
+        .await
+        .into_diagnostic()
+}
+
+/// Restricts a library query to songs with a genre
+pub fn filter_by_genre(query: Select<library::Entity>, genre_id: i32) -> Select<library::Entity> {
+    query.filter(
+        library::Column::Hash.in_subquery(
+            Query::select()
+                .column(song_genres::Column::SongHash)
+                .from(song_genres::Entity)
+                .and_where(Expr::col(song_genres::Column::GenreId).eq(genre_id))
+                .to_owned(),
+        ),
+    )
+}
@@ -0,0 +1,207 @@
+use std::collections::{HashMap, HashSet};
+
+use super::{
+    errors::{report, Category, Severity},
+    model::{legacy_hashes, library},
+};
+use miette::{IntoDiagnostic, Result};
+use sea_orm::{
+    ColumnTrait, ConnectionTrait, DatabaseTransaction, EntityTrait, QueryFilter, Statement,
+};
+use sea_query::Expr;
+use twox_hash::XxHash64;
+
+/// Tables that refer to songs by their hash
+const REFERENCES: [&str; 9] = [
+    "playlist_entries",
+    "song_genres",
+    "song_artists",
+    "history",
+    "downloads",
+    "remote_files",
+    "song_stats",
+    "tag_edits",
+    "resume_points",
+];
+
+/// How much of a song's audio its hash covers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashScope {
+    /// Every packet of the audio track
+    Full,
+    /// The packets in the first this many bytes, followed by the number of frames of audio
+    /// and the size of the file. That's still enough to tell songs apart,
+    /// without reading all of long files.
+    Prefix(u64),
+}
+
+impl HashScope {
+    /// The scope's value in the `hash_bytes` column of songs. Hashes of different scopes
+    /// never match for the same file, so songs are hashed again once their scope changes.
+    pub fn bytes(self) -> Option<i64> {
+        match self {
+            HashScope::Full => None,
+            HashScope::Prefix(bytes) => Some(bytes as i64),
+        }
+    }
+}
+
+/// Hasher for the audio packets of a song. The hash is stored with its bits as they are,
+/// as a signed number, since that's the only kind of integer SQLite has.
+pub fn audio_hasher() -> XxHash64 {
+    XxHash64::with_seed(0)
+}
+
+/// Songs of a source still stored under the Adler-32 hash of their audio,
+/// by directory and file name
+pub async fn legacy_songs(
+    source_id: u8,
+    db: &impl ConnectionTrait,
+) -> Result<HashMap<(String, String), i64>> {
+    let legacy: HashSet<i64> = legacy_hashes::Entity::find()
+        .all(db)
+        .await
+        .into_diagnostic()?
+        .into_iter()
+        .map(|v| v.song_hash)
+        .collect();
+
+    if legacy.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    Ok(library::Entity::find()
+        .filter(library::Column::SourceId.eq(source_id))
+        .all(db)
+        .await
+        .into_diagnostic()?
+        .into_iter()
+        .filter(|v| legacy.contains(&v.hash))
+        .map(|v| ((v.path, v.filename), v.hash))
+        .collect())
+}
+
+/// Moves a song from its old hash to a new one, along with everything that refers to it,
+/// like its plays and playlist entries. If the same file is already stored under the new hash,
+/// the old row is dropped for it, and only what doesn't clash with it is moved.
+///
+/// Returns false without moving anything if the new hash belongs to a different file,
+/// which would otherwise lose the song and its history.
+pub async fn remap(old: i64, new: i64, txn: &DatabaseTransaction) -> Result<bool> {
+    if old != new {
+        let song = library::Entity::find()
+            .filter(library::Column::Hash.eq(old))
+            .one(txn)
+            .await
+            .into_diagnostic()?;
+        let taken = library::Entity::find()
+            .filter(library::Column::Hash.eq(new))
+            .one(txn)
+            .await
+            .into_diagnostic()?;
+
+        if let (Some(song), Some(other)) = (&song, &taken) {
+            if (song.source_id, &song.path, &song.filename)
+                != (other.source_id, &other.path, &other.filename)
+            {
+                report(
+                    Severity::Warning,
+                    Category::Indexing,
+                    Some(format!("{}/{}", song.path, song.filename)),
+                    format!(
+                        "Kept the old hash of {}/{}, its new one belongs to {}/{} in source {}",
+                        song.path, song.filename, other.path, other.filename, other.source_id
+                    ),
+                );
+                return Ok(false);
+            }
+        }
+
+        // The song and the rows referring to it can't change all at once,
+        // so foreign keys are only checked when the transaction is committed
+        execute("PRAGMA defer_foreign_keys = ON".to_string(), vec![], txn).await?;
+
+        for table in REFERENCES {
+            execute(
+                format!("UPDATE OR IGNORE {table} SET song_hash = ? WHERE song_hash = ?"),
+                vec![new.into(), old.into()],
+                txn,
+            )
+            .await?;
+        }
+
+        if taken.is_some() {
+            library::Entity::delete_many()
+                .filter(library::Column::Hash.eq(old))
+                .exec(txn)
+                .await
+                .into_diagnostic()?;
+        } else {
+            library::Entity::update_many()
+                .col_expr(library::Column::Hash, Expr::value(new))
+                .filter(library::Column::Hash.eq(old))
+                .exec(txn)
+                .await
+                .into_diagnostic()?;
+        }
+    }
+
+    legacy_hashes::Entity::delete_many()
+        .filter(legacy_hashes::Column::SongHash.eq(old))
+        .exec(txn)
+        .await
+        .into_diagnostic()?;
+
+    Ok(true)
+}
+
+async fn execute(
+    sql: String,
+    values: Vec<sea_orm::Value>,
+    txn: &DatabaseTransaction,
+) -> Result<()> {
+    txn.execute(Statement::from_sql_and_values(
+        txn.get_database_backend(),
+        &sql,
+        values,
+    ))
+    .await
+    .into_diagnostic()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::test_util::{memory_db, song};
+    use sea_orm::{ActiveValue::NotSet, IntoActiveModel, QueryOrder, TransactionTrait};
+
+    #[tokio::test]
+    async fn remap_keeps_other_files() {
+        let db = memory_db().await;
+
+        for hash in [1, 2] {
+            let mut song = song(hash).into_active_model();
+            song.id = NotSet;
+            library::Entity::insert(song).exec(&db).await.unwrap();
+        }
+
+        let txn = db.begin().await.unwrap();
+        // 2 is another file, so 1 can't take its hash
+        assert!(!remap(1, 2, &txn).await.unwrap());
+        assert!(remap(1, 3, &txn).await.unwrap());
+        txn.commit().await.unwrap();
+
+        let songs: Vec<(i64, String)> = library::Entity::find()
+            .order_by_asc(library::Column::Hash)
+            .all(&db)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|v| (v.hash, v.filename))
+            .collect();
+
+        assert_eq!(songs, [(2, "2.flac".into()), (3, "1.flac".into())]);
+    }
+}
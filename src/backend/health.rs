@@ -0,0 +1,282 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+use super::{extra_tags::extra_tag, model::library};
+use miette::{IntoDiagnostic, Result};
+use sea_orm::{DatabaseConnection, EntityTrait};
+
+/// Tags the number of tracks on a disc is stored in, depending on the format
+const TRACK_TOTAL: [&str; 2] = ["TRACKTOTAL", "TOTALTRACKS"];
+/// Most tracks a disc is reported to be missing. Past that the track total or number is more
+/// likely wrong than the tracks missing, e.g. a year in the track number.
+const MAX_MISSING: usize = 100;
+
+/// Something wrong with the tags of an album
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// Track numbers between 1 and the disc's track total, or its highest track number if no
+    /// track says how many there are, that no song has. Not checked past `MAX_MISSING`.
+    MissingTracks {
+        disc: i32,
+        tracks: Vec<i32>,
+    },
+    /// Several songs with the same disc and track number
+    DuplicateTracks {
+        disc: i32,
+        track: i32,
+    },
+    /// Songs without a track number
+    Unnumbered(Vec<String>),
+    MixedAlbumArtists(Vec<String>),
+    MixedYears(Vec<i32>),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn list(items: &[impl ToString]) -> String {
+            items
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
+
+        match self {
+            Problem::MissingTracks { disc, tracks } => {
+                write!(f, "disc {disc} is missing tracks {}", list(tracks))
+            }
+            Problem::DuplicateTracks { disc, track } => {
+                write!(f, "disc {disc} has track {track} more than once")
+            }
+            Problem::Unnumbered(songs) => write!(f, "no track number on {}", songs.join(", ")),
+            Problem::MixedAlbumArtists(artists) => {
+                write!(f, "mixed album artists {}", artists.join(", "))
+            }
+            Problem::MixedYears(years) => write!(f, "mixed years {}", list(years)),
+        }
+    }
+}
+
+/// An album with problems in its tags
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlbumHealth {
+    pub source_id: i32,
+    /// Directory the album is in
+    pub path: String,
+    pub album: String,
+    pub problems: Vec<Problem>,
+}
+
+/// Goes through every album looking for missing tracks and inconsistent tags. Songs are
+/// grouped into albums by their album tag and directory, so each copy of an album in another
+/// source or format is checked on its own. Songs without album tags aren't checked.
+/// Only albums with problems are returned, sorted by source and directory.
+pub async fn album_health(db: &DatabaseConnection) -> Result<Vec<AlbumHealth>> {
+    let songs = library::Entity::find().all(db).await.into_diagnostic()?;
+
+    let mut albums: BTreeMap<(i32, &str, &str), Vec<&library::Model>> = BTreeMap::new();
+    for song in &songs {
+        if let Some(album) = &song.album {
+            albums
+                .entry((song.source_id, &song.path, album))
+                .or_default()
+                .push(song);
+        }
+    }
+
+    Ok(albums
+        .into_iter()
+        .filter_map(|((source_id, path, album), songs)| {
+            let problems = problems(&songs);
+
+            (!problems.is_empty()).then(|| AlbumHealth {
+                source_id,
+                path: path.to_string(),
+                album: album.to_string(),
+                problems,
+            })
+        })
+        .collect())
+}
+
+fn problems(songs: &[&library::Model]) -> Vec<Problem> {
+    let mut problems = vec![];
+
+    // Track numbers of each disc, and the track totals tagged on it
+    let mut discs: BTreeMap<i32, (Vec<i32>, Option<i32>)> = BTreeMap::new();
+    let mut unnumbered = vec![];
+
+    for song in songs {
+        let disc = song.disc.unwrap_or(1);
+
+        let Some(track) = song.track else {
+            unnumbered.push(song.name.clone().unwrap_or_else(|| song.filename.clone()));
+            continue;
+        };
+
+        let (tracks, total) = discs.entry(disc).or_default();
+        tracks.push(track);
+
+        let tagged = TRACK_TOTAL
+            .iter()
+            .find_map(|key| extra_tag(song, key)?.trim().parse().ok());
+        *total = (*total).max(tagged);
+    }
+
+    for (disc, (mut tracks, total)) in discs {
+        tracks.sort_unstable();
+
+        for pair in tracks.windows(2).filter(|v| v[0] == v[1]) {
+            let duplicate = Problem::DuplicateTracks {
+                disc,
+                track: pair[0],
+            };
+
+            if problems.last() != Some(&duplicate) {
+                problems.push(duplicate);
+            }
+        }
+
+        let last = total
+            .into_iter()
+            .chain(tracks.last().copied())
+            .max()
+            .unwrap_or(0);
+        if usize::try_from(last).unwrap_or(0) > tracks.len() + MAX_MISSING {
+            continue;
+        }
+
+        let missing: Vec<i32> = (1..=last)
+            .filter(|v| tracks.binary_search(v).is_err())
+            .collect();
+
+        if !missing.is_empty() {
+            problems.push(Problem::MissingTracks {
+                disc,
+                tracks: missing,
+            });
+        }
+    }
+
+    // A single song without a number is a single, not a gap
+    if !unnumbered.is_empty() && songs.len() > 1 {
+        problems.push(Problem::Unnumbered(unnumbered));
+    }
+
+    let album_artists: BTreeSet<&String> = songs
+        .iter()
+        .filter_map(|v| v.album_artist.as_ref())
+        .collect();
+    if album_artists.len() > 1 {
+        problems.push(Problem::MixedAlbumArtists(
+            album_artists.into_iter().cloned().collect(),
+        ));
+    }
+
+    let years: BTreeSet<i32> = songs.iter().filter_map(|v| v.year).collect();
+    if years.len() > 1 {
+        problems.push(Problem::MixedYears(years.into_iter().collect()));
+    }
+
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::test_util;
+    use sea_orm::entity::prelude::Json;
+
+    fn song(disc: Option<i32>, track: Option<i32>) -> library::Model {
+        library::Model {
+            filename: format!("{}-{}.flac", disc.unwrap_or(0), track.unwrap_or(0)),
+            album: Some("Album".into()),
+            track,
+            disc,
+            ..test_util::song(0)
+        }
+    }
+
+    fn total(key: &str, value: &str) -> Option<Json> {
+        Some([(key, value)].into_iter().collect())
+    }
+
+    fn check(songs: &[library::Model]) -> Vec<Problem> {
+        problems(&songs.iter().collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn complete_album_has_no_problems() {
+        let songs = [
+            song(None, Some(1)),
+            song(None, Some(2)),
+            song(None, Some(3)),
+        ];
+
+        assert_eq!(check(&songs), []);
+    }
+
+    #[test]
+    fn gaps_and_duplicates_are_found_per_disc() {
+        let songs = [
+            song(Some(1), Some(1)),
+            song(Some(1), Some(3)),
+            song(Some(2), Some(1)),
+            song(Some(2), Some(1)),
+        ];
+
+        assert_eq!(
+            check(&songs),
+            [
+                Problem::MissingTracks {
+                    disc: 1,
+                    tracks: vec![2]
+                },
+                Problem::DuplicateTracks { disc: 2, track: 1 },
+            ]
+        );
+    }
+
+    #[test]
+    fn track_total_counts_missing_tracks_at_the_end() {
+        let songs = [
+            song(None, Some(1)),
+            library::Model {
+                extra_tags: total("TrackTotal", "4"),
+                ..song(None, Some(2))
+            },
+        ];
+
+        assert_eq!(
+            check(&songs),
+            [Problem::MissingTracks {
+                disc: 1,
+                tracks: vec![3, 4]
+            }]
+        );
+    }
+
+    #[test]
+    fn implausible_track_numbers_are_not_gaps() {
+        let songs = [
+            song(None, Some(1)),
+            library::Model {
+                extra_tags: total("TRACKTOTAL", "2147483647"),
+                ..song(None, Some(2019))
+            },
+        ];
+
+        assert_eq!(check(&songs), []);
+    }
+
+    #[test]
+    fn unnumbered_songs_are_listed_unless_alone() {
+        assert_eq!(check(&[song(None, None)]), []);
+        assert_eq!(
+            check(&[song(None, None), song(None, Some(1))]),
+            [Problem::Unnumbered(vec!["0-0.flac".into()])]
+        );
+    }
+}
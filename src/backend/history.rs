@@ -0,0 +1,305 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+    diagnostics::record_rows,
+    events::{subscribe, Event},
+    model::{history, library},
+};
+use miette::{IntoDiagnostic, Result};
+use paris::{info, warn};
+use sea_orm::{
+    sea_query::Expr, ColumnTrait, Condition, ConnectionTrait, DatabaseConnection, EntityTrait,
+    QueryFilter, QueryOrder, Set, TransactionTrait,
+};
+use tokio::{sync::broadcast::error::RecvError, task::JoinHandle};
+
+/// Adds a play of a song to the history, and updates the song's play times
+pub async fn record(hash: i64, finished: bool, db: &DatabaseConnection) -> Result<()> {
+    let played_at = unix_time(SystemTime::now());
+
+    let txn = db.begin().await.into_diagnostic()?;
+
+    history::Entity::insert(history::ActiveModel {
+        song_hash: Set(hash),
+        played_at: Set(played_at),
+        finished: Set(finished),
+        ..Default::default()
+    })
+    .exec(&txn)
+    .await
+    .into_diagnostic()?;
+
+    library::Entity::update_many()
+        .col_expr(library::Column::LastPlayed, Expr::value(played_at))
+        .filter(library::Column::Hash.eq(hash))
+        .exec(&txn)
+        .await
+        .into_diagnostic()?;
+
+    library::Entity::update_many()
+        .col_expr(library::Column::FirstPlayed, Expr::value(played_at))
+        .filter(library::Column::Hash.eq(hash))
+        .filter(library::Column::FirstPlayed.is_null())
+        .exec(&txn)
+        .await
+        .into_diagnostic()?;
+
+    txn.commit().await.into_diagnostic()
+}
+
+/// Records every song that stops playing, until the event bus closes
+pub fn track(db: DatabaseConnection) -> JoinHandle<()> {
+    let mut events = subscribe();
+
+    tokio::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(Event::TrackEnded { hash, finished }) => {
+                    if let Err(e) = record(hash, finished, &db).await {
+                        warn!("Couldn't add song to history: {e}");
+                    }
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("History missed {skipped} events");
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+/// Removes specific entries, e.g. ones that were played by accident
+pub async fn delete_entries(ids: &[i32], db: &DatabaseConnection) -> Result<u64> {
+    let txn = db.begin().await.into_diagnostic()?;
+
+    let hashes = affected_songs(
+        Condition::all().add(history::Column::Id.is_in(ids.to_vec())),
+        &txn,
+    )
+    .await?;
+
+    let result = history::Entity::delete_many()
+        .filter(history::Column::Id.is_in(ids.to_vec()))
+        .exec(&txn)
+        .await
+        .into_diagnostic()?;
+
+    refresh_play_times(&hashes, &txn).await?;
+    txn.commit().await.into_diagnostic()?;
+
+    Ok(result.rows_affected)
+}
+
+/// Removes every entry played within a time range, like a queue left repeating overnight.
+/// With `hash` set, only that song's entries are removed.
+pub async fn delete_between(
+    from: SystemTime,
+    to: SystemTime,
+    hash: Option<i64>,
+    db: &DatabaseConnection,
+) -> Result<u64> {
+    let mut condition =
+        Condition::all().add(history::Column::PlayedAt.between(unix_time(from), unix_time(to)));
+
+    if let Some(hash) = hash {
+        condition = condition.add(history::Column::SongHash.eq(hash));
+    }
+
+    let txn = db.begin().await.into_diagnostic()?;
+
+    let hashes = affected_songs(condition.clone(), &txn).await?;
+
+    let result = history::Entity::delete_many()
+        .filter(condition)
+        .exec(&txn)
+        .await
+        .into_diagnostic()?;
+
+    refresh_play_times(&hashes, &txn).await?;
+    txn.commit().await.into_diagnostic()?;
+
+    Ok(result.rows_affected)
+}
+
+/// Adds deleted entries back as they were, e.g. to undo `delete_entries`
+pub async fn restore_entries(entries: &[history::Model], db: &DatabaseConnection) -> Result<()> {
+    if entries.is_empty() {
+        return Ok(());
+    }
+
+    let txn = db.begin().await.into_diagnostic()?;
+
+    // Keep the number of bound parameters low
+    for batch in entries.chunks(100) {
+        history::Entity::insert_many(batch.iter().map(|v| history::ActiveModel {
+            id: Set(v.id),
+            song_hash: Set(v.song_hash),
+            played_at: Set(v.played_at),
+            finished: Set(v.finished),
+        }))
+        .exec(&txn)
+        .await
+        .into_diagnostic()?;
+    }
+
+    let hashes: HashSet<i64> = entries.iter().map(|v| v.song_hash).collect();
+    refresh_play_times(&hashes.into_iter().collect::<Vec<_>>(), &txn).await?;
+
+    txn.commit().await.into_diagnostic()
+}
+
+/// Merges plays of the same song that are less than `window` apart into the first one,
+/// so a song recorded twice or restarted a few times only counts once.
+/// The merged entry counts as finished if any of the plays was.
+pub async fn merge_duplicates(window: Duration, db: &DatabaseConnection) -> Result<u64> {
+    let started = Instant::now();
+    let entries = history::Entity::find()
+        .order_by_asc(history::Column::SongHash)
+        .order_by_asc(history::Column::PlayedAt)
+        .all(db)
+        .await
+        .into_diagnostic()?;
+
+    let window = window.as_secs() as i64;
+
+    let mut duplicates = vec![];
+    let mut finished = vec![];
+    let mut kept: Option<&history::Model> = None;
+    let mut kept_finished = false;
+
+    for entry in &entries {
+        match kept {
+            Some(first)
+                if first.song_hash == entry.song_hash
+                    && entry.played_at - first.played_at < window =>
+            {
+                duplicates.push(entry.id);
+                kept_finished |= entry.finished;
+            }
+            _ => {
+                if let Some(first) = kept {
+                    if kept_finished && !first.finished {
+                        finished.push(first.id);
+                    }
+                }
+
+                kept = Some(entry);
+                kept_finished = entry.finished;
+            }
+        }
+    }
+
+    if let Some(first) = kept {
+        if kept_finished && !first.finished {
+            finished.push(first.id);
+        }
+    }
+
+    if !finished.is_empty() {
+        history::Entity::update_many()
+            .col_expr(history::Column::Finished, Expr::value(true))
+            .filter(history::Column::Id.is_in(finished))
+            .exec(db)
+            .await
+            .into_diagnostic()?;
+    }
+
+    let mut merged = 0;
+
+    // Keep the number of bound parameters low
+    for batch in duplicates.chunks(100) {
+        merged += delete_entries(batch, db).await?;
+    }
+
+    if merged > 0 {
+        info!("Merged {merged} duplicate history entries");
+    }
+
+    record_rows("history merge", merged, started.elapsed());
+
+    Ok(merged)
+}
+
+/// Hashes of the songs with entries matching a condition
+async fn affected_songs(condition: Condition, db: &impl ConnectionTrait) -> Result<Vec<i64>> {
+    let hashes: HashSet<i64> = history::Entity::find()
+        .filter(condition)
+        .all(db)
+        .await
+        .into_diagnostic()?
+        .into_iter()
+        .map(|v| v.song_hash)
+        .collect();
+
+    Ok(hashes.into_iter().collect())
+}
+
+/// Recomputes the play times of songs from what's left of their history
+async fn refresh_play_times(hashes: &[i64], db: &impl ConnectionTrait) -> Result<()> {
+    // Keep the number of bound parameters low
+    for batch in hashes.chunks(100) {
+        let mut times: HashMap<i64, (i64, i64)> = HashMap::new();
+
+        for entry in history::Entity::find()
+            .filter(history::Column::SongHash.is_in(batch.to_vec()))
+            .all(db)
+            .await
+            .into_diagnostic()?
+        {
+            let (first, last) = times
+                .entry(entry.song_hash)
+                .or_insert((entry.played_at, entry.played_at));
+
+            *first = (*first).min(entry.played_at);
+            *last = (*last).max(entry.played_at);
+        }
+
+        for hash in batch {
+            let (first, last) = times.get(hash).copied().unzip();
+
+            library::Entity::update_many()
+                .col_expr(library::Column::FirstPlayed, Expr::value(first))
+                .col_expr(library::Column::LastPlayed, Expr::value(last))
+                .filter(library::Column::Hash.eq(*hash))
+                .exec(db)
+                .await
+                .into_diagnostic()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes entries older than the retention period.
+/// Songs keep their play times, so long forgotten songs can still be found.
+pub async fn prune(retention_days: u64, db: &DatabaseConnection) -> Result<u64> {
+    let started = Instant::now();
+    let cutoff = SystemTime::now() - Duration::from_secs(retention_days * 24 * 60 * 60);
+
+    let result = history::Entity::delete_many()
+        .filter(history::Column::PlayedAt.lt(unix_time(cutoff)))
+        .exec(db)
+        .await
+        .into_diagnostic()?;
+
+    if result.rows_affected > 0 {
+        info!(
+            "Removed {} history entries older than {retention_days} days",
+            result.rows_affected
+        );
+    }
+
+    record_rows("history pruning", result.rows_affected, started.elapsed());
+
+    Ok(result.rows_affected)
+}
+
+fn unix_time(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|v| v.as_secs() as i64)
+        .unwrap_or(0)
+}
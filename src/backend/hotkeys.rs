@@ -0,0 +1,128 @@
+use std::{collections::HashMap, str::FromStr};
+
+use super::{
+    config::{Config, HotkeyBindings},
+    playback::MediaCommand,
+};
+use global_hotkey::{hotkey::HotKey, GlobalHotKeyEvent, GlobalHotKeyManager};
+use miette::{miette, IntoDiagnostic, Result};
+use paris::warn;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+/// Volume change of one press of the volume shortcuts
+const VOLUME_STEP: f32 = 0.05;
+/// Speed change of one press of the speed shortcuts, in percent
+const SPEED_STEP: i16 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Action {
+    Command(MediaCommand),
+    /// Change the volume by this much
+    Volume(f32),
+}
+
+/// Registers the keyboard shortcuts set in the config with the OS, so they work while
+/// another window is focused. Playback shortcuts are sent to the receiver returned by `start`,
+/// like media keys are, and volume shortcuts change the volume in the config.
+/// On Windows and macOS, shortcuts are only delivered while the main thread runs an event loop.
+/// Shortcuts are unregistered when this is dropped.
+pub struct Hotkeys {
+    manager: GlobalHotKeyManager,
+    registered: Vec<HotKey>,
+}
+
+impl Hotkeys {
+    pub fn start(bindings: &HotkeyBindings) -> Result<(Self, UnboundedReceiver<MediaCommand>)> {
+        let manager = GlobalHotKeyManager::new()
+            .map_err(|e| miette!("Couldn't set up keyboard shortcuts: {}", e))?;
+
+        let actions = [
+            (&bindings.play_pause, Action::Command(MediaCommand::Toggle)),
+            (&bindings.next, Action::Command(MediaCommand::Next)),
+            (&bindings.previous, Action::Command(MediaCommand::Previous)),
+            (&bindings.volume_up, Action::Volume(VOLUME_STEP)),
+            (&bindings.volume_down, Action::Volume(-VOLUME_STEP)),
+            (
+                &bindings.speed_up,
+                Action::Command(MediaCommand::ChangeSpeed(SPEED_STEP)),
+            ),
+            (
+                &bindings.speed_down,
+                Action::Command(MediaCommand::ChangeSpeed(-SPEED_STEP)),
+            ),
+        ];
+
+        let mut registered = vec![];
+        let mut by_id = HashMap::new();
+
+        for (binding, action) in actions {
+            let Some(binding) = binding else {
+                continue;
+            };
+
+            let hotkey = HotKey::from_str(binding)
+                .map_err(|e| miette!("Invalid keyboard shortcut {}: {}", binding, e))?;
+
+            // A shortcut taken by another app shouldn't disable the rest
+            if let Err(e) = manager.register(hotkey) {
+                warn!("Couldn't register keyboard shortcut {binding}: {e}");
+                continue;
+            }
+
+            registered.push(hotkey);
+            by_id.insert(hotkey.id(), action);
+        }
+
+        let (sender, receiver) = unbounded_channel();
+
+        std::thread::Builder::new()
+            .name("eleanor-hotkeys".into())
+            .spawn(move || listen(by_id, sender))
+            .into_diagnostic()?;
+
+        Ok((
+            Hotkeys {
+                manager,
+                registered,
+            },
+            receiver,
+        ))
+    }
+}
+
+impl Drop for Hotkeys {
+    fn drop(&mut self) {
+        if let Err(e) = self.manager.unregister_all(&self.registered) {
+            warn!("Couldn't unregister keyboard shortcuts: {e}");
+        }
+    }
+}
+
+/// Handles shortcut presses until the receiver is dropped
+fn listen(actions: HashMap<u32, Action>, sender: UnboundedSender<MediaCommand>) {
+    let events = GlobalHotKeyEvent::receiver();
+
+    while let Ok(event) = events.recv() {
+        match actions.get(&event.id) {
+            Some(Action::Command(command)) => {
+                if sender.send(*command).is_err() {
+                    return;
+                }
+            }
+            Some(Action::Volume(change)) => {
+                if let Err(e) = change_volume(*change) {
+                    warn!("Couldn't change the volume: {e}");
+                }
+            }
+            None => {}
+        }
+    }
+}
+
+/// The player picks the new volume up from the config, like any other config change
+fn change_volume(change: f32) -> Result<()> {
+    let mut config = Config::read_config()?;
+    config.volume = (config.volume + change).clamp(0.0, 1.0);
+
+    Config::write_config(&config)
+}
@@ -0,0 +1,547 @@
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use super::{
+    browse::{albums, Album},
+    config::PartyMode,
+    events::{publish, Event},
+    model::{library, playlist_entries, playlists},
+    party::{Party, Refusal, SongRequest},
+    playback::{MediaCommand, PlaybackState},
+    position,
+    queue::{durations, eta, Eta, Queue, Repeat, ShuffleMode},
+    shutdown,
+    stats::{encode_stats, local_stats, STATS_MEDIA_TYPE},
+    waveform::{seek_preview, SeekPreview},
+};
+use axum::{
+    extract::{ConnectInfo, Path, Query},
+    http::{
+        header::{ACCEPT, CONTENT_TYPE},
+        HeaderMap, Method, Request, StatusCode,
+    },
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Extension, Json, Router, Server,
+};
+use miette::{IntoDiagnostic, Report, Result};
+use paris::{success, warn};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        watch,
+    },
+    task::JoinHandle,
+};
+
+/// The queue and what's playing, as reported by `GET /queue`
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct PlayerState {
+    pub state: PlaybackState,
+    /// Tracks in the order they will be played
+    pub tracks: Vec<i64>,
+    /// Index of the current track in `tracks`
+    pub position: Option<usize>,
+    /// How far into the current track playback is, in milliseconds
+    pub progress_ms: u64,
+    pub shuffle: bool,
+    pub shuffle_mode: ShuffleMode,
+    pub repeat: Repeat,
+    pub radio: bool,
+    /// When the upcoming tracks start, as of the moment the state was requested
+    #[serde(flatten)]
+    pub eta: Eta,
+}
+
+/// The last reported state, along with when it was reported
+#[derive(Debug, Clone)]
+struct Reported {
+    player: PlayerState,
+    at: Instant,
+}
+
+/// Query of `GET /songs/{hash}/preview`
+#[derive(Deserialize, Debug)]
+struct PreviewAt {
+    position_ms: u64,
+}
+
+/// Body of `POST /seek`
+#[derive(Deserialize, Debug)]
+struct Seek {
+    /// Jump to this position
+    position_ms: Option<u64>,
+    /// Move forward, or backward if negative
+    by_ms: Option<i64>,
+}
+
+/// Body of `POST /shuffle`
+#[derive(Deserialize, Debug)]
+struct Shuffle {
+    /// What shuffle keeps together, shuffle is turned off without one
+    mode: Option<ShuffleMode>,
+}
+
+/// Body of `POST /party/requests`
+#[derive(Deserialize, Debug)]
+struct Requested {
+    hash: i64,
+}
+
+/// Header guests send the party PIN in
+const PIN_HEADER: &str = "x-party-pin";
+
+/// Body of `POST /speed`, in percent
+#[derive(Deserialize, Debug)]
+struct Speed {
+    /// Play at this speed, e.g. 150 for 1.5x
+    percent: Option<u16>,
+    /// Speed up, or slow down if negative
+    by_percent: Option<i16>,
+}
+
+#[derive(Clone)]
+struct Shared {
+    db: DatabaseConnection,
+    commands: UnboundedSender<MediaCommand>,
+    player: watch::Receiver<Reported>,
+    party: Arc<Mutex<Party>>,
+}
+
+/// A local HTTP server for web remotes and scripts. It only listens on localhost,
+/// unless party mode is on.
+///
+/// Read endpoints return JSON: `GET /songs`, `/songs/{hash}`, `/songs/{hash}/preview?position_ms=`
+/// for the seek bar, `/albums`, `/playlists`, `/playlists/{id}` with the hashes of its songs,
+/// `/queue` with the time until each upcoming track starts, and `/stats`.
+/// `POST /play`, `/pause`, `/toggle`, `/stop`, `/next`, `/previous`, `/seek`, `/speed`
+/// and `/shuffle` send commands to the receiver returned by `start`, the player reports back
+/// through `update`.
+///
+/// In party mode it listens on every interface. Other devices can then use the read endpoints
+/// and request songs with `POST /party/requests`, sending the PIN in the `X-Party-Pin` header.
+/// Requests are queued right away, or wait for `approve` if approval is on.
+pub struct HttpApi {
+    player: watch::Sender<Reported>,
+    commands: UnboundedSender<MediaCommand>,
+    party: Arc<Mutex<Party>>,
+    handle: JoinHandle<()>,
+}
+
+impl HttpApi {
+    /// Starts serving on `port` until shutdown is requested or the API is dropped
+    pub async fn start(
+        port: u16,
+        party: PartyMode,
+        db: DatabaseConnection,
+    ) -> Result<(Self, UnboundedReceiver<MediaCommand>)> {
+        let (commands, receiver) = unbounded_channel();
+        let (player, player_receiver) = watch::channel(Reported {
+            player: PlayerState::default(),
+            at: Instant::now(),
+        });
+
+        let host = if party.enabled {
+            Ipv4Addr::UNSPECIFIED
+        } else {
+            Ipv4Addr::LOCALHOST
+        };
+        let party = Arc::new(Mutex::new(Party::new(party)));
+
+        let shared = Shared {
+            db,
+            commands: commands.clone(),
+            player: player_receiver,
+            party: party.clone(),
+        };
+
+        let app = Router::new()
+            .route("/songs", get(songs))
+            .route("/songs/:hash", get(song))
+            .route("/songs/:hash/preview", get(preview))
+            .route("/albums", get(album_list))
+            .route("/playlists", get(playlist_list))
+            .route("/playlists/:id", get(playlist))
+            .route("/queue", get(queue))
+            .route("/stats", get(stats))
+            .route("/play", post(|v| send(v, MediaCommand::Play)))
+            .route("/pause", post(|v| send(v, MediaCommand::Pause)))
+            .route("/toggle", post(|v| send(v, MediaCommand::Toggle)))
+            .route("/stop", post(|v| send(v, MediaCommand::Stop)))
+            .route("/next", post(|v| send(v, MediaCommand::Next)))
+            .route("/previous", post(|v| send(v, MediaCommand::Previous)))
+            .route("/seek", post(seek))
+            .route("/speed", post(speed))
+            .route("/shuffle", post(shuffle))
+            .route("/party/requests", post(request_song))
+            .layer(middleware::from_fn(guests))
+            .layer(Extension(shared));
+
+        let address = SocketAddr::from((host, port));
+        let server = Server::try_bind(&address)
+            .into_diagnostic()?
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+            .with_graceful_shutdown(shutdown::requested());
+
+        success!("Serving the HTTP API on http://{address}");
+
+        let handle = tokio::spawn(async move {
+            if let Err(e) = server.await {
+                warn!("HTTP API stopped: {e}");
+            }
+        });
+
+        Ok((
+            HttpApi {
+                player,
+                commands,
+                party,
+                handle,
+            },
+            receiver,
+        ))
+    }
+
+    /// Songs guests requested that wait for approval, oldest first
+    pub fn song_requests(&self) -> Vec<SongRequest> {
+        lock(&self.party).pending().to_vec()
+    }
+
+    /// Queues a requested song. Returns false if there's no such request,
+    /// or nothing is listening for commands.
+    pub fn approve(&self, id: u64) -> bool {
+        match lock(&self.party).resolve(id) {
+            Some(request) => self
+                .commands
+                .send(MediaCommand::Enqueue(request.hash))
+                .is_ok(),
+            None => false,
+        }
+    }
+
+    /// Drops a requested song. Returns false if there's no such request.
+    pub fn reject(&self, id: u64) -> bool {
+        lock(&self.party).resolve(id).is_some()
+    }
+
+    /// Turns approval of requested songs on or off, see `Party::set_approval`
+    pub fn set_approval(&self, approval: bool) {
+        lock(&self.party).set_approval(approval);
+    }
+
+    /// Reports a change to the queue or playback state
+    pub fn update(&self, queue: &Queue, state: PlaybackState) {
+        self.player.send_replace(Reported {
+            player: PlayerState {
+                state,
+                tracks: queue.ordered().collect(),
+                position: queue.position(),
+                progress_ms: queue.progress.as_millis() as u64,
+                shuffle: queue.shuffle(),
+                shuffle_mode: queue.shuffle_mode(),
+                repeat: queue.repeat,
+                radio: queue.radio,
+                // Filled in when requested, since it changes as playback goes on
+                eta: Eta::default(),
+            },
+            at: Instant::now(),
+        });
+    }
+}
+
+impl Drop for HttpApi {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// Errors are returned as plain text
+struct ApiError(StatusCode, String);
+
+impl From<Report> for ApiError {
+    fn from(e: Report) -> Self {
+        ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+    }
+}
+
+impl From<Refusal> for ApiError {
+    fn from(refusal: Refusal) -> Self {
+        match refusal {
+            Refusal::Closed => ApiError(StatusCode::FORBIDDEN, "Party mode is off".into()),
+            Refusal::WrongPin => ApiError(StatusCode::UNAUTHORIZED, "Wrong party PIN".into()),
+            Refusal::TooManyRequests => ApiError(
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many songs requested, try again in a minute".into(),
+            ),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, self.1).into_response()
+    }
+}
+
+type ApiResult<T> = std::result::Result<Json<T>, ApiError>;
+
+async fn songs(Extension(shared): Extension<Shared>) -> ApiResult<Vec<library::Model>> {
+    Ok(Json(
+        library::Entity::find()
+            .all(&shared.db)
+            .await
+            .into_diagnostic()?,
+    ))
+}
+
+async fn song(
+    Path(hash): Path<i64>,
+    Extension(shared): Extension<Shared>,
+) -> ApiResult<library::Model> {
+    find_song(hash, &shared.db).await.map(Json)
+}
+
+async fn preview(
+    Path(hash): Path<i64>,
+    Query(at): Query<PreviewAt>,
+    Extension(shared): Extension<Shared>,
+) -> ApiResult<SeekPreview> {
+    let song = find_song(hash, &shared.db).await?;
+    let position = Duration::from_millis(at.position_ms);
+
+    Ok(Json(seek_preview(&song, position, &shared.db).await?))
+}
+
+async fn find_song(
+    hash: i64,
+    db: &DatabaseConnection,
+) -> std::result::Result<library::Model, ApiError> {
+    library::Entity::find()
+        .filter(library::Column::Hash.eq(hash))
+        .one(db)
+        .await
+        .into_diagnostic()?
+        .ok_or(ApiError(
+            StatusCode::NOT_FOUND,
+            format!("No song with hash {hash}"),
+        ))
+}
+
+async fn album_list(Extension(shared): Extension<Shared>) -> ApiResult<Vec<Album>> {
+    Ok(Json(albums(&shared.db).await?))
+}
+
+async fn playlist_list(Extension(shared): Extension<Shared>) -> ApiResult<Vec<playlists::Model>> {
+    Ok(Json(
+        playlists::Entity::find()
+            .all(&shared.db)
+            .await
+            .into_diagnostic()?,
+    ))
+}
+
+async fn playlist(
+    Path(id): Path<i32>,
+    Extension(shared): Extension<Shared>,
+) -> ApiResult<Vec<i64>> {
+    let exists = playlists::Entity::find_by_id(id)
+        .one(&shared.db)
+        .await
+        .into_diagnostic()?
+        .is_some();
+
+    if !exists {
+        return Err(ApiError(
+            StatusCode::NOT_FOUND,
+            format!("Playlist {id} does not exist"),
+        ));
+    }
+
+    Ok(Json(
+        playlist_entries::Entity::find()
+            .filter(playlist_entries::Column::PlaylistId.eq(id))
+            .order_by_asc(playlist_entries::Column::Ordinal)
+            .all(&shared.db)
+            .await
+            .into_diagnostic()?
+            .into_iter()
+            .map(|v| v.song_hash)
+            .collect(),
+    ))
+}
+
+async fn queue(Extension(shared): Extension<Shared>) -> ApiResult<PlayerState> {
+    let Reported { mut player, at } = shared.player.borrow().clone();
+
+    let current = player.position.and_then(|v| player.tracks.get(v)).copied();
+
+    match position::current() {
+        Some(now) if Some(now.hash) == current => {
+            player.progress_ms = now.position.as_millis() as u64;
+        }
+        // The player only reports changes, so the time played since then is added here
+        _ if player.state == PlaybackState::Playing => {
+            player.progress_ms += at.elapsed().as_millis() as u64;
+        }
+        _ => {}
+    }
+
+    let durations = durations(&player.tracks, &shared.db).await?;
+    player.eta = eta(
+        &player.tracks,
+        player.position,
+        Duration::from_millis(player.progress_ms),
+        player.repeat,
+        &durations,
+    );
+
+    Ok(Json(player))
+}
+
+/// JSON like the other endpoints, unless it's another Eleanor instance asking for messagepack,
+/// see `stats::remote_stats`
+async fn stats(
+    headers: HeaderMap,
+    Extension(shared): Extension<Shared>,
+) -> std::result::Result<Response, ApiError> {
+    let stats = local_stats(&shared.db).await?;
+
+    let msgpack = headers
+        .get(ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains(STATS_MEDIA_TYPE));
+
+    Ok(if msgpack {
+        ([(CONTENT_TYPE, STATS_MEDIA_TYPE)], encode_stats(&stats)?).into_response()
+    } else {
+        Json(stats).into_response()
+    })
+}
+
+async fn send(Extension(shared): Extension<Shared>, command: MediaCommand) -> StatusCode {
+    match shared.commands.send(command) {
+        Ok(()) => StatusCode::NO_CONTENT,
+        // Nothing is listening for commands
+        Err(_) => StatusCode::SERVICE_UNAVAILABLE,
+    }
+}
+
+async fn seek(
+    Extension(shared): Extension<Shared>,
+    Json(seek): Json<Seek>,
+) -> std::result::Result<StatusCode, ApiError> {
+    let command = match seek {
+        Seek {
+            position_ms: Some(position),
+            ..
+        } => MediaCommand::SetPosition(Duration::from_millis(position)),
+        Seek {
+            by_ms: Some(offset),
+            ..
+        } => MediaCommand::SeekBy(offset),
+        _ => {
+            return Err(ApiError(
+                StatusCode::BAD_REQUEST,
+                "Either position_ms or by_ms is required".into(),
+            ))
+        }
+    };
+
+    Ok(send(Extension(shared), command).await)
+}
+
+async fn speed(
+    Extension(shared): Extension<Shared>,
+    Json(speed): Json<Speed>,
+) -> std::result::Result<StatusCode, ApiError> {
+    let command = match speed {
+        Speed {
+            percent: Some(percent),
+            ..
+        } => MediaCommand::SetSpeed(percent),
+        Speed {
+            by_percent: Some(change),
+            ..
+        } => MediaCommand::ChangeSpeed(change),
+        _ => {
+            return Err(ApiError(
+                StatusCode::BAD_REQUEST,
+                "Either percent or by_percent is required".into(),
+            ))
+        }
+    };
+
+    Ok(send(Extension(shared), command).await)
+}
+
+async fn shuffle(Extension(shared): Extension<Shared>, Json(shuffle): Json<Shuffle>) -> StatusCode {
+    send(Extension(shared), MediaCommand::SetShuffle(shuffle.mode)).await
+}
+
+/// Other devices only get to use what guests need in party mode: browsing the library,
+/// looking at the queue and requesting songs. Wrong PINs are limited per guest address,
+/// and song requests check the PIN themselves, since they count towards the guest's limit.
+async fn guests<B>(request: Request<B>, next: Next<B>) -> Response {
+    let guest = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|v| v.0.ip());
+
+    if guest.is_some_and(|v| v.is_loopback()) {
+        return next.run(request).await;
+    }
+
+    let admitted = match (request.method(), request.uri().path(), guest) {
+        (&Method::POST, "/party/requests", _) => Ok(()),
+        (&Method::GET, _, Some(guest)) => match request.extensions().get::<Shared>() {
+            Some(shared) => lock(&shared.party).admit(guest, pin(request.headers())),
+            None => Err(Refusal::Closed),
+        },
+        _ => Err(Refusal::Closed),
+    };
+
+    match admitted {
+        Ok(()) => next.run(request).await,
+        Err(refusal) => ApiError::from(refusal).into_response(),
+    }
+}
+
+async fn request_song(
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Extension(shared): Extension<Shared>,
+    Json(requested): Json<Requested>,
+) -> std::result::Result<Response, ApiError> {
+    let pending = lock(&shared.party).request(requested.hash, address.ip(), pin(&headers))?;
+
+    if let Err(e) = find_song(requested.hash, &shared.db).await {
+        if let Some(request) = &pending {
+            lock(&shared.party).resolve(request.id);
+        }
+        return Err(e);
+    }
+
+    Ok(match pending {
+        Some(request) => {
+            publish(Event::SongRequested(request.clone()));
+            (StatusCode::ACCEPTED, Json(request)).into_response()
+        }
+        None => send(Extension(shared), MediaCommand::Enqueue(requested.hash))
+            .await
+            .into_response(),
+    })
+}
+
+fn pin(headers: &HeaderMap) -> Option<&str> {
+    headers.get(PIN_HEADER).and_then(|v| v.to_str().ok())
+}
+
+fn lock(party: &Mutex<Party>) -> MutexGuard<'_, Party> {
+    party.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,781 @@
+use std::{
+    collections::HashMap,
+    fs::read_dir,
+    io::Read,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+    cancellation::CancellationToken,
+    config::{Config, Source, SourceKind},
+    fetching::{index_source, IndexMode},
+    model::{library, playlist_entries, playlists, song_stats},
+    utils::song_path,
+};
+use flate2::read::GzDecoder;
+use miette::{miette, IntoDiagnostic, Result};
+use paris::{info, success};
+use plist::Value;
+use quick_xml::{events::Event, Reader};
+use sea_orm::{
+    sea_query::OnConflict, ColumnTrait, ConnectionTrait, Database, DatabaseBackend,
+    DatabaseConnection, EntityTrait, QueryFilter, QueryResult, Set, Statement, TransactionTrait,
+};
+use url::Url;
+
+/// Library of another player to import
+#[derive(Debug, Clone)]
+pub enum ImportFrom {
+    Mpd {
+        /// MPD's `db_file`, compressed or not
+        database: PathBuf,
+        /// MPD's `music_directory`, which paths in the database are relative to
+        music_dir: PathBuf,
+        /// MPD's `playlist_directory`, containing `.m3u` playlists
+        playlist_dir: Option<PathBuf>,
+        /// MPD's `sticker_file`, which ratings are read from
+        sticker_db: Option<PathBuf>,
+    },
+    /// `iTunes Library.xml`, or `iTunes Music Library.xml` in older versions
+    Itunes(PathBuf),
+    Rhythmbox {
+        /// `rhythmdb.xml`
+        database: PathBuf,
+        /// `playlists.xml`
+        playlists: Option<PathBuf>,
+    },
+    /// `clementine.db`, also used by Strawberry as `strawberry.db`
+    Clementine(PathBuf),
+}
+
+impl ImportFrom {
+    fn player(&self) -> &'static str {
+        match self {
+            ImportFrom::Mpd { .. } => "MPD",
+            ImportFrom::Itunes(_) => "iTunes",
+            ImportFrom::Rhythmbox { .. } => "Rhythmbox",
+            ImportFrom::Clementine(_) => "Clementine",
+        }
+    }
+}
+
+/// What was imported
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    /// Sources added for music directories that weren't part of the library yet
+    pub sources: Vec<u8>,
+    /// Tracks found in the library
+    pub matched: usize,
+    /// Tracks that couldn't be found in the library, so their ratings and plays were skipped
+    pub unmatched: usize,
+    pub playlists: usize,
+}
+
+/// A song in another player's library
+#[derive(Debug, Default)]
+struct Track {
+    path: Option<PathBuf>,
+    artist: Option<String>,
+    title: Option<String>,
+    album: Option<String>,
+    /// From 0 to 100
+    rating: Option<i32>,
+    plays: i32,
+    /// In seconds since the unix epoch
+    last_played: Option<i64>,
+}
+
+/// Playlists either point to a track of the imported library, or just to a file
+#[derive(Debug)]
+enum TrackRef {
+    Index(usize),
+    Path(PathBuf),
+}
+
+#[derive(Debug)]
+struct Playlist {
+    name: String,
+    tracks: Vec<TrackRef>,
+}
+
+#[derive(Debug, Default)]
+struct Library {
+    /// Directories the music is stored in
+    roots: Vec<PathBuf>,
+    tracks: Vec<Track>,
+    playlists: Vec<Playlist>,
+}
+
+/// Imports the library of another player. Music directories that aren't part of a local source yet
+/// are added as sources and indexed. Tracks are matched by path, or by tags if they moved.
+/// Their ratings and play counts are stored in `song_stats`, and playlists that don't exist
+/// yet are created. Importing the same library again overwrites the imported stats.
+pub async fn import(from: ImportFrom, db: &DatabaseConnection) -> Result<ImportSummary> {
+    let imported = match &from {
+        ImportFrom::Mpd {
+            database,
+            music_dir,
+            playlist_dir,
+            sticker_db,
+        } => {
+            read_mpd(
+                database,
+                music_dir,
+                playlist_dir.as_deref(),
+                sticker_db.as_deref(),
+            )
+            .await?
+        }
+        ImportFrom::Itunes(path) => read_itunes(path)?,
+        ImportFrom::Rhythmbox {
+            database,
+            playlists,
+        } => read_rhythmbox(database, playlists.as_deref())?,
+        ImportFrom::Clementine(path) => read_clementine(path).await?,
+    };
+
+    info!(
+        "Importing {} tracks and {} playlists from {}",
+        imported.tracks.len(),
+        imported.playlists.len(),
+        from.player()
+    );
+
+    let mut summary = ImportSummary::default();
+
+    for root in &imported.roots {
+        if let Some(source) = add_source(root, from.player())? {
+            summary.sources.push(source.id);
+            index_source(source, IndexMode::Initial, &CancellationToken::new(), db).await?;
+        }
+    }
+
+    let songs = library::Entity::find().all(db).await.into_diagnostic()?;
+
+    let by_path: HashMap<PathBuf, i64> = songs.iter().map(|v| (song_path(v), v.hash)).collect();
+    let by_tags: HashMap<_, i64> = songs
+        .iter()
+        .filter_map(|v| {
+            let key = tag_key(v.artist.as_deref(), v.name.as_deref(), v.album.as_deref())?;
+            Some((key, v.hash))
+        })
+        .collect();
+
+    let hashes: Vec<Option<i64>> = imported
+        .tracks
+        .iter()
+        .map(|track| {
+            track
+                .path
+                .as_ref()
+                .and_then(|v| by_path.get(v))
+                .or_else(|| {
+                    let key = tag_key(
+                        track.artist.as_deref(),
+                        track.title.as_deref(),
+                        track.album.as_deref(),
+                    )?;
+                    by_tags.get(&key)
+                })
+                .copied()
+        })
+        .collect();
+
+    for (track, hash) in imported.tracks.iter().zip(&hashes) {
+        let Some(hash) = hash else {
+            summary.unmatched += 1;
+            continue;
+        };
+        summary.matched += 1;
+
+        if track.rating.is_some() || track.plays > 0 {
+            save_stats(*hash, track, db).await?;
+        }
+    }
+
+    for playlist in &imported.playlists {
+        let exists = playlists::Entity::find()
+            .filter(playlists::Column::Name.eq(playlist.name.as_str()))
+            .one(db)
+            .await
+            .into_diagnostic()?
+            .is_some();
+
+        if exists {
+            info!("Skipping playlist {}, which already exists", playlist.name);
+            continue;
+        }
+
+        let tracks: Vec<i64> = playlist
+            .tracks
+            .iter()
+            .filter_map(|v| match v {
+                TrackRef::Index(index) => hashes.get(*index).copied().flatten(),
+                TrackRef::Path(path) => by_path.get(path).copied(),
+            })
+            .collect();
+
+        if tracks.is_empty() {
+            continue;
+        }
+
+        // A playlist is only created together with its entries
+        let txn = db.begin().await.into_diagnostic()?;
+
+        let id = playlists::Entity::insert(playlists::ActiveModel {
+            name: Set(Some(playlist.name.clone())),
+            ..Default::default()
+        })
+        .exec(&txn)
+        .await
+        .into_diagnostic()?
+        .last_insert_id;
+
+        playlist_entries::Entity::insert_many(tracks.into_iter().enumerate().map(
+            |(ordinal, hash)| playlist_entries::ActiveModel {
+                playlist_id: Set(id),
+                song_hash: Set(hash),
+                ordinal: Set(Some(ordinal as i32)),
+                ..Default::default()
+            },
+        ))
+        .exec(&txn)
+        .await
+        .into_diagnostic()?;
+
+        txn.commit().await.into_diagnostic()?;
+        summary.playlists += 1;
+    }
+
+    success!(
+        "Imported {} of {} tracks and {} playlists from {}",
+        summary.matched,
+        summary.matched + summary.unmatched,
+        summary.playlists,
+        from.player()
+    );
+
+    Ok(summary)
+}
+
+/// Adds a local source for a music directory, unless it's already part of one
+fn add_source(root: &Path, player: &str) -> Result<Option<Source>> {
+    let mut config = Config::read_config()?;
+
+    let covered = config.sources.iter().any(|v| match &v.source {
+        SourceKind::Local { path } => root.starts_with(path),
+        _ => false,
+    });
+
+    if covered {
+        return Ok(None);
+    }
+
+    let path = root
+        .to_str()
+        .ok_or(miette!("Invalid music directory {}", root.display()))?
+        .to_string();
+
+    let id = config.add_source(
+        &format!("{player} library"),
+        SourceKind::Local { path },
+        &[],
+    )?;
+    let source = config.sources.iter().find(|v| v.id == id).cloned();
+
+    Config::write_config(&config)?;
+
+    info!("Added source {id} for {}", root.display());
+    Ok(source)
+}
+
+async fn save_stats(hash: i64, track: &Track, db: &DatabaseConnection) -> Result<()> {
+    let mut columns = vec![
+        song_stats::Column::ImportedPlays,
+        song_stats::Column::ImportedLastPlayed,
+    ];
+
+    // Keep ratings that were set before if the other player has none
+    if track.rating.is_some() {
+        columns.push(song_stats::Column::Rating);
+    }
+
+    song_stats::Entity::insert(song_stats::ActiveModel {
+        song_hash: Set(hash),
+        rating: Set(track.rating.map(|v| v.clamp(0, 100))),
+        imported_plays: Set(track.plays),
+        imported_last_played: Set(track.last_played),
+        ..Default::default()
+    })
+    .on_conflict(
+        OnConflict::column(song_stats::Column::SongHash)
+            .update_columns(columns)
+            .to_owned(),
+    )
+    .exec(db)
+    .await
+    .into_diagnostic()?;
+
+    Ok(())
+}
+
+/// Tracks are matched by tags case insensitively, and only if they have an artist and title
+fn tag_key(
+    artist: Option<&str>,
+    title: Option<&str>,
+    album: Option<&str>,
+) -> Option<(String, String, String)> {
+    Some((
+        artist?.trim().to_lowercase(),
+        title?.trim().to_lowercase(),
+        album.unwrap_or_default().trim().to_lowercase(),
+    ))
+}
+
+/// Converts `file://` URLs, which most players store locations as, to paths
+fn url_path(url: &str) -> Option<PathBuf> {
+    Url::parse(url).ok()?.to_file_path().ok()
+}
+
+fn unix_time(time: SystemTime) -> Option<i64> {
+    Some(time.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64)
+}
+
+/// Reads MPD's text database, where every song is listed under the directory it's in:
+///
+/// ```text
+/// begin: Artist/Album
+/// song_begin: 01 Song.flac
+/// Title: Song
+/// song_end
+/// end: Artist/Album
+/// ```
+async fn read_mpd(
+    database: &Path,
+    music_dir: &Path,
+    playlist_dir: Option<&Path>,
+    sticker_db: Option<&Path>,
+) -> Result<Library> {
+    let bytes = std::fs::read(database).into_diagnostic()?;
+
+    let contents = if bytes.starts_with(&[0x1f, 0x8b]) {
+        let mut contents = String::new();
+        GzDecoder::new(bytes.as_slice())
+            .read_to_string(&mut contents)
+            .into_diagnostic()?;
+        contents
+    } else {
+        String::from_utf8(bytes).into_diagnostic()?
+    };
+
+    let mut library = Library {
+        roots: vec![music_dir.to_path_buf()],
+        ..Default::default()
+    };
+
+    let mut directories: Vec<&str> = vec![];
+    let mut current: Option<Track> = None;
+
+    for line in contents.lines() {
+        let (key, value) = line.split_once(": ").unwrap_or((line, ""));
+
+        match (key, &mut current) {
+            ("begin", _) => directories.push(value),
+            ("end", _) => {
+                directories.pop();
+            }
+            ("song_begin", _) => {
+                let directory = directories.last().copied().unwrap_or_default();
+
+                current = Some(Track {
+                    path: Some(music_dir.join(directory).join(value)),
+                    ..Default::default()
+                });
+            }
+            ("song_end", _) => library.tracks.extend(current.take()),
+            ("Artist", Some(track)) => track.artist = Some(value.to_string()),
+            ("Title", Some(track)) => track.title = Some(value.to_string()),
+            ("Album", Some(track)) => track.album = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    // Ratings are stored as stickers from 0 to 10
+    if let Some(sticker_db) = sticker_db {
+        let indices: HashMap<PathBuf, usize> = library
+            .tracks
+            .iter()
+            .enumerate()
+            .filter_map(|(index, track)| Some((track.path.clone()?, index)))
+            .collect();
+
+        let rows = query_sqlite(
+            sticker_db,
+            "SELECT uri, value FROM sticker WHERE type = 'song' AND name = 'rating'",
+        )
+        .await?;
+
+        for row in rows {
+            let uri: String = row.try_get("", "uri").into_diagnostic()?;
+            let value: String = row.try_get("", "value").into_diagnostic()?;
+
+            if let (Some(index), Ok(rating)) = (
+                indices.get(&music_dir.join(uri)),
+                value.trim().parse::<i32>(),
+            ) {
+                library.tracks[*index].rating = Some(rating * 10);
+            }
+        }
+    }
+
+    if let Some(playlist_dir) = playlist_dir {
+        for entry in read_dir(playlist_dir).into_diagnostic()? {
+            let path = entry.into_diagnostic()?.path();
+
+            let (Some(name), Some("m3u")) =
+                (path.file_stem(), path.extension().and_then(|v| v.to_str()))
+            else {
+                continue;
+            };
+            let name = name.to_string_lossy().to_string();
+
+            let tracks = std::fs::read_to_string(&path)
+                .into_diagnostic()?
+                .lines()
+                .map(str::trim)
+                .filter(|v| !v.is_empty() && !v.starts_with('#') && !v.contains("://"))
+                // Relative paths are relative to the music directory
+                .map(|v| TrackRef::Path(music_dir.join(v)))
+                .collect();
+
+            library.playlists.push(Playlist { name, tracks });
+        }
+    }
+
+    Ok(library)
+}
+
+/// Reads the XML property list iTunes exports its library as
+fn read_itunes(path: &Path) -> Result<Library> {
+    let value = Value::from_file(path).into_diagnostic()?;
+    let root = value
+        .as_dictionary()
+        .ok_or(miette!("{} is not an iTunes library", path.display()))?;
+
+    let mut library = Library {
+        roots: root
+            .get("Music Folder")
+            .and_then(Value::as_string)
+            .and_then(url_path)
+            .into_iter()
+            .collect(),
+        ..Default::default()
+    };
+
+    let mut ids = HashMap::new();
+
+    for (id, track) in root
+        .get("Tracks")
+        .and_then(Value::as_dictionary)
+        .into_iter()
+        .flatten()
+    {
+        let Some(track) = track.as_dictionary() else {
+            continue;
+        };
+
+        let string = |key| {
+            track
+                .get(key)
+                .and_then(Value::as_string)
+                .map(str::to_string)
+        };
+        let integer = |key| track.get(key).and_then(Value::as_signed_integer);
+
+        // Computed ratings are inherited from the album
+        let computed = track
+            .get("Rating Computed")
+            .and_then(Value::as_boolean)
+            .unwrap_or_default();
+
+        ids.insert(id.as_str(), library.tracks.len());
+        library.tracks.push(Track {
+            path: track
+                .get("Location")
+                .and_then(Value::as_string)
+                .and_then(url_path),
+            artist: string("Artist"),
+            title: string("Name"),
+            album: string("Album"),
+            rating: integer("Rating").filter(|_| !computed).map(|v| v as i32),
+            plays: integer("Play Count").unwrap_or_default() as i32,
+            last_played: track
+                .get("Play Date UTC")
+                .and_then(Value::as_date)
+                .and_then(|v| unix_time(v.into())),
+        });
+    }
+
+    for playlist in root
+        .get("Playlists")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_dictionary)
+    {
+        // Skip the whole library, built-in playlists like "Music", and folders
+        if ["Master", "Distinguished Kind", "Folder"]
+            .iter()
+            .any(|v| playlist.contains_key(v))
+        {
+            continue;
+        }
+
+        let Some(name) = playlist.get("Name").and_then(Value::as_string) else {
+            continue;
+        };
+
+        let tracks = playlist
+            .get("Playlist Items")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(|v| v.as_dictionary()?.get("Track ID")?.as_signed_integer())
+            .filter_map(|v| ids.get(v.to_string().as_str()))
+            .map(|v| TrackRef::Index(*v))
+            .collect();
+
+        library.playlists.push(Playlist {
+            name: name.to_string(),
+            tracks,
+        });
+    }
+
+    Ok(library)
+}
+
+/// Reads Rhythmbox's `rhythmdb.xml`, with an `<entry type="song">` for every song,
+/// and the static playlists in `playlists.xml`
+fn read_rhythmbox(database: &Path, playlists: Option<&Path>) -> Result<Library> {
+    let mut library = Library::default();
+
+    let mut reader = Reader::from_file(database).into_diagnostic()?;
+    reader.trim_text(true);
+
+    let mut buf = vec![];
+    let mut current: Option<Track> = None;
+    let mut field: Option<String> = None;
+
+    loop {
+        match reader.read_event_into(&mut buf).into_diagnostic()? {
+            Event::Start(e) if e.name().as_ref() == b"entry" => {
+                let kind = e
+                    .try_get_attribute("type")
+                    .into_diagnostic()?
+                    .map(|v| v.value.into_owned());
+
+                if kind.as_deref() == Some(b"song") {
+                    current = Some(Track::default());
+                }
+            }
+            Event::Start(e) => {
+                field = Some(String::from_utf8_lossy(e.name().as_ref()).to_string());
+            }
+            Event::Text(e) => {
+                let (Some(track), Some(field)) = (&mut current, &field) else {
+                    continue;
+                };
+                let text = e.unescape().into_diagnostic()?;
+
+                match field.as_str() {
+                    "title" => track.title = Some(text.to_string()),
+                    "artist" => track.artist = Some(text.to_string()),
+                    "album" => track.album = Some(text.to_string()),
+                    "location" => track.path = url_path(&text),
+                    "play-count" => track.plays = text.parse().unwrap_or_default(),
+                    "last-played" => track.last_played = text.parse().ok(),
+                    // From 0 to 5 stars
+                    "rating" => track.rating = text.parse::<i32>().ok().map(|v| v * 20),
+                    _ => {}
+                }
+            }
+            Event::End(e) if e.name().as_ref() == b"entry" => {
+                library.tracks.extend(current.take());
+            }
+            Event::End(_) => field = None,
+            Event::Eof => break,
+            _ => {}
+        }
+
+        buf.clear();
+    }
+
+    // Rhythmbox doesn't store its library locations here, so use the directory
+    // all songs are in, unless that's the whole file system
+    let mut paths = library.tracks.iter().filter_map(|v| v.path.as_deref());
+    if let Some(first) = paths.next() {
+        let mut root = first.parent().unwrap_or(first).to_path_buf();
+
+        for path in paths {
+            while !path.starts_with(&root) && root.pop() {}
+        }
+
+        if root.parent().is_some() {
+            library.roots.push(root);
+        }
+    }
+
+    let Some(playlists) = playlists else {
+        return Ok(library);
+    };
+
+    let mut reader = Reader::from_file(playlists).into_diagnostic()?;
+    reader.trim_text(true);
+
+    let mut current: Option<Playlist> = None;
+    let mut in_location = false;
+
+    loop {
+        match reader.read_event_into(&mut buf).into_diagnostic()? {
+            Event::Start(e) if e.name().as_ref() == b"playlist" => {
+                let attribute = |name| -> Result<Option<String>> {
+                    Ok(e.try_get_attribute(name)
+                        .into_diagnostic()?
+                        .map(|v| String::from_utf8_lossy(&v.value).to_string()))
+                };
+
+                // Automatic playlists are saved searches, and the queue is a playlist too
+                if attribute("type")?.as_deref() == Some("static") {
+                    current = attribute("name")?.map(|name| Playlist {
+                        name,
+                        tracks: vec![],
+                    });
+                }
+            }
+            Event::Start(e) if e.name().as_ref() == b"location" => in_location = true,
+            Event::Text(e) if in_location => {
+                if let (Some(playlist), Some(path)) =
+                    (&mut current, url_path(&e.unescape().into_diagnostic()?))
+                {
+                    playlist.tracks.push(TrackRef::Path(path));
+                }
+            }
+            Event::End(e) if e.name().as_ref() == b"playlist" => {
+                library.playlists.extend(current.take());
+            }
+            Event::End(_) => in_location = false,
+            Event::Eof => break,
+            _ => {}
+        }
+
+        buf.clear();
+    }
+
+    Ok(library)
+}
+
+/// Reads Clementine's SQLite database
+async fn read_clementine(path: &Path) -> Result<Library> {
+    let mut library = Library::default();
+
+    for row in query_sqlite(path, "SELECT path FROM directories").await? {
+        library.roots.push(PathBuf::from(
+            row.try_get::<String>("", "path").into_diagnostic()?,
+        ));
+    }
+
+    let mut ids = HashMap::new();
+
+    let rows = query_sqlite(
+        path,
+        "SELECT ROWID AS id, title, artist, album, filename, rating, playcount, lastplayed \
+        FROM songs",
+    )
+    .await?;
+
+    for row in rows {
+        let id: i64 = row.try_get("", "id").into_diagnostic()?;
+        let string = |column| {
+            row.try_get::<Option<String>>("", column)
+                .ok()
+                .flatten()
+                .filter(|v| !v.is_empty())
+        };
+
+        // Newer versions store locations as blobs
+        let location = string("filename").or_else(|| {
+            row.try_get::<Option<Vec<u8>>>("", "filename")
+                .ok()
+                .flatten()
+                .map(|v| String::from_utf8_lossy(&v).to_string())
+        });
+
+        // Unrated songs and songs that were never played have -1
+        let rating: Option<f64> = row.try_get("", "rating").into_diagnostic()?;
+        let last_played: Option<i64> = row.try_get("", "lastplayed").into_diagnostic()?;
+
+        ids.insert(id, library.tracks.len());
+        library.tracks.push(Track {
+            path: location.as_deref().and_then(url_path),
+            artist: string("artist"),
+            title: string("title"),
+            album: string("album"),
+            rating: rating
+                .filter(|v| *v >= 0.0)
+                .map(|v| (v * 100.0).round() as i32),
+            plays: row
+                .try_get::<Option<i32>>("", "playcount")
+                .into_diagnostic()?
+                .unwrap_or_default(),
+            last_played: last_played.filter(|v| *v > 0),
+        });
+    }
+
+    let mut names = HashMap::new();
+    for row in query_sqlite(path, "SELECT ROWID AS id, name FROM playlists").await? {
+        let id: i64 = row.try_get("", "id").into_diagnostic()?;
+        let name: String = row.try_get("", "name").into_diagnostic()?;
+        names.insert(id, library.playlists.len());
+
+        library.playlists.push(Playlist {
+            name,
+            tracks: vec![],
+        });
+    }
+
+    let rows = query_sqlite(
+        path,
+        "SELECT playlist, library_id FROM playlist_items \
+        WHERE type = 'Library' ORDER BY ROWID",
+    )
+    .await?;
+
+    for row in rows {
+        let playlist: i64 = row.try_get("", "playlist").into_diagnostic()?;
+        let song: Option<i64> = row.try_get("", "library_id").into_diagnostic()?;
+
+        if let (Some(playlist), Some(song)) = (names.get(&playlist), song.and_then(|v| ids.get(&v)))
+        {
+            library.playlists[*playlist]
+                .tracks
+                .push(TrackRef::Index(*song));
+        }
+    }
+
+    Ok(library)
+}
+
+/// Runs a query on another player's SQLite database, without changing it
+async fn query_sqlite(path: &Path, query: &str) -> Result<Vec<QueryResult>> {
+    let db = Database::connect(format!("sqlite://{}?mode=ro", path.display()))
+        .await
+        .into_diagnostic()?;
+
+    db.query_all(Statement::from_string(
+        DatabaseBackend::Sqlite,
+        query.to_string(),
+    ))
+    .await
+    .into_diagnostic()
+}
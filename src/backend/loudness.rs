@@ -0,0 +1,307 @@
+use std::{
+    fs::{create_dir_all, File},
+    io::{Cursor, Write},
+    path::{Path, PathBuf},
+};
+
+use super::{
+    config::{Config, Playback},
+    downloads::playable_path,
+    model::library,
+    network::is_metered,
+    replaygain::Gain,
+    utils::{audio_location, cache_dir, http_client},
+};
+use ebur128::{EbuR128, Mode};
+use miette::{miette, IntoDiagnostic, Result};
+use sea_orm::DatabaseConnection;
+use serde::{Deserialize, Serialize};
+use symphonia::{
+    core::{
+        audio::SampleBuffer,
+        io::{MediaSource, MediaSourceStream},
+        probe::Hint,
+    },
+    default::{get_codecs, get_probe},
+};
+
+/// Loudness that measured tracks are adjusted to, same as ReplayGain 2.0
+const REFERENCE_LUFS: f64 = -18.0;
+
+/// Extra bytes fetched for previews, to make up for headers and variable bitrates
+const PREVIEW_MARGIN: u64 = 256 * 1024;
+
+/// How remote songs without ReplayGain values are measured before they play
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LoudnessAnalysis {
+    /// Play them unadjusted
+    Off,
+    /// Measure only the beginning of the song, see `Config::loudness_preview_seconds`
+    Preview,
+    /// Measure the whole song once and keep the result
+    Full,
+}
+
+/// Which ReplayGain values songs are leveled with
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Normalization {
+    Off,
+    /// Every song is as loud as the others
+    #[default]
+    Track,
+    /// Keeps the differences in loudness between songs of an album, e.g. a quiet intro
+    Album,
+}
+
+/// A cached measurement of a remote song
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+struct Measurement {
+    gain: Gain,
+    /// Whether the whole song was measured, or only a preview
+    full: bool,
+}
+
+/// The gain the player should apply to a song. Songs with ReplayGain values use those,
+/// remote songs without them are measured according to `Config::loudness_analysis`.
+pub async fn playback_gain(song: &library::Model, db: &DatabaseConnection) -> Result<Option<Gain>> {
+    if let (Some(gain), Some(peak)) = (song.rg_track_gain, song.rg_track_peak) {
+        return Ok(Some(Gain { gain, peak }));
+    }
+
+    let config = Config::read_config()?;
+
+    // Local songs are analyzed while indexing
+    if config.local_source_ids().contains(&song.source_id) {
+        return Ok(None);
+    }
+
+    let full = match config.loudness_analysis {
+        LoudnessAnalysis::Off => return Ok(None),
+        LoudnessAnalysis::Preview => false,
+        // Downloading whole songs just to measure them isn't worth it on a metered connection
+        LoudnessAnalysis::Full => !is_metered(),
+    };
+
+    // A full measurement is good enough for previews, but not the other way around
+    if let Some(cached) = cached_measurement(song.hash) {
+        if cached.full || !full {
+            return Ok(Some(cached.gain));
+        }
+    }
+
+    let preview_seconds = (!full).then_some(config.loudness_preview_seconds);
+    let ext = extension(&song.filename);
+
+    let gain = match playable_path(song, db).await? {
+        // Downloaded songs don't need to be fetched again
+        Some(path) => {
+            let file = File::open(path).into_diagnostic()?;
+            measure_blocking(Box::new(file), ext, preview_seconds).await?
+        }
+        None => {
+            let data = fetch_audio(song, &config, preview_seconds).await?;
+            measure_blocking(Box::new(Cursor::new(data)), ext, preview_seconds).await?
+        }
+    };
+
+    save_measurement(song.hash, Measurement { gain, full })?;
+
+    Ok(Some(gain))
+}
+
+/// The gain for a song in a normalization mode. Songs without album values use their track gain.
+pub async fn normalization_gain(
+    song: &library::Model,
+    mode: Normalization,
+    db: &DatabaseConnection,
+) -> Result<Option<Gain>> {
+    match (mode, song.rg_album_gain, song.rg_album_peak) {
+        (Normalization::Off, ..) => Ok(None),
+        (Normalization::Album, Some(gain), Some(peak)) => Ok(Some(Gain { gain, peak })),
+        _ => playback_gain(song, db).await,
+    }
+}
+
+/// Linear factor to multiply samples by for a gain plus the preamp.
+/// Without the limiter, it's lowered if needed so the peak doesn't clip,
+/// unless `Playback::prevent_clipping` is off.
+pub fn volume_factor(gain: Gain, settings: &Playback) -> f32 {
+    let factor = 10f32.powf((gain.gain + settings.preamp) / 20.0);
+
+    if gain.peak > 0.0 && settings.prevent_clipping && !settings.limiter {
+        factor.min(1.0 / gain.peak)
+    } else {
+        factor
+    }
+}
+
+/// Downloads a remote song, or only about as much of it as is needed for a preview
+async fn fetch_audio(
+    song: &library::Model,
+    config: &Config,
+    preview_seconds: Option<u64>,
+) -> Result<Vec<u8>> {
+    let source = config
+        .sources
+        .iter()
+        .find(|v| i32::from(v.id) == song.source_id)
+        .ok_or(miette!("Source {} does not exist", song.source_id))?;
+
+    let (url, credentials) = audio_location(source, song, None)?;
+
+    let mut request = http_client(config, Some(source))?.get(url);
+
+    if let Some((username, password)) = credentials {
+        request = request.basic_auth(username, Some(password));
+    }
+
+    let mut response = request
+        .send()
+        .await
+        .into_diagnostic()?
+        .error_for_status()
+        .into_diagnostic()?;
+
+    // Estimate how many bytes the preview takes up from the file size and duration
+    let limit = match (preview_seconds, response.content_length()) {
+        (Some(seconds), Some(size)) if song.duration > 0 => {
+            Some(size * seconds / song.duration as u64 + PREVIEW_MARGIN)
+        }
+        _ => None,
+    };
+
+    let mut data = vec![];
+
+    while let Some(chunk) = response.chunk().await.into_diagnostic()? {
+        data.extend_from_slice(&chunk);
+
+        if limit.is_some_and(|v| data.len() as u64 >= v) {
+            break;
+        }
+    }
+
+    Ok(data)
+}
+
+async fn measure_blocking(
+    source: Box<dyn MediaSource>,
+    ext: String,
+    seconds: Option<u64>,
+) -> Result<Gain> {
+    tokio::task::spawn_blocking(move || measure(source, &ext, seconds))
+        .await
+        .into_diagnostic()?
+}
+
+/// Measures integrated loudness and sample peak as specified by EBU R128.
+/// Decoding stops after `seconds`, or at the end of the data, which may be cut off.
+fn measure(source: Box<dyn MediaSource>, ext: &str, seconds: Option<u64>) -> Result<Gain> {
+    let source = MediaSourceStream::new(source, Default::default());
+    let mut format = get_probe()
+        .format(
+            Hint::new().with_extension(ext),
+            source,
+            &Default::default(),
+            &Default::default(),
+        )
+        .into_diagnostic()?
+        .format;
+
+    let track = format
+        .default_track()
+        .ok_or(miette!("No audio track found"))?;
+    let track_id = track.id;
+
+    let sample_rate = track
+        .codec_params
+        .sample_rate
+        .ok_or(miette!("Unknown sample rate"))?;
+    let channels = track
+        .codec_params
+        .channels
+        .ok_or(miette!("Unknown channel layout"))?
+        .count() as u32;
+
+    let mut meter =
+        EbuR128::new(channels, sample_rate, Mode::I | Mode::SAMPLE_PEAK).into_diagnostic()?;
+
+    let mut decoder = get_codecs()
+        .make(&track.codec_params, &Default::default())
+        .into_diagnostic()?;
+
+    let max_frames = seconds.map(|v| v * sample_rate as u64);
+    let mut frames = 0;
+    let mut buffer: Option<SampleBuffer<f32>> = None;
+
+    // A truncated preview ends in an error rather than a clean end of stream
+    while let Ok(packet) = format.next_packet() {
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(v) => v,
+            // Skip over corrupted packets
+            Err(symphonia::core::errors::Error::DecodeError(_)) => continue,
+            Err(_) => break,
+        };
+
+        frames += decoded.frames() as u64;
+
+        let buffer = buffer
+            .get_or_insert_with(|| SampleBuffer::new(decoded.capacity() as u64, *decoded.spec()));
+        buffer.copy_interleaved_ref(decoded);
+
+        meter.add_frames_f32(buffer.samples()).into_diagnostic()?;
+
+        if max_frames.is_some_and(|v| frames >= v) {
+            break;
+        }
+    }
+
+    miette::ensure!(frames > 0, "No audio could be decoded");
+
+    let loudness = meter.loudness_global().into_diagnostic()?;
+    miette::ensure!(loudness.is_finite(), "Track is silent");
+
+    let mut peak: f64 = 0.0;
+    for channel in 0..channels {
+        peak = peak.max(meter.sample_peak(channel).into_diagnostic()?);
+    }
+
+    Ok(Gain {
+        gain: (REFERENCE_LUFS - loudness) as f32,
+        peak: peak as f32,
+    })
+}
+
+/// Extension of a file name, or an empty string if it has none
+pub fn extension(filename: &str) -> String {
+    Path::new(filename)
+        .extension()
+        .and_then(|v| v.to_str())
+        .unwrap_or("")
+        .to_string()
+}
+
+fn cached_measurement(hash: i64) -> Option<Measurement> {
+    let data = std::fs::read(measurement_path(hash)?).ok()?;
+    rmp_serde::from_slice(&data).ok()
+}
+
+fn save_measurement(hash: i64, measurement: Measurement) -> Result<()> {
+    let path = measurement_path(hash).ok_or(miette!("Cache directory does not exist"))?;
+    create_dir_all(path.parent().unwrap_or(&path)).into_diagnostic()?;
+
+    let data = rmp_serde::to_vec(&measurement).into_diagnostic()?;
+
+    File::create(path)
+        .and_then(|mut v| v.write_all(&data))
+        .into_diagnostic()
+}
+
+fn measurement_path(hash: i64) -> Option<PathBuf> {
+    cache_dir().map(|v| v.join("loudness").join(format!("{hash}.mp")))
+}
@@ -0,0 +1,144 @@
+use std::{path::Path, time::Duration};
+
+use lofty::{
+    id3::v2::{SynchronizedText, TimestampFormat},
+    read_from_path, ItemKey, Tag,
+};
+use miette::{IntoDiagnostic, Result};
+use serde::{Deserialize, Serialize};
+
+/// A single line of synchronized lyrics
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LyricLine {
+    /// Position in the song at which the line starts
+    pub timestamp: Duration,
+    pub text: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Lyrics {
+    /// Timestamped lines, sorted by their timestamp
+    Synced(Vec<LyricLine>),
+    Unsynced(String),
+}
+
+impl Lyrics {
+    /// Index of the line being sung at a playback position
+    pub fn line_at(&self, position: Duration) -> Option<usize> {
+        match self {
+            Lyrics::Synced(lines) => lines
+                .partition_point(|v| v.timestamp <= position)
+                .checked_sub(1),
+            Lyrics::Unsynced(_) => None,
+        }
+    }
+}
+
+/// Reads the lyrics of a file, preferring synchronized lyrics.
+/// Embedded tags are checked first, then an `.lrc` file next to the song.
+pub fn read_lyrics(path: &Path) -> Result<Option<Lyrics>> {
+    let audio = read_from_path(path, false).into_diagnostic()?;
+
+    let mut unsynced = None;
+
+    for tag in audio.tags() {
+        if let Some(lines) = tag_synced_lyrics(tag) {
+            return Ok(Some(Lyrics::Synced(lines)));
+        }
+
+        if let Some(text) = tag.get_string(&ItemKey::Lyrics) {
+            // Some taggers store LRC formatted text in the plain lyrics tag
+            let lines = parse_lrc(text);
+            if !lines.is_empty() {
+                return Ok(Some(Lyrics::Synced(lines)));
+            }
+
+            unsynced.get_or_insert_with(|| text.to_string());
+        }
+    }
+
+    if let Ok(contents) = std::fs::read_to_string(path.with_extension("lrc")) {
+        let lines = parse_lrc(&contents);
+        if !lines.is_empty() {
+            return Ok(Some(Lyrics::Synced(lines)));
+        }
+    }
+
+    Ok(unsynced.map(Lyrics::Unsynced))
+}
+
+/// Reads an ID3v2 SYLT frame, as long as its timestamps are in milliseconds
+fn tag_synced_lyrics(tag: &Tag) -> Option<Vec<LyricLine>> {
+    let data = tag.get_binary(&ItemKey::Unknown("SYLT".into()), false)?;
+
+    let frame = SynchronizedText::parse(data).ok()?;
+
+    if frame.information.timestamp_format != TimestampFormat::MS {
+        return None;
+    }
+
+    let mut lines: Vec<LyricLine> = frame
+        .content
+        .into_iter()
+        .map(|(timestamp, text)| LyricLine {
+            timestamp: Duration::from_millis(timestamp.into()),
+            text: text.trim().to_string(),
+        })
+        .collect();
+
+    lines.sort_by_key(|v| v.timestamp);
+
+    Some(lines)
+}
+
+/// Parses LRC formatted lyrics, e.g. `[01:23.45]text`.
+/// Lines may have multiple timestamps, and the `[offset:ms]` tag is respected.
+pub fn parse_lrc(contents: &str) -> Vec<LyricLine> {
+    let mut offset: i64 = 0;
+    let mut lines = vec![];
+
+    for line in contents.lines() {
+        let mut rest = line.trim();
+        let mut timestamps = vec![];
+
+        while let Some((tag, remainder)) = rest.strip_prefix('[').and_then(|v| v.split_once(']')) {
+            if let Some(value) = tag.strip_prefix("offset:") {
+                offset = value.trim().parse().unwrap_or(0);
+            } else if let Some(timestamp) = parse_timestamp(tag) {
+                timestamps.push(timestamp);
+            }
+
+            rest = remainder;
+        }
+
+        for timestamp in timestamps {
+            // A positive offset shifts lyrics to appear sooner
+            let millis = (timestamp - offset).max(0) as u64;
+
+            lines.push(LyricLine {
+                timestamp: Duration::from_millis(millis),
+                text: rest.trim().to_string(),
+            });
+        }
+    }
+
+    lines.sort_by_key(|v| v.timestamp);
+
+    lines
+}
+
+/// Parses an LRC `mm:ss.xx` timestamp into milliseconds.
+/// Long files may also use `hh:mm:ss.xx`.
+fn parse_timestamp(tag: &str) -> Option<i64> {
+    let (minutes, seconds) = tag.rsplit_once(':')?;
+
+    let minutes: i64 = match minutes.split_once(':') {
+        Some((hours, minutes)) => {
+            hours.trim().parse::<i64>().ok()? * 60 + minutes.trim().parse::<i64>().ok()?
+        }
+        None => minutes.trim().parse().ok()?,
+    };
+    let seconds: f64 = seconds.trim().parse().ok()?;
+
+    Some(minutes * 60_000 + (seconds * 1000.0).round() as i64)
+}
@@ -0,0 +1,176 @@
+use super::model::{library, playlist_entries, playlists};
+use miette::{IntoDiagnostic, Result};
+use paris::{info, success, warn};
+use sea_orm::{
+    sea_query::Query, ColumnTrait, Condition, ConnectionTrait, DatabaseConnection, EntityTrait,
+    QueryFilter, Statement,
+};
+
+/// A row whose foreign key points at a row that doesn't exist, from `PRAGMA foreign_key_check`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForeignKeyViolation {
+    pub table: String,
+    pub rowid: Option<i64>,
+    /// Table the key should point into
+    pub parent: String,
+}
+
+/// What `doctor` found, and what it fixed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    /// Problems reported by SQLite's integrity check, empty if the file is fine
+    pub integrity: Vec<String>,
+    pub foreign_keys: Vec<ForeignKeyViolation>,
+    pub orphaned_entries: usize,
+    /// Whether the problems that can be fixed were
+    pub fixed: bool,
+}
+
+impl Report {
+    pub fn is_healthy(&self) -> bool {
+        self.integrity.is_empty() && self.foreign_keys.is_empty() && self.orphaned_entries == 0
+    }
+}
+
+/// Runs every check, logging what was found. With `fix`, orphaned playlist entries are removed
+/// and the database is vacuumed afterwards.
+pub async fn doctor(fix: bool, db: &DatabaseConnection) -> Result<Report> {
+    let mut report = Report {
+        integrity: integrity_check(db).await?,
+        foreign_keys: foreign_key_violations(db).await?,
+        orphaned_entries: orphaned_playlist_entries(db).await?.len(),
+        fixed: false,
+    };
+
+    for problem in &report.integrity {
+        warn!("Integrity check: {problem}");
+    }
+
+    for violation in &report.foreign_keys {
+        warn!(
+            "Row {} of {} points to a missing row in {}",
+            violation
+                .rowid
+                .map_or("without id".to_string(), |v| v.to_string()),
+            violation.table,
+            violation.parent
+        );
+    }
+
+    if report.orphaned_entries > 0 {
+        warn!(
+            "{} playlist entries point to missing songs or playlists",
+            report.orphaned_entries
+        );
+    }
+
+    if fix {
+        let removed = remove_orphaned_playlist_entries(db).await?;
+        if removed > 0 {
+            info!("Removed {removed} orphaned playlist entries");
+        }
+
+        vacuum(db).await?;
+        report.fixed = true;
+    }
+
+    if report.is_healthy() {
+        success!("The database is healthy");
+    }
+
+    Ok(report)
+}
+
+/// Checks the database file for corruption
+pub async fn integrity_check(db: &DatabaseConnection) -> Result<Vec<String>> {
+    let problems = pragma(db, "PRAGMA integrity_check")
+        .await?
+        .into_iter()
+        .map(|row| row.try_get::<String>("", "integrity_check"))
+        .collect::<Result<Vec<_>, _>>()
+        .into_diagnostic()?;
+
+    // A healthy database reports a single "ok"
+    Ok(problems.into_iter().filter(|v| v != "ok").collect())
+}
+
+/// Rows referencing rows that don't exist, e.g. left over from when keys weren't enforced
+pub async fn foreign_key_violations(db: &DatabaseConnection) -> Result<Vec<ForeignKeyViolation>> {
+    pragma(db, "PRAGMA foreign_key_check")
+        .await?
+        .into_iter()
+        .map(|row| {
+            Ok(ForeignKeyViolation {
+                table: row.try_get("", "table")?,
+                rowid: row.try_get("", "rowid")?,
+                parent: row.try_get("", "parent")?,
+            })
+        })
+        .collect::<Result<_, sea_orm::DbErr>>()
+        .into_diagnostic()
+}
+
+/// Playlist entries whose song or playlist doesn't exist anymore
+pub async fn orphaned_playlist_entries(
+    db: &DatabaseConnection,
+) -> Result<Vec<playlist_entries::Model>> {
+    playlist_entries::Entity::find()
+        .filter(orphaned())
+        .all(db)
+        .await
+        .into_diagnostic()
+}
+
+/// Returns how many entries were removed
+pub async fn remove_orphaned_playlist_entries(db: &DatabaseConnection) -> Result<u64> {
+    Ok(playlist_entries::Entity::delete_many()
+        .filter(orphaned())
+        .exec(db)
+        .await
+        .into_diagnostic()?
+        .rows_affected)
+}
+
+/// Reclaims the space of deleted rows and refreshes the statistics the query planner uses
+pub async fn vacuum(db: &DatabaseConnection) -> Result<()> {
+    for statement in ["VACUUM", "ANALYZE"] {
+        db.execute(Statement::from_string(
+            db.get_database_backend(),
+            statement.to_string(),
+        ))
+        .await
+        .into_diagnostic()?;
+    }
+
+    success!("Vacuumed the database");
+    Ok(())
+}
+
+fn orphaned() -> Condition {
+    Condition::any()
+        .add(
+            playlist_entries::Column::SongHash.not_in_subquery(
+                Query::select()
+                    .column(library::Column::Hash)
+                    .from(library::Entity)
+                    .to_owned(),
+            ),
+        )
+        .add(
+            playlist_entries::Column::PlaylistId.not_in_subquery(
+                Query::select()
+                    .column(playlists::Column::Id)
+                    .from(playlists::Entity)
+                    .to_owned(),
+            ),
+        )
+}
+
+async fn pragma(db: &DatabaseConnection, statement: &str) -> Result<Vec<sea_orm::QueryResult>> {
+    db.query_all(Statement::from_string(
+        db.get_database_backend(),
+        statement.to_string(),
+    ))
+    .await
+    .into_diagnostic()
+}
@@ -0,0 +1,650 @@
+use std::{path::PathBuf, time::Duration};
+
+pub use super::playback::{MediaCommand, PlaybackState};
+use miette::Result;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+/// What the OS shows as currently playing
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NowPlaying {
+    pub hash: i64,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub duration: Option<Duration>,
+    pub position: Duration,
+    /// Image file of the album art
+    pub art: Option<PathBuf>,
+    pub state: PlaybackState,
+}
+
+/// The OS media integration of one platform
+trait Platform: Send {
+    fn update(&mut self, now: &NowPlaying) -> Result<()>;
+}
+
+/// Connects Eleanor to the OS media controls: MPRIS on Linux, System Media Transport Controls
+/// on Windows and `MPNowPlayingInfoCenter` on macOS. Commands from media keys and overlays
+/// are sent to the receiver returned by `start`, the player reports back through `update`.
+pub struct MediaKeys {
+    platform: Box<dyn Platform>,
+}
+
+impl MediaKeys {
+    /// Registers with the OS. On macOS, commands are only delivered while the main run loop runs.
+    pub async fn start() -> Result<(Self, UnboundedReceiver<MediaCommand>)> {
+        let (sender, receiver) = unbounded_channel();
+
+        let platform = platform::start(sender).await?;
+
+        Ok((MediaKeys { platform }, receiver))
+    }
+
+    /// Shows a new song or playback state in the OS
+    pub fn update(&mut self, now: &NowPlaying) -> Result<()> {
+        self.platform.update(now)
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use std::collections::HashMap;
+
+    use super::{MediaCommand, NowPlaying, Platform, PlaybackState};
+    use crate::backend::position;
+    use miette::{IntoDiagnostic, Result};
+    use paris::warn;
+    use tokio::sync::{mpsc::UnboundedSender, watch};
+    use zbus::{
+        dbus_interface,
+        zvariant::{ObjectPath, OwnedValue, Value},
+        Connection, ConnectionBuilder, SignalContext,
+    };
+
+    const PATH: &str = "/org/mpris/MediaPlayer2";
+
+    struct Root;
+
+    #[dbus_interface(name = "org.mpris.MediaPlayer2")]
+    impl Root {
+        fn raise(&self) {}
+
+        fn quit(&self) {
+            crate::backend::shutdown::request();
+        }
+
+        #[dbus_interface(property)]
+        fn can_quit(&self) -> bool {
+            true
+        }
+
+        #[dbus_interface(property)]
+        fn can_raise(&self) -> bool {
+            false
+        }
+
+        #[dbus_interface(property)]
+        fn has_track_list(&self) -> bool {
+            false
+        }
+
+        #[dbus_interface(property)]
+        fn identity(&self) -> String {
+            "Eleanor".into()
+        }
+
+        #[dbus_interface(property)]
+        fn supported_uri_schemes(&self) -> Vec<String> {
+            vec![]
+        }
+
+        #[dbus_interface(property)]
+        fn supported_mime_types(&self) -> Vec<String> {
+            vec![]
+        }
+    }
+
+    struct Player {
+        sender: UnboundedSender<MediaCommand>,
+        now: NowPlaying,
+    }
+
+    impl Player {
+        fn send(&self, command: MediaCommand) {
+            let _ = self.sender.send(command);
+        }
+    }
+
+    #[dbus_interface(name = "org.mpris.MediaPlayer2.Player")]
+    impl Player {
+        fn next(&self) {
+            self.send(MediaCommand::Next);
+        }
+
+        fn previous(&self) {
+            self.send(MediaCommand::Previous);
+        }
+
+        fn pause(&self) {
+            self.send(MediaCommand::Pause);
+        }
+
+        fn play_pause(&self) {
+            self.send(MediaCommand::Toggle);
+        }
+
+        fn stop(&self) {
+            self.send(MediaCommand::Stop);
+        }
+
+        fn play(&self) {
+            self.send(MediaCommand::Play);
+        }
+
+        /// Offset in microseconds
+        fn seek(&self, offset: i64) {
+            self.send(MediaCommand::SeekBy(offset / 1000));
+        }
+
+        fn set_position(&self, track_id: ObjectPath<'_>, position: i64) {
+            // Requests for a track that already ended are ignored, as the spec requires
+            if track_id.as_str() == track_path(self.now.hash) && position >= 0 {
+                self.send(MediaCommand::SetPosition(std::time::Duration::from_micros(
+                    position as u64,
+                )));
+            }
+        }
+
+        #[dbus_interface(property)]
+        fn playback_status(&self) -> String {
+            match self.now.state {
+                PlaybackState::Playing => "Playing",
+                PlaybackState::Paused => "Paused",
+                PlaybackState::Stopped => "Stopped",
+            }
+            .into()
+        }
+
+        #[dbus_interface(property)]
+        fn metadata(&self) -> HashMap<String, OwnedValue> {
+            let now = &self.now;
+            let mut metadata = HashMap::new();
+
+            if let Ok(path) = ObjectPath::try_from(track_path(now.hash)) {
+                metadata.insert("mpris:trackid".into(), Value::from(path).into());
+            }
+            if let Some(duration) = now.duration {
+                metadata.insert(
+                    "mpris:length".into(),
+                    Value::from(duration.as_micros() as i64).into(),
+                );
+            }
+            if let Some(title) = &now.title {
+                metadata.insert("xesam:title".into(), Value::from(title.clone()).into());
+            }
+            if let Some(artist) = &now.artist {
+                metadata.insert(
+                    "xesam:artist".into(),
+                    Value::from(vec![artist.clone()]).into(),
+                );
+            }
+            if let Some(album) = &now.album {
+                metadata.insert("xesam:album".into(), Value::from(album.clone()).into());
+            }
+            if let Some(art) = &now.art {
+                metadata.insert(
+                    "mpris:artUrl".into(),
+                    Value::from(format!("file://{}", art.display())).into(),
+                );
+            }
+
+            metadata
+        }
+
+        /// Position in microseconds
+        #[dbus_interface(property)]
+        fn position(&self) -> i64 {
+            let position = match position::current() {
+                Some(current) if current.hash == self.now.hash => current.position,
+                _ => self.now.position,
+            };
+
+            position.as_micros() as i64
+        }
+
+        #[dbus_interface(property)]
+        fn rate(&self) -> f64 {
+            1.0
+        }
+
+        #[dbus_interface(property)]
+        fn minimum_rate(&self) -> f64 {
+            1.0
+        }
+
+        #[dbus_interface(property)]
+        fn maximum_rate(&self) -> f64 {
+            1.0
+        }
+
+        #[dbus_interface(property)]
+        fn can_go_next(&self) -> bool {
+            true
+        }
+
+        #[dbus_interface(property)]
+        fn can_go_previous(&self) -> bool {
+            true
+        }
+
+        #[dbus_interface(property)]
+        fn can_play(&self) -> bool {
+            true
+        }
+
+        #[dbus_interface(property)]
+        fn can_pause(&self) -> bool {
+            true
+        }
+
+        #[dbus_interface(property)]
+        fn can_seek(&self) -> bool {
+            self.now.duration.is_some()
+        }
+
+        #[dbus_interface(property)]
+        fn can_control(&self) -> bool {
+            true
+        }
+    }
+
+    fn track_path(hash: i64) -> String {
+        format!("/org/eleanor/track/{hash:x}")
+    }
+
+    struct Mpris {
+        updates: watch::Sender<NowPlaying>,
+    }
+
+    impl Platform for Mpris {
+        fn update(&mut self, now: &NowPlaying) -> Result<()> {
+            let _ = self.updates.send(now.clone());
+            Ok(())
+        }
+    }
+
+    pub async fn start(sender: UnboundedSender<MediaCommand>) -> Result<Box<dyn Platform>> {
+        let connection = ConnectionBuilder::session()
+            .into_diagnostic()?
+            .name(format!(
+                "org.mpris.MediaPlayer2.eleanor.instance{}",
+                std::process::id()
+            ))
+            .into_diagnostic()?
+            .serve_at(PATH, Root)
+            .into_diagnostic()?
+            .serve_at(
+                PATH,
+                Player {
+                    sender,
+                    now: NowPlaying::default(),
+                },
+            )
+            .into_diagnostic()?
+            .build()
+            .await
+            .into_diagnostic()?;
+
+        let (updates, mut changes) = watch::channel(NowPlaying::default());
+
+        // D-Bus calls are async, so changes are applied by a task
+        tokio::spawn(async move {
+            while changes.changed().await.is_ok() {
+                let now = changes.borrow().clone();
+
+                if let Err(e) = publish(&connection, now).await {
+                    warn!("Couldn't update MPRIS: {e}");
+                }
+            }
+        });
+
+        Ok(Box::new(Mpris { updates }))
+    }
+
+    async fn publish(connection: &Connection, now: NowPlaying) -> zbus::Result<()> {
+        let player = connection
+            .object_server()
+            .interface::<_, Player>(PATH)
+            .await?;
+        let context = SignalContext::new(connection, PATH)?;
+
+        let mut player = player.get_mut().await;
+        let changed_track = player.now.hash != now.hash || player.now.title != now.title;
+        let changed_state = player.now.state != now.state;
+        player.now = now;
+
+        if changed_track {
+            player.metadata_changed(&context).await?;
+            player.can_seek_changed(&context).await?;
+        }
+        if changed_state {
+            player.playback_status_changed(&context).await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod platform {
+    use super::{MediaCommand, NowPlaying, Platform, PlaybackState};
+    use miette::{IntoDiagnostic, Result};
+    use tokio::sync::mpsc::UnboundedSender;
+    use windows::{
+        core::HSTRING,
+        Foundation::{TimeSpan, TypedEventHandler, Uri},
+        Media::{
+            MediaPlaybackStatus, MediaPlaybackType, Playback::MediaPlayer,
+            PlaybackPositionChangeRequestedEventArgs, SystemMediaTransportControls,
+            SystemMediaTransportControlsButton, SystemMediaTransportControlsButtonPressedEventArgs,
+            SystemMediaTransportControlsTimelineProperties,
+        },
+        Storage::Streams::RandomAccessStreamReference,
+    };
+
+    /// System Media Transport Controls, reached through a `MediaPlayer`
+    /// so no window handle is needed
+    struct Smtc {
+        // Keeps the controls registered
+        _player: MediaPlayer,
+        controls: SystemMediaTransportControls,
+    }
+
+    // WinRT objects used here are agile, so they can be used from any thread
+    unsafe impl Send for Smtc {}
+
+    pub async fn start(sender: UnboundedSender<MediaCommand>) -> Result<Box<dyn Platform>> {
+        let player = MediaPlayer::new().into_diagnostic()?;
+        // Otherwise the unused player would handle the buttons itself
+        player
+            .CommandManager()
+            .and_then(|v| v.SetIsEnabled(false))
+            .into_diagnostic()?;
+
+        let controls = player.SystemMediaTransportControls().into_diagnostic()?;
+        controls.SetIsEnabled(true).into_diagnostic()?;
+        controls.SetIsPlayEnabled(true).into_diagnostic()?;
+        controls.SetIsPauseEnabled(true).into_diagnostic()?;
+        controls.SetIsStopEnabled(true).into_diagnostic()?;
+        controls.SetIsNextEnabled(true).into_diagnostic()?;
+        controls.SetIsPreviousEnabled(true).into_diagnostic()?;
+
+        let buttons = sender.clone();
+        controls
+            .ButtonPressed(&TypedEventHandler::<
+                SystemMediaTransportControls,
+                SystemMediaTransportControlsButtonPressedEventArgs,
+            >::new(move |_, args| {
+                let Some(args) = args else {
+                    return Ok(());
+                };
+
+                let command = match args.Button()? {
+                    SystemMediaTransportControlsButton::Play => MediaCommand::Play,
+                    SystemMediaTransportControlsButton::Pause => MediaCommand::Pause,
+                    SystemMediaTransportControlsButton::Stop => MediaCommand::Stop,
+                    SystemMediaTransportControlsButton::Next => MediaCommand::Next,
+                    SystemMediaTransportControlsButton::Previous => MediaCommand::Previous,
+                    _ => return Ok(()),
+                };
+
+                let _ = buttons.send(command);
+                Ok(())
+            }))
+            .into_diagnostic()?;
+
+        controls
+            .PlaybackPositionChangeRequested(&TypedEventHandler::<
+                SystemMediaTransportControls,
+                PlaybackPositionChangeRequestedEventArgs,
+            >::new(move |_, args| {
+                if let Some(args) = args {
+                    let position = args.RequestedPlaybackPosition()?.Duration;
+                    let _ = sender.send(MediaCommand::SetPosition(
+                        std::time::Duration::from_nanos(position.max(0) as u64 * 100),
+                    ));
+                }
+
+                Ok(())
+            }))
+            .into_diagnostic()?;
+
+        Ok(Box::new(Smtc {
+            _player: player,
+            controls,
+        }))
+    }
+
+    /// Durations are counted in 100 ns ticks
+    fn time_span(duration: std::time::Duration) -> TimeSpan {
+        TimeSpan {
+            Duration: (duration.as_nanos() / 100) as i64,
+        }
+    }
+
+    impl Platform for Smtc {
+        fn update(&mut self, now: &NowPlaying) -> Result<()> {
+            let updater = self.controls.DisplayUpdater().into_diagnostic()?;
+            updater
+                .SetType(MediaPlaybackType::Music)
+                .into_diagnostic()?;
+
+            let music = updater.MusicProperties().into_diagnostic()?;
+            music
+                .SetTitle(&HSTRING::from(now.title.clone().unwrap_or_default()))
+                .into_diagnostic()?;
+            music
+                .SetArtist(&HSTRING::from(now.artist.clone().unwrap_or_default()))
+                .into_diagnostic()?;
+            music
+                .SetAlbumTitle(&HSTRING::from(now.album.clone().unwrap_or_default()))
+                .into_diagnostic()?;
+
+            if let Some(art) = &now.art {
+                let uri = Uri::CreateUri(&HSTRING::from(format!("file:///{}", art.display())))
+                    .into_diagnostic()?;
+                updater
+                    .SetThumbnail(
+                        &RandomAccessStreamReference::CreateFromUri(&uri).into_diagnostic()?,
+                    )
+                    .into_diagnostic()?;
+            }
+
+            updater.Update().into_diagnostic()?;
+
+            self.controls
+                .SetPlaybackStatus(match now.state {
+                    PlaybackState::Playing => MediaPlaybackStatus::Playing,
+                    PlaybackState::Paused => MediaPlaybackStatus::Paused,
+                    PlaybackState::Stopped => MediaPlaybackStatus::Stopped,
+                })
+                .into_diagnostic()?;
+
+            if let Some(duration) = now.duration {
+                let timeline =
+                    SystemMediaTransportControlsTimelineProperties::new().into_diagnostic()?;
+                timeline
+                    .SetStartTime(time_span(Default::default()))
+                    .into_diagnostic()?;
+                timeline
+                    .SetMinSeekTime(time_span(Default::default()))
+                    .into_diagnostic()?;
+                timeline.SetEndTime(time_span(duration)).into_diagnostic()?;
+                timeline
+                    .SetMaxSeekTime(time_span(duration))
+                    .into_diagnostic()?;
+                timeline
+                    .SetPosition(time_span(now.position))
+                    .into_diagnostic()?;
+
+                self.controls
+                    .UpdateTimelineProperties(&timeline)
+                    .into_diagnostic()?;
+            }
+
+            Ok(())
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::{MediaCommand, NowPlaying, Platform, PlaybackState};
+    use block::ConcreteBlock;
+    use miette::Result;
+    use objc::{
+        class, msg_send,
+        runtime::{Object, YES},
+        sel, sel_impl,
+    };
+    use tokio::sync::mpsc::UnboundedSender;
+
+    #[link(name = "MediaPlayer", kind = "framework")]
+    extern "C" {
+        static MPMediaItemPropertyTitle: *mut Object;
+        static MPMediaItemPropertyArtist: *mut Object;
+        static MPMediaItemPropertyAlbumTitle: *mut Object;
+        static MPMediaItemPropertyPlaybackDuration: *mut Object;
+        static MPNowPlayingInfoPropertyElapsedPlaybackTime: *mut Object;
+    }
+
+    /// `MPRemoteCommandHandlerStatusSuccess`
+    const HANDLED: isize = 0;
+    const UTF8_ENCODING: usize = 4;
+
+    /// `MPNowPlayingInfoCenter` and `MPRemoteCommandCenter`, which are process wide singletons
+    struct NowPlayingCenter;
+
+    pub async fn start(sender: UnboundedSender<MediaCommand>) -> Result<Box<dyn Platform>> {
+        unsafe {
+            let center: *mut Object = msg_send![class!(MPRemoteCommandCenter), sharedCommandCenter];
+
+            let commands: [(*mut Object, MediaCommand); 6] = [
+                (msg_send![center, playCommand], MediaCommand::Play),
+                (msg_send![center, pauseCommand], MediaCommand::Pause),
+                (
+                    msg_send![center, togglePlayPauseCommand],
+                    MediaCommand::Toggle,
+                ),
+                (msg_send![center, stopCommand], MediaCommand::Stop),
+                (msg_send![center, nextTrackCommand], MediaCommand::Next),
+                (
+                    msg_send![center, previousTrackCommand],
+                    MediaCommand::Previous,
+                ),
+            ];
+
+            for (command, action) in commands {
+                let sender = sender.clone();
+                let handler = ConcreteBlock::new(move |_event: *mut Object| -> isize {
+                    let _ = sender.send(action);
+                    HANDLED
+                })
+                .copy();
+
+                let _: () = msg_send![command, setEnabled: YES];
+                let _: *mut Object = msg_send![command, addTargetWithHandler: &*handler];
+                // The command center keeps the handler
+                std::mem::forget(handler);
+            }
+
+            let seek: *mut Object = msg_send![center, changePlaybackPositionCommand];
+            let handler = ConcreteBlock::new(move |event: *mut Object| -> isize {
+                let position: f64 = msg_send![event, positionTime];
+                let _ = sender.send(MediaCommand::SetPosition(
+                    std::time::Duration::from_secs_f64(position.max(0.0)),
+                ));
+                HANDLED
+            })
+            .copy();
+
+            let _: () = msg_send![seek, setEnabled: YES];
+            let _: *mut Object = msg_send![seek, addTargetWithHandler: &*handler];
+            std::mem::forget(handler);
+        }
+
+        Ok(Box::new(NowPlayingCenter))
+    }
+
+    unsafe fn ns_string(text: &str) -> *mut Object {
+        let string: *mut Object = msg_send![class!(NSString), alloc];
+        msg_send![string, initWithBytes: text.as_ptr() length: text.len() encoding: UTF8_ENCODING]
+    }
+
+    unsafe fn ns_number(value: f64) -> *mut Object {
+        msg_send![class!(NSNumber), numberWithDouble: value]
+    }
+
+    impl Platform for NowPlayingCenter {
+        fn update(&mut self, now: &NowPlaying) -> Result<()> {
+            unsafe {
+                let info: *mut Object = msg_send![class!(NSMutableDictionary), new];
+
+                let texts = [
+                    (MPMediaItemPropertyTitle, &now.title),
+                    (MPMediaItemPropertyArtist, &now.artist),
+                    (MPMediaItemPropertyAlbumTitle, &now.album),
+                ];
+
+                for (key, value) in texts {
+                    if let Some(value) = value {
+                        let value = ns_string(value);
+                        let _: () = msg_send![info, setObject: value forKey: key];
+                        let _: () = msg_send![value, release];
+                    }
+                }
+
+                if let Some(duration) = now.duration {
+                    let _: () = msg_send![info, setObject: ns_number(duration.as_secs_f64())
+                        forKey: MPMediaItemPropertyPlaybackDuration];
+                }
+                let _: () = msg_send![info, setObject: ns_number(now.position.as_secs_f64())
+                    forKey: MPNowPlayingInfoPropertyElapsedPlaybackTime];
+
+                let center: *mut Object = msg_send![class!(MPNowPlayingInfoCenter), defaultCenter];
+                let _: () = msg_send![center, setNowPlayingInfo: info];
+                let _: () = msg_send![info, release];
+
+                // MPNowPlayingPlaybackState
+                let state: usize = match now.state {
+                    PlaybackState::Playing => 1,
+                    PlaybackState::Paused => 2,
+                    PlaybackState::Stopped => 3,
+                };
+                let _: () = msg_send![center, setPlaybackState: state];
+            }
+
+            Ok(())
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows", target_os = "macos")))]
+mod platform {
+    use super::{MediaCommand, NowPlaying, Platform};
+    use miette::Result;
+    use tokio::sync::mpsc::UnboundedSender;
+
+    /// Media controls aren't supported on this platform
+    struct Unsupported;
+
+    impl Platform for Unsupported {
+        fn update(&mut self, _now: &NowPlaying) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    pub async fn start(_sender: UnboundedSender<MediaCommand>) -> Result<Box<dyn Platform>> {
+        Ok(Box::new(Unsupported))
+    }
+}
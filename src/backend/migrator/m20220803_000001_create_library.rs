@@ -65,4 +65,42 @@ pub enum Song {
     /// Number of the track in the album
     Track,
     Year,
+    /// Number of the disc in the album
+    Disc,
+    /// ReplayGain adjustment in dB
+    RgTrackGain,
+    /// Linear sample peak, where 1.0 is full scale
+    RgTrackPeak,
+    RgAlbumGain,
+    RgAlbumPeak,
+    /// Whether the song is part of an album by various artists
+    Compilation,
+    /// Description of the channels, like "stereo" or "5.1"
+    ChannelLayout,
+    /// When the song was first and last played, in seconds since the Unix epoch.
+    /// Copied from the history so songs can be sorted by them without a join.
+    FirstPlayed,
+    LastPlayed,
+    /// Short name of the codec, like "flac" or "mp3"
+    Codec,
+    /// Audio bitrate in kbps
+    Bitrate,
+    /// Sample rate in Hz
+    SampleRate,
+    /// Bits per sample, only known for lossless formats
+    BitDepth,
+    /// Number of channels
+    Channels,
+    Composer,
+    Comment,
+    /// JSON object of the tags without a column of their own, see `extra_tags::extra_tags`
+    ExtraTags,
+    /// When the song was first indexed, in seconds since the Unix epoch
+    AddedDate,
+    /// Size of the file in bytes when it was last read, see `fetching::IndexMode::Metadata`
+    FileSize,
+    /// When the file was last modified as of reading it, in seconds since the Unix epoch
+    FileModified,
+    /// Bytes of audio the hash covers, see `hashes::HashScope`. Empty if it covers all of it.
+    HashBytes,
 }
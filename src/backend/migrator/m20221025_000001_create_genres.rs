@@ -0,0 +1,48 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Genre::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Genre::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(Genre::Name)
+                            .string()
+                            .not_null()
+                            .unique_key()
+                            .extra("COLLATE NOCASE".into()),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Genre::Table).to_owned())
+            .await
+    }
+}
+
+/// A Table containing normalized genre names
+#[derive(Iden)]
+pub enum Genre {
+    #[iden = "genres"]
+    Table,
+    Id,
+    /// Title cased name, compared case insensitively
+    Name,
+}
@@ -0,0 +1,73 @@
+use sea_orm_migration::prelude::*;
+
+use super::{m20220803_000001_create_library::Song, m20221025_000001_create_genres::Genre};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(SongGenre::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(SongGenre::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(SongGenre::SongHash).integer().not_null())
+                    .col(ColumnDef::new(SongGenre::GenreId).integer().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-song-genre-hash")
+                            .from(SongGenre::Table, SongGenre::SongHash)
+                            .to(Song::Table, Song::Hash)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-song-genre-id")
+                            .from(SongGenre::Table, SongGenre::GenreId)
+                            .to(Genre::Table, Genre::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-song-genre")
+                    .table(SongGenre::Table)
+                    .col(SongGenre::SongHash)
+                    .col(SongGenre::GenreId)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(SongGenre::Table).to_owned())
+            .await
+    }
+}
+
+/// A Table containing mappings between songs and their genres
+#[derive(Iden)]
+pub enum SongGenre {
+    #[iden = "song_genres"]
+    Table,
+    Id,
+    /// Hash of the song
+    SongHash,
+    /// Id of one of the song's genres
+    GenreId,
+}
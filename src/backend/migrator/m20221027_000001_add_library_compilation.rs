@@ -0,0 +1,36 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20220803_000001_create_library::Song;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Song::Table)
+                    .add_column(
+                        ColumnDef::new(Song::Compilation)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Song::Table)
+                    .drop_column(Song::Compilation)
+                    .to_owned(),
+            )
+            .await
+    }
+}
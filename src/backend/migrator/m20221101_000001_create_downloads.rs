@@ -0,0 +1,73 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20220803_000001_create_library::Song;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Download::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Download::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(Download::SongHash)
+                            .integer()
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .col(ColumnDef::new(Download::Status).string().not_null())
+                    .col(
+                        ColumnDef::new(Download::Downloaded)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(ColumnDef::new(Download::Size).big_integer())
+                    .col(ColumnDef::new(Download::Error).string())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-download-hash")
+                            .from(Download::Table, Download::SongHash)
+                            .to(Song::Table, Song::Hash)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Download::Table).to_owned())
+            .await
+    }
+}
+
+/// Remote songs that are kept in the cache for offline playback
+#[derive(Iden)]
+pub enum Download {
+    #[iden = "downloads"]
+    Table,
+    Id,
+    /// Hash of the song
+    SongHash,
+    /// Either pending, downloading, completed or failed
+    Status,
+    /// Number of bytes downloaded so far
+    Downloaded,
+    /// Size of the file in bytes, once known
+    Size,
+    /// Why the last attempt failed
+    Error,
+}
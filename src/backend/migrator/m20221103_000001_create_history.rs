@@ -0,0 +1,62 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20220803_000001_create_library::Song;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(History::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(History::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(History::SongHash).integer().not_null())
+                    .col(ColumnDef::new(History::PlayedAt).big_integer().not_null())
+                    .col(
+                        ColumnDef::new(History::Finished)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-history-hash")
+                            .from(History::Table, History::SongHash)
+                            .to(Song::Table, Song::Hash)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(History::Table).to_owned())
+            .await
+    }
+}
+
+/// Songs that were played, one row per play
+#[derive(Iden)]
+pub enum History {
+    #[iden = "history"]
+    Table,
+    Id,
+    /// Hash of the song
+    SongHash,
+    /// When the song stopped playing, in seconds since the Unix epoch
+    PlayedAt,
+    /// Whether the song played until the end, rather than being skipped
+    Finished,
+}
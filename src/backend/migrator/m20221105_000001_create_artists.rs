@@ -0,0 +1,48 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Artist::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Artist::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(Artist::Name)
+                            .string()
+                            .not_null()
+                            .unique_key()
+                            .extra("COLLATE NOCASE".into()),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Artist::Table).to_owned())
+            .await
+    }
+}
+
+/// A Table containing individual artists, split out of artist tags
+#[derive(Iden)]
+pub enum Artist {
+    #[iden = "artists"]
+    Table,
+    Id,
+    /// Name as it appears in tags, compared case insensitively
+    Name,
+}
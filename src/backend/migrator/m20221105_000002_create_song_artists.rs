@@ -0,0 +1,73 @@
+use sea_orm_migration::prelude::*;
+
+use super::{m20220803_000001_create_library::Song, m20221105_000001_create_artists::Artist};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(SongArtist::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(SongArtist::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(SongArtist::SongHash).integer().not_null())
+                    .col(ColumnDef::new(SongArtist::ArtistId).integer().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-song-artist-hash")
+                            .from(SongArtist::Table, SongArtist::SongHash)
+                            .to(Song::Table, Song::Hash)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-song-artist-id")
+                            .from(SongArtist::Table, SongArtist::ArtistId)
+                            .to(Artist::Table, Artist::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-song-artist")
+                    .table(SongArtist::Table)
+                    .col(SongArtist::SongHash)
+                    .col(SongArtist::ArtistId)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(SongArtist::Table).to_owned())
+            .await
+    }
+}
+
+/// A Table containing mappings between songs and their artists
+#[derive(Iden)]
+pub enum SongArtist {
+    #[iden = "song_artists"]
+    Table,
+    Id,
+    /// Hash of the song
+    SongHash,
+    /// Id of one of the song's artists
+    ArtistId,
+}
@@ -0,0 +1,66 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20220803_000001_create_library::Song;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(RemoteFile::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(RemoteFile::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(RemoteFile::SongHash)
+                            .integer()
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .col(ColumnDef::new(RemoteFile::Size).big_integer().not_null())
+                    .col(
+                        ColumnDef::new(RemoteFile::StreamedAt)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-remote-file-hash")
+                            .from(RemoteFile::Table, RemoteFile::SongHash)
+                            .to(Song::Table, Song::Hash)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(RemoteFile::Table).to_owned())
+            .await
+    }
+}
+
+/// Remote songs that were streamed completely at least once
+#[derive(Iden)]
+pub enum RemoteFile {
+    #[iden = "remote_files"]
+    Table,
+    Id,
+    /// Hash of the song
+    SongHash,
+    /// Size of the file in bytes
+    Size,
+    /// When the song was last streamed, in seconds since the Unix epoch
+    StreamedAt,
+}
@@ -0,0 +1,70 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20220803_000001_create_library::Song;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(SongStats::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(SongStats::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(SongStats::SongHash)
+                            .integer()
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .col(ColumnDef::new(SongStats::Rating).integer())
+                    .col(
+                        ColumnDef::new(SongStats::ImportedPlays)
+                            .integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(ColumnDef::new(SongStats::ImportedLastPlayed).big_integer())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-song-stats-hash")
+                            .from(SongStats::Table, SongStats::SongHash)
+                            .to(Song::Table, Song::Hash)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(SongStats::Table).to_owned())
+            .await
+    }
+}
+
+/// Ratings, and play counts from before a song was played in Eleanor
+#[derive(Iden)]
+pub enum SongStats {
+    #[iden = "song_stats"]
+    Table,
+    Id,
+    /// Hash of the song
+    SongHash,
+    /// From 0 to 100, 20 per star
+    Rating,
+    /// Plays imported from another player, on top of the ones in the history
+    ImportedPlays,
+    /// When the song was last played in another player, in seconds since the Unix epoch
+    ImportedLastPlayed,
+}
@@ -0,0 +1,76 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20220803_000001_create_library::Song;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(TagEdit::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(TagEdit::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(TagEdit::Batch).integer().not_null())
+                    .col(ColumnDef::new(TagEdit::SongHash).integer().not_null())
+                    .col(ColumnDef::new(TagEdit::Field).string().not_null())
+                    .col(ColumnDef::new(TagEdit::OldValue).string())
+                    .col(ColumnDef::new(TagEdit::NewValue).string())
+                    .col(ColumnDef::new(TagEdit::EditedAt).big_integer().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-tag-edit-hash")
+                            .from(TagEdit::Table, TagEdit::SongHash)
+                            .to(Song::Table, Song::Hash)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-tag-edit-batch")
+                    .table(TagEdit::Table)
+                    .col(TagEdit::Batch)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(TagEdit::Table).to_owned())
+            .await
+    }
+}
+
+/// Log of changes made to tags, so they can be undone
+#[derive(Iden)]
+pub enum TagEdit {
+    #[iden = "tag_edits"]
+    Table,
+    Id,
+    /// Edits made together share a batch, and are undone together
+    Batch,
+    /// Hash of the song
+    SongHash,
+    /// Tag that was changed
+    Field,
+    /// Value before the edit, `NULL` if the tag was empty
+    OldValue,
+    /// Value after the edit, `NULL` if the tag was removed
+    NewValue,
+    /// When the edit was made, in seconds since the Unix epoch
+    EditedAt,
+}
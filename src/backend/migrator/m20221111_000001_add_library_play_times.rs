@@ -0,0 +1,58 @@
+use sea_orm_migration::{
+    prelude::*,
+    sea_orm::{ConnectionTrait, Statement},
+};
+
+use super::m20220803_000001_create_library::Song;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // SQLite only supports adding a single column per statement
+        for mut column in [
+            ColumnDef::new(Song::FirstPlayed).big_integer().to_owned(),
+            ColumnDef::new(Song::LastPlayed).big_integer().to_owned(),
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Song::Table)
+                        .add_column(&mut column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        // Songs played before the columns existed get their times from the history
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                "UPDATE library SET \
+                    first_played = (SELECT MIN(played_at) FROM history WHERE song_hash = library.hash), \
+                    last_played = (SELECT MAX(played_at) FROM history WHERE song_hash = library.hash)"
+                    .to_string(),
+            ))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for column in [Song::FirstPlayed, Song::LastPlayed] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Song::Table)
+                        .drop_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+}
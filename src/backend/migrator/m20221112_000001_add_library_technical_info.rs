@@ -0,0 +1,52 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20220803_000001_create_library::Song;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // SQLite only supports adding a single column per statement
+        for mut column in [
+            ColumnDef::new(Song::Codec).string().to_owned(),
+            ColumnDef::new(Song::Bitrate).integer().to_owned(),
+            ColumnDef::new(Song::SampleRate).integer().to_owned(),
+            ColumnDef::new(Song::BitDepth).integer().to_owned(),
+            ColumnDef::new(Song::Channels).integer().to_owned(),
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Song::Table)
+                        .add_column(&mut column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for column in [
+            Song::Codec,
+            Song::Bitrate,
+            Song::SampleRate,
+            Song::BitDepth,
+            Song::Channels,
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Song::Table)
+                        .drop_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,58 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Podcast::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Podcast::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(Podcast::FeedUrl)
+                            .string()
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .col(ColumnDef::new(Podcast::Title).string().not_null())
+                    .col(ColumnDef::new(Podcast::Author).string())
+                    .col(ColumnDef::new(Podcast::Description).string())
+                    .col(ColumnDef::new(Podcast::ImageUrl).string())
+                    .col(ColumnDef::new(Podcast::LastChecked).big_integer())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Podcast::Table).to_owned())
+            .await
+    }
+}
+
+/// Podcast feeds the user subscribed to
+#[derive(Iden)]
+pub enum Podcast {
+    #[iden = "podcasts"]
+    Table,
+    Id,
+    /// RSS or Atom feed the episodes are read from
+    FeedUrl,
+    Title,
+    Author,
+    Description,
+    ImageUrl,
+    /// When the feed was last fetched, in seconds since the Unix epoch
+    LastChecked,
+}
@@ -0,0 +1,113 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20221114_000001_create_podcasts::Podcast;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(PodcastEpisode::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(PodcastEpisode::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(PodcastEpisode::PodcastId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(PodcastEpisode::Hash)
+                            .integer()
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .col(ColumnDef::new(PodcastEpisode::Guid).string().not_null())
+                    .col(ColumnDef::new(PodcastEpisode::Title).string().not_null())
+                    .col(ColumnDef::new(PodcastEpisode::Description).string())
+                    .col(ColumnDef::new(PodcastEpisode::Url).string().not_null())
+                    .col(ColumnDef::new(PodcastEpisode::MimeType).string())
+                    .col(ColumnDef::new(PodcastEpisode::Published).big_integer())
+                    .col(ColumnDef::new(PodcastEpisode::Duration).integer())
+                    .col(
+                        ColumnDef::new(PodcastEpisode::PositionMs)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(
+                        ColumnDef::new(PodcastEpisode::Finished)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .col(
+                        ColumnDef::new(PodcastEpisode::Downloaded)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-podcast-episode-podcast")
+                            .from(PodcastEpisode::Table, PodcastEpisode::PodcastId)
+                            .to(Podcast::Table, Podcast::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-podcast-episode-podcast")
+                    .table(PodcastEpisode::Table)
+                    .col(PodcastEpisode::PodcastId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PodcastEpisode::Table).to_owned())
+            .await
+    }
+}
+
+/// Episodes of subscribed podcasts, with how far they were listened to
+#[derive(Iden)]
+pub enum PodcastEpisode {
+    #[iden = "podcast_episodes"]
+    Table,
+    Id,
+    PodcastId,
+    /// Identifies the episode in the queue, like songs are identified by their hash
+    Hash,
+    /// The feed's id of the episode, which stays the same when its other details change
+    Guid,
+    Title,
+    Description,
+    /// Where the audio is downloaded or streamed from
+    Url,
+    MimeType,
+    /// In seconds since the Unix epoch
+    Published,
+    /// In seconds, as stated by the feed
+    Duration,
+    /// Where playback stopped, so the episode can be resumed
+    PositionMs,
+    Finished,
+    /// Whether the episode was downloaded for offline playback
+    Downloaded,
+}
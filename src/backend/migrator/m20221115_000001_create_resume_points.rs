@@ -0,0 +1,68 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20220803_000001_create_library::Song;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(ResumePoint::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ResumePoint::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(ResumePoint::SongHash)
+                            .integer()
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .col(
+                        ColumnDef::new(ResumePoint::PositionMs)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(ResumePoint::UpdatedAt)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-resume-point-hash")
+                            .from(ResumePoint::Table, ResumePoint::SongHash)
+                            .to(Song::Table, Song::Hash)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ResumePoint::Table).to_owned())
+            .await
+    }
+}
+
+/// Where playback of audiobook tracks stopped, so books can be picked up again
+#[derive(Iden)]
+pub enum ResumePoint {
+    #[iden = "resume_points"]
+    Table,
+    Id,
+    SongHash,
+    PositionMs,
+    /// When the position was saved, in seconds since the Unix epoch
+    UpdatedAt,
+}
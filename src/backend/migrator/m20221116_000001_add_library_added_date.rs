@@ -0,0 +1,52 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20220803_000001_create_library::Song;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Songs indexed before the column existed are left without a date,
+        // so upgrading doesn't list the whole library as recently added
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Song::Table)
+                    .add_column(ColumnDef::new(Song::AddedDate).big_integer())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-library-added-date")
+                    .table(Song::Table)
+                    .col(Song::AddedDate)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx-library-added-date")
+                    .table(Song::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Song::Table)
+                    .drop_column(Song::AddedDate)
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,69 @@
+use sea_orm_migration::{prelude::*, sea_orm::ConnectionTrait};
+
+use super::m20220803_000001_create_library::Song;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(LegacyHash::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(LegacyHash::SongHash)
+                            .integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-legacy-hash-hash")
+                            .from(LegacyHash::Table, LegacyHash::SongHash)
+                            .to(Song::Table, Song::Hash)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // SQLite keeps integers in up to 8 bytes whatever the column was declared as,
+        // so the hash columns already fit 64 bit hashes. Only the values need replacing,
+        // which happens as songs are indexed or synced again.
+        let existing = Query::insert()
+            .into_table(LegacyHash::Table)
+            .columns([LegacyHash::SongHash])
+            .select_from(
+                Query::select()
+                    .column(Song::Hash)
+                    .from(Song::Table)
+                    .to_owned(),
+            )
+            .map_err(|e| DbErr::Custom(e.to_string()))?
+            .to_owned();
+
+        let db = manager.get_connection();
+        db.execute(db.get_database_backend().build(&existing))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(LegacyHash::Table).to_owned())
+            .await
+    }
+}
+
+/// Songs still stored under the Adler-32 hash of their audio, from before songs were
+/// hashed with XXH64
+#[derive(Iden)]
+pub enum LegacyHash {
+    #[iden = "legacy_hashes"]
+    Table,
+    SongHash,
+}
@@ -0,0 +1,73 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(ScanIssue::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ScanIssue::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(ScanIssue::SourceId).integer().not_null())
+                    .col(ColumnDef::new(ScanIssue::Path).string().not_null())
+                    .col(ColumnDef::new(ScanIssue::Filename).string().not_null())
+                    .col(ColumnDef::new(ScanIssue::Status).string().not_null())
+                    .col(ColumnDef::new(ScanIssue::Message).string())
+                    .col(
+                        ColumnDef::new(ScanIssue::CheckedAt)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-scan-issue-file")
+                    .table(ScanIssue::Table)
+                    .col(ScanIssue::SourceId)
+                    .col(ScanIssue::Path)
+                    .col(ScanIssue::Filename)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ScanIssue::Table).to_owned())
+            .await
+    }
+}
+
+/// Files that indexing couldn't read completely, or songs whose file is gone.
+/// Files that were read without problems have no row.
+#[derive(Iden)]
+pub enum ScanIssue {
+    #[iden = "scan_issues"]
+    Table,
+    Id,
+    SourceId,
+    /// Directory of the file, like the path of songs in the library
+    Path,
+    Filename,
+    /// What went wrong, see `ScanStatus`
+    Status,
+    /// The error that was encountered, if there was one
+    Message,
+    /// When the file was last indexed, in seconds since the Unix epoch
+    CheckedAt,
+}
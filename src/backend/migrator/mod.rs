@@ -3,6 +3,29 @@ use sea_orm_migration::prelude::*;
 mod m20220803_000001_create_library;
 mod m20220803_000001_create_playlist_entries;
 mod m20220803_000001_create_playlists;
+mod m20221020_000001_add_library_analysis;
+mod m20221025_000001_create_genres;
+mod m20221025_000002_create_song_genres;
+mod m20221027_000001_add_library_compilation;
+mod m20221101_000001_create_downloads;
+mod m20221103_000001_create_history;
+mod m20221105_000001_create_artists;
+mod m20221105_000002_create_song_artists;
+mod m20221107_000001_create_remote_files;
+mod m20221108_000001_create_song_stats;
+mod m20221109_000001_create_tag_edits;
+mod m20221110_000001_add_library_channel_layout;
+mod m20221111_000001_add_library_play_times;
+mod m20221112_000001_add_library_technical_info;
+mod m20221113_000001_add_library_extra_tags;
+mod m20221114_000001_create_podcasts;
+mod m20221114_000002_create_podcast_episodes;
+mod m20221115_000001_create_resume_points;
+mod m20221116_000001_add_library_added_date;
+mod m20221117_000001_create_legacy_hashes;
+mod m20221118_000001_add_library_file_info;
+mod m20221119_000001_create_scan_issues;
+mod m20221120_000001_add_library_hash_bytes;
 
 pub struct Migrator;
 
@@ -13,6 +36,29 @@ impl MigratorTrait for Migrator {
             Box::new(m20220803_000001_create_library::Migration),
             Box::new(m20220803_000001_create_playlists::Migration),
             Box::new(m20220803_000001_create_playlist_entries::Migration),
+            Box::new(m20221020_000001_add_library_analysis::Migration),
+            Box::new(m20221025_000001_create_genres::Migration),
+            Box::new(m20221025_000002_create_song_genres::Migration),
+            Box::new(m20221027_000001_add_library_compilation::Migration),
+            Box::new(m20221101_000001_create_downloads::Migration),
+            Box::new(m20221103_000001_create_history::Migration),
+            Box::new(m20221105_000001_create_artists::Migration),
+            Box::new(m20221105_000002_create_song_artists::Migration),
+            Box::new(m20221107_000001_create_remote_files::Migration),
+            Box::new(m20221108_000001_create_song_stats::Migration),
+            Box::new(m20221109_000001_create_tag_edits::Migration),
+            Box::new(m20221110_000001_add_library_channel_layout::Migration),
+            Box::new(m20221111_000001_add_library_play_times::Migration),
+            Box::new(m20221112_000001_add_library_technical_info::Migration),
+            Box::new(m20221113_000001_add_library_extra_tags::Migration),
+            Box::new(m20221114_000001_create_podcasts::Migration),
+            Box::new(m20221114_000002_create_podcast_episodes::Migration),
+            Box::new(m20221115_000001_create_resume_points::Migration),
+            Box::new(m20221116_000001_add_library_added_date::Migration),
+            Box::new(m20221117_000001_create_legacy_hashes::Migration),
+            Box::new(m20221118_000001_add_library_file_info::Migration),
+            Box::new(m20221119_000001_create_scan_issues::Migration),
+            Box::new(m20221120_000001_add_library_hash_bytes::Migration),
         ]
     }
 }
@@ -1,9 +1,83 @@
+#[cfg(feature = "acoustid")]
+pub mod acoustid;
+pub mod artists;
+pub mod artwork;
+pub mod audiobooks;
+pub mod availability;
+pub mod backup;
+pub mod browse;
+pub mod buffering;
+pub mod cancellation;
+pub mod channels;
+pub mod chapters;
+pub mod compilations;
+pub mod composers;
 pub mod config;
+pub mod details;
+pub mod diagnostics;
+pub mod downloads;
+pub mod duplicates;
+pub mod equalizer;
+pub mod errors;
+pub mod events;
+pub mod exclusions;
+pub mod export;
+pub mod external;
+pub mod extra_tags;
 pub mod fetching;
+pub mod genres;
+pub mod hashes;
+pub mod health;
+pub mod history;
+#[cfg(feature = "hotkeys")]
+pub mod hotkeys;
+#[cfg(feature = "http_api")]
+pub mod http_api;
+pub mod import;
+pub mod loudness;
+pub mod lyrics;
+pub mod maintenance;
+#[cfg(feature = "media_keys")]
+pub mod media_keys;
 mod migrator;
 pub mod model;
+pub mod network;
+#[cfg(feature = "online_artwork")]
+pub mod online_artwork;
+pub mod output;
+pub mod party;
+pub mod paths;
 pub mod playback;
+#[cfg(feature = "plugins")]
+pub mod plugins;
+pub mod podcasts;
+pub mod position;
+pub mod prefetch;
+pub mod presets;
+pub mod queue;
+pub mod replaygain;
+pub mod resampler;
+pub mod scan_status;
+pub mod scheduler;
+pub mod shutdown;
+pub mod sleep_timer;
+pub mod spectrum;
+pub mod stats;
+pub mod streaming;
+pub mod subsonic;
+pub mod sync;
+pub mod tag_cleanup;
+pub mod tagging;
+pub mod tempo;
+#[cfg(test)]
+mod test_util;
+pub mod ui_state;
+pub mod undo;
+pub mod upgrade;
 pub mod utils;
+pub mod vfs;
+pub mod waveform;
+pub mod webdav;
 
 use std::fs::{create_dir_all, File};
 
@@ -29,15 +103,26 @@ pub fn create_app_data() -> Result<()> {
     // Create Eleanor's cache directory
     create_dir_all(&cache_path).into_diagnostic()?;
 
-    File::create(&config_path.join("eleanor.db")).into_diagnostic()?;
+    File::create(config_path.join("eleanor.db")).into_diagnostic()?;
     Config::write_config(&Default::default())?;
     success!("Created configuration file");
 
     Ok(())
 }
 
-/// Run unapplied migrations
+/// Run unapplied migrations. The database is snapshotted first, unless it's empty.
 pub async fn prepare_db(db: &sea_orm::DatabaseConnection) -> Result<()> {
+    let applied = Migrator::get_applied_migrations(db)
+        .await
+        .into_diagnostic()?;
+    let pending = Migrator::get_pending_migrations(db)
+        .await
+        .into_diagnostic()?;
+
+    if !applied.is_empty() && !pending.is_empty() {
+        backup::snapshot("migration", db).await?;
+    }
+
     Migrator::up(db, None).await.into_diagnostic()?;
 
     success!("Applied migrations");
@@ -11,7 +11,7 @@ pub struct Model {
     pub path: String,
     pub filename: String,
     pub source_id: i32,
-    pub hash: u32,
+    pub hash: i64,
     pub artist: Option<String>,
     pub album_artist: Option<String>,
     pub name: Option<String>,
@@ -20,12 +20,80 @@ pub struct Model {
     pub genres: Option<String>,
     pub track: Option<i32>,
     pub year: Option<i32>,
+    #[serde(default)]
+    pub disc: Option<i32>,
+    #[serde(default)]
+    pub rg_track_gain: Option<f32>,
+    #[serde(default)]
+    pub rg_track_peak: Option<f32>,
+    #[serde(default)]
+    pub rg_album_gain: Option<f32>,
+    #[serde(default)]
+    pub rg_album_peak: Option<f32>,
+    #[serde(default)]
+    pub compilation: bool,
+    #[serde(default)]
+    pub channel_layout: Option<String>,
+    #[serde(default)]
+    pub first_played: Option<i64>,
+    #[serde(default)]
+    pub last_played: Option<i64>,
+    #[serde(default)]
+    pub codec: Option<String>,
+    #[serde(default)]
+    pub bitrate: Option<i32>,
+    #[serde(default)]
+    pub sample_rate: Option<i32>,
+    #[serde(default)]
+    pub bit_depth: Option<i32>,
+    #[serde(default)]
+    pub channels: Option<i32>,
+    #[serde(default)]
+    pub composer: Option<String>,
+    #[serde(default)]
+    pub comment: Option<String>,
+    #[serde(default)]
+    pub extra_tags: Option<Json>,
+    #[serde(default)]
+    pub added_date: Option<i64>,
+    #[serde(default)]
+    pub file_size: Option<i64>,
+    #[serde(default)]
+    pub file_modified: Option<i64>,
+    #[serde(default)]
+    pub hash_bytes: Option<i64>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
+    #[sea_orm(has_many = "super::downloads::Entity")]
+    Downloads,
+    #[sea_orm(has_many = "super::history::Entity")]
+    History,
     #[sea_orm(has_many = "super::playlist_entries::Entity")]
     PlaylistEntries,
+    #[sea_orm(has_many = "super::remote_files::Entity")]
+    RemoteFiles,
+    #[sea_orm(has_many = "super::song_artists::Entity")]
+    SongArtists,
+    #[sea_orm(has_many = "super::song_genres::Entity")]
+    SongGenres,
+    #[sea_orm(has_many = "super::song_stats::Entity")]
+    SongStats,
+    #[sea_orm(has_many = "super::tag_edits::Entity")]
+    TagEdits,
+}
+
+impl Related<super::downloads::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Downloads.def()
+    }
+}
+
+impl Related<super::history::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::History.def()
+    }
 }
 
 impl Related<super::playlist_entries::Entity> for Entity {
@@ -34,4 +102,54 @@ impl Related<super::playlist_entries::Entity> for Entity {
     }
 }
 
+impl Related<super::remote_files::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::RemoteFiles.def()
+    }
+}
+
+impl Related<super::song_artists::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::SongArtists.def()
+    }
+}
+
+impl Related<super::artists::Entity> for Entity {
+    fn to() -> RelationDef {
+        super::song_artists::Relation::Artists.def()
+    }
+
+    fn via() -> Option<RelationDef> {
+        Some(super::song_artists::Relation::Library.def().rev())
+    }
+}
+
+impl Related<super::song_genres::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::SongGenres.def()
+    }
+}
+
+impl Related<super::song_stats::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::SongStats.def()
+    }
+}
+
+impl Related<super::tag_edits::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::TagEdits.def()
+    }
+}
+
+impl Related<super::genres::Entity> for Entity {
+    fn to() -> RelationDef {
+        super::song_genres::Relation::Genres.def()
+    }
+
+    fn via() -> Option<RelationDef> {
+        Some(super::song_genres::Relation::Library.def().rev())
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
@@ -2,6 +2,21 @@
 
 pub mod prelude;
 
+pub mod artists;
+pub mod downloads;
+pub mod genres;
+pub mod history;
+pub mod legacy_hashes;
 pub mod library;
 pub mod playlist_entries;
 pub mod playlists;
+pub mod podcast_episodes;
+pub mod podcasts;
+pub mod remote_files;
+pub mod resume_points;
+pub mod scan_issues;
+pub mod sea_orm_active_enums;
+pub mod song_artists;
+pub mod song_genres;
+pub mod song_stats;
+pub mod tag_edits;
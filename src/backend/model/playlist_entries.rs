@@ -9,7 +9,7 @@ pub struct Model {
     #[sea_orm(primary_key)]
     pub id: i32,
     pub playlist_id: i32,
-    pub song_hash: i32,
+    pub song_hash: i64,
     pub ordinal: Option<i32>,
     pub added_date: Option<i32>,
 }
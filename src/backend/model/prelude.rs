@@ -1,5 +1,19 @@
 //! SeaORM Entity. Generated by sea-orm-codegen 0.9.1
 
+pub use super::artists::Entity as Artists;
+pub use super::downloads::Entity as Downloads;
+pub use super::genres::Entity as Genres;
+pub use super::history::Entity as History;
+pub use super::legacy_hashes::Entity as LegacyHashes;
 pub use super::library::Entity as Library;
 pub use super::playlist_entries::Entity as PlaylistEntries;
 pub use super::playlists::Entity as Playlists;
+pub use super::podcast_episodes::Entity as PodcastEpisodes;
+pub use super::podcasts::Entity as Podcasts;
+pub use super::remote_files::Entity as RemoteFiles;
+pub use super::resume_points::Entity as ResumePoints;
+pub use super::scan_issues::Entity as ScanIssues;
+pub use super::song_artists::Entity as SongArtists;
+pub use super::song_genres::Entity as SongGenres;
+pub use super::song_stats::Entity as SongStats;
+pub use super::tag_edits::Entity as TagEdits;
@@ -0,0 +1,146 @@
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use super::{
+    config::Config,
+    events::{publish, subscribe, Event},
+};
+use paris::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::broadcast::error::RecvError, task::JoinHandle};
+
+/// How often the connection is checked again in `auto` mode
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Whether the network connection is treated as metered
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MeteredMode {
+    /// Ask the OS. Needs the `metered_detection` feature, and is only supported
+    /// with NetworkManager on Linux and on Windows.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+static METERED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the connection is metered. While it is, pinned downloads pause, details aren't
+/// prefetched, loudness analysis only measures previews and streams are transcoded.
+pub fn is_metered() -> bool {
+    METERED.load(Ordering::Relaxed)
+}
+
+/// Checks the connection again, publishing `Event::MeteredChanged` if the state changed
+pub async fn refresh() -> bool {
+    let mode = Config::read_config().map(|v| v.metered).unwrap_or_default();
+
+    let metered = match mode {
+        MeteredMode::Always => true,
+        MeteredMode::Never => false,
+        MeteredMode::Auto => match platform::detect().await {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Couldn't tell if the connection is metered: {e}");
+                false
+            }
+        },
+    };
+
+    if METERED.swap(metered, Ordering::Relaxed) != metered {
+        info!(
+            "Connection is {}",
+            if metered { "metered" } else { "unmetered" }
+        );
+        publish(Event::MeteredChanged { metered });
+    }
+
+    metered
+}
+
+/// Keeps the metered state up to date, checking periodically and whenever the configuration
+/// changes, until the event bus closes
+pub fn watch() -> JoinHandle<()> {
+    let mut events = subscribe();
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                event = events.recv() => match event {
+                    Ok(Event::ConfigChanged) | Err(RecvError::Lagged(_)) => {}
+                    Ok(_) => continue,
+                    Err(RecvError::Closed) => break,
+                },
+            }
+
+            refresh().await;
+        }
+    })
+}
+
+#[cfg(all(feature = "metered_detection", target_os = "linux"))]
+mod platform {
+    use miette::{IntoDiagnostic, Result};
+    use zbus::{dbus_proxy, Connection};
+
+    #[dbus_proxy(
+        interface = "org.freedesktop.NetworkManager",
+        default_service = "org.freedesktop.NetworkManager",
+        default_path = "/org/freedesktop/NetworkManager"
+    )]
+    trait NetworkManager {
+        /// `NMMetered`: 1 is yes, 3 is a guess that it's metered
+        #[dbus_proxy(property)]
+        fn metered(&self) -> zbus::Result<u32>;
+    }
+
+    pub async fn detect() -> Result<bool> {
+        let connection = Connection::system().await.into_diagnostic()?;
+        let proxy = NetworkManagerProxy::new(&connection)
+            .await
+            .into_diagnostic()?;
+
+        Ok(matches!(proxy.metered().await.into_diagnostic()?, 1 | 3))
+    }
+}
+
+#[cfg(all(feature = "metered_detection", target_os = "windows"))]
+mod platform {
+    use miette::{IntoDiagnostic, Result};
+    use windows::Networking::Connectivity::{NetworkCostType, NetworkInformation};
+
+    pub async fn detect() -> Result<bool> {
+        let Ok(profile) = NetworkInformation::GetInternetConnectionProfile() else {
+            // Not connected at all
+            return Ok(false);
+        };
+
+        let cost = profile
+            .GetConnectionCost()
+            .and_then(|v| v.NetworkCostType())
+            .into_diagnostic()?;
+
+        Ok(matches!(
+            cost,
+            NetworkCostType::Fixed | NetworkCostType::Variable
+        ))
+    }
+}
+
+#[cfg(not(all(
+    feature = "metered_detection",
+    any(target_os = "linux", target_os = "windows")
+)))]
+mod platform {
+    use miette::Result;
+
+    pub async fn detect() -> Result<bool> {
+        Ok(false)
+    }
+}
@@ -0,0 +1,368 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use super::{
+    artwork::{artist_image, has_album_art, save_album_art, save_artist_image, Artwork},
+    cancellation::CancellationToken,
+    config::Config,
+    model::library,
+    network::is_metered,
+    utils::{cache_dir, http_client},
+};
+use miette::{miette, IntoDiagnostic, Result};
+use paris::info;
+use reqwest::{header::CONTENT_TYPE, Client, StatusCode};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use serde::Deserialize;
+
+/// MusicBrainz allows one request per second
+const MUSICBRAINZ_INTERVAL: Duration = Duration::from_secs(1);
+/// Deezer allows 50 requests every 5 seconds
+const DEEZER_INTERVAL: Duration = Duration::from_millis(100);
+
+/// MusicBrainz releases matching the album with a lower score are ignored
+const MIN_SCORE: u8 = 90;
+
+/// When the next request to each provider may be sent
+static NEXT_REQUEST: Mutex<Option<HashMap<&'static str, Instant>>> = Mutex::new(None);
+
+/// What `fetch_missing` found
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FetchSummary {
+    pub covers: usize,
+    pub artists: usize,
+    /// Albums and artists no provider had a picture of.
+    /// They're remembered, so they aren't looked up again.
+    pub missing: usize,
+}
+
+#[derive(Deserialize, Debug)]
+struct ReleaseSearch {
+    #[serde(default)]
+    releases: Vec<Release>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Release {
+    id: String,
+    #[serde(default)]
+    score: u8,
+}
+
+#[derive(Deserialize, Debug)]
+struct DeezerSearch<T> {
+    // A plain `default` would need `T: Default`
+    #[serde(default = "Vec::new")]
+    data: Vec<T>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DeezerAlbum {
+    cover_xl: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DeezerArtist {
+    name: String,
+    picture_xl: Option<String>,
+}
+
+/// Downloads covers of albums that only have a placeholder, and pictures of artists,
+/// into the artwork cache. Only songs of sources with `SourceSettings::fetch_artwork` are
+/// looked at, and nothing is fetched if external metadata is turned off or the connection
+/// is metered. Albums are looked up on MusicBrainz to get their cover from Cover Art Archive,
+/// then on Deezer if it has none. Artists are looked up on Deezer.
+///
+/// Requests to each provider are spaced out to stay within their rate limits, so this
+/// takes a while on large libraries. It can be interrupted, since every picture is cached
+/// as soon as it's found.
+pub async fn fetch_missing(
+    cancel: &CancellationToken,
+    db: &DatabaseConnection,
+) -> Result<FetchSummary> {
+    let config = Config::read_config()?;
+    let mut summary = FetchSummary::default();
+
+    if !config.external_metadata.enabled || is_metered() {
+        return Ok(summary);
+    }
+
+    let sources: Vec<i32> = config
+        .sources
+        .iter()
+        .filter(|v| v.settings.fetch_artwork)
+        .map(|v| i32::from(v.id))
+        .collect();
+
+    if sources.is_empty() {
+        return Ok(summary);
+    }
+
+    let songs = library::Entity::find()
+        .filter(library::Column::SourceId.is_in(sources))
+        .all(db)
+        .await
+        .into_diagnostic()?;
+
+    let client = http_client(&config, None)?;
+    let timeout = Duration::from_secs(config.external_metadata.timeout_seconds);
+    let mut misses = read_misses();
+
+    let mut albums = HashSet::new();
+    let mut artists = HashSet::new();
+
+    for song in &songs {
+        let artist = song.album_artist.as_deref().or(song.artist.as_deref());
+
+        let Some(artist) = artist.filter(|v| artists.insert(v.to_lowercase())) else {
+            continue;
+        };
+
+        if cancel.is_cancelled() {
+            break;
+        }
+
+        let key = format!("artist:{}", artist.to_lowercase());
+        if misses.contains(&key) || artist_image(artist)?.is_some() {
+            continue;
+        }
+
+        match deezer_artist(&client, artist, timeout).await? {
+            Some(picture) => {
+                save_artist_image(artist, &picture)?;
+                summary.artists += 1;
+            }
+            None => {
+                misses.insert(key);
+                summary.missing += 1;
+            }
+        }
+    }
+
+    for song in &songs {
+        let (Some(album), Some(artist)) = (
+            song.album.as_deref(),
+            song.album_artist.as_deref().or(song.artist.as_deref()),
+        ) else {
+            continue;
+        };
+
+        let key = format!("album:{}\0{}", artist.to_lowercase(), album.to_lowercase());
+
+        if cancel.is_cancelled() {
+            break;
+        }
+
+        if !albums.insert(key.clone()) || misses.contains(&key) || has_album_art(song)? {
+            continue;
+        }
+
+        let found = match cover_art_archive(&client, album, artist, timeout).await? {
+            Some(cover) => Some(cover),
+            None => deezer_cover(&client, album, artist, timeout).await?,
+        };
+
+        match found {
+            Some(cover) => {
+                save_album_art(song, &cover)?;
+                summary.covers += 1;
+            }
+            None => {
+                misses.insert(key);
+                summary.missing += 1;
+            }
+        }
+    }
+
+    write_misses(&misses)?;
+
+    if summary.covers + summary.artists > 0 {
+        info!(
+            "Fetched {} album covers and {} artist pictures",
+            summary.covers, summary.artists
+        );
+    }
+
+    Ok(summary)
+}
+
+/// Waits until a request to the provider may be sent. Fetches can run concurrently,
+/// so each request reserves its own slot.
+async fn throttle(provider: &'static str, interval: Duration) {
+    let wait = {
+        let mut next = NEXT_REQUEST.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let next = next
+            .get_or_insert_with(HashMap::new)
+            .entry(provider)
+            .or_insert(now);
+        let slot = (*next).max(now);
+        *next = slot + interval;
+
+        slot - now
+    };
+
+    tokio::time::sleep(wait).await;
+}
+
+/// The front cover of the best matching release on MusicBrainz, from Cover Art Archive
+async fn cover_art_archive(
+    client: &Client,
+    album: &str,
+    artist: &str,
+    timeout: Duration,
+) -> Result<Option<Artwork>> {
+    throttle("musicbrainz", MUSICBRAINZ_INTERVAL).await;
+
+    let query = format!(
+        "release:\"{}\" AND artist:\"{}\"",
+        escape(album),
+        escape(artist)
+    );
+
+    let search: ReleaseSearch = client
+        .get("https://musicbrainz.org/ws/2/release/")
+        .query(&[("query", query.as_str()), ("fmt", "json"), ("limit", "5")])
+        .timeout(timeout)
+        .send()
+        .await
+        .into_diagnostic()?
+        .error_for_status()
+        .into_diagnostic()?
+        .json()
+        .await
+        .into_diagnostic()?;
+
+    for release in search.releases.iter().filter(|v| v.score >= MIN_SCORE) {
+        let url = format!(
+            "https://coverartarchive.org/release/{}/front-500",
+            release.id
+        );
+
+        if let Some(cover) = download(client, &url, timeout).await? {
+            return Ok(Some(cover));
+        }
+    }
+
+    Ok(None)
+}
+
+async fn deezer_cover(
+    client: &Client,
+    album: &str,
+    artist: &str,
+    timeout: Duration,
+) -> Result<Option<Artwork>> {
+    throttle("deezer", DEEZER_INTERVAL).await;
+
+    let query = format!("artist:\"{}\" album:\"{}\"", escape(artist), escape(album));
+
+    let search: DeezerSearch<DeezerAlbum> = client
+        .get("https://api.deezer.com/search/album")
+        .query(&[("q", query.as_str()), ("limit", "1")])
+        .timeout(timeout)
+        .send()
+        .await
+        .into_diagnostic()?
+        .json()
+        .await
+        .into_diagnostic()?;
+
+    match search.data.into_iter().find_map(|v| v.cover_xl) {
+        Some(url) => download(client, &url, timeout).await,
+        None => Ok(None),
+    }
+}
+
+async fn deezer_artist(
+    client: &Client,
+    artist: &str,
+    timeout: Duration,
+) -> Result<Option<Artwork>> {
+    throttle("deezer", DEEZER_INTERVAL).await;
+
+    let search: DeezerSearch<DeezerArtist> = client
+        .get("https://api.deezer.com/search/artist")
+        .query(&[("q", artist), ("limit", "5")])
+        .timeout(timeout)
+        .send()
+        .await
+        .into_diagnostic()?
+        .json()
+        .await
+        .into_diagnostic()?;
+
+    // Search results include similarly named artists, so only an exact match counts.
+    // Artists without a picture have a generic one, with an empty image id in its URL.
+    let url = search
+        .data
+        .into_iter()
+        .find(|v| v.name.to_lowercase() == artist.to_lowercase())
+        .and_then(|v| v.picture_xl)
+        .filter(|v| !v.contains("/artist//"));
+
+    match url {
+        Some(url) => download(client, &url, timeout).await,
+        None => Ok(None),
+    }
+}
+
+/// Downloads an image, `None` if there's none at the URL
+async fn download(client: &Client, url: &str, timeout: Duration) -> Result<Option<Artwork>> {
+    let response = client
+        .get(url)
+        .timeout(timeout)
+        .send()
+        .await
+        .into_diagnostic()?;
+
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+
+    let response = response.error_for_status().into_diagnostic()?;
+
+    let mime = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("image/jpeg")
+        .to_string();
+
+    // Anything else couldn't be cached, see `artwork::file_name`
+    if mime != "image/jpeg" && mime != "image/png" {
+        return Err(miette!("{} is not a JPEG or PNG image, but {}", url, mime));
+    }
+
+    Ok(Some(Artwork {
+        data: response.bytes().await.into_diagnostic()?.to_vec(),
+        mime,
+    }))
+}
+
+/// Quotes and backslashes would end a quoted Lucene term early
+fn escape(term: &str) -> String {
+    term.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn misses_path() -> Option<PathBuf> {
+    Some(cache_dir()?.join("artwork_misses.mp"))
+}
+
+/// Albums and artists that were looked up without finding anything
+fn read_misses() -> HashSet<String> {
+    misses_path()
+        .and_then(|v| std::fs::read(v).ok())
+        .and_then(|v| rmp_serde::from_slice(&v).ok())
+        .unwrap_or_default()
+}
+
+fn write_misses(misses: &HashSet<String>) -> Result<()> {
+    let path = misses_path().ok_or(miette!("Cache directory does not exist"))?;
+
+    std::fs::write(path, rmp_serde::to_vec(misses).into_diagnostic()?).into_diagnostic()
+}
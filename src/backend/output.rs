@@ -0,0 +1,163 @@
+use std::{
+    io::Write,
+    net::{TcpStream, ToSocketAddrs},
+    time::{Duration, Instant},
+};
+
+use super::{
+    config::Playback,
+    playback::{BitDepth, StreamFormat},
+};
+use miette::{miette, IntoDiagnostic, Result};
+use paris::{info, warn};
+use serde::{Deserialize, Serialize};
+
+/// Connecting again after the connection dropped is only tried this often
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+/// Writes taking longer than this count as a dropped connection
+const WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Where the player sends its audio
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Sink {
+    /// The OS's default output device
+    #[default]
+    Local,
+    /// A Snapcast server's TCP source, or anything else that takes raw PCM over TCP,
+    /// for playing in several rooms at once
+    Network,
+}
+
+/// Where the audio goes, and in which format for network sinks
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Output {
+    pub sink: Sink,
+    /// Address of the network sink, e.g. `192.168.1.2:4953` for a Snapcast source set up as
+    /// `tcp://0.0.0.0:4953?name=Eleanor&mode=server`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    /// Sample rate the network sink expects. Snapcast takes 48 kHz, 16-bit stereo by default.
+    pub sample_rate: u32,
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Output {
+            sink: Sink::Local,
+            address: None,
+            sample_rate: 48_000,
+        }
+    }
+}
+
+impl Output {
+    /// Playback settings with the format the sink takes. Network sinks get 16-bit audio
+    /// at their sample rate, since raw PCM doesn't say what format it's in.
+    pub fn playback(&self, settings: &Playback) -> Playback {
+        match self.sink {
+            Sink::Local => settings.clone(),
+            Sink::Network => Playback {
+                sample_rate: Some(self.sample_rate),
+                bit_depth: BitDepth::S16,
+                ..settings.clone()
+            },
+        }
+    }
+}
+
+/// Sends the audio coming out of `playback::Chain` to a network sink as interleaved
+/// 16-bit little-endian stereo PCM. If the connection drops, audio is thrown away
+/// until connecting again works, so playback keeps its pace while the sink is gone.
+pub struct NetworkSink {
+    address: String,
+    sample_rate: u32,
+    stream: Option<TcpStream>,
+    last_attempt: Option<Instant>,
+}
+
+impl NetworkSink {
+    pub fn new(output: &Output) -> Result<Self> {
+        let address = output
+            .address
+            .clone()
+            .ok_or(miette!("The network output has no address"))?;
+
+        Ok(NetworkSink {
+            address,
+            sample_rate: output.sample_rate,
+            stream: None,
+            last_attempt: None,
+        })
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Sends a block of samples, blocking while the sink is behind so it sets the pace
+    pub fn write(&mut self, samples: &[f32], format: StreamFormat) -> Result<()> {
+        if format.sample_rate != self.sample_rate || format.channels != 2 {
+            return Err(miette!(
+                "The network output takes stereo audio at {} Hz, not {} channels at {} Hz",
+                self.sample_rate,
+                format.channels,
+                format.sample_rate
+            ));
+        }
+
+        let Some(stream) = self.connect() else {
+            return Ok(());
+        };
+
+        let bytes: Vec<u8> = samples
+            .iter()
+            .flat_map(|v| ((v.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).to_le_bytes())
+            .collect();
+
+        if let Err(e) = stream.write_all(&bytes) {
+            warn!("Lost the connection to the network output: {e}");
+            self.stream = None;
+        }
+
+        Ok(())
+    }
+
+    /// The connection to the sink, connecting first if it's time to try again
+    fn connect(&mut self) -> Option<&mut TcpStream> {
+        let due = self
+            .last_attempt
+            .is_none_or(|v| v.elapsed() >= RECONNECT_INTERVAL);
+
+        if self.stream.is_none() && due {
+            self.last_attempt = Some(Instant::now());
+
+            match open(&self.address) {
+                Ok(stream) => {
+                    info!("Sending audio to {}", self.address);
+                    self.stream = Some(stream);
+                }
+                Err(e) => warn!("Couldn't connect to the network output: {e}"),
+            }
+        }
+
+        self.stream.as_mut()
+    }
+}
+
+fn open(address: &str) -> Result<TcpStream> {
+    let address = address
+        .to_socket_addrs()
+        .into_diagnostic()?
+        .next()
+        .ok_or(miette!("{} doesn't resolve to any address", address))?;
+
+    let stream = TcpStream::connect_timeout(&address, WRITE_TIMEOUT).into_diagnostic()?;
+    stream
+        .set_write_timeout(Some(WRITE_TIMEOUT))
+        .into_diagnostic()?;
+    stream.set_nodelay(true).into_diagnostic()?;
+
+    Ok(stream)
+}
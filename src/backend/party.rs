@@ -0,0 +1,193 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use super::config::PartyMode;
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+
+/// Requests older than this don't count towards a guest's limit
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+/// Wrong PINs a guest can send within `RATE_WINDOW` before everything they send is turned down
+const PIN_ATTEMPTS: usize = 5;
+
+/// A song a guest asked for, waiting for the host to approve it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SongRequest {
+    pub id: u64,
+    pub hash: i64,
+    /// Address of the device that asked for it
+    pub guest: IpAddr,
+}
+
+/// Why a guest's request was turned down
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refusal {
+    /// Party mode is off
+    Closed,
+    WrongPin,
+    /// The guest asked for more songs than `PartyMode::requests_per_minute` allows
+    TooManyRequests,
+}
+
+/// Keeps track of the songs guests ask for in party mode.
+/// Whoever serves the requests queues the songs, e.g. `http_api::HttpApi`.
+pub struct Party {
+    settings: PartyMode,
+    /// Requests waiting for approval, oldest first
+    pending: Vec<SongRequest>,
+    next_id: u64,
+    /// When each guest asked for something within the last `RATE_WINDOW`
+    recent: HashMap<IpAddr, VecDeque<Instant>>,
+    /// When each guest sent a wrong PIN within the last `RATE_WINDOW`
+    wrong_pins: HashMap<IpAddr, VecDeque<Instant>>,
+}
+
+impl Party {
+    pub fn new(settings: PartyMode) -> Self {
+        Party {
+            settings,
+            pending: vec![],
+            next_id: 0,
+            recent: HashMap::new(),
+            wrong_pins: HashMap::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.settings.enabled
+    }
+
+    pub fn approval(&self) -> bool {
+        self.settings.approval
+    }
+
+    /// Turns approval on or off while the party goes on.
+    /// Requests that are already waiting keep waiting until they're approved or rejected.
+    pub fn set_approval(&mut self, approval: bool) {
+        self.settings.approval = approval;
+    }
+
+    /// Whether a guest may use the API with this PIN. Guests that sent too many wrong PINs
+    /// are turned down for a while, so PINs can't be guessed quickly.
+    pub fn admit(&mut self, guest: IpAddr, pin: Option<&str>) -> Result<(), Refusal> {
+        if !self.settings.enabled {
+            return Err(Refusal::Closed);
+        }
+
+        let Some(expected) = &self.settings.pin else {
+            return Ok(());
+        };
+
+        let now = Instant::now();
+        let wrong = self.wrong_pins.entry(guest).or_default();
+        forget_old(wrong, now);
+
+        if wrong.len() >= PIN_ATTEMPTS {
+            return Err(Refusal::TooManyRequests);
+        }
+
+        // Compared in constant time, so the time taken doesn't tell how much of a guess was right
+        let right: bool = pin
+            .unwrap_or_default()
+            .as_bytes()
+            .ct_eq(expected.as_bytes())
+            .into();
+
+        if !right {
+            wrong.push_back(now);
+            return Err(Refusal::WrongPin);
+        }
+
+        Ok(())
+    }
+
+    /// A guest asks for a song. Returns the request if it waits for approval,
+    /// `None` if the song can be queued right away.
+    /// Requests with a wrong PIN count towards the limit too.
+    pub fn request(
+        &mut self,
+        hash: i64,
+        guest: IpAddr,
+        pin: Option<&str>,
+    ) -> Result<Option<SongRequest>, Refusal> {
+        let now = Instant::now();
+        let recent = self.recent.entry(guest).or_default();
+        forget_old(recent, now);
+
+        if recent.len() >= self.settings.requests_per_minute {
+            return Err(Refusal::TooManyRequests);
+        }
+        recent.push_back(now);
+
+        self.admit(guest, pin)?;
+
+        if !self.settings.approval {
+            return Ok(None);
+        }
+
+        let request = SongRequest {
+            id: self.next_id,
+            hash,
+            guest,
+        };
+        self.next_id += 1;
+        self.pending.push(request.clone());
+
+        Ok(Some(request))
+    }
+
+    /// Requests waiting for approval, oldest first
+    pub fn pending(&self) -> &[SongRequest] {
+        &self.pending
+    }
+
+    /// Takes a request out of the waiting ones, to be queued or dropped.
+    /// `None` if there's no such request, e.g. because it was already resolved.
+    pub fn resolve(&mut self, id: u64) -> Option<SongRequest> {
+        let i = self.pending.iter().position(|v| v.id == id)?;
+
+        Some(self.pending.remove(i))
+    }
+}
+
+/// Drops the times that are out of `RATE_WINDOW`
+fn forget_old(times: &mut VecDeque<Instant>, now: Instant) {
+    while times
+        .front()
+        .is_some_and(|v| now.duration_since(*v) >= RATE_WINDOW)
+    {
+        times.pop_front();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn wrong_pins_are_limited_per_guest() {
+        let mut party = Party::new(PartyMode {
+            enabled: true,
+            pin: Some("1234".into()),
+            ..Default::default()
+        });
+        let guest = IpAddr::from(Ipv4Addr::new(192, 168, 1, 20));
+        let other = IpAddr::from(Ipv4Addr::new(192, 168, 1, 21));
+
+        for _ in 0..PIN_ATTEMPTS {
+            assert_eq!(party.admit(guest, Some("0000")), Err(Refusal::WrongPin));
+        }
+
+        assert_eq!(party.admit(guest, None), Err(Refusal::TooManyRequests));
+        assert_eq!(
+            party.admit(guest, Some("1234")),
+            Err(Refusal::TooManyRequests)
+        );
+        assert_eq!(party.admit(other, Some("1234")), Ok(()));
+        assert_eq!(party.admit(other, Some("12345")), Err(Refusal::WrongPin));
+    }
+}
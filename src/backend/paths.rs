@@ -0,0 +1,188 @@
+use std::{
+    collections::HashSet,
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
+
+use paris::warn;
+use walkdir::{DirEntry, WalkDir};
+
+/// Bytes of a path that aren't valid UTF-8, which are always 0x80 or above, are stored as the
+/// character this far past their value, in the last private use plane. Actual file names don't use those characters, and valid names
+/// are stored as they are, so paths indexed before stay the same.
+#[cfg(unix)]
+const ESCAPE_BASE: u32 = 0x10FF00;
+
+/// Turns a path into the string it's stored as in the library. Unlike `to_string_lossy`,
+/// nothing is lost, so `decode` gets back the path the file can be opened with.
+#[cfg(unix)]
+pub fn encode(path: &OsStr) -> String {
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut encoded = String::with_capacity(path.len());
+
+    for chunk in path.as_bytes().utf8_chunks() {
+        encoded.push_str(chunk.valid());
+        encoded.extend(
+            chunk
+                .invalid()
+                .iter()
+                .filter_map(|&v| char::from_u32(ESCAPE_BASE + v as u32)),
+        );
+    }
+
+    encoded
+}
+
+/// Paths that aren't valid Unicode can't be stored without loss outside Unix
+#[cfg(not(unix))]
+pub fn encode(path: &OsStr) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+/// The path a string from `encode` was made from
+#[cfg(unix)]
+pub fn decode(stored: &str) -> PathBuf {
+    use std::{ffi::OsString, os::unix::ffi::OsStringExt};
+
+    let mut bytes = Vec::with_capacity(stored.len());
+
+    for c in stored.chars() {
+        match (c as u32).checked_sub(ESCAPE_BASE) {
+            Some(byte) if byte >= 0x80 => bytes.push(byte as u8),
+            _ => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+
+    OsString::from_vec(bytes).into()
+}
+
+#[cfg(not(unix))]
+pub fn decode(stored: &str) -> PathBuf {
+    PathBuf::from(stored)
+}
+
+/// Every file under a directory, following symlinks. Symlinks that point back to a directory
+/// they're in would be followed forever, so they're skipped with a warning, and files reached
+/// through several links are only listed once, under the first path they're found at.
+/// Directories are walked in name order, so that's the same path every time.
+/// Paths are listed under the root as it's given, not as it resolves, so they stay the same
+/// if a linked directory moves.
+pub fn files(root: &Path) -> Vec<DirEntry> {
+    let mut seen = HashSet::new();
+
+    WalkDir::new(root)
+        .follow_links(true)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|v| match v {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                match e.loop_ancestor() {
+                    Some(ancestor) => warn!(
+                        "Skipped {}, which links back to {}",
+                        e.path().unwrap_or(root).display(),
+                        ancestor.display()
+                    ),
+                    None => warn!("Couldn't read {}: {e}", e.path().unwrap_or(root).display()),
+                }
+
+                None
+            }
+        })
+        .filter(|v| !v.file_type().is_dir())
+        // Files removed while walking can't be resolved, and can't be read either
+        .filter(|v| match v.path().canonicalize() {
+            Ok(canonical) => seen.insert(canonical),
+            Err(_) => false,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, remove_dir_all, File};
+
+    /// An empty directory of its own for each test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("eleanor-{name}-{}", std::process::id()));
+
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    #[test]
+    fn keeps_unicode_paths() {
+        for path in [
+            "/music/Sigur Rós/()/01 ágætis byrjun.flac",
+            "100%/a\\b",
+            "🎵",
+            "",
+        ] {
+            assert_eq!(encode(OsStr::new(path)), path);
+            assert_eq!(decode(path), PathBuf::from(path));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn round_trips_invalid_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let paths: [&[u8]; 4] = [
+            b"/music/caf\xe9.mp3",
+            b"\xff\xfe\x80",
+            b"half \xe2\x82 a euro",
+            b"\xc3\xa9 and \xc3",
+        ];
+
+        for path in paths {
+            let encoded = encode(OsStr::from_bytes(path));
+
+            assert_eq!(decode(&encoded).as_os_str().as_bytes(), path);
+        }
+
+        // Valid parts stay readable
+        assert!(encode(OsStr::from_bytes(b"/music/caf\xe9.mp3")).starts_with("/music/caf"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn skips_symlink_loops() {
+        use std::os::unix::fs::symlink;
+
+        let root = temp_dir("loops");
+        create_dir_all(root.join("album")).unwrap();
+        File::create(root.join("album/song.flac")).unwrap();
+
+        symlink(&root, root.join("album/back")).unwrap();
+        symlink(root.join("album"), root.join("linked")).unwrap();
+        symlink(root.join("missing"), root.join("broken.flac")).unwrap();
+
+        let files: Vec<PathBuf> = files(&root).into_iter().map(|v| v.into_path()).collect();
+
+        assert_eq!(files, [root.join("album/song.flac")]);
+
+        remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn lists_invalid_utf8_names() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let root = temp_dir("names");
+        let name = OsStr::from_bytes(b"caf\xe9.flac");
+        File::create(root.join(name)).unwrap();
+
+        let files = files(&root);
+
+        assert_eq!(files.len(), 1);
+        assert_eq!(decode(&encode(files[0].file_name())), PathBuf::from(name));
+
+        remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,472 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use super::{
+    channels::Downmix, config::Playback, equalizer::Equalizer, loudness::volume_factor,
+    queue::ShuffleMode, replaygain::Gain, resampler::Resampler, spectrum::SpectrumFeed,
+    tempo::Tempo,
+};
+use miette::Result;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use symphonia::core::audio::Channels;
+
+/// A request to the player, e.g. from a hardware media key, the OS media overlay or the HTTP API
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaCommand {
+    Play,
+    Pause,
+    Toggle,
+    Stop,
+    Next,
+    Previous,
+    /// Move forward, or backward if negative, by this many milliseconds
+    SeekBy(i64),
+    SetPosition(Duration),
+    /// Play at this speed in percent, e.g. 150 for 1.5x. It's clamped to `tempo::MIN_SPEED`
+    /// and `tempo::MAX_SPEED`.
+    SetSpeed(u16),
+    /// Speed up, or slow down if negative, by this many percent
+    ChangeSpeed(i16),
+    /// Add the song with this hash to the end of the queue
+    Enqueue(i64),
+    /// Shuffle what the mode keeps together, see `Queue::set_shuffle_mode`. `None` turns
+    /// shuffle off.
+    SetShuffle(Option<ShuffleMode>),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaybackState {
+    Playing,
+    Paused,
+    #[default]
+    Stopped,
+}
+
+/// Sample format of the audio sent to the output device
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BitDepth {
+    S16,
+    S24,
+    /// Passes samples through as they're decoded
+    #[default]
+    F32,
+}
+
+impl BitDepth {
+    /// Distance between two values that can be represented, relative to full scale
+    fn step(self) -> Option<f32> {
+        match self {
+            BitDepth::S16 => Some(1.0 / 32_768.0),
+            BitDepth::S24 => Some(1.0 / 8_388_608.0),
+            BitDepth::F32 => None,
+        }
+    }
+}
+
+/// Format of the stream that's playing
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamFormat {
+    /// Sample rate of the file
+    pub source_rate: u32,
+    /// Sample rate sent to the output device, which differs from `source_rate` when resampling
+    pub sample_rate: u32,
+    /// Channels in the file
+    pub source_channels: usize,
+    /// Channels sent to the output device, which is always stereo unless the file is stereo
+    /// already, see `channels::Downmix`
+    pub channels: usize,
+    pub bit_depth: BitDepth,
+}
+
+impl StreamFormat {
+    pub fn is_resampled(&self) -> bool {
+        self.source_rate != self.sample_rate
+    }
+}
+
+/// How long the player fades out before carrying out a command, and in after it, e.g. when
+/// pausing and resuming. Zero for commands that don't interrupt the sound.
+pub fn command_fade(command: MediaCommand, settings: &Playback) -> Duration {
+    let ms = match command {
+        MediaCommand::Play | MediaCommand::Pause | MediaCommand::Toggle => settings.pause_fade_ms,
+        MediaCommand::Stop => settings.stop_fade_ms,
+        MediaCommand::SeekBy(_) | MediaCommand::SetPosition(_) => settings.seek_fade_ms,
+        _ => 0,
+    };
+
+    Duration::from_millis(ms)
+}
+
+/// Level the limiter keeps peaks under, -1 dBFS
+const LIMITER_CEILING: f32 = 0.891;
+/// How quickly the limiter turns the gain down once a peak goes over the ceiling
+const LIMITER_ATTACK: Duration = Duration::from_millis(1);
+/// How quickly the gain comes back up after the peak
+const LIMITER_RELEASE: Duration = Duration::from_millis(150);
+
+static CURRENT: Mutex<Option<(u64, StreamFormat)>> = Mutex::new(None);
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Format of the stream that's playing, if any
+pub fn stream_format() -> Option<StreamFormat> {
+    CURRENT
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .map(|(_, format)| format)
+}
+
+/// Processing between the decoder and the output device: mixing to stereo, applying ReplayGain,
+/// equalizing, limiting, changing the speed, resampling to the configured sample rate, fading,
+/// feeding the visualizer and reducing samples to the configured bit depth. The format of the
+/// newest chain is reported by `stream_format` until it's dropped.
+pub struct Chain {
+    id: u64,
+    format: StreamFormat,
+    settings: Playback,
+    /// Linear factor samples are multiplied by
+    gain: f32,
+    downmix: Option<Downmix>,
+    equalizer: Option<Equalizer>,
+    limiter: Option<Limiter>,
+    /// Only set up once the speed is changed from normal
+    tempo: Option<Tempo>,
+    resampler: Option<Resampler>,
+    fade: Fade,
+    spectrum: Option<SpectrumFeed>,
+}
+
+impl Chain {
+    pub fn new(source_rate: u32, layout: Channels, settings: &Playback) -> Result<Self> {
+        let sample_rate = settings.sample_rate.unwrap_or(source_rate);
+
+        let downmix = Downmix::new(layout);
+        let channels = match downmix {
+            Some(_) => 2,
+            None => layout.count(),
+        };
+
+        let format = StreamFormat {
+            source_rate,
+            sample_rate,
+            source_channels: layout.count(),
+            channels,
+            bit_depth: settings.bit_depth,
+        };
+
+        let equalizer = Equalizer::new(&settings.eq, source_rate, channels);
+
+        let limiter = settings
+            .limiter
+            .then(|| Limiter::new(source_rate, channels));
+
+        let tempo = settings
+            .speed
+            .filter(|v| *v != 1.0)
+            .map(|v| Tempo::new(source_rate, channels, v, !settings.varispeed));
+
+        let resampler = format
+            .is_resampled()
+            .then(|| Resampler::new(source_rate, sample_rate, channels))
+            .transpose()?;
+
+        let spectrum = settings
+            .visualization
+            .then(|| SpectrumFeed::new(sample_rate, channels));
+
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        *CURRENT.lock().unwrap_or_else(|e| e.into_inner()) = Some((id, format));
+
+        Ok(Chain {
+            id,
+            format,
+            settings: settings.clone(),
+            gain: 1.0,
+            downmix,
+            equalizer,
+            limiter,
+            tempo,
+            resampler,
+            fade: Fade::default(),
+            spectrum,
+        })
+    }
+
+    pub fn format(&self) -> StreamFormat {
+        self.format
+    }
+
+    /// Sets the ReplayGain adjustment of the song, see `loudness::playback_gain`.
+    /// Songs without one play unadjusted, and without the preamp.
+    pub fn set_gain(&mut self, gain: Option<Gain>) {
+        self.gain = gain.map_or(1.0, |v| volume_factor(v, &self.settings));
+    }
+
+    /// Changes the playback speed, e.g. to `audiobooks::playback_speed` for the song,
+    /// or following `MediaCommand::SetSpeed`. It's clamped to the supported range.
+    pub fn set_speed(&mut self, speed: f32) {
+        let pitch_correction = !self.settings.varispeed;
+
+        match &mut self.tempo {
+            Some(tempo) => tempo.set_speed(speed, pitch_correction),
+            None if speed != 1.0 => {
+                self.tempo = Some(Tempo::new(
+                    self.format.source_rate,
+                    self.format.channels,
+                    speed,
+                    pitch_correction,
+                ))
+            }
+            None => {}
+        }
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.tempo.as_ref().map_or(1.0, |v| v.speed())
+    }
+
+    /// Ramps the volume down to silence over `duration`, e.g. before pausing or seeking.
+    /// The player keeps feeding the chain until `is_faded_out`.
+    pub fn fade_out(&mut self, duration: Duration) {
+        self.fade.start(0.0, self.frames(duration));
+    }
+
+    /// Ramps the volume back up over `duration`, e.g. after resuming or seeking.
+    /// Starts from silence if it wasn't faded out before.
+    pub fn fade_in(&mut self, duration: Duration) {
+        if duration.is_zero() {
+            self.fade.start(1.0, 0);
+            return;
+        }
+
+        if self.fade.target == 1.0 && self.fade.level == 1.0 {
+            self.fade.level = 0.0;
+        }
+
+        self.fade.start(1.0, self.frames(duration));
+    }
+
+    pub fn is_faded_out(&self) -> bool {
+        self.fade.level == 0.0 && self.fade.target == 0.0
+    }
+
+    fn frames(&self, duration: Duration) -> usize {
+        (duration.as_secs_f64() * self.format.sample_rate as f64) as usize
+    }
+
+    /// Processes a block of interleaved samples as they come out of the decoder
+    pub fn process(&mut self, samples: &[f32]) -> Result<Vec<f32>> {
+        let mut samples = match &self.downmix {
+            Some(downmix) => downmix.process(samples),
+            None => samples.to_vec(),
+        };
+
+        if self.gain != 1.0 {
+            samples.iter_mut().for_each(|v| *v *= self.gain);
+        }
+
+        if let Some(equalizer) = &mut self.equalizer {
+            equalizer.process(&mut samples);
+        }
+
+        if let Some(limiter) = &mut self.limiter {
+            limiter.process(&mut samples);
+        }
+
+        if let Some(tempo) = &mut self.tempo {
+            samples = tempo.process(&samples);
+        }
+
+        let mut samples = match &mut self.resampler {
+            Some(resampler) => resampler.process(&samples)?,
+            None => samples,
+        };
+
+        self.fade.apply(&mut samples, self.format.channels);
+
+        if let Some(spectrum) = &mut self.spectrum {
+            spectrum.process(&samples);
+        }
+
+        Ok(self.quantize(samples))
+    }
+
+    /// Returns audio still held back by the chain once the decoder reached the end of the file
+    pub fn finish(&mut self) -> Result<Vec<f32>> {
+        let mut samples = match &mut self.tempo {
+            Some(tempo) => tempo.finish(),
+            None => vec![],
+        };
+
+        if let Some(resampler) = &mut self.resampler {
+            let mut flushed = resampler.process(&samples)?;
+            flushed.extend(resampler.flush()?);
+            samples = flushed;
+        }
+
+        self.fade.apply(&mut samples, self.format.channels);
+
+        if let Some(spectrum) = &mut self.spectrum {
+            spectrum.process(&samples);
+        }
+
+        Ok(self.quantize(samples))
+    }
+
+    /// Rounds samples to the output bit depth. Triangular dither keeps the rounding error
+    /// from turning into distortion in quiet passages.
+    fn quantize(&self, mut samples: Vec<f32>) -> Vec<f32> {
+        let Some(step) = self.format.bit_depth.step() else {
+            return samples;
+        };
+
+        let mut rng = rand::thread_rng();
+
+        for sample in &mut samples {
+            let dither = (rng.gen::<f32>() - rng.gen::<f32>()) * step;
+            *sample = (((*sample + dither) / step).round() * step).clamp(-1.0, 1.0 - step);
+        }
+
+        samples
+    }
+}
+
+/// Turns the volume down while peaks go over the ceiling and brings it back up slowly after,
+/// so loud passages are compressed instead of clipping. All channels share the gain, to keep
+/// the stereo image in place.
+#[derive(Debug, Clone, Copy)]
+struct Limiter {
+    channels: usize,
+    /// Linear factor samples are multiplied by, at most 1
+    gain: f32,
+    /// Share of the way to the target gain that's left after a frame, while turning down
+    attack: f32,
+    /// Same as `attack`, while coming back up
+    release: f32,
+}
+
+impl Limiter {
+    fn new(sample_rate: u32, channels: usize) -> Self {
+        let coefficient =
+            |time: Duration| (-1.0 / (time.as_secs_f32() * sample_rate.max(1) as f32)).exp();
+
+        Limiter {
+            channels: channels.max(1),
+            gain: 1.0,
+            attack: coefficient(LIMITER_ATTACK),
+            release: coefficient(LIMITER_RELEASE),
+        }
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        for frame in samples.chunks_mut(self.channels) {
+            let peak = frame.iter().fold(0.0f32, |peak, v| peak.max(v.abs()));
+            let target = if peak > LIMITER_CEILING {
+                LIMITER_CEILING / peak
+            } else {
+                1.0
+            };
+
+            let coefficient = if target < self.gain {
+                self.attack
+            } else {
+                self.release
+            };
+            self.gain = target + (self.gain - target) * coefficient;
+
+            // The attack isn't instant, so the start of a peak could still go over
+            for sample in frame {
+                *sample = (*sample * self.gain).clamp(-LIMITER_CEILING, LIMITER_CEILING);
+            }
+        }
+    }
+}
+
+/// A volume ramp, linear in amplitude
+#[derive(Debug, Clone, Copy)]
+struct Fade {
+    level: f32,
+    target: f32,
+    /// Change of the level per frame
+    step: f32,
+}
+
+impl Default for Fade {
+    fn default() -> Self {
+        Fade {
+            level: 1.0,
+            target: 1.0,
+            step: 0.0,
+        }
+    }
+}
+
+impl Fade {
+    /// Ramps from the current level to `target` over this many frames
+    fn start(&mut self, target: f32, frames: usize) {
+        self.target = target;
+
+        if frames == 0 {
+            self.level = target;
+        }
+        self.step = (target - self.level).abs() / frames.max(1) as f32;
+    }
+
+    fn apply(&mut self, samples: &mut [f32], channels: usize) {
+        if self.level == 1.0 && self.target == 1.0 {
+            return;
+        }
+
+        for frame in samples.chunks_mut(channels.max(1)) {
+            self.level = if self.level < self.target {
+                (self.level + self.step).min(self.target)
+            } else {
+                (self.level - self.step).max(self.target)
+            };
+
+            frame.iter_mut().for_each(|v| *v *= self.level);
+        }
+    }
+}
+
+impl Drop for Chain {
+    fn drop(&mut self) {
+        let mut current = CURRENT.lock().unwrap_or_else(|e| e.into_inner());
+
+        // A newer chain may have taken over already, e.g. while crossfading
+        if current.is_some_and(|(id, _)| id == self.id) {
+            *current = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limiter_keeps_peaks_under_the_ceiling() {
+        let mut limiter = Limiter::new(48000, 2);
+
+        let mut loud = vec![1.5; 4800];
+        limiter.process(&mut loud);
+        assert!(loud.iter().all(|v| v.abs() <= LIMITER_CEILING));
+        // Once the attack caught up, the gain does the work, not the clamp
+        assert!((limiter.gain - LIMITER_CEILING / 1.5).abs() < 0.001);
+
+        let mut quiet = vec![0.5; 4800];
+        limiter.process(&mut quiet);
+        assert!(quiet[0] < 0.5, "gain comes back up gradually");
+
+        let mut quiet = vec![0.5; 96000];
+        limiter.process(&mut quiet);
+        assert!((quiet.last().unwrap() - 0.5).abs() < 0.001);
+    }
+}
@@ -0,0 +1,315 @@
+use std::{
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use super::{
+    config::Config,
+    events::{subscribe, Event},
+    model::library,
+    tagging::{write_tags, TagEdit},
+    utils::config_dir,
+};
+use miette::{miette, IntoDiagnostic, Result};
+use paris::{info, warn};
+use rhai::{module_resolvers::DummyModuleResolver, Dynamic, Engine, Map, Scope, AST, INT};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use tokio::sync::{
+    broadcast::error::RecvError,
+    mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+};
+
+/// Limits that keep a misbehaving script from hanging or exhausting memory
+const MAX_OPERATIONS: u64 = 1_000_000;
+const MAX_CALL_LEVELS: usize = 32;
+const MAX_STRING_SIZE: usize = 64 * 1024;
+const MAX_COLLECTION_SIZE: usize = 10_000;
+
+/// Something a script asked the app to do that the backend can't do by itself
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Add a song to the end of the queue
+    Enqueue(i64),
+}
+
+/// Work requested by scripts while handling an event, carried out once they return
+#[derive(Debug, Clone)]
+enum Action {
+    Command(Command),
+    EditTags(i64, TagEdit),
+}
+
+struct Script {
+    name: String,
+    ast: AST,
+    scope: Scope<'static>,
+}
+
+/// Runs the scripts enabled in the configuration. They live in the `plugins` directory
+/// of the configuration directory, e.g. `plugins/autotag.rhai` for a plugin named `autotag`.
+///
+/// Scripts react to events by defining any of these functions:
+/// `on_track_started(song)`, `on_track_ended(song, finished)`, `on_index_finished(source_id)`,
+/// `on_queue_updated()` and `on_config_changed()`. Songs are passed as maps of their metadata.
+///
+/// They can call `enqueue(hash)` to add a song to the queue and `set_tags(hash, tags)`
+/// to edit a local song's tags, with `tags` being a map like `#{ genre: "Jazz" }`.
+/// Scripts can't access files or the network.
+pub struct PluginHost {
+    engine: Engine,
+    scripts: Vec<Script>,
+    actions: Arc<Mutex<Vec<Action>>>,
+}
+
+impl PluginHost {
+    /// Compiles every enabled script, skipping ones that fail to compile
+    pub fn load(config: &Config) -> Result<Self> {
+        let actions = Arc::new(Mutex::new(vec![]));
+        let engine = engine(actions.clone());
+
+        let mut scripts = vec![];
+
+        for name in &config.plugins {
+            if !is_plain_name(name) {
+                warn!("Skipped plugin {name}, its name isn't a plain file name");
+                continue;
+            }
+
+            let path = plugins_dir()?.join(format!("{name}.rhai"));
+
+            match engine.compile_file(path) {
+                Ok(ast) => {
+                    let mut scope = Scope::new();
+
+                    // Run top level statements once, so scripts can set up their state
+                    if let Err(e) = engine.run_ast_with_scope(&mut scope, &ast) {
+                        warn!("Plugin {name} failed to start: {e}");
+                        continue;
+                    }
+
+                    scripts.push(Script {
+                        name: name.clone(),
+                        ast,
+                        scope,
+                    });
+                }
+                Err(e) => warn!("Couldn't load plugin {name}: {e}"),
+            }
+        }
+
+        Ok(PluginHost {
+            engine,
+            scripts,
+            actions,
+        })
+    }
+
+    /// Handles events until the event bus closes. Commands scripts issue are sent to the returned channel.
+    pub fn start(mut self, db: DatabaseConnection) -> UnboundedReceiver<Command> {
+        let (sender, receiver) = unbounded_channel();
+        let mut events = subscribe();
+
+        tokio::spawn(async move {
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Plugins missed {skipped} events");
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+
+                if let Err(e) = self.handle(event, &sender, &db).await {
+                    warn!("Couldn't run plugins: {e}");
+                }
+            }
+        });
+
+        receiver
+    }
+
+    async fn handle(
+        &mut self,
+        event: Event,
+        commands: &UnboundedSender<Command>,
+        db: &DatabaseConnection,
+    ) -> Result<()> {
+        let (function, args): (&str, Vec<Dynamic>) = match event {
+            Event::TrackStarted { hash } => ("on_track_started", vec![song_map(hash, db).await?]),
+            Event::TrackEnded { hash, finished } => (
+                "on_track_ended",
+                vec![song_map(hash, db).await?, finished.into()],
+            ),
+            Event::IndexFinished { source_id } => {
+                ("on_index_finished", vec![(source_id as INT).into()])
+            }
+            Event::QueueUpdated => ("on_queue_updated", vec![]),
+            Event::ConfigChanged => ("on_config_changed", vec![]),
+            _ => return Ok(()),
+        };
+
+        for script in &mut self.scripts {
+            let defined = script
+                .ast
+                .iter_functions()
+                .any(|v| v.name == function && v.params.len() == args.len());
+
+            if !defined {
+                continue;
+            }
+
+            if let Err(e) = self.engine.call_fn::<Dynamic>(
+                &mut script.scope,
+                &script.ast,
+                function,
+                args.clone(),
+            ) {
+                warn!("Plugin {} failed in {function}: {e}", script.name);
+            }
+        }
+
+        let actions: Vec<_> = self
+            .actions
+            .lock()
+            .map(|mut v| v.drain(..).collect())
+            .unwrap_or_default();
+
+        for action in actions {
+            match action {
+                Action::Command(command) => {
+                    let _ = commands.send(command);
+                }
+                Action::EditTags(hash, edit) => {
+                    if let Err(e) = write_tags(hash, &edit, db).await {
+                        warn!("Couldn't apply tags set by a plugin: {e}");
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Sets up a sandboxed engine with the functions scripts can call
+fn engine(actions: Arc<Mutex<Vec<Action>>>) -> Engine {
+    let mut engine = Engine::new();
+
+    // The default resolver loads modules from any path
+    engine.set_module_resolver(DummyModuleResolver::new());
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.set_max_call_levels(MAX_CALL_LEVELS);
+    engine.set_max_string_size(MAX_STRING_SIZE);
+    engine.set_max_array_size(MAX_COLLECTION_SIZE);
+    engine.set_max_map_size(MAX_COLLECTION_SIZE);
+
+    engine.on_print(|text| info!("{text}"));
+
+    let queued = actions.clone();
+    engine.register_fn("enqueue", move |hash: INT| {
+        if let Ok(mut actions) = queued.lock() {
+            actions.push(Action::Command(Command::Enqueue(hash)));
+        }
+    });
+
+    engine.register_fn("set_tags", move |hash: INT, tags: Map| {
+        let text = |key: &str| tags.get(key).map(|v| v.to_string());
+        let number = |key: &str| {
+            tags.get(key)
+                .and_then(|v| v.as_int().ok())
+                .map(|v| v as u32)
+        };
+
+        let edit = TagEdit {
+            artist: text("artist"),
+            album_artist: text("album_artist"),
+            title: text("title"),
+            album: text("album"),
+            track: number("track"),
+            year: number("year"),
+            genre: text("genre"),
+            ..Default::default()
+        };
+
+        if let Ok(mut actions) = actions.lock() {
+            actions.push(Action::EditTags(hash, edit));
+        }
+    });
+
+    engine
+}
+
+/// A song's metadata as a script map
+async fn song_map(hash: i64, db: &DatabaseConnection) -> Result<Dynamic> {
+    let song = library::Entity::find()
+        .filter(library::Column::Hash.eq(hash))
+        .one(db)
+        .await
+        .into_diagnostic()?
+        .ok_or(miette!("No song with hash {}", hash))?;
+
+    let text = |v: Option<String>| v.map(Dynamic::from).unwrap_or(Dynamic::UNIT);
+    let number = |v: Option<i32>| v.map(|v| Dynamic::from(v as INT)).unwrap_or(Dynamic::UNIT);
+
+    let mut map = Map::new();
+    map.insert("hash".into(), (song.hash as INT).into());
+    map.insert("path".into(), song.path.into());
+    map.insert("filename".into(), song.filename.into());
+    map.insert("source_id".into(), (song.source_id as INT).into());
+    map.insert("artist".into(), text(song.artist));
+    map.insert("album_artist".into(), text(song.album_artist));
+    map.insert("title".into(), text(song.name));
+    map.insert("album".into(), text(song.album));
+    map.insert("genres".into(), text(song.genres));
+    map.insert("track".into(), number(song.track));
+    map.insert("disc".into(), number(song.disc));
+    map.insert("year".into(), number(song.year));
+    map.insert("duration".into(), (song.duration as INT).into());
+
+    Ok(map.into())
+}
+
+fn plugins_dir() -> Result<PathBuf> {
+    config_dir()
+        .map(|v| v.join("plugins"))
+        .ok_or(miette!("Configuration directory not found"))
+}
+
+/// Whether a plugin name is a plain file name, which can't point outside of the plugin directory
+fn is_plain_name(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+
+    !name.contains(['/', '\\'])
+        && matches!(components.next(), Some(Component::Normal(_)))
+        && components.next().is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plugin_names_stay_in_their_directory() {
+        assert!(is_plain_name("scrobbler"));
+        assert!(is_plain_name("my.plugin"));
+
+        for name in ["", ".", "..", "../keys", "a/b", "/etc/keys", "a\\b"] {
+            assert!(!is_plain_name(name), "{name}");
+        }
+    }
+
+    #[test]
+    fn scripts_cannot_import_files() {
+        let path = std::env::temp_dir().join(format!("eleanor-module-{}.rhai", std::process::id()));
+        std::fs::write(&path, "fn secret() { 42 }").unwrap();
+
+        // Modules are looked up without their extension
+        let module = path.with_extension("");
+        let script = format!("import \"{}\" as m; m::secret()", module.display());
+        let result = engine(Arc::default()).eval::<INT>(&script);
+
+        std::fs::remove_file(path).unwrap();
+        assert!(result.is_err());
+    }
+}
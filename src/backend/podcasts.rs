@@ -0,0 +1,755 @@
+use std::{
+    collections::HashSet,
+    fs::{create_dir_all, rename, File, OpenOptions},
+    hash::Hasher,
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+    config::Config,
+    downloads::downloads_dir,
+    errors::{report, Category, Severity},
+    events::{subscribe, Event},
+    model::{podcast_episodes, podcasts},
+    network::is_metered,
+    playback::PlaybackState,
+    streaming::{HttpTransport, StreamingReader, PREBUFFER},
+    utils::http_client,
+};
+use adler::Adler32;
+use miette::{miette, IntoDiagnostic, Result};
+use paris::{info, success, warn};
+use quick_xml::{
+    events::{BytesStart, Event as XmlEvent},
+    Reader,
+};
+use reqwest::{header::RANGE, StatusCode};
+use sea_orm::{
+    sea_query::OnConflict, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder,
+    Set,
+};
+use symphonia::core::io::MediaSource;
+use tokio::{sync::broadcast::error::RecvError, task::JoinHandle};
+use url::Url;
+
+/// Episodes are inserted in batches, to stay under SQLite's limit on query parameters
+const BATCH_SIZE: usize = 50;
+/// How often the position of a playing episode is saved
+const SAVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A podcast as described by its feed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Feed {
+    pub title: String,
+    pub author: Option<String>,
+    pub description: Option<String>,
+    pub image_url: Option<String>,
+    pub episodes: Vec<FeedEpisode>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FeedEpisode {
+    /// The episode's id in the feed, its audio URL if the feed doesn't give it one
+    pub guid: String,
+    pub title: String,
+    pub description: Option<String>,
+    pub url: String,
+    pub mime_type: Option<String>,
+    /// In seconds since the Unix epoch
+    pub published: Option<i64>,
+    /// In seconds
+    pub duration: Option<i32>,
+}
+
+/// Subscribes to the podcast behind an RSS or Atom feed, and adds its episodes
+pub async fn subscribe_to(feed_url: &str, db: &DatabaseConnection) -> Result<podcasts::Model> {
+    let existing = podcasts::Entity::find()
+        .filter(podcasts::Column::FeedUrl.eq(feed_url))
+        .one(db)
+        .await
+        .into_diagnostic()?;
+
+    if existing.is_some() {
+        return Err(miette!("Already subscribed to {}", feed_url));
+    }
+
+    let feed = fetch_feed(feed_url).await?;
+
+    let id = podcasts::Entity::insert(podcasts::ActiveModel {
+        feed_url: Set(feed_url.to_string()),
+        title: Set(feed.title.clone()),
+        ..Default::default()
+    })
+    .exec(db)
+    .await
+    .into_diagnostic()?
+    .last_insert_id;
+
+    let podcast = update(id, feed_url, feed, db).await?;
+    success!("Subscribed to {}", podcast.title);
+
+    Ok(podcast)
+}
+
+/// Removes a podcast with its episodes and their downloads
+pub async fn unsubscribe(podcast_id: i32, db: &DatabaseConnection) -> Result<()> {
+    for episode in episodes(podcast_id, db).await? {
+        remove_files(&episode)?;
+    }
+
+    podcast_episodes::Entity::delete_many()
+        .filter(podcast_episodes::Column::PodcastId.eq(podcast_id))
+        .exec(db)
+        .await
+        .into_diagnostic()?;
+
+    podcasts::Entity::delete_by_id(podcast_id)
+        .exec(db)
+        .await
+        .into_diagnostic()?;
+
+    Ok(())
+}
+
+/// Fetches a podcast's feed again. Returns the number of new episodes.
+/// Episodes that changed are updated, keeping how far they were listened to.
+pub async fn refresh(podcast: &podcasts::Model, db: &DatabaseConnection) -> Result<usize> {
+    let before = episodes(podcast.id, db).await?.len();
+
+    let feed = fetch_feed(&podcast.feed_url).await?;
+    update(podcast.id, &podcast.feed_url, feed, db).await?;
+
+    let new = episodes(podcast.id, db).await?.len().saturating_sub(before);
+    if new > 0 {
+        info!("{new} new episodes of {}", podcast.title);
+    }
+
+    Ok(new)
+}
+
+/// Refreshes every podcast. A feed that can't be fetched doesn't stop the others.
+pub async fn refresh_all(db: &DatabaseConnection) -> Result<()> {
+    for podcast in podcasts::Entity::find()
+        .order_by_asc(podcasts::Column::Title)
+        .all(db)
+        .await
+        .into_diagnostic()?
+    {
+        if let Err(e) = refresh(&podcast, db).await {
+            report(
+                Severity::Warning,
+                Category::Network,
+                Some(podcast.title.clone()),
+                format!("Couldn't refresh {}: {e}", podcast.title),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Episodes of a podcast, newest first
+pub async fn episodes(
+    podcast_id: i32,
+    db: &DatabaseConnection,
+) -> Result<Vec<podcast_episodes::Model>> {
+    podcast_episodes::Entity::find()
+        .filter(podcast_episodes::Column::PodcastId.eq(podcast_id))
+        .order_by_desc(podcast_episodes::Column::Published)
+        .all(db)
+        .await
+        .into_diagnostic()
+}
+
+/// The episode with a hash, if there is one. Episodes are put in the queue by their hash
+/// like songs are, so the player looks up hashes it can't find in the library here.
+pub async fn episode(
+    hash: i64,
+    db: &DatabaseConnection,
+) -> Result<Option<podcast_episodes::Model>> {
+    podcast_episodes::Entity::find()
+        .filter(podcast_episodes::Column::Hash.eq(hash))
+        .one(db)
+        .await
+        .into_diagnostic()
+}
+
+/// Where playback of an episode should start. Finished episodes start over.
+pub fn resume_position(episode: &podcast_episodes::Model) -> Duration {
+    if episode.finished {
+        Duration::ZERO
+    } else {
+        Duration::from_millis(episode.position_ms.max(0) as u64)
+    }
+}
+
+/// Opens an episode for playback, from its download if there is one
+pub fn open_episode(episode: &podcast_episodes::Model) -> Result<Box<dyn MediaSource>> {
+    let path = download_path(episode)?;
+
+    if episode.downloaded && path.exists() {
+        return Ok(Box::new(File::open(path).into_diagnostic()?));
+    }
+
+    let client = http_client(&Config::read_config()?, None)?;
+    let transport = HttpTransport::new(client, episode.url.clone(), None);
+
+    Ok(Box::new(StreamingReader::new(transport, PREBUFFER)))
+}
+
+/// Extension of an episode's audio, so the player can guess its format
+pub fn extension(episode: &podcast_episodes::Model) -> String {
+    Url::parse(&episode.url)
+        .ok()
+        .and_then(|v| {
+            Path::new(v.path())
+                .extension()
+                .and_then(|v| v.to_str())
+                .map(str::to_lowercase)
+        })
+        .or_else(|| {
+            let mime = episode.mime_type.as_deref()?;
+            mime_guess::get_mime_extensions_str(mime)?
+                .first()
+                .map(|v| v.to_string())
+        })
+        .unwrap_or_else(|| "mp3".into())
+}
+
+/// Saves how far an episode was listened to
+pub async fn save_position(hash: i64, position: Duration, db: &DatabaseConnection) -> Result<()> {
+    podcast_episodes::Entity::update_many()
+        .set(podcast_episodes::ActiveModel {
+            position_ms: Set(position.as_millis() as i64),
+            ..Default::default()
+        })
+        .filter(podcast_episodes::Column::Hash.eq(hash))
+        .exec(db)
+        .await
+        .into_diagnostic()?;
+
+    Ok(())
+}
+
+pub async fn set_finished(hash: i64, finished: bool, db: &DatabaseConnection) -> Result<()> {
+    podcast_episodes::Entity::update_many()
+        .set(podcast_episodes::ActiveModel {
+            finished: Set(finished),
+            position_ms: Set(0),
+            ..Default::default()
+        })
+        .filter(podcast_episodes::Column::Hash.eq(hash))
+        .exec(db)
+        .await
+        .into_diagnostic()?;
+
+    Ok(())
+}
+
+/// Remembers where episodes stop playing, until the event bus closes
+pub fn track(db: DatabaseConnection) -> JoinHandle<()> {
+    let mut events = subscribe();
+
+    tokio::spawn(async move {
+        let mut playing: Option<i64> = None;
+        let mut last_saved = Instant::now();
+
+        loop {
+            let result = match events.recv().await {
+                Ok(Event::TrackStarted { hash }) => match episode(hash, &db).await {
+                    Ok(episode) => {
+                        playing = episode.map(|v| v.hash);
+                        last_saved = Instant::now();
+                        Ok(())
+                    }
+                    Err(e) => Err(e),
+                },
+                Ok(Event::Position { position, state }) => match playing {
+                    // Pausing saves right away, in case the app is closed next
+                    Some(hash)
+                        if state != PlaybackState::Playing
+                            || last_saved.elapsed() >= SAVE_INTERVAL =>
+                    {
+                        last_saved = Instant::now();
+                        save_position(hash, position, &db).await
+                    }
+                    _ => Ok(()),
+                },
+                Ok(Event::TrackEnded { hash, finished }) if playing == Some(hash) => {
+                    playing = None;
+
+                    if finished {
+                        set_finished(hash, true, &db).await
+                    } else {
+                        Ok(())
+                    }
+                }
+                Ok(_) => Ok(()),
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Podcast tracking missed {skipped} events");
+                    Ok(())
+                }
+                Err(RecvError::Closed) => break,
+            };
+
+            if let Err(e) = result {
+                warn!("Couldn't save the position of an episode: {e}");
+            }
+        }
+    })
+}
+
+/// Downloads an episode for offline playback. Interrupted downloads continue where they stopped.
+/// Returns whether the download completed, rather than being paused on a metered connection.
+pub async fn download_episode(
+    episode: &podcast_episodes::Model,
+    db: &DatabaseConnection,
+) -> Result<bool> {
+    if is_metered() {
+        info!("Not downloading episodes on a metered connection");
+        return Ok(false);
+    }
+
+    let partial = partial_path(episode)?;
+    create_dir_all(partial.parent().unwrap_or(&partial)).into_diagnostic()?;
+
+    let existing = partial.metadata().map(|v| v.len()).unwrap_or(0);
+
+    let mut request = http_client(&Config::read_config()?, None)?.get(&episode.url);
+
+    if existing > 0 {
+        request = request.header(RANGE, format!("bytes={existing}-"));
+    }
+
+    let mut response = request
+        .send()
+        .await
+        .into_diagnostic()?
+        .error_for_status()
+        .into_diagnostic()?;
+
+    // Servers that don't support ranges send the whole file again
+    let resumed = response.status() == StatusCode::PARTIAL_CONTENT;
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resumed)
+        .truncate(!resumed)
+        .open(&partial)
+        .into_diagnostic()?;
+
+    while let Some(chunk) = response.chunk().await.into_diagnostic()? {
+        file.write_all(&chunk).into_diagnostic()?;
+
+        if is_metered() {
+            file.flush().into_diagnostic()?;
+            return Ok(false);
+        }
+    }
+
+    file.flush().into_diagnostic()?;
+    rename(&partial, download_path(episode)?).into_diagnostic()?;
+
+    podcast_episodes::Entity::update_many()
+        .set(podcast_episodes::ActiveModel {
+            downloaded: Set(true),
+            ..Default::default()
+        })
+        .filter(podcast_episodes::Column::Id.eq(episode.id))
+        .exec(db)
+        .await
+        .into_diagnostic()?;
+
+    Ok(true)
+}
+
+/// Removes the download of an episode, so it's streamed again
+pub async fn remove_download(
+    episode: &podcast_episodes::Model,
+    db: &DatabaseConnection,
+) -> Result<()> {
+    remove_files(episode)?;
+
+    podcast_episodes::Entity::update_many()
+        .set(podcast_episodes::ActiveModel {
+            downloaded: Set(false),
+            ..Default::default()
+        })
+        .filter(podcast_episodes::Column::Id.eq(episode.id))
+        .exec(db)
+        .await
+        .into_diagnostic()?;
+
+    Ok(())
+}
+
+/// Stores a podcast's details and episodes from its feed
+async fn update(
+    id: i32,
+    feed_url: &str,
+    feed: Feed,
+    db: &DatabaseConnection,
+) -> Result<podcasts::Model> {
+    let podcast = podcasts::Entity::update(podcasts::ActiveModel {
+        id: Set(id),
+        title: Set(feed.title),
+        author: Set(feed.author),
+        description: Set(feed.description),
+        image_url: Set(feed.image_url),
+        last_checked: Set(Some(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .into_diagnostic()?
+                .as_secs() as i64,
+        )),
+        ..Default::default()
+    })
+    .exec(db)
+    .await
+    .into_diagnostic()?;
+
+    // Feeds sometimes list an episode twice
+    let mut seen = HashSet::new();
+
+    let rows: Vec<_> = feed
+        .episodes
+        .into_iter()
+        .map(|v| (episode_hash(feed_url, &v.guid), v))
+        .filter(|(hash, _)| seen.insert(*hash))
+        .map(|(hash, v)| podcast_episodes::ActiveModel {
+            podcast_id: Set(id),
+            hash: Set(hash),
+            guid: Set(v.guid),
+            title: Set(v.title),
+            description: Set(v.description),
+            url: Set(v.url),
+            mime_type: Set(v.mime_type),
+            published: Set(v.published),
+            duration: Set(v.duration),
+            ..Default::default()
+        })
+        .collect();
+
+    for batch in rows.chunks(BATCH_SIZE) {
+        podcast_episodes::Entity::insert_many(batch.to_vec())
+            .on_conflict(
+                OnConflict::column(podcast_episodes::Column::Hash)
+                    .update_columns([
+                        podcast_episodes::Column::Title,
+                        podcast_episodes::Column::Description,
+                        podcast_episodes::Column::Url,
+                        podcast_episodes::Column::MimeType,
+                        podcast_episodes::Column::Published,
+                        podcast_episodes::Column::Duration,
+                    ])
+                    .to_owned(),
+            )
+            .exec(db)
+            .await
+            .into_diagnostic()?;
+    }
+
+    Ok(podcast)
+}
+
+async fn fetch_feed(url: &str) -> Result<Feed> {
+    let body = http_client(&Config::read_config()?, None)?
+        .get(url)
+        .send()
+        .await
+        .into_diagnostic()?
+        .error_for_status()
+        .into_diagnostic()?
+        .bytes()
+        .await
+        .into_diagnostic()?;
+
+    parse_feed(&body)
+}
+
+/// Reads an RSS or Atom feed, including the tags podcast apps use, like `itunes:duration`.
+/// Entries without audio are left out.
+pub fn parse_feed(xml: &[u8]) -> Result<Feed> {
+    let mut reader = Reader::from_reader(xml);
+    reader.trim_text(true);
+
+    let mut buf = vec![];
+    let mut feed = Feed::default();
+    let mut episode: Option<FeedEpisode> = None;
+    // Elements enclosing the current one
+    let mut names: Vec<String> = vec![];
+    let mut text = String::new();
+
+    loop {
+        buf.clear();
+
+        match reader.read_event_into(&mut buf).into_diagnostic()? {
+            XmlEvent::Start(e) => {
+                let name = String::from_utf8_lossy(e.name().as_ref()).to_string();
+
+                if name == "item" || name == "entry" {
+                    episode = Some(FeedEpisode::default());
+                }
+
+                read_attributes(&e, &name, &mut feed, &mut episode)?;
+                names.push(name);
+                text.clear();
+            }
+            XmlEvent::Empty(e) => {
+                let name = String::from_utf8_lossy(e.name().as_ref()).to_string();
+                read_attributes(&e, &name, &mut feed, &mut episode)?;
+            }
+            XmlEvent::Text(e) => text.push_str(&e.unescape().into_diagnostic()?),
+            XmlEvent::CData(e) => text.push_str(&String::from_utf8_lossy(&e)),
+            XmlEvent::End(_) => {
+                let Some(name) = names.pop() else {
+                    continue;
+                };
+                let parent = names.last().map(String::as_str).unwrap_or_default();
+                let value = std::mem::take(&mut text).trim().to_string();
+
+                if name == "item" || name == "entry" {
+                    if let Some(mut finished) = episode.take() {
+                        if finished.url.is_empty() {
+                            continue;
+                        }
+
+                        if finished.guid.is_empty() {
+                            finished.guid = finished.url.clone();
+                        }
+
+                        feed.episodes.push(finished);
+                    }
+
+                    continue;
+                }
+
+                if value.is_empty() {
+                    continue;
+                }
+
+                match &mut episode {
+                    Some(episode) => match name.as_str() {
+                        "title" => episode.title = value,
+                        "guid" | "id" => episode.guid = value,
+                        "description" | "summary" | "itunes:summary" | "content:encoded" => {
+                            episode.description.get_or_insert(value);
+                        }
+                        "pubDate" | "published" | "updated" if episode.published.is_none() => {
+                            episode.published = parse_date(&value);
+                        }
+                        "itunes:duration" => episode.duration = parse_duration(&value),
+                        _ => {}
+                    },
+                    None => match (parent, name.as_str()) {
+                        ("channel" | "feed", "title") => feed.title = value,
+                        ("channel" | "feed", "description" | "subtitle" | "itunes:summary") => {
+                            feed.description.get_or_insert(value);
+                        }
+                        ("channel", "itunes:author") | ("author", "name") => {
+                            feed.author.get_or_insert(value);
+                        }
+                        ("image", "url") | ("feed", "logo" | "icon") => {
+                            feed.image_url.get_or_insert(value);
+                        }
+                        _ => {}
+                    },
+                }
+            }
+            XmlEvent::Eof => break,
+            _ => {}
+        }
+    }
+
+    if feed.title.is_empty() {
+        return Err(miette!("Not a podcast feed"));
+    }
+
+    Ok(feed)
+}
+
+/// Reads the audio of RSS `<enclosure>` and Atom `<link rel="enclosure">`, and iTunes cover art
+fn read_attributes(
+    element: &BytesStart,
+    name: &str,
+    feed: &mut Feed,
+    episode: &mut Option<FeedEpisode>,
+) -> Result<()> {
+    let attribute = |name: &str| -> Result<Option<String>> {
+        Ok(element
+            .try_get_attribute(name)
+            .into_diagnostic()?
+            .map(|v| String::from_utf8_lossy(&v.value).to_string()))
+    };
+
+    match (name, episode) {
+        ("enclosure", Some(episode)) => {
+            if let Some(url) = attribute("url")? {
+                episode.url = url;
+                episode.mime_type = attribute("type")?;
+            }
+        }
+        ("link", Some(episode)) if attribute("rel")?.as_deref() == Some("enclosure") => {
+            if let Some(url) = attribute("href")? {
+                episode.url = url;
+                episode.mime_type = attribute("type")?;
+            }
+        }
+        ("itunes:image", None) => {
+            if let Some(url) = attribute("href")? {
+                feed.image_url = Some(url);
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Identifies an episode across refreshes, even if its audio moves
+fn episode_hash(feed_url: &str, guid: &str) -> i64 {
+    let mut hasher = Adler32::new();
+    hasher.write(feed_url.as_bytes());
+    hasher.write(&[0]);
+    hasher.write(guid.as_bytes());
+
+    hasher.finish() as i64
+}
+
+/// Durations are given as seconds, `MM:SS` or `HH:MM:SS`
+fn parse_duration(text: &str) -> Option<i32> {
+    text.split(':').try_fold(0, |total: i32, part| {
+        let part: f64 = part.trim().parse().ok()?;
+        Some(total * 60 + part as i32)
+    })
+}
+
+/// Parses RFC 2822 dates used by RSS, like `Wed, 02 Oct 2002 13:00:00 GMT`,
+/// and RFC 3339 dates used by Atom, like `2002-10-02T13:00:00Z`. Returns seconds since the Unix epoch.
+fn parse_date(text: &str) -> Option<i64> {
+    let text = text.trim();
+
+    if text.as_bytes().get(4) == Some(&b'-') {
+        return parse_rfc3339(text);
+    }
+
+    // The day of the week is optional
+    let text = text.split_once(',').map_or(text, |(_, rest)| rest);
+    let mut parts = text.split_whitespace();
+
+    let day: i64 = parts.next()?.parse().ok()?;
+    let month = parts.next()?.to_lowercase();
+    let month = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ]
+    .iter()
+    .position(|v| month.starts_with(v))? as i64
+        + 1;
+    let year: i64 = match parts.next()?.parse().ok()? {
+        // Two digit years are still around in old feeds
+        year @ 0..=49 => year + 2000,
+        year @ 50..=99 => year + 1900,
+        year => year,
+    };
+
+    let mut time = parts.next()?.split(':').map(|v| v.parse::<i64>().ok());
+    let hours = time.next()??;
+    let minutes = time.next()??;
+    let seconds = time.next().flatten().unwrap_or(0);
+
+    let offset = match parts.next() {
+        Some(zone) if zone.starts_with(['+', '-']) && zone.len() == 5 => {
+            let sign = if zone.starts_with('-') { -1 } else { 1 };
+            let hours: i64 = zone.get(1..3)?.parse().ok()?;
+            let minutes: i64 = zone.get(3..5)?.parse().ok()?;
+            sign * (hours * 60 + minutes) * 60
+        }
+        Some("EDT") => -4 * 3600,
+        Some("EST" | "CDT") => -5 * 3600,
+        Some("CST" | "MDT") => -6 * 3600,
+        Some("MST" | "PDT") => -7 * 3600,
+        Some("PST") => -8 * 3600,
+        _ => 0,
+    };
+
+    Some(days_from_civil(year, month, day) * 86400 + hours * 3600 + minutes * 60 + seconds - offset)
+}
+
+fn parse_rfc3339(text: &str) -> Option<i64> {
+    let number = |range: std::ops::Range<usize>| text.get(range)?.parse::<i64>().ok();
+
+    let year = number(0..4)?;
+    let month = number(5..7)?;
+    let day = number(8..10)?;
+
+    // Dates without a time are at midnight
+    let (hours, minutes, seconds) = if text.len() > 10 {
+        (
+            number(11..13)?,
+            number(14..16)?,
+            number(17..19).unwrap_or(0),
+        )
+    } else {
+        (0, 0, 0)
+    };
+
+    // Fractions of a second come before the offset
+    let zone = text
+        .get(19..)
+        .unwrap_or_default()
+        .trim_start_matches(|v: char| v == '.' || v.is_ascii_digit());
+
+    let offset = match zone.as_bytes().first() {
+        Some(sign @ (b'+' | b'-')) => {
+            let hours: i64 = zone.get(1..3)?.parse().ok()?;
+            let minutes: i64 = zone.get(4..6).and_then(|v| v.parse().ok()).unwrap_or(0);
+            let offset = (hours * 60 + minutes) * 60;
+
+            if *sign == b'-' {
+                -offset
+            } else {
+                offset
+            }
+        }
+        _ => 0,
+    };
+
+    Some(days_from_civil(year, month, day) * 86400 + hours * 3600 + minutes * 60 + seconds - offset)
+}
+
+/// Days between the Unix epoch and a date in the Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146097 + day_of_era - 719468
+}
+
+/// Downloaded episodes are kept with downloaded songs, so cleaning the cache leaves them alone
+fn download_path(episode: &podcast_episodes::Model) -> Result<PathBuf> {
+    Ok(downloads_dir()?
+        .join("podcasts")
+        .join(format!("{}.{}", episode.hash, extension(episode))))
+}
+
+fn partial_path(episode: &podcast_episodes::Model) -> Result<PathBuf> {
+    Ok(downloads_dir()?
+        .join("podcasts")
+        .join(format!("{}.part", episode.hash)))
+}
+
+fn remove_files(episode: &podcast_episodes::Model) -> Result<()> {
+    for path in [download_path(episode)?, partial_path(episode)?] {
+        if path.exists() {
+            std::fs::remove_file(path).into_diagnostic()?;
+        }
+    }
+
+    Ok(())
+}
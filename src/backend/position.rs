@@ -0,0 +1,101 @@
+use std::{
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use super::{
+    events::{publish, Event},
+    playback::PlaybackState,
+};
+use serde::{Deserialize, Serialize};
+
+/// `Event::Position` is published this often while a track plays
+pub const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Moves further than this from where playback should be are reported right away, as seeks
+const JUMP: Duration = Duration::from_millis(500);
+
+/// Where playback is in the current track
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrackPosition {
+    pub hash: i64,
+    pub position: Duration,
+    pub state: PlaybackState,
+}
+
+struct Tracker {
+    /// Position as of `at`
+    current: TrackPosition,
+    at: Instant,
+    /// When `Event::Position` was last published
+    reported: Instant,
+}
+
+static TRACKER: Mutex<Option<Tracker>> = Mutex::new(None);
+
+fn tracker() -> MutexGuard<'static, Option<Tracker>> {
+    TRACKER.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Where playback is in the current track, if one is loaded. Everything showing or acting on
+/// the position, like the seek bar, the MPRIS position and scrobbling, reads it from here,
+/// so they never disagree. Between updates from the player, it moves on with the clock.
+pub fn current() -> Option<TrackPosition> {
+    tracker().as_ref().map(|v| {
+        let mut current = v.current;
+
+        if current.state == PlaybackState::Playing {
+            current.position += v.at.elapsed();
+        }
+
+        current
+    })
+}
+
+/// Called by the player as a track plays, with the position of the audio coming out of the
+/// output, see `DecoderThread::played`. Publishes `Event::Position` every `REPORT_INTERVAL`,
+/// and right away when a track starts, the state changes or playback jumps to another position.
+pub fn update(hash: i64, position: Duration, state: PlaybackState) {
+    let now = Instant::now();
+    let new = TrackPosition {
+        hash,
+        position,
+        state,
+    };
+
+    let mut tracker = tracker();
+
+    let due = tracker.as_ref().is_none_or(|v| {
+        let expected = match v.current.state {
+            PlaybackState::Playing => v.current.position + now.duration_since(v.at),
+            _ => v.current.position,
+        };
+
+        v.current.hash != hash
+            || v.current.state != state
+            || position.abs_diff(expected) > JUMP
+            || now.duration_since(v.reported) >= REPORT_INTERVAL
+    });
+
+    let reported = match &*tracker {
+        Some(v) if !due => v.reported,
+        _ => now,
+    };
+
+    *tracker = Some(Tracker {
+        current: new,
+        at: now,
+        reported,
+    });
+    // Subscribers may ask for the position themselves
+    drop(tracker);
+
+    if due {
+        publish(Event::Position { position, state });
+    }
+}
+
+/// Forgets the position once nothing is loaded anymore
+pub fn clear() {
+    *tracker() = None;
+}
@@ -0,0 +1,108 @@
+use std::time::Duration;
+
+use super::{
+    buffering::{Consumer, DecoderThread},
+    config::Config,
+    downloads::playable_path,
+    loudness::{extension, normalization_gain},
+    model::library,
+    queue::Queue,
+    streaming::stream_song,
+};
+use miette::{IntoDiagnostic, Result};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+
+/// The next track of the queue, buffering while the current one finishes
+pub struct Prefetched {
+    pub hash: i64,
+    pub decoder: DecoderThread,
+    /// Decoded audio of the start of the track
+    pub buffer: Consumer,
+}
+
+/// Starts streaming and decoding the next remote track in the queue shortly before
+/// the current one ends, so it's ready to be mixed in when the crossfade starts instead of
+/// waiting for the server. Local and downloaded tracks open instantly, so they're left alone.
+#[derive(Default)]
+pub struct Prefetcher {
+    next: Option<Prefetched>,
+    /// Last track that was looked at, so tracks that don't need buffering are only looked up once
+    checked: Option<i64>,
+}
+
+impl Prefetcher {
+    /// Checks whether the next track should start buffering. Called by the player as the
+    /// current track plays, it starts at most one stream per upcoming track.
+    pub async fn update(
+        &mut self,
+        queue: &Queue,
+        current_duration: Duration,
+        config: &Config,
+        db: &DatabaseConnection,
+    ) -> Result<()> {
+        // Buffering has to start before the crossfade does
+        let window = config
+            .prefetch_seconds
+            .max(config.crossfade_duration.into());
+
+        if current_duration.saturating_sub(queue.progress) > Duration::from_secs(window) {
+            return Ok(());
+        }
+
+        let Some(next) = queue.peek_next() else {
+            self.clear();
+            return Ok(());
+        };
+
+        if self.checked == Some(next) {
+            return Ok(());
+        }
+        self.next = None;
+        self.checked = Some(next);
+
+        let Some(song) = library::Entity::find()
+            .filter(library::Column::Hash.eq(next))
+            .one(db)
+            .await
+            .into_diagnostic()?
+        else {
+            return Ok(());
+        };
+
+        if playable_path(&song, db).await?.is_some() {
+            return Ok(());
+        }
+
+        let gain = normalization_gain(&song, config.playback.normalization, db).await?;
+        let reader = stream_song(&song, db)?;
+        let ext = extension(&song.filename);
+        let settings = config.playback.clone();
+
+        // Probing the format waits for the first response from the server
+        let (decoder, buffer) = tokio::task::spawn_blocking(move || {
+            DecoderThread::spawn(next, Box::new(reader), &ext, gain, &settings)
+        })
+        .await
+        .into_diagnostic()??;
+
+        self.next = Some(Prefetched {
+            hash: next,
+            decoder,
+            buffer,
+        });
+
+        Ok(())
+    }
+
+    /// Hands over the buffered track when the player moves on to it.
+    /// Anything buffered for another track is dropped.
+    pub fn take(&mut self, hash: i64) -> Option<Prefetched> {
+        self.next.take().filter(|v| v.hash == hash)
+    }
+
+    /// Drops the buffered track, e.g. when the queue changed
+    pub fn clear(&mut self) {
+        self.next = None;
+        self.checked = None;
+    }
+}
@@ -0,0 +1,117 @@
+use super::{
+    artists::split_artists,
+    config::{Config, Playback},
+    genres::normalize_genres,
+    loudness::{normalization_gain, Normalization},
+    model::library,
+    replaygain::Gain,
+};
+use miette::Result;
+use sea_orm::DatabaseConnection;
+use serde::{Deserialize, Serialize};
+
+/// Playback settings attached to genres or artists, e.g. to turn crossfading off for classical.
+///
+/// Each setting comes from the most specific preset that sets it: artist presets win over
+/// genre presets, and among those earlier presets win over later ones.
+/// Settings no matching preset sets come from the global configuration.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct Preset {
+    pub name: String,
+    /// Genres it applies to, compared case insensitively
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub genres: Vec<String>,
+    /// Artists it applies to, compared case insensitively with the song's artists
+    /// and album artist
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub artists: Vec<String>,
+    /// Gains of the equalizer bands in dB, see `equalizer::BANDS`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eq: Option<Vec<f32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub normalization: Option<Normalization>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crossfade: Option<bool>,
+}
+
+/// Settings to play a song with once presets were applied
+#[derive(Debug, Clone)]
+pub struct Applied {
+    /// The configured playback settings with the preset's equalizer and normalization
+    pub playback: Playback,
+    pub crossfade: bool,
+    /// ReplayGain adjustment in the resulting normalization mode
+    pub gain: Option<Gain>,
+    /// Names of the matching presets, most specific first
+    pub presets: Vec<String>,
+}
+
+/// Presets matching a song, most specific first
+pub fn matching<'a>(song: &library::Model, config: &'a Config) -> Vec<&'a Preset> {
+    let mut artists: Vec<String> = song
+        .artist
+        .iter()
+        .flat_map(|v| split_artists(v, &config.artist_separators))
+        .chain(song.album_artist.clone())
+        .map(|v| v.to_lowercase())
+        .collect();
+    artists.dedup();
+
+    let genres: Vec<String> = song
+        .genres
+        .as_deref()
+        .map(normalize_genres)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|v| v.to_lowercase())
+        .collect();
+
+    let contains = |names: &[String], wanted: &[String]| {
+        names.iter().any(|v| wanted.contains(&v.to_lowercase()))
+    };
+
+    let by_artist = config
+        .presets
+        .iter()
+        .filter(|v| contains(&v.artists, &artists));
+    let by_genre = config
+        .presets
+        .iter()
+        .filter(|v| !contains(&v.artists, &artists) && contains(&v.genres, &genres));
+
+    by_artist.chain(by_genre).collect()
+}
+
+/// Settings to play a song with, to be used once it starts
+pub async fn apply(
+    song: &library::Model,
+    config: &Config,
+    db: &DatabaseConnection,
+) -> Result<Applied> {
+    let presets = matching(song, config);
+
+    let mut playback = config.playback.clone();
+
+    if let Some(eq) = presets.iter().find_map(|v| v.eq.as_ref()) {
+        playback.eq = eq.clone();
+    }
+
+    if let Some(normalization) = presets.iter().find_map(|v| v.normalization) {
+        playback.normalization = normalization;
+    }
+
+    let crossfade = presets
+        .iter()
+        .find_map(|v| v.crossfade)
+        .unwrap_or(config.crossfade);
+
+    let gain = normalization_gain(song, playback.normalization, db).await?;
+
+    Ok(Applied {
+        playback,
+        crossfade,
+        gain,
+        presets: presets.iter().map(|v| v.name.clone()).collect(),
+    })
+}
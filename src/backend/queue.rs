@@ -0,0 +1,1175 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{create_dir_all, read_dir, remove_file, File},
+    io::Write,
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+
+use super::{
+    browse::{album_tracks, Album},
+    compilations::album_artist,
+    config::Config,
+    events::{publish, Event},
+    model::{history, library, playlist_entries, song_genres, song_stats},
+    utils::config_dir,
+};
+use async_trait::async_trait;
+use miette::{miette, IntoDiagnostic, Result};
+use rand::seq::SliceRandom;
+use sea_orm::{
+    sea_query::{Expr, Query},
+    ColumnTrait, Condition, DatabaseConnection, EntityTrait, Order, QueryFilter, QueryOrder,
+    QuerySelect,
+};
+use serde::{Deserialize, Serialize};
+
+/// Number of tracks added at a time in station mode
+const STATION_BATCH: usize = 10;
+/// Number of tracks radio mode keeps queued after the current one
+const RADIO_AHEAD: usize = 5;
+/// Radio mode doesn't pick songs that were among this many last plays
+const RECENT_PLAYS: u64 = 50;
+/// Songs released at most this many years apart count as the same era
+const ERA_YEARS: i32 = 5;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Repeat {
+    #[default]
+    Off,
+    /// Repeat the current track
+    Track,
+    /// Start over once the last track ends
+    Queue,
+}
+
+/// What shuffle keeps together
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ShuffleMode {
+    /// Every track on its own
+    #[default]
+    Tracks,
+    /// Albums are played in random order, with their tracks in the order they were queued
+    Albums,
+    /// Like `Albums`, for the tracks of each album artist
+    Artists,
+}
+
+impl ShuffleMode {
+    /// Which group each of the tracks is shuffled with, numbered in the order the groups first
+    /// appear. Songs that aren't in the library get a group of their own.
+    pub async fn groups(self, hashes: &[i64], db: &DatabaseConnection) -> Result<Vec<usize>> {
+        if self == ShuffleMode::Tracks {
+            return Ok((0..hashes.len()).collect());
+        }
+
+        let songs = library::Entity::find()
+            .filter(library::Column::Hash.is_in(hashes.iter().copied()))
+            .all(db)
+            .await
+            .into_diagnostic()?;
+        let songs: HashMap<i64, &library::Model> = songs.iter().map(|v| (v.hash, v)).collect();
+
+        let mut ids = HashMap::new();
+        let mut next = 0;
+
+        Ok(hashes
+            .iter()
+            .map(|hash| {
+                let id = match songs.get(hash) {
+                    Some(song) => *ids.entry(self.key(song)).or_insert(next),
+                    None => next,
+                };
+
+                if id == next {
+                    next += 1;
+                }
+
+                id
+            })
+            .collect())
+    }
+
+    fn key(self, song: &library::Model) -> String {
+        let artist = album_artist(song).unwrap_or_default().to_lowercase();
+
+        match (self, &song.album) {
+            (ShuffleMode::Albums, Some(album)) => format!("{artist}\0{album}"),
+            // Grouped by directory, like in `same_album`
+            (ShuffleMode::Albums, None) => format!("{}\0{}\0", song.source_id, song.path),
+            _ => artist,
+        }
+    }
+}
+
+/// What happens once the last track in the queue ends
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum EndOfQueue {
+    #[default]
+    Stop,
+    /// Start over from the first track
+    Repeat,
+    /// Keep adding tracks similar to the last one
+    Station,
+    /// Replace the queue with the configured fallback playlist
+    Playlist,
+}
+
+/// Where playback stops instead of moving on, e.g. to fall asleep to the end of an album
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StopAfter {
+    /// Once the current track ends
+    Track,
+    /// Once the last track of the current album ends, i.e. when the next track is from
+    /// another album
+    Album,
+}
+
+/// Where tracks go when they're queued, the usual options of a track's context menu
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Placement {
+    /// Right after the current track, which is skipped to play them
+    Now,
+    /// Right after the current track
+    Next,
+    /// After every other track
+    End,
+    /// Instead of every track in the queue
+    Replace,
+}
+
+/// Tracks picked in the library to be queued
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Selection {
+    Tracks(Vec<i64>),
+    /// Every track of an album, see `browse::album_tracks`
+    Album(Album),
+    /// Every track of a playlist by its id, in playlist order
+    Playlist(i32),
+}
+
+impl Selection {
+    /// Hashes of the selected tracks, in the order they're queued
+    pub async fn hashes(&self, db: &DatabaseConnection) -> Result<Vec<i64>> {
+        match self {
+            Selection::Tracks(hashes) => Ok(hashes.clone()),
+            Selection::Album(album) => Ok(album_tracks(album, db)
+                .await?
+                .into_iter()
+                .map(|v| v.hash)
+                .collect()),
+            Selection::Playlist(id) => playlist_tracks(*id, db).await,
+        }
+    }
+}
+
+/// When a track in the queue starts playing
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Upcoming {
+    pub hash: i64,
+    /// Time until it starts, in milliseconds
+    pub starts_in_ms: u64,
+}
+
+/// How long the rest of the queue plays, so "plays in 23 min" can be shown for each track
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Eta {
+    /// Every track after the current one, in the order they will be played
+    pub upcoming: Vec<Upcoming>,
+    /// Time until the last track ends, in milliseconds
+    pub remaining_ms: u64,
+}
+
+/// Songs waiting to be played, referenced by their hashes
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct Queue {
+    pub tracks: Vec<i64>,
+    /// Order tracks are played in, as indices into `tracks`
+    order: Vec<usize>,
+    /// Index into `order` of the current track
+    position: Option<usize>,
+    shuffle: bool,
+    shuffle_mode: ShuffleMode,
+    /// Group of each track in `tracks` for grouped shuffle modes, see `ShuffleMode::groups`.
+    /// Tracks added since it was set have a group of their own.
+    groups: Vec<usize>,
+    pub repeat: Repeat,
+    /// How far into the current track playback is
+    pub progress: Duration,
+    /// Keeps the queue topped up with tracks similar to the current one, see `Radio`
+    pub radio: bool,
+    /// Stop once the current track or album ends. Cleared once playback stopped there.
+    pub stop_after: Option<StopAfter>,
+}
+
+impl Queue {
+    pub fn new(tracks: Vec<i64>) -> Self {
+        Queue {
+            order: (0..tracks.len()).collect(),
+            position: (!tracks.is_empty()).then_some(0),
+            tracks,
+            ..Default::default()
+        }
+    }
+
+    /// Hash of the song that's currently playing
+    pub fn current(&self) -> Option<i64> {
+        self.position
+            .and_then(|v| self.order.get(v))
+            .and_then(|&v| self.tracks.get(v).copied())
+    }
+
+    /// Tracks in the order they will be played
+    pub fn ordered(&self) -> impl Iterator<Item = i64> + '_ {
+        self.order
+            .iter()
+            .filter_map(|&v| self.tracks.get(v).copied())
+    }
+
+    /// Index of the current track in `ordered`
+    pub fn position(&self) -> Option<usize> {
+        self.position
+    }
+
+    pub fn shuffle(&self) -> bool {
+        self.shuffle
+    }
+
+    pub fn shuffle_mode(&self) -> ShuffleMode {
+        self.shuffle_mode
+    }
+
+    /// Turns shuffling on or off. The current track keeps playing either way.
+    pub fn set_shuffle(&mut self, shuffle: bool) {
+        let current = self.position.and_then(|v| self.order.get(v)).copied();
+
+        self.shuffle = shuffle;
+        self.order = match shuffle {
+            true => self.shuffled_order(),
+            false => (0..self.tracks.len()).collect(),
+        };
+
+        if let (true, Some(current)) = (shuffle, current) {
+            // Move the current track's group to the front, so every other group is still ahead
+            let group = self.group(current);
+            let (mut order, rest): (Vec<_>, Vec<_>) = self
+                .order
+                .iter()
+                .copied()
+                .partition(|&v| self.group(v) == group);
+            order.extend(rest);
+
+            self.order = order;
+        }
+
+        self.position = current.and_then(|current| self.order.iter().position(|&v| v == current));
+
+        publish(Event::QueueUpdated);
+    }
+
+    /// Picks what shuffle keeps together, and reshuffles if shuffle is on
+    pub async fn set_shuffle_mode(
+        &mut self,
+        mode: ShuffleMode,
+        db: &DatabaseConnection,
+    ) -> Result<()> {
+        self.groups = mode.groups(&self.tracks, db).await?;
+        self.shuffle_mode = mode;
+
+        // Also announces the change
+        self.set_shuffle(self.shuffle);
+
+        Ok(())
+    }
+
+    /// Group of a track by its index in `tracks`
+    fn group(&self, index: usize) -> usize {
+        match self.shuffle_mode {
+            ShuffleMode::Tracks => index,
+            // Past every group id that's set, so it's one of its own
+            _ => self
+                .groups
+                .get(index)
+                .copied()
+                .unwrap_or(self.tracks.len() + index),
+        }
+    }
+
+    /// Every track in random order, keeping the tracks of each group together in queue order
+    fn shuffled_order(&self) -> Vec<usize> {
+        let mut groups: Vec<Vec<usize>> = vec![];
+        let mut ids = HashMap::new();
+
+        for index in 0..self.tracks.len() {
+            let i = *ids.entry(self.group(index)).or_insert_with(|| {
+                groups.push(vec![]);
+                groups.len() - 1
+            });
+
+            groups[i].push(index);
+        }
+
+        groups.shuffle(&mut rand::thread_rng());
+        groups.concat()
+    }
+
+    /// When each upcoming track starts, from the durations of the songs in milliseconds
+    pub fn eta(&self, durations: &HashMap<i64, u32>) -> Eta {
+        let ordered: Vec<i64> = self.ordered().collect();
+
+        eta(
+            &ordered,
+            self.position,
+            self.progress,
+            self.repeat,
+            durations,
+        )
+    }
+
+    pub fn set_stop_after(&mut self, stop_after: Option<StopAfter>) {
+        self.stop_after = stop_after;
+
+        publish(Event::QueueUpdated);
+    }
+
+    /// Whether playback should stop once the current track ends, instead of advancing,
+    /// following `stop_after`. The rule is cleared if it does.
+    pub async fn stops_here(&mut self, db: &DatabaseConnection) -> Result<bool> {
+        let stops = match self.stop_after {
+            None => false,
+            Some(StopAfter::Track) => true,
+            Some(StopAfter::Album) => match (self.current(), self.peek_next()) {
+                (Some(current), Some(next)) => {
+                    let songs = library::Entity::find()
+                        .filter(library::Column::Hash.is_in([current, next]))
+                        .all(db)
+                        .await
+                        .into_diagnostic()?;
+
+                    let song = |hash| songs.iter().find(|v| v.hash == hash);
+
+                    match (song(current), song(next)) {
+                        (Some(current), Some(next)) => !same_album(current, next),
+                        _ => true,
+                    }
+                }
+                // Nothing follows, so the album ends here
+                _ => true,
+            },
+        };
+
+        if stops {
+            self.set_stop_after(None);
+        }
+
+        Ok(stops)
+    }
+
+    /// Adds a track to the end of the queue
+    pub fn push(&mut self, hash: i64) {
+        self.extend([hash]);
+    }
+
+    /// Adds tracks to the end of the queue
+    pub fn extend(&mut self, hashes: impl IntoIterator<Item = i64>) {
+        for hash in hashes {
+            self.tracks.push(hash);
+            self.order.push(self.tracks.len() - 1);
+
+            if self.position.is_none() {
+                self.position = Some(self.order.len() - 1);
+            }
+        }
+
+        publish(Event::QueueUpdated);
+    }
+
+    /// Queues the selected tracks as set by `placement`. Returns the track to switch to,
+    /// if the current one changed and playback should start over with it.
+    pub async fn queue(
+        &mut self,
+        selection: &Selection,
+        placement: Placement,
+        db: &DatabaseConnection,
+    ) -> Result<Option<i64>> {
+        let hashes = selection.hashes(db).await?;
+
+        Ok(match placement {
+            Placement::Now => self.play_now(hashes),
+            Placement::Next => {
+                self.play_next(hashes);
+                None
+            }
+            Placement::End => {
+                let groups = self.shuffle_mode.groups(&hashes, db).await?;
+                self.append_grouped(hashes, groups);
+                None
+            }
+            Placement::Replace => {
+                let groups = self.shuffle_mode.groups(&hashes, db).await?;
+                self.replace(hashes, groups)
+            }
+        })
+    }
+
+    /// Queues tracks right after the current one and skips to the first of them,
+    /// returning it. The rest of the queue plays after them.
+    pub fn play_now(&mut self, hashes: Vec<i64>) -> Option<i64> {
+        let start = self.insert_next(&hashes)?;
+
+        self.position = Some(start);
+        self.progress = Duration::ZERO;
+
+        queued(hashes, Placement::Now);
+        self.current()
+    }
+
+    /// Queues tracks right after the current one, also when shuffling
+    pub fn play_next(&mut self, hashes: Vec<i64>) {
+        if self.insert_next(&hashes).is_some() {
+            queued(hashes, Placement::Next);
+        }
+    }
+
+    /// Queues tracks after every other track, like `extend`.
+    /// Like `replace_with`, grouped shuffle modes shuffle each of them on its own,
+    /// `queue` groups them right away.
+    pub fn append(&mut self, hashes: Vec<i64>) {
+        self.append_grouped(hashes, vec![]);
+    }
+
+    fn append_grouped(&mut self, hashes: Vec<i64>, groups: Vec<usize>) {
+        if hashes.is_empty() {
+            return;
+        }
+
+        // Tracks without a group keep one of their own, and the new groups come after
+        // every group in use, so they don't join earlier ones
+        let mut next = self.groups.iter().max().map_or(0, |v| v + 1);
+        while self.groups.len() < self.tracks.len() {
+            self.groups.push(next);
+            next += 1;
+        }
+        self.groups.extend(groups.into_iter().map(|v| v + next));
+
+        self.extend(hashes.iter().copied());
+        queued(hashes, Placement::End);
+    }
+
+    /// Replaces every track in the queue, starting with the first new one, which is returned.
+    /// Shuffle, repeat and radio mode stay as they were, and tracks are shuffled if shuffle is on.
+    /// Nothing changes if there are no tracks to queue.
+    /// Grouped shuffle modes shuffle every track on its own until `set_shuffle_mode` is called
+    /// again, `queue` groups them right away.
+    pub fn replace_with(&mut self, hashes: Vec<i64>) -> Option<i64> {
+        self.replace(hashes, vec![])
+    }
+
+    fn replace(&mut self, hashes: Vec<i64>, groups: Vec<usize>) -> Option<i64> {
+        if hashes.is_empty() {
+            return None;
+        }
+
+        let mut queue = Queue::new(hashes.clone());
+        queue.repeat = self.repeat;
+        queue.radio = self.radio;
+        queue.shuffle_mode = self.shuffle_mode;
+        queue.groups = groups;
+        // Also announces the new queue
+        queue.set_shuffle(self.shuffle);
+        *self = queue;
+
+        queued(hashes, Placement::Replace);
+        self.current()
+    }
+
+    /// Inserts tracks into the order right after the current track, or at the start if
+    /// nothing is playing. Returns the index into `order` of the first one.
+    fn insert_next(&mut self, hashes: &[i64]) -> Option<usize> {
+        if hashes.is_empty() {
+            return None;
+        }
+
+        let start = self.position.map_or(self.order.len(), |v| v + 1);
+        let indices = self.tracks.len()..self.tracks.len() + hashes.len();
+
+        self.tracks.extend(hashes);
+        self.order.splice(start..start, indices);
+
+        if self.position.is_none() {
+            self.position = Some(start);
+        }
+
+        publish(Event::QueueUpdated);
+        Some(start)
+    }
+
+    /// The track `advance` will move to, without moving. `None` if it's not known yet,
+    /// like at the end of a shuffled queue on repeat, which is reshuffled first.
+    pub fn peek_next(&self) -> Option<i64> {
+        let position = self.position?;
+
+        let next = match self.repeat {
+            Repeat::Track => position,
+            _ if position + 1 < self.order.len() => position + 1,
+            Repeat::Queue if !self.shuffle => 0,
+            _ => return None,
+        };
+
+        self.order
+            .get(next)
+            .and_then(|&v| self.tracks.get(v).copied())
+    }
+
+    /// Moves to the next track, returning it. Returns `None` once the end of the queue is reached.
+    pub fn advance(&mut self) -> Option<i64> {
+        let position = self.position?;
+        self.progress = Duration::ZERO;
+
+        self.position = match self.repeat {
+            Repeat::Track => Some(position),
+            _ if position + 1 < self.order.len() => Some(position + 1),
+            Repeat::Queue => return self.restart(),
+            Repeat::Off => None,
+        };
+
+        self.current()
+    }
+
+    /// Adds tracks picked by `filler` until at least `ahead` tracks follow the current one
+    pub async fn fill(
+        &mut self,
+        filler: &dyn QueueFiller,
+        ahead: usize,
+        db: &DatabaseConnection,
+    ) -> Result<()> {
+        let queued = self
+            .position
+            .map_or(0, |v| self.order.len().saturating_sub(v + 1));
+
+        if queued >= ahead {
+            return Ok(());
+        }
+
+        let tracks = filler.next_tracks(self, ahead - queued, db).await?;
+        self.extend(tracks);
+
+        Ok(())
+    }
+
+    /// Moves to the next track like `advance`, but once the queue runs out
+    /// continues as set by `end_of_queue` in the configuration.
+    /// In radio mode, the queue is topped up first and never runs out.
+    pub async fn advance_or_continue(
+        &mut self,
+        config: &Config,
+        db: &DatabaseConnection,
+    ) -> Result<Option<i64>> {
+        let next = self.advance();
+
+        if self.radio {
+            // Picks the first track itself once the queue ran out
+            self.fill(&Radio, RADIO_AHEAD, db).await?;
+        }
+
+        if let Some(next) = next.or_else(|| self.current()) {
+            return Ok(Some(next));
+        }
+
+        match config.end_of_queue {
+            EndOfQueue::Stop => Ok(None),
+            EndOfQueue::Repeat => Ok(self.restart()),
+            EndOfQueue::Station => {
+                let tracks = Station.next_tracks(self, STATION_BATCH, db).await?;
+                let start = self.order.len();
+
+                self.extend(tracks);
+                self.position = (start < self.order.len()).then_some(start);
+                Ok(self.current())
+            }
+            EndOfQueue::Playlist => {
+                let id = config
+                    .fallback_playlist
+                    .ok_or(miette!("No fallback playlist is set"))?;
+
+                let tracks = playlist_tracks(id, db).await?;
+                let groups = self.shuffle_mode.groups(&tracks, db).await?;
+
+                let mut queue = Queue::new(tracks);
+                queue.repeat = self.repeat;
+                queue.shuffle_mode = self.shuffle_mode;
+                queue.groups = groups;
+                // Also announces the new queue
+                queue.set_shuffle(self.shuffle);
+                *self = queue;
+
+                Ok(self.current())
+            }
+        }
+    }
+
+    /// Goes back to the first track, reshuffling if shuffle is on
+    fn restart(&mut self) -> Option<i64> {
+        if self.order.is_empty() {
+            return None;
+        }
+
+        if self.shuffle {
+            self.order = self.shuffled_order();
+        }
+        self.position = Some(0);
+        self.progress = Duration::ZERO;
+
+        self.current()
+    }
+
+    /// Moves to the previous track, or restarts the first one
+    pub fn previous(&mut self) -> Option<i64> {
+        let position = self.position?;
+        self.progress = Duration::ZERO;
+
+        self.position = Some(match self.repeat {
+            Repeat::Queue if position == 0 => self.order.len() - 1,
+            _ => position.saturating_sub(1),
+        });
+
+        self.current()
+    }
+
+    /// Saves the queue under a name, overwriting any session with the same name
+    pub fn save_session(&self, name: &str) -> Result<()> {
+        let contents = toml::to_string(self).into_diagnostic()?;
+
+        let dir = sessions_dir()?;
+        create_dir_all(&dir).into_diagnostic()?;
+
+        File::create(session_path(&dir, name)?)
+            .and_then(|mut v| v.write_all(contents.as_bytes()))
+            .into_diagnostic()
+    }
+
+    /// Restores a saved queue, including its position and shuffle and repeat modes
+    pub fn load_session(name: &str) -> Result<Self> {
+        let path = session_path(&sessions_dir()?, name)?;
+
+        let contents = std::fs::read_to_string(path)
+            .map_err(|_| miette!("Session {} does not exist", name))?;
+
+        toml::from_str(&contents).into_diagnostic()
+    }
+
+    /// Saves the queue to the current session and restores another one, so both can be resumed
+    pub fn switch_session(&self, current: &str, name: &str) -> Result<Self> {
+        self.save_session(current)?;
+
+        let queue = match Self::load_session(name) {
+            Ok(queue) => queue,
+            // Switching to a session that doesn't exist yet starts an empty one
+            Err(_) if !session_path(&sessions_dir()?, name)?.exists() => Default::default(),
+            Err(e) => return Err(e),
+        };
+
+        publish(Event::QueueUpdated);
+        Ok(queue)
+    }
+}
+
+fn queued(hashes: Vec<i64>, placement: Placement) {
+    publish(Event::TracksQueued { hashes, placement });
+}
+
+/// Hashes of the songs in a playlist, in playlist order
+async fn playlist_tracks(playlist_id: i32, db: &DatabaseConnection) -> Result<Vec<i64>> {
+    Ok(playlist_entries::Entity::find()
+        .filter(playlist_entries::Column::PlaylistId.eq(playlist_id))
+        .order_by_asc(playlist_entries::Column::Ordinal)
+        .all(db)
+        .await
+        .into_diagnostic()?
+        .into_iter()
+        .map(|v| v.song_hash)
+        .collect())
+}
+
+/// Picks tracks to add to the queue, e.g. once it runs out
+#[async_trait]
+pub trait QueueFiller: Send + Sync {
+    /// Returns up to `count` tracks to add after the ones in `queue`
+    async fn next_tracks(
+        &self,
+        queue: &Queue,
+        count: usize,
+        db: &DatabaseConnection,
+    ) -> Result<Vec<i64>>;
+}
+
+/// Picks random tracks sharing an artist or genre with the last track, that aren't in the queue yet.
+/// Falls back to any tracks if there are no similar ones. Audiobooks are left out.
+pub struct Station;
+
+#[async_trait]
+impl QueueFiller for Station {
+    async fn next_tracks(
+        &self,
+        queue: &Queue,
+        count: usize,
+        db: &DatabaseConnection,
+    ) -> Result<Vec<i64>> {
+        station_tracks(queue.ordered().last(), &queue.tracks, count, db).await
+    }
+}
+
+async fn station_tracks(
+    seed: Option<i64>,
+    queued: &[i64],
+    count: usize,
+    db: &DatabaseConnection,
+) -> Result<Vec<i64>> {
+    let seed = match seed {
+        Some(hash) => library::Entity::find()
+            .filter(library::Column::Hash.eq(hash))
+            .one(db)
+            .await
+            .into_diagnostic()?,
+        None => None,
+    };
+
+    let mut similar = Condition::any().add(
+        library::Column::Hash.in_subquery(
+            Query::select()
+                .column(song_genres::Column::SongHash)
+                .from(song_genres::Entity)
+                .and_where(
+                    Expr::col(song_genres::Column::GenreId).in_subquery(
+                        Query::select()
+                            .column(song_genres::Column::GenreId)
+                            .from(song_genres::Entity)
+                            .and_where(
+                                Expr::col(song_genres::Column::SongHash)
+                                    .eq(seed.as_ref().map(|v| v.hash)),
+                            )
+                            .to_owned(),
+                    ),
+                )
+                .to_owned(),
+        ),
+    );
+
+    if let Some(artist) = seed.as_ref().and_then(|v| v.artist.as_deref()) {
+        similar = similar.add(library::Column::Artist.eq(artist));
+    }
+
+    let audiobooks = Config::read_config()?.audiobook_source_ids();
+
+    for condition in [similar, Condition::all()] {
+        let tracks: Vec<i64> = library::Entity::find()
+            .filter(library::Column::Hash.is_not_in(queued.iter().copied()))
+            .filter(library::Column::SourceId.is_not_in(audiobooks.clone()))
+            .filter(condition)
+            .order_by(Expr::cust("RANDOM()"), Order::Asc)
+            .limit(count as u64)
+            .all(db)
+            .await
+            .into_diagnostic()?
+            .into_iter()
+            .map(|v| v.hash)
+            .collect();
+
+        if !tracks.is_empty() {
+            return Ok(tracks);
+        }
+    }
+
+    Ok(vec![])
+}
+
+/// Picks tracks similar to the current one, sharing its genres or artist or released around
+/// the same time. Songs that are rated higher or usually played to the end are picked more often,
+/// ones that are usually skipped less often. Songs that are queued or were played recently
+/// are left out, and so are audiobooks.
+pub struct Radio;
+
+#[async_trait]
+impl QueueFiller for Radio {
+    async fn next_tracks(
+        &self,
+        queue: &Queue,
+        count: usize,
+        db: &DatabaseConnection,
+    ) -> Result<Vec<i64>> {
+        let songs = library::Entity::find()
+            .filter(
+                library::Column::SourceId.is_not_in(Config::read_config()?.audiobook_source_ids()),
+            )
+            .all(db)
+            .await
+            .into_diagnostic()?;
+
+        let seed = queue
+            .current()
+            .or_else(|| queue.ordered().last())
+            .and_then(|hash| songs.iter().find(|v| v.hash == hash));
+
+        let mut genres: HashMap<_, HashSet<i32>> = HashMap::new();
+        for entry in song_genres::Entity::find()
+            .all(db)
+            .await
+            .into_diagnostic()?
+        {
+            genres
+                .entry(entry.song_hash)
+                .or_default()
+                .insert(entry.genre_id);
+        }
+
+        // Finished and skipped plays of every song
+        let mut plays: HashMap<i64, (u32, u32)> = HashMap::new();
+        for entry in history::Entity::find().all(db).await.into_diagnostic()? {
+            let counts = plays.entry(entry.song_hash).or_default();
+
+            if entry.finished {
+                counts.0 += 1;
+            } else {
+                counts.1 += 1;
+            }
+        }
+
+        let mut ratings = HashMap::new();
+        for stats in song_stats::Entity::find().all(db).await.into_diagnostic()? {
+            // Plays imported from other players count as finished
+            plays.entry(stats.song_hash).or_default().0 += stats.imported_plays.max(0) as u32;
+
+            if let Some(rating) = stats.rating {
+                ratings.insert(stats.song_hash, rating);
+            }
+        }
+
+        let mut excluded: HashSet<i64> = history::Entity::find()
+            .order_by_desc(history::Column::PlayedAt)
+            .limit(RECENT_PLAYS)
+            .all(db)
+            .await
+            .into_diagnostic()?
+            .into_iter()
+            .map(|v| v.song_hash)
+            .collect();
+        excluded.extend(&queue.tracks);
+
+        let no_genres = HashSet::new();
+        let seed_genres = seed.and_then(|v| genres.get(&v.hash)).unwrap_or(&no_genres);
+
+        let candidates: Vec<_> = songs
+            .iter()
+            .filter(|v| !excluded.contains(&v.hash))
+            .map(|song| {
+                let similarity = seed.map_or(0.0, |seed| {
+                    similarity(seed, song, seed_genres, genres.get(&song.hash))
+                });
+
+                let (finished, skipped) = plays.get(&song.hash).copied().unwrap_or_default();
+                let mut preference = (2.0 + finished as f64) / (2.0 + skipped as f64);
+
+                // Three stars are neutral, every star above doubles the chance of a song
+                if let Some(rating) = ratings.get(&song.hash) {
+                    preference *= 2f64.powf((*rating - 60) as f64 / 20.0);
+                }
+
+                (song.hash, similarity, preference)
+            })
+            .collect();
+
+        // Falls back to any tracks if there are no similar ones
+        let pool: Vec<_> = match candidates.iter().any(|v| v.1 > 0.0) {
+            true => candidates
+                .into_iter()
+                .filter(|v| v.1 > 0.0)
+                .map(|(hash, similarity, preference)| (hash, similarity * preference))
+                .collect(),
+            false => candidates
+                .into_iter()
+                .map(|(hash, _, preference)| (hash, preference))
+                .collect(),
+        };
+
+        if pool.is_empty() {
+            return Ok(vec![]);
+        }
+
+        Ok(pool
+            .choose_multiple_weighted(&mut rand::thread_rng(), count, |v| v.1)
+            .into_diagnostic()?
+            .map(|v| v.0)
+            .collect())
+    }
+}
+
+/// How similar a song is to the seed of radio mode, 0 if they have nothing in common
+fn similarity(
+    seed: &library::Model,
+    song: &library::Model,
+    seed_genres: &HashSet<i32>,
+    song_genres: Option<&HashSet<i32>>,
+) -> f64 {
+    let mut score = 0.0;
+
+    if let Some(song_genres) = song_genres {
+        score += 2.0 * seed_genres.intersection(song_genres).count() as f64;
+    }
+
+    let artists = [song.artist.as_deref(), song.album_artist.as_deref()];
+    if [seed.artist.as_deref(), seed.album_artist.as_deref()]
+        .into_iter()
+        .flatten()
+        .any(|v| {
+            artists
+                .into_iter()
+                .flatten()
+                .any(|w| w.eq_ignore_ascii_case(v))
+        })
+    {
+        score += 2.0;
+    }
+
+    if let (Some(a), Some(b)) = (seed.year, song.year) {
+        if (a - b).abs() <= ERA_YEARS {
+            score += 1.0;
+        }
+    }
+
+    score
+}
+
+/// Whether two songs are from the same album. Songs without album tags are grouped
+/// by directory, like in `browse::albums`.
+pub fn same_album(a: &library::Model, b: &library::Model) -> bool {
+    match (&a.album, &b.album) {
+        (Some(album), Some(other)) => album == other && album_artist(a) == album_artist(b),
+        (None, None) => a.source_id == b.source_id && a.path == b.path,
+        _ => false,
+    }
+}
+
+/// Whether to crossfade from one track into the next, `crossfade` being the setting the next
+/// track is played with, see `presets::apply`. Tracks of the same album follow each other
+/// gaplessly unless `crossfade_within_albums` is set.
+pub fn crossfades(
+    from: &library::Model,
+    to: &library::Model,
+    crossfade: bool,
+    config: &Config,
+) -> bool {
+    crossfade && (config.crossfade_within_albums || !same_album(from, to))
+}
+
+/// When each track after `position` in `ordered` starts, given how far into the current one
+/// playback is. Songs without a known duration count as instantly over, and while repeating
+/// the current track nothing else is coming up.
+pub fn eta(
+    ordered: &[i64],
+    position: Option<usize>,
+    progress: Duration,
+    repeat: Repeat,
+    durations: &HashMap<i64, u32>,
+) -> Eta {
+    let position = match position.filter(|&v| v < ordered.len()) {
+        Some(position) => position,
+        None => return Eta::default(),
+    };
+
+    let duration = |hash: &i64| durations.get(hash).copied().unwrap_or(0) as u64;
+
+    let mut elapsed = duration(&ordered[position])
+        .saturating_sub(progress.as_millis().try_into().unwrap_or(u64::MAX));
+
+    if repeat == Repeat::Track {
+        return Eta {
+            upcoming: vec![],
+            remaining_ms: elapsed,
+        };
+    }
+
+    let upcoming = ordered[position + 1..]
+        .iter()
+        .map(|hash| {
+            let starts_in_ms = elapsed;
+            elapsed += duration(hash);
+
+            Upcoming {
+                hash: *hash,
+                starts_in_ms,
+            }
+        })
+        .collect();
+
+    Eta {
+        upcoming,
+        remaining_ms: elapsed,
+    }
+}
+
+/// Durations of songs in milliseconds, by hash
+pub async fn durations(hashes: &[i64], db: &DatabaseConnection) -> Result<HashMap<i64, u32>> {
+    Ok(library::Entity::find()
+        .filter(library::Column::Hash.is_in(hashes.iter().copied()))
+        .all(db)
+        .await
+        .into_diagnostic()?
+        .into_iter()
+        .map(|v| (v.hash, v.duration))
+        .collect())
+}
+
+/// Names of every saved session, sorted alphabetically
+pub fn list_sessions() -> Result<Vec<String>> {
+    let dir = sessions_dir()?;
+
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut sessions: Vec<String> = read_dir(dir)
+        .into_diagnostic()?
+        .filter_map(|v| v.ok())
+        .map(|v| v.path())
+        .filter(|v| v.extension().is_some_and(|v| v == "toml"))
+        .filter_map(|v| Some(v.file_stem()?.to_string_lossy().to_string()))
+        .collect();
+
+    sessions.sort();
+
+    Ok(sessions)
+}
+
+pub fn delete_session(name: &str) -> Result<()> {
+    remove_file(session_path(&sessions_dir()?, name)?).into_diagnostic()
+}
+
+fn sessions_dir() -> Result<PathBuf> {
+    config_dir()
+        .map(|v| v.join("sessions"))
+        .ok_or(miette!("Configuration directory not found"))
+}
+
+/// Session names are used as file names, so they can't contain paths
+fn session_path(dir: &Path, name: &str) -> Result<PathBuf> {
+    let mut components = Path::new(name).components();
+
+    miette::ensure!(
+        matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(_)), None)
+        ),
+        "Invalid session name {}",
+        name
+    );
+
+    Ok(dir.join(format!("{name}.toml")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::test_util::{self, memory_db};
+    use sea_orm::{ActiveValue::NotSet, IntoActiveModel};
+
+    fn song(hash: i64, artist: &str, album: &str) -> library::Model {
+        library::Model {
+            path: album.into(),
+            artist: Some(artist.into()),
+            album: Some(album.into()),
+            ..test_util::song(hash)
+        }
+    }
+
+    /// A queue of tracks 0 to 9 in four albums, shuffled by album
+    fn grouped() -> Queue {
+        Queue {
+            shuffle_mode: ShuffleMode::Albums,
+            groups: vec![0, 0, 0, 1, 1, 2, 2, 2, 2, 3],
+            ..Queue::new((0..10).collect())
+        }
+    }
+
+    /// Asserts the tracks of each group are played one after another, in queue order
+    fn assert_grouped(queue: &Queue) {
+        let mut seen = HashSet::new();
+
+        for run in queue
+            .order
+            .chunk_by(|&a, &b| queue.group(a) == queue.group(b))
+        {
+            assert!(seen.insert(queue.group(run[0])), "{:?}", queue.order);
+            assert!(run.windows(2).all(|v| v[0] < v[1]), "{:?}", queue.order);
+        }
+
+        assert_eq!(seen.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn songs_are_grouped_by_album_and_artist() {
+        let db = memory_db().await;
+
+        for song in [
+            song(1, "Artist", "First"),
+            song(2, "Artist", "Second"),
+            song(3, "Other", "Third"),
+        ] {
+            let mut song = song.into_active_model();
+            song.id = NotSet;
+            library::Entity::insert(song).exec(&db).await.unwrap();
+        }
+
+        // 4 isn't in the library
+        let hashes = [1, 3, 2, 4, 1];
+
+        let groups = |mode: ShuffleMode| mode.groups(&hashes, &db);
+        assert_eq!(groups(ShuffleMode::Tracks).await.unwrap(), [0, 1, 2, 3, 4]);
+        assert_eq!(groups(ShuffleMode::Albums).await.unwrap(), [0, 1, 2, 3, 0]);
+        assert_eq!(groups(ShuffleMode::Artists).await.unwrap(), [0, 1, 0, 2, 0]);
+    }
+
+    #[test]
+    fn shuffle_keeps_groups_together() {
+        let queue = grouped();
+
+        for _ in 0..20 {
+            let shuffled = Queue {
+                order: queue.shuffled_order(),
+                ..queue.clone()
+            };
+            assert_grouped(&shuffled);
+        }
+    }
+
+    #[test]
+    fn shuffle_starts_with_the_current_group() {
+        for _ in 0..20 {
+            let mut queue = grouped();
+            queue.position = Some(6);
+            queue.set_shuffle(true);
+
+            assert_grouped(&queue);
+            assert_eq!(queue.current(), Some(6));
+            assert_eq!(queue.order[..4], [5, 6, 7, 8]);
+
+            queue.set_shuffle(false);
+            assert_eq!(queue.order, (0..10).collect::<Vec<_>>());
+            assert_eq!(queue.current(), Some(6));
+        }
+    }
+
+    #[test]
+    fn appended_tracks_get_groups_of_their_own() {
+        let mut queue = grouped();
+        // Added without groups, so each is shuffled on its own
+        queue.push(10);
+        queue.append_grouped(vec![11, 12, 13], vec![0, 0, 1]);
+
+        assert_eq!(queue.groups, [0, 0, 0, 1, 1, 2, 2, 2, 2, 3, 4, 5, 5, 6]);
+    }
+}
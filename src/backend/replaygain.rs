@@ -0,0 +1,347 @@
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    browse::{folder_album, source_root},
+    channels::Downmix,
+    config::Config,
+    model::library,
+    utils::song_path,
+};
+use lofty::{
+    id3::v2::{EncodedTextFrame, Frame, FrameFlags, FrameValue, ID3v2Tag, TextEncoding},
+    read_from_path, ItemKey, Tag, TagExt, TagType,
+};
+use miette::{miette, IntoDiagnostic, Result};
+use paris::{info, success, warn};
+use replaygain::ReplayGain;
+use sea_orm::{
+    sea_query::Expr, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter,
+};
+use serde::{Deserialize, Serialize};
+use symphonia::{
+    core::{
+        audio::SampleBuffer,
+        codecs::{CodecParameters, Decoder},
+        formats::Packet,
+        io::MediaSourceStream,
+        probe::Hint,
+    },
+    default::{get_codecs, get_probe},
+};
+
+/// ReplayGain values of a track or album
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Gain {
+    /// Adjustment in dB
+    pub gain: f32,
+    /// Linear sample peak, where 1.0 is full scale
+    pub peak: f32,
+}
+
+/// Reads existing track ReplayGain values from a tag
+pub fn from_tag(tag: &Tag) -> Option<Gain> {
+    let gain = tag.get_string(&ItemKey::ReplayGainTrackGain)?;
+    let peak = tag.get_string(&ItemKey::ReplayGainTrackPeak)?;
+
+    Some(Gain {
+        gain: gain.trim().trim_end_matches("dB").trim().parse().ok()?,
+        peak: peak.trim().parse().ok()?,
+    })
+}
+
+/// Reads track ReplayGain values from a tag if present, analyzing the file otherwise
+pub fn track_gain(path: &Path, tag: Option<&Tag>) -> Result<Gain> {
+    match tag.and_then(from_tag) {
+        Some(gain) => Ok(gain),
+        None => analyze(path),
+    }
+}
+
+/// Decodes a file and computes its track ReplayGain values
+pub fn analyze(path: &Path) -> Result<Gain> {
+    let file = Box::new(File::open(path).into_diagnostic()?);
+
+    let ext = path.extension().and_then(OsStr::to_str).unwrap_or("");
+
+    let source = MediaSourceStream::new(file, Default::default());
+    let mut format = get_probe()
+        .format(
+            Hint::new().with_extension(ext),
+            source,
+            &Default::default(),
+            &Default::default(),
+        )
+        .into_diagnostic()?
+        .format;
+
+    let track = format
+        .default_track()
+        .ok_or(miette!("No audio track found in {}", path.display()))?;
+    let track_id = track.id;
+
+    let mut analyzer = Analyzer::new(&track.codec_params)
+        .map_err(|e| miette!("Can't analyze {}: {e}", path.display()))?;
+
+    while let Ok(packet) = format.next_packet() {
+        if packet.track_id() == track_id {
+            analyzer.process(&packet)?;
+        }
+    }
+
+    Ok(analyzer.finish())
+}
+
+/// Computes track ReplayGain values packet by packet, as a file is read.
+/// Decoded samples are dropped once they're analyzed, so memory use doesn't depend on
+/// track length.
+pub struct Analyzer {
+    rg: ReplayGain,
+    decoder: Box<dyn Decoder>,
+    /// The analyzer only takes stereo, so other layouts are mixed like they would be for playback
+    downmix: Option<Downmix>,
+    buffer: Option<SampleBuffer<f32>>,
+}
+
+impl Analyzer {
+    pub fn new(params: &CodecParameters) -> Result<Self> {
+        let sample_rate = params.sample_rate.ok_or(miette!("Unknown sample rate"))?;
+        let layout = params.channels.ok_or(miette!("Unknown channel layout"))?;
+
+        let rg = ReplayGain::new(sample_rate as usize)
+            .ok_or(miette!("Unsupported sample rate {}", sample_rate))?;
+
+        let decoder = get_codecs()
+            .make(params, &Default::default())
+            .into_diagnostic()?;
+
+        Ok(Analyzer {
+            rg,
+            decoder,
+            downmix: Downmix::new(layout),
+            buffer: None,
+        })
+    }
+
+    /// Decodes and analyzes a packet of the track
+    pub fn process(&mut self, packet: &Packet) -> Result<()> {
+        let decoded = match self.decoder.decode(packet) {
+            Ok(v) => v,
+            // Skip over corrupted packets
+            Err(symphonia::core::errors::Error::DecodeError(_)) => return Ok(()),
+            Err(e) => return Err(e).into_diagnostic(),
+        };
+
+        let buffer = self
+            .buffer
+            .get_or_insert_with(|| SampleBuffer::new(decoded.capacity() as u64, *decoded.spec()));
+        buffer.copy_interleaved_ref(decoded);
+
+        match &self.downmix {
+            Some(downmix) => self.rg.process_samples(&downmix.process(buffer.samples())),
+            None => self.rg.process_samples(buffer.samples()),
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(self) -> Gain {
+        let (gain, peak) = self.rg.finish();
+
+        Gain { gain, peak }
+    }
+}
+
+/// Combines the values of an album's tracks, weighting their loudness by duration
+pub fn album_gain(tracks: impl IntoIterator<Item = (Gain, u32)>) -> Option<Gain> {
+    let mut power = 0.0;
+    let mut total_duration = 0.0;
+    let mut peak: f32 = 0.0;
+
+    for (track, duration) in tracks {
+        let duration = duration as f64;
+
+        power += duration * 10f64.powf(-track.gain as f64 / 10.0);
+        total_duration += duration;
+        peak = peak.max(track.peak);
+    }
+
+    (total_duration > 0.0).then(|| Gain {
+        gain: (-10.0 * (power / total_duration).log10()) as f32,
+        peak,
+    })
+}
+
+/// Recomputes album ReplayGain values for every album in a source.
+/// Songs without album tags are grouped by directory, like in `browse::albums`.
+pub async fn update_album_gain(source_id: i32, db: &impl ConnectionTrait) -> Result<()> {
+    let songs = library::Entity::find()
+        .filter(library::Column::SourceId.eq(source_id))
+        .filter(library::Column::RgTrackGain.is_not_null())
+        .all(db)
+        .await
+        .into_diagnostic()?;
+
+    let root = source_root(source_id.try_into().into_diagnostic()?, db).await?;
+
+    let mut albums: HashMap<_, Vec<library::Model>> = HashMap::new();
+
+    for song in songs {
+        let folder = folder_album(&song, &root).map(|_| song.path.clone());
+
+        if song.album.is_none() && folder.is_none() {
+            continue;
+        }
+
+        albums
+            .entry((song.album.clone(), song.album_artist.clone(), folder))
+            .or_default()
+            .push(song);
+    }
+
+    for songs in albums.into_values() {
+        let Some(album) = album_gain(songs.iter().filter_map(|v| {
+            Some((
+                Gain {
+                    gain: v.rg_track_gain?,
+                    peak: v.rg_track_peak?,
+                },
+                v.duration,
+            ))
+        })) else {
+            continue;
+        };
+
+        library::Entity::update_many()
+            .col_expr(library::Column::RgAlbumGain, Expr::value(album.gain))
+            .col_expr(library::Column::RgAlbumPeak, Expr::value(album.peak))
+            .filter(library::Column::Id.is_in(songs.iter().map(|v| v.id)))
+            .exec(db)
+            .await
+            .into_diagnostic()?;
+    }
+
+    Ok(())
+}
+
+/// Writes the ReplayGain values computed while indexing to the tags of a local source's files,
+/// so other players can use them too. Files that already have ReplayGain tags are skipped.
+/// With `dry_run` set, nothing is written and the files are only listed.
+/// Returns the files that were, or would have been, changed.
+pub async fn write_back(
+    source_id: i32,
+    dry_run: bool,
+    db: &DatabaseConnection,
+) -> Result<Vec<PathBuf>> {
+    miette::ensure!(
+        Config::read_config()?
+            .local_source_ids()
+            .contains(&source_id),
+        "Source {} is not a local source",
+        source_id
+    );
+
+    let songs = library::Entity::find()
+        .filter(library::Column::SourceId.eq(source_id))
+        .filter(library::Column::RgTrackGain.is_not_null())
+        .filter(library::Column::RgTrackPeak.is_not_null())
+        .all(db)
+        .await
+        .into_diagnostic()?;
+
+    let mut changed = vec![];
+
+    for song in songs {
+        let path = song_path(&song);
+
+        match write_song_gain(&path, &song, dry_run) {
+            Ok(true) => changed.push(path),
+            Ok(false) => {}
+            Err(e) => warn!("Couldn't write ReplayGain tags to {}: {e}", path.display()),
+        }
+    }
+
+    if dry_run {
+        for path in &changed {
+            info!("Would write ReplayGain tags to {}", path.display());
+        }
+    } else if !changed.is_empty() {
+        success!("Wrote ReplayGain tags to {} files", changed.len());
+    }
+
+    Ok(changed)
+}
+
+/// Returns whether the file needs ReplayGain tags
+fn write_song_gain(path: &Path, song: &library::Model, dry_run: bool) -> Result<bool> {
+    let mut file = read_from_path(path, false).into_diagnostic()?;
+
+    if file.tags().iter().any(|v| from_tag(v).is_some()) {
+        return Ok(false);
+    }
+
+    if dry_run {
+        return Ok(true);
+    }
+
+    let mut items = vec![];
+    if let (Some(gain), Some(peak)) = (song.rg_track_gain, song.rg_track_peak) {
+        items.push((ItemKey::ReplayGainTrackGain, format!("{gain:.2} dB")));
+        items.push((ItemKey::ReplayGainTrackPeak, format!("{peak:.6}")));
+    }
+    if let (Some(gain), Some(peak)) = (song.rg_album_gain, song.rg_album_peak) {
+        items.push((ItemKey::ReplayGainAlbumGain, format!("{gain:.2} dB")));
+        items.push((ItemKey::ReplayGainAlbumPeak, format!("{peak:.6}")));
+    }
+
+    let tag_type = file.primary_tag_type();
+    let tag = file
+        .primary_tag()
+        .cloned()
+        .unwrap_or_else(|| Tag::new(tag_type));
+
+    // lofty doesn't convert ReplayGain items to ID3v2 frames, they're stored as TXXX frames instead
+    if tag_type == TagType::ID3v2 {
+        let mut id3 = ID3v2Tag::from(tag);
+
+        for (key, value) in items {
+            let description = key
+                .map_key(TagType::APE, false)
+                .ok_or(miette!("Unknown ReplayGain key {key:?}"))?;
+
+            let frame = Frame::new(
+                "TXXX",
+                FrameValue::UserText(EncodedTextFrame {
+                    encoding: TextEncoding::UTF8,
+                    description: description.to_string(),
+                    content: value,
+                }),
+                FrameFlags::default(),
+            )
+            .into_diagnostic()?;
+
+            id3.insert(frame);
+        }
+
+        id3.save_to_path(path).into_diagnostic()?;
+    } else {
+        let mut tag = tag;
+
+        for (key, value) in items {
+            miette::ensure!(
+                tag.insert_text(key, value),
+                "{:?} tags don't support ReplayGain values",
+                tag_type
+            );
+        }
+
+        file.insert_tag(tag);
+        file.save_to_path(path).into_diagnostic()?;
+    }
+
+    Ok(true)
+}
@@ -0,0 +1,101 @@
+use miette::{IntoDiagnostic, Result};
+use rubato::{FftFixedInOut, Resampler as _};
+
+/// Frames handed to rubato at a time
+const CHUNK: usize = 1024;
+
+/// Converts interleaved audio from one sample rate to another
+pub struct Resampler {
+    inner: FftFixedInOut<f32>,
+    from: u32,
+    to: u32,
+    channels: usize,
+    /// Input frames waiting for a full chunk, one buffer per channel
+    pending: Vec<Vec<f32>>,
+    /// Output frames still to be dropped, since rubato's output starts with silence
+    delay: usize,
+    frames_in: u64,
+    frames_out: u64,
+}
+
+impl Resampler {
+    pub fn new(from: u32, to: u32, channels: usize) -> Result<Self> {
+        miette::ensure!(channels > 0, "Can't resample audio without channels");
+
+        let inner =
+            FftFixedInOut::new(from as usize, to as usize, CHUNK, channels).into_diagnostic()?;
+
+        Ok(Resampler {
+            delay: inner.output_delay(),
+            pending: vec![Vec::with_capacity(inner.input_frames_max()); channels],
+            inner,
+            from,
+            to,
+            channels,
+            frames_in: 0,
+            frames_out: 0,
+        })
+    }
+
+    /// Resamples a block of interleaved samples. Output lags behind the input,
+    /// the rest is returned by `flush`.
+    pub fn process(&mut self, samples: &[f32]) -> Result<Vec<f32>> {
+        let mut output = vec![];
+
+        for frame in samples.chunks_exact(self.channels) {
+            for (channel, sample) in self.pending.iter_mut().zip(frame) {
+                channel.push(*sample);
+            }
+            self.frames_in += 1;
+
+            if self.pending[0].len() == self.inner.input_frames_next() {
+                let resampled = self.inner.process(&self.pending, None).into_diagnostic()?;
+                self.pending.iter_mut().for_each(Vec::clear);
+
+                self.interleave(&resampled, &mut output);
+            }
+        }
+
+        Ok(output)
+    }
+
+    /// Returns the remaining audio once the input ended
+    pub fn flush(&mut self) -> Result<Vec<f32>> {
+        let expected = self.frames_in * self.to as u64 / self.from as u64;
+        let mut output = vec![];
+
+        let resampled = self
+            .inner
+            .process_partial(Some(&self.pending), None)
+            .into_diagnostic()?;
+        self.pending.iter_mut().for_each(Vec::clear);
+        self.interleave(&resampled, &mut output);
+
+        // The end of the audio is still inside the resampler
+        while self.frames_out < expected {
+            let resampled = self
+                .inner
+                .process_partial::<Vec<f32>>(None, None)
+                .into_diagnostic()?;
+            self.interleave(&resampled, &mut output);
+        }
+
+        // The last chunk was padded with silence
+        let excess = (self.frames_out - expected) as usize;
+        output.truncate(output.len().saturating_sub(excess * self.channels));
+        self.frames_out = expected;
+
+        Ok(output)
+    }
+
+    fn interleave(&mut self, channels: &[Vec<f32>], output: &mut Vec<f32>) {
+        let frames = channels.first().map_or(0, Vec::len);
+        let skipped = self.delay.min(frames);
+        self.delay -= skipped;
+        self.frames_out += (frames - skipped) as u64;
+
+        for frame in skipped..frames {
+            output.extend(channels.iter().map(|v| v[frame]));
+        }
+    }
+}
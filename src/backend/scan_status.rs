@@ -0,0 +1,175 @@
+use std::{
+    collections::HashSet,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+    model::{library, scan_issues, sea_orm_active_enums::ScanStatus},
+    vfs::Entry,
+};
+use miette::{IntoDiagnostic, Result};
+use sea_orm::{
+    sea_query::OnConflict, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait,
+    QueryFilter, QueryOrder, Set,
+};
+
+/// Files of a source with a problem recorded, by directory and file name
+pub async fn files_with_issues(
+    source_id: u8,
+    db: &DatabaseConnection,
+) -> Result<HashSet<(String, String)>> {
+    Ok(scan_issues::Entity::find()
+        .filter(scan_issues::Column::SourceId.eq(source_id))
+        .all(db)
+        .await
+        .into_diagnostic()?
+        .into_iter()
+        .map(|v| (v.path, v.filename))
+        .collect())
+}
+
+/// Records how indexing a file went. Files that were read fine have their problem forgotten.
+pub async fn record(
+    source_id: u8,
+    dir: &str,
+    filename: &str,
+    status: ScanStatus,
+    message: Option<String>,
+    db: &impl ConnectionTrait,
+) -> Result<()> {
+    if status == ScanStatus::Ok {
+        return forget(source_id, dir, filename, db).await;
+    }
+
+    scan_issues::Entity::insert(scan_issues::ActiveModel {
+        source_id: Set(source_id.into()),
+        path: Set(dir.to_string()),
+        filename: Set(filename.to_string()),
+        status: Set(status),
+        message: Set(message),
+        checked_at: Set(now()?),
+        ..Default::default()
+    })
+    .on_conflict(
+        OnConflict::columns([
+            scan_issues::Column::SourceId,
+            scan_issues::Column::Path,
+            scan_issues::Column::Filename,
+        ])
+        .update_columns([
+            scan_issues::Column::Status,
+            scan_issues::Column::Message,
+            scan_issues::Column::CheckedAt,
+        ])
+        .to_owned(),
+    )
+    .exec(db)
+    .await
+    .into_diagnostic()?;
+
+    Ok(())
+}
+
+async fn forget(source_id: u8, dir: &str, filename: &str, db: &impl ConnectionTrait) -> Result<()> {
+    scan_issues::Entity::delete_many()
+        .filter(scan_issues::Column::SourceId.eq(source_id))
+        .filter(scan_issues::Column::Path.eq(dir))
+        .filter(scan_issues::Column::Filename.eq(filename))
+        .exec(db)
+        .await
+        .into_diagnostic()?;
+
+    Ok(())
+}
+
+/// Compares the songs of a source with the files it lists. Songs without a file are
+/// recorded as missing, and problems of files that are gone are forgotten.
+pub async fn update_missing(
+    source_id: u8,
+    files: &[Entry],
+    db: &impl ConnectionTrait,
+) -> Result<()> {
+    let listed: HashSet<(&str, &str)> = files
+        .iter()
+        .map(|v| (v.dir.as_str(), v.filename.as_str()))
+        .collect();
+
+    for issue in scan_issues::Entity::find()
+        .filter(scan_issues::Column::SourceId.eq(source_id))
+        .all(db)
+        .await
+        .into_diagnostic()?
+    {
+        if !listed.contains(&(issue.path.as_str(), issue.filename.as_str())) {
+            forget(source_id, &issue.path, &issue.filename, db).await?;
+        }
+    }
+
+    let songs = library::Entity::find()
+        .filter(library::Column::SourceId.eq(source_id))
+        .all(db)
+        .await
+        .into_diagnostic()?;
+
+    for song in songs {
+        if listed.contains(&(song.path.as_str(), song.filename.as_str())) {
+            continue;
+        }
+
+        record(
+            source_id,
+            &song.path,
+            &song.filename,
+            ScanStatus::Missing,
+            None,
+            db,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// How indexing a song's file went the last time
+pub async fn status_of(song: &library::Model, db: &DatabaseConnection) -> Result<ScanStatus> {
+    Ok(scan_issues::Entity::find()
+        .filter(scan_issues::Column::SourceId.eq(song.source_id))
+        .filter(scan_issues::Column::Path.eq(song.path.as_str()))
+        .filter(scan_issues::Column::Filename.eq(song.filename.as_str()))
+        .one(db)
+        .await
+        .into_diagnostic()?
+        .map_or(ScanStatus::Ok, |v| v.status))
+}
+
+/// Files with a problem, for users to fix. Can be narrowed down to a source
+/// or a kind of problem, and are ordered by source and path.
+pub async fn issues(
+    source_id: Option<u8>,
+    status: Option<ScanStatus>,
+    db: &DatabaseConnection,
+) -> Result<Vec<scan_issues::Model>> {
+    let mut query = scan_issues::Entity::find();
+
+    if let Some(source_id) = source_id {
+        query = query.filter(scan_issues::Column::SourceId.eq(source_id));
+    }
+    if let Some(status) = status {
+        query = query.filter(scan_issues::Column::Status.eq(status));
+    }
+
+    query
+        .order_by_asc(scan_issues::Column::SourceId)
+        .order_by_asc(scan_issues::Column::Path)
+        .order_by_asc(scan_issues::Column::Filename)
+        .all(db)
+        .await
+        .into_diagnostic()
+}
+
+fn now() -> Result<i64> {
+    Ok(SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .into_diagnostic()?
+        .as_secs() as i64)
+}
@@ -0,0 +1,234 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use super::{
+    backup::{snapshot, snapshots_dir},
+    cancellation::CancellationToken,
+    config::{Config, Schedule, SourceKind},
+    downloads::downloads_dir,
+    errors::{report, Category, Severity},
+    fetching::{index_source, IndexMode},
+    history::prune,
+    podcasts::refresh_all,
+    utils::cache_dir,
+};
+use miette::{miette, IntoDiagnostic, Result};
+use paris::info;
+use sea_orm::DatabaseConnection;
+use tokio::{
+    task::JoinHandle,
+    time::{interval_at, Instant, MissedTickBehavior},
+};
+use walkdir::WalkDir;
+
+/// Work that can be repeated in the background
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Job {
+    Rescan,
+    RefreshRemote,
+    CleanCache,
+    Snapshot,
+    RefreshPodcasts,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct JobStatus {
+    pub running: bool,
+    pub last_run: Option<SystemTime>,
+    /// Error of the last run, if it failed
+    pub last_error: Option<String>,
+    pub next_run: Option<SystemTime>,
+}
+
+/// Runs jobs at the intervals set in the configuration until stopped
+pub struct Scheduler {
+    status: Arc<Mutex<HashMap<Job, JobStatus>>>,
+    cancel: CancellationToken,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl Scheduler {
+    /// Starts a task for every job that has an interval. Jobs first run one interval after starting,
+    /// since sources are indexed on startup anyway.
+    pub fn start(schedule: &Schedule, db: &DatabaseConnection) -> Self {
+        let status = Arc::new(Mutex::new(HashMap::new()));
+        let cancel = CancellationToken::new();
+
+        let handles = [
+            (Job::Rescan, schedule.rescan),
+            (Job::RefreshRemote, schedule.refresh_remote),
+            (Job::CleanCache, schedule.clean_cache),
+            (Job::Snapshot, schedule.snapshot),
+            (Job::RefreshPodcasts, schedule.refresh_podcasts),
+        ]
+        .into_iter()
+        .filter_map(|(job, hours)| Some((job, Duration::from_secs(hours? * 60 * 60))))
+        .filter(|(_, period)| !period.is_zero())
+        .map(|(job, period)| {
+            tokio::spawn(run_periodically(
+                job,
+                period,
+                db.clone(),
+                status.clone(),
+                cancel.clone(),
+            ))
+        })
+        .collect();
+
+        Scheduler {
+            status,
+            cancel,
+            handles,
+        }
+    }
+
+    /// State of every scheduled job
+    pub fn status(&self) -> HashMap<Job, JobStatus> {
+        self.status.lock().map(|v| v.clone()).unwrap_or_default()
+    }
+
+    /// Cancels all jobs, including ones that are currently running, and waits for them to stop.
+    /// Running rescans stop after the song they're on.
+    pub async fn stop(self) {
+        self.cancel.cancel();
+
+        for handle in self.handles {
+            let _ = handle.await;
+        }
+    }
+}
+
+async fn run_periodically(
+    job: Job,
+    period: Duration,
+    db: DatabaseConnection,
+    status: Arc<Mutex<HashMap<Job, JobStatus>>>,
+    cancel: CancellationToken,
+) {
+    let update = |change: &dyn Fn(&mut JobStatus)| {
+        if let Ok(mut status) = status.lock() {
+            change(status.entry(job).or_default());
+        }
+    };
+
+    let mut interval = interval_at(Instant::now() + period, period);
+    // Don't catch up on runs missed while a long job was running
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        update(&|v| v.next_run = Some(SystemTime::now() + period));
+
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = cancel.cancelled() => break,
+        }
+
+        update(&|v| v.running = true);
+
+        // Jobs wind down on their own once cancelled, so nothing is left half-done
+        let result = run(job, &cancel, &db).await;
+
+        if let Err(e) = &result {
+            let category = match job {
+                Job::Rescan | Job::RefreshRemote => Category::Indexing,
+                Job::RefreshPodcasts => Category::Network,
+                Job::CleanCache | Job::Snapshot => Category::Other,
+            };
+
+            report(
+                Severity::Error,
+                category,
+                Some(format!("{job:?}")),
+                format!("Scheduled job {job:?} failed: {e}"),
+            );
+        }
+
+        update(&|v| {
+            v.running = false;
+            v.last_run = Some(SystemTime::now());
+            v.last_error = result.as_ref().err().map(|e| e.to_string());
+        });
+
+        if cancel.is_cancelled() {
+            break;
+        }
+    }
+
+    update(&|v| {
+        v.running = false;
+        v.next_run = None;
+    });
+}
+
+async fn run(job: Job, cancel: &CancellationToken, db: &DatabaseConnection) -> Result<()> {
+    let config = Config::read_config()?;
+
+    match job {
+        Job::Rescan | Job::RefreshRemote => {
+            for source in config.sources {
+                if cancel.is_cancelled() {
+                    break;
+                }
+
+                let local = matches!(source.source, SourceKind::Local { .. });
+
+                if source.settings.watch && local == (job == Job::Rescan) {
+                    index_source(source, IndexMode::New, cancel, db).await?;
+                }
+            }
+        }
+        Job::CleanCache => {
+            clean_cache(config.cache_expire_days)?;
+
+            if let Some(days) = config.history_retention_days {
+                prune(days, db).await?;
+            }
+        }
+        Job::Snapshot => {
+            snapshot("scheduled", db).await?;
+        }
+        Job::RefreshPodcasts => refresh_all(db).await?,
+    }
+
+    Ok(())
+}
+
+/// Removes cached files that haven't been modified in a number of days.
+/// Stored credentials, songs downloaded for offline playback and database snapshots are kept.
+pub fn clean_cache(expire_days: usize) -> Result<()> {
+    let dir = cache_dir().ok_or(miette!("Cache directory does not exist"))?;
+    let downloads = downloads_dir()?;
+    let snapshots = snapshots_dir()?;
+    let max_age = Duration::from_secs(expire_days as u64 * 24 * 60 * 60);
+
+    let mut removed = 0;
+
+    for entry in WalkDir::new(dir)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|v| v.file_type().is_file())
+        .filter(|v| v.path().extension().is_none_or(|v| v != "auth"))
+        .filter(|v| !v.path().starts_with(&downloads))
+        .filter(|v| !v.path().starts_with(&snapshots))
+    {
+        let modified = entry
+            .metadata()
+            .into_diagnostic()?
+            .modified()
+            .into_diagnostic()?;
+
+        if modified.elapsed().is_ok_and(|v| v > max_age) {
+            std::fs::remove_file(entry.path()).into_diagnostic()?;
+            removed += 1;
+        }
+    }
+
+    if removed > 0 {
+        info!("Removed {removed} expired files from the cache");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,80 @@
+use std::{sync::OnceLock, time::Duration};
+
+use super::{
+    events::{publish, Event},
+    queue::Queue,
+    scheduler::Scheduler,
+    ui_state::UiState,
+};
+use miette::{IntoDiagnostic, Result};
+use paris::{info, success};
+use sea_orm::{ConnectionTrait, DatabaseConnection, Statement};
+use tokio::sync::watch;
+
+/// Time the player is given to fade out before the app exits
+const FADE_OUT: Duration = Duration::from_millis(300);
+
+fn channel() -> &'static watch::Sender<bool> {
+    static CHANNEL: OnceLock<watch::Sender<bool>> = OnceLock::new();
+
+    CHANNEL.get_or_init(|| watch::channel(false).0)
+}
+
+/// Asks the app to quit, e.g. when the GUI is closed
+pub fn request() {
+    let _ = channel().send(true);
+}
+
+/// Resolves once the app is asked to quit, through `request` or Ctrl+C
+pub async fn requested() {
+    let mut requested = channel().subscribe();
+
+    let from_app = async {
+        while !*requested.borrow() {
+            if requested.changed().await.is_err() {
+                return;
+            }
+        }
+    };
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = from_app => {}
+    }
+}
+
+/// Stops everything in order, so nothing is interrupted halfway through writing:
+/// playback fades out, background jobs are cancelled, the queue is saved to the active session
+/// and pending database writes are flushed to the database file.
+pub async fn shutdown(
+    db: &DatabaseConnection,
+    scheduler: Option<Scheduler>,
+    queue: Option<&Queue>,
+) -> Result<()> {
+    info!("Shutting down");
+
+    // The player fades out when it receives this
+    publish(Event::ShuttingDown);
+    if queue.and_then(Queue::current).is_some() {
+        tokio::time::sleep(FADE_OUT).await;
+    }
+
+    if let Some(scheduler) = scheduler {
+        scheduler.stop().await;
+    }
+
+    if let Some(queue) = queue {
+        queue.save_session(&UiState::read_state()?.session)?;
+    }
+
+    // Move everything from the write-ahead log into the database file
+    db.execute(Statement::from_string(
+        db.get_database_backend(),
+        "PRAGMA wal_checkpoint(TRUNCATE)".to_string(),
+    ))
+    .await
+    .into_diagnostic()?;
+
+    success!("Shut down");
+    Ok(())
+}
@@ -0,0 +1,133 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use super::events::{publish, subscribe, Event};
+use tokio::{
+    sync::broadcast::error::RecvError,
+    task::JoinHandle,
+    time::{sleep_until, Instant},
+};
+
+/// How long playback fades out before the timer runs out
+const FADE_OUT: Duration = Duration::from_secs(10);
+
+/// What's left until playback pauses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SleepRemaining {
+    Time(Duration),
+    /// Tracks that still end before playback pauses, including the current one
+    Tracks(u32),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Until(Instant),
+    Tracks(u32),
+}
+
+/// Pauses playback after some time or a number of tracks, e.g. when falling asleep to music.
+/// Once it runs out, `Event::SleepTimerExpired` tells the player to fade out and pause.
+#[derive(Default)]
+pub struct SleepTimer {
+    state: Arc<Mutex<Option<State>>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl SleepTimer {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Pauses playback once `duration` has passed, fading out towards the end.
+    /// Replaces the timer that was set before.
+    pub fn sleep_after(&mut self, duration: Duration) {
+        self.cancel();
+
+        let deadline = Instant::now() + duration;
+        let fade_out = FADE_OUT.min(duration);
+        self.set(Some(State::Until(deadline)));
+
+        let state = self.state.clone();
+
+        self.handle = Some(tokio::spawn(async move {
+            sleep_until(deadline - fade_out).await;
+            publish(Event::SleepTimerExpired { fade_out });
+
+            sleep_until(deadline).await;
+            set(&state, None);
+        }));
+    }
+
+    /// Pauses playback once `tracks` more tracks ended, counting the current one.
+    /// Replaces the timer that was set before.
+    pub fn sleep_after_track(&mut self, tracks: u32) {
+        self.cancel();
+
+        if tracks == 0 {
+            publish(Event::SleepTimerExpired { fade_out: FADE_OUT });
+            return;
+        }
+
+        self.set(Some(State::Tracks(tracks)));
+
+        let state = self.state.clone();
+        let mut events = subscribe();
+
+        self.handle = Some(tokio::spawn(async move {
+            let mut remaining = tracks;
+
+            while remaining > 0 {
+                match events.recv().await {
+                    Ok(Event::TrackEnded { .. }) => {
+                        remaining -= 1;
+                        set(&state, Some(State::Tracks(remaining)));
+                    }
+                    Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => return,
+                }
+            }
+
+            set(&state, None);
+            // The track already ended, so there's nothing to fade
+            publish(Event::SleepTimerExpired {
+                fade_out: Duration::ZERO,
+            });
+        }));
+    }
+
+    pub fn cancel(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.abort();
+        }
+
+        self.set(None);
+    }
+
+    /// What's left until playback pauses, if the timer is set. Shown as a countdown.
+    pub fn remaining(&self) -> Option<SleepRemaining> {
+        let state = *self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        state.map(|v| match v {
+            State::Until(deadline) => {
+                SleepRemaining::Time(deadline.saturating_duration_since(Instant::now()))
+            }
+            State::Tracks(tracks) => SleepRemaining::Tracks(tracks),
+        })
+    }
+
+    fn set(&self, value: Option<State>) {
+        set(&self.state, value);
+    }
+}
+
+impl Drop for SleepTimer {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+fn set(state: &Mutex<Option<State>>, value: Option<State>) {
+    *state.lock().unwrap_or_else(|e| e.into_inner()) = value;
+}
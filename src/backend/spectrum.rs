@@ -0,0 +1,179 @@
+use std::{ops::Range, sync::Arc};
+
+use super::events::{publish, Event};
+use realfft::{num_complex::Complex, RealFftPlanner, RealToComplex};
+use serde::{Deserialize, Serialize};
+
+/// Number of frequency bands in a frame
+pub const BANDS: usize = 32;
+
+/// Samples each transform looks at, about 43 ms at 48 kHz
+const FFT_SIZE: usize = 2048;
+/// How often frames are published
+const FRAMES_PER_SECOND: usize = 30;
+/// Lowest and highest frequencies shown, in Hz
+const MIN_FREQUENCY: f32 = 30.0;
+const MAX_FREQUENCY: f32 = 16_000.0;
+/// Level shown as silence, in dBFS
+const FLOOR_DB: f32 = -70.0;
+
+/// Levels of the audio that's playing, for drawing a spectrum or a VU meter
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SpectrumFrame {
+    /// Level of each band from low to high frequencies, spaced logarithmically.
+    /// From 0 at `FLOOR_DB` or below to 1 at full scale.
+    pub bands: Vec<f32>,
+    /// Highest sample level of each channel since the last frame, from 0 to 1
+    pub peaks: Vec<f32>,
+    /// Average (RMS) level of each channel since the last frame, from 0 to 1
+    pub rms: Vec<f32>,
+}
+
+/// Publishes `Event::Spectrum` frames for the audio passing through it, at `FRAMES_PER_SECOND`.
+/// Frames are published as audio leaves the playback chain, which is ahead of what's heard
+/// by however much the output device buffers.
+pub struct SpectrumFeed {
+    fft: Arc<dyn RealToComplex<f32>>,
+    channels: usize,
+    /// Hann window, so the edges of the ring don't show up as noise across every band
+    window: Vec<f32>,
+    /// The last `FFT_SIZE` frames mixed to mono, oldest at `next`
+    ring: Vec<f32>,
+    next: usize,
+    input: Vec<f32>,
+    output: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+    /// Bins of the transform that make up each band
+    bands: Vec<Range<usize>>,
+    /// Frames between two published frames
+    interval: usize,
+    /// Frames since the last published frame
+    elapsed: usize,
+    peaks: Vec<f32>,
+    squares: Vec<f32>,
+}
+
+impl SpectrumFeed {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        let fft = RealFftPlanner::<f32>::new().plan_fft_forward(FFT_SIZE);
+        let channels = channels.max(1);
+
+        let window = (0..FFT_SIZE)
+            .map(|i| {
+                let phase = std::f32::consts::TAU * i as f32 / (FFT_SIZE - 1) as f32;
+                0.5 - 0.5 * phase.cos()
+            })
+            .collect();
+
+        SpectrumFeed {
+            input: fft.make_input_vec(),
+            output: fft.make_output_vec(),
+            scratch: fft.make_scratch_vec(),
+            fft,
+            channels,
+            window,
+            ring: vec![0.0; FFT_SIZE],
+            next: 0,
+            bands: band_bins(sample_rate),
+            interval: (sample_rate as usize / FRAMES_PER_SECOND).max(1),
+            elapsed: 0,
+            peaks: vec![0.0; channels],
+            squares: vec![0.0; channels],
+        }
+    }
+
+    /// Takes in a block of interleaved samples, publishing frames as they're due
+    pub fn process(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for (channel, sample) in frame.iter().enumerate() {
+                self.peaks[channel] = self.peaks[channel].max(sample.abs());
+                self.squares[channel] += sample * sample;
+            }
+
+            self.ring[self.next] = frame.iter().sum::<f32>() / self.channels as f32;
+            self.next = (self.next + 1) % FFT_SIZE;
+            self.elapsed += 1;
+
+            if self.elapsed >= self.interval {
+                let frame = self.frame();
+                publish(Event::Spectrum(frame));
+            }
+        }
+    }
+
+    fn frame(&mut self) -> SpectrumFrame {
+        for (i, input) in self.input.iter_mut().enumerate() {
+            *input = self.ring[(self.next + i) % FFT_SIZE] * self.window[i];
+        }
+
+        // Only fails if the buffers have the wrong length, which they're made with
+        let bands = match self.fft.process_with_scratch(
+            &mut self.input,
+            &mut self.output,
+            &mut self.scratch,
+        ) {
+            Ok(()) => {
+                // Scales magnitudes so a full scale sine reads as 0 dB
+                let scale = 4.0 / FFT_SIZE as f32;
+
+                self.bands
+                    .iter()
+                    .map(|bins| {
+                        let magnitude = self.output[bins.clone()]
+                            .iter()
+                            .map(|v| v.norm() * scale)
+                            .fold(0.0, f32::max);
+
+                        level(magnitude)
+                    })
+                    .collect()
+            }
+            Err(_) => vec![0.0; self.bands.len()],
+        };
+
+        let frames = self.elapsed as f32;
+        let frame = SpectrumFrame {
+            bands,
+            peaks: self.peaks.iter().map(|v| v.min(1.0)).collect(),
+            rms: self
+                .squares
+                .iter()
+                .map(|v| (v / frames).sqrt().min(1.0))
+                .collect(),
+        };
+
+        self.elapsed = 0;
+        self.peaks.iter_mut().for_each(|v| *v = 0.0);
+        self.squares.iter_mut().for_each(|v| *v = 0.0);
+
+        frame
+    }
+}
+
+/// Ranges of bins for bands spaced evenly on a logarithmic scale.
+/// Low bands narrower than a bin still get one, so some share it.
+fn band_bins(sample_rate: u32) -> Vec<Range<usize>> {
+    let bins = FFT_SIZE / 2 + 1;
+    let max = MAX_FREQUENCY.min(sample_rate as f32 / 2.0);
+    let bin = |frequency: f32| {
+        ((frequency * FFT_SIZE as f32 / sample_rate as f32).round() as usize).min(bins - 1)
+    };
+
+    let edge = |band: usize| MIN_FREQUENCY * (max / MIN_FREQUENCY).powf(band as f32 / BANDS as f32);
+
+    (0..BANDS)
+        .map(|band| {
+            let start = bin(edge(band));
+            let end = bin(edge(band + 1)).max(start + 1);
+
+            start..end
+        })
+        .collect()
+}
+
+/// A magnitude in dB above `FLOOR_DB`, from 0 to 1
+fn level(magnitude: f32) -> f32 {
+    let db = 20.0 * magnitude.max(f32::MIN_POSITIVE).log10();
+
+    ((db - FLOOR_DB) / -FLOOR_DB).clamp(0.0, 1.0)
+}
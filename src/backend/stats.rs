@@ -0,0 +1,270 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{create_dir_all, File},
+    io::Write,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+    compilations::album_artist,
+    config::{Config, SourceKind},
+    model::{library, song_artists},
+    utils::{basic_auth, cache_dir, http_client, song_path},
+};
+use miette::{miette, IntoDiagnostic, Result};
+use reqwest::header::ACCEPT;
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use serde::{Deserialize, Serialize};
+
+/// Media type of the statistics servers send to other Eleanor instances, see `encode_stats`
+pub const STATS_MEDIA_TYPE: &str = "application/msgpack";
+
+/// Summary of a library. Servers report this at `/stats`,
+/// so clients can confirm a remote source is healthy and up to date.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct LibraryStats {
+    pub tracks: u64,
+    pub albums: u64,
+    /// Size of every audio file in bytes
+    pub total_size: u64,
+    /// When indexing last finished, in seconds since the unix epoch
+    pub last_indexed: Option<i64>,
+}
+
+/// Statistics of the local sources, which is what a server shares with its clients
+pub async fn local_stats(db: &DatabaseConnection) -> Result<LibraryStats> {
+    let local = Config::read_config()?.local_source_ids();
+
+    let songs = library::Entity::find()
+        .filter(library::Column::SourceId.is_in(local.clone()))
+        .all(db)
+        .await
+        .into_diagnostic()?;
+
+    let times = index_times();
+
+    Ok(LibraryStats {
+        tracks: songs.len() as u64,
+        albums: count_albums(&songs),
+        total_size: songs
+            .iter()
+            .filter_map(|v| std::fs::metadata(song_path(v)).ok())
+            .map(|v| v.len())
+            .sum(),
+        last_indexed: local
+            .iter()
+            .filter_map(|v| times.get(&(*v as u8)))
+            .max()
+            .copied(),
+    })
+}
+
+/// What a source contributes to the library, for the sources settings page
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceSummary {
+    pub source_id: u8,
+    pub tracks: u64,
+    pub albums: u64,
+    pub artists: u64,
+    /// Total length of the tracks, in milliseconds
+    pub duration_ms: u64,
+    /// Size of the audio files in bytes. `None` for sources that aren't on this machine.
+    pub size: Option<u64>,
+    /// When indexing last finished, in seconds since the unix epoch
+    pub last_indexed: Option<i64>,
+}
+
+/// A summary of every configured source, in the order of the config.
+/// Sources without songs are included with zero counts.
+pub async fn source_summary(db: &DatabaseConnection) -> Result<Vec<SourceSummary>> {
+    let config = Config::read_config()?;
+    let local = config.local_source_ids();
+    let times = index_times();
+
+    let mut songs: HashMap<i32, Vec<library::Model>> = HashMap::new();
+    for song in library::Entity::find().all(db).await.into_diagnostic()? {
+        songs.entry(song.source_id).or_default().push(song);
+    }
+
+    let mut artists: HashMap<i64, Vec<i32>> = HashMap::new();
+    for link in song_artists::Entity::find()
+        .all(db)
+        .await
+        .into_diagnostic()?
+    {
+        artists
+            .entry(link.song_hash)
+            .or_default()
+            .push(link.artist_id);
+    }
+
+    Ok(config
+        .sources
+        .iter()
+        .map(|source| {
+            let id = i32::from(source.id);
+            let songs = songs.get(&id).map(Vec::as_slice).unwrap_or_default();
+
+            SourceSummary {
+                source_id: source.id,
+                tracks: songs.len() as u64,
+                albums: count_albums(songs),
+                artists: songs
+                    .iter()
+                    .flat_map(|v| artists.get(&v.hash).into_iter().flatten())
+                    .collect::<HashSet<_>>()
+                    .len() as u64,
+                duration_ms: songs.iter().map(|v| u64::from(v.duration)).sum(),
+                size: local.contains(&id).then(|| {
+                    songs
+                        .iter()
+                        .filter_map(|v| std::fs::metadata(song_path(v)).ok())
+                        .map(|v| v.len())
+                        .sum()
+                }),
+                last_indexed: times.get(&source.id).copied(),
+            }
+        })
+        .collect())
+}
+
+/// Asks the server of a remote source for its statistics
+pub async fn remote_stats(source_id: u8) -> Result<LibraryStats> {
+    let config = Config::read_config()?;
+
+    let source = config
+        .sources
+        .iter()
+        .find(|v| v.id == source_id)
+        .ok_or(miette!("Source {} does not exist", source_id))?;
+
+    let SourceKind::Remote { address } = &source.source else {
+        return Err(miette!("Source {} is not a remote source", source_id));
+    };
+
+    let mut request = http_client(&config, Some(source))?
+        .get(format!("{address}/stats"))
+        .header(ACCEPT, STATS_MEDIA_TYPE);
+
+    if let Some((username, password)) = basic_auth(source)? {
+        request = request.basic_auth(username, Some(password));
+    }
+
+    let response = request
+        .send()
+        .await
+        .into_diagnostic()?
+        .error_for_status()
+        .into_diagnostic()?
+        .bytes()
+        .await
+        .into_diagnostic()?;
+
+    decode_stats(&response)
+}
+
+/// Statistics as messagepack, which is what `remote_stats` asks servers for
+pub fn encode_stats(stats: &LibraryStats) -> Result<Vec<u8>> {
+    rmp_serde::to_vec(stats).into_diagnostic()
+}
+
+fn decode_stats(data: &[u8]) -> Result<LibraryStats> {
+    rmp_serde::from_slice(data).into_diagnostic()
+}
+
+/// Albums are counted the same way they're listed, by name and album artist
+fn count_albums(songs: &[library::Model]) -> u64 {
+    songs
+        .iter()
+        .filter_map(|v| Some((v.album.as_deref()?, album_artist(v), v.compilation)))
+        .collect::<HashSet<_>>()
+        .len() as u64
+}
+
+/// Remembers that a source was just indexed
+pub fn mark_indexed(source_id: u8) -> Result<()> {
+    let mut times = index_times();
+    times.insert(source_id, now()?);
+    write_times(&times, index_times_path())?;
+
+    // A finished run replaces any earlier cancelled one
+    let mut partial = partial_index_times();
+    if partial.remove(&source_id).is_some() {
+        write_times(&partial, partial_index_times_path())?;
+    }
+
+    Ok(())
+}
+
+/// Remembers that indexing a source was cancelled partway through.
+/// This doesn't count as the source being indexed, so `index_times` keeps the last finished run.
+pub fn mark_partially_indexed(source_id: u8) -> Result<()> {
+    let mut times = partial_index_times();
+    times.insert(source_id, now()?);
+
+    write_times(&times, partial_index_times_path())
+}
+
+/// When each source was last indexed, in seconds since the unix epoch
+pub fn index_times() -> HashMap<u8, i64> {
+    read_times(index_times_path())
+}
+
+/// When indexing each source was last cancelled, for sources whose last run didn't finish
+pub fn partial_index_times() -> HashMap<u8, i64> {
+    read_times(partial_index_times_path())
+}
+
+fn now() -> Result<i64> {
+    Ok(SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .into_diagnostic()?
+        .as_secs() as i64)
+}
+
+fn read_times(path: Option<PathBuf>) -> HashMap<u8, i64> {
+    path.and_then(|v| std::fs::read(v).ok())
+        .and_then(|v| rmp_serde::from_slice(&v).ok())
+        .unwrap_or_default()
+}
+
+fn write_times(times: &HashMap<u8, i64>, path: Option<PathBuf>) -> Result<()> {
+    let contents = rmp_serde::to_vec(times).into_diagnostic()?;
+
+    let path = path.ok_or(miette!("Cache directory does not exist"))?;
+    create_dir_all(path.parent().unwrap_or(&path)).into_diagnostic()?;
+
+    File::create(path)
+        .and_then(|mut v| v.write_all(&contents))
+        .into_diagnostic()
+}
+
+fn index_times_path() -> Option<PathBuf> {
+    cache_dir().map(|v| v.join("index_times.mp"))
+}
+
+fn partial_index_times_path() -> Option<PathBuf> {
+    cache_dir().map(|v| v.join("partial_index_times.mp"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_round_trip() {
+        let stats = LibraryStats {
+            tracks: 1200,
+            albums: 95,
+            total_size: 38_000_000_000,
+            last_indexed: Some(1_668_000_000),
+        };
+
+        assert_eq!(decode_stats(&encode_stats(&stats).unwrap()).unwrap(), stats);
+        assert_eq!(
+            decode_stats(&encode_stats(&LibraryStats::default()).unwrap()).unwrap(),
+            LibraryStats::default()
+        );
+    }
+}
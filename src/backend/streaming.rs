@@ -0,0 +1,891 @@
+use std::{
+    future::Future,
+    io::{self, Read, Seek, SeekFrom},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use super::{
+    availability::track_completion,
+    config::{Config, SourceKind},
+    model::library,
+    network::is_metered,
+    utils::{audio_location, http_client},
+};
+use miette::{miette, Result};
+use reqwest::{
+    header::{RANGE, RETRY_AFTER},
+    Client, StatusCode,
+};
+use sea_orm::DatabaseConnection;
+use symphonia::core::io::MediaSource;
+use tokio::{sync::watch, time::Instant};
+
+/// Bytes buffered before the first read returns, so decoding doesn't start on an empty buffer
+pub const PREBUFFER: usize = 256 * 1024;
+
+/// Failed requests in a row after which streaming gives up
+pub const MAX_ATTEMPTS: u32 = 6;
+const BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(8);
+/// Wait used when a throttling server doesn't say how long to wait
+pub const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(2);
+/// Header Eleanor servers answer with when they transcoded a song to stay under `max_bitrate`
+const TRANSCODED: &str = "x-transcoded-bitrate";
+
+/// Why fetching part of a file failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FetchError {
+    /// The server asked to slow down, optionally saying for how long
+    Throttled(Option<Duration>),
+    /// The connection failed or was closed early. Worth retrying.
+    Dropped(String),
+    /// The server doesn't have the file
+    NotFound,
+    /// Retrying won't help, e.g. the credentials were rejected
+    Fatal(String),
+}
+
+/// Wait before reconnecting after `failures` dropped connections in a row
+pub fn backoff(failures: u32) -> Duration {
+    (BACKOFF * 2u32.pow(failures - 1)).min(MAX_BACKOFF)
+}
+
+/// An opened request for a file, starting at some offset
+pub struct Response<B> {
+    pub body: B,
+    /// Length of the whole file, if known
+    pub total: Option<u64>,
+    /// Whether the body starts at the requested offset. Servers ignoring ranges send the whole file.
+    pub partial: bool,
+    /// Bytes per second the body is received at, at most, once the reader's prebuffer is filled.
+    /// That way playback doesn't take long to start.
+    pub max_rate: Option<u64>,
+}
+
+/// A way of fetching a remote file, so streaming can be tested without a server
+pub trait Transport: Send + Sync + 'static {
+    type Body: Body;
+
+    /// Requests the file starting at `offset`
+    fn open(
+        &self,
+        offset: u64,
+    ) -> impl Future<Output = Result<Response<Self::Body>, FetchError>> + Send;
+}
+
+/// The data of an opened request, received in chunks
+pub trait Body: Send + 'static {
+    /// The next chunk, or `None` once everything was received
+    fn chunk(&mut self) -> impl Future<Output = Result<Option<Vec<u8>>, FetchError>> + Send;
+}
+
+/// What happened while streaming a file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamStats {
+    /// Bytes received so far
+    pub buffered: u64,
+    /// Times a read had to wait for data after playback started
+    pub starvations: u32,
+    /// Times the connection was opened again after it failed
+    pub reconnects: u32,
+    /// Times the server asked to slow down
+    pub throttled: u32,
+}
+
+#[derive(Default)]
+struct Buffer {
+    data: Vec<u8>,
+    total: Option<u64>,
+    /// Set once the server responded, so the total length is known if the server sent it
+    opened: bool,
+    finished: bool,
+    error: Option<String>,
+    /// Set once the reader is dropped, so fetching stops
+    closed: bool,
+    /// Whether a read is currently waiting for data
+    waiting: bool,
+    stats: StreamStats,
+}
+
+type Shared = Arc<(Mutex<Buffer>, Condvar)>;
+
+/// Reads a remote file while it's being downloaded in the background.
+/// Downloading resumes where it stopped when the connection drops, and backs off when the
+/// server throttles. Reads block until the data they need has arrived.
+///
+/// The whole file is kept in memory, so seeking backwards never needs another request.
+pub struct StreamingReader {
+    shared: Shared,
+    completed: watch::Receiver<Option<u64>>,
+    position: u64,
+    prebuffer: usize,
+    started: bool,
+}
+
+impl StreamingReader {
+    /// Starts downloading in the background. Has to be called within a Tokio runtime.
+    pub fn new<T: Transport>(transport: T, prebuffer: usize) -> Self {
+        let shared: Shared = Arc::default();
+        let (complete, completed) = watch::channel(None);
+
+        tokio::spawn(fetch(transport, shared.clone(), complete, prebuffer as u64));
+
+        StreamingReader {
+            shared,
+            completed,
+            position: 0,
+            prebuffer,
+            started: false,
+        }
+    }
+
+    pub fn stats(&self) -> StreamStats {
+        lock(&self.shared).stats
+    }
+
+    /// Holds the size of the file once all of it was received.
+    /// Closes without a value if streaming failed or the reader was dropped first.
+    pub fn completed(&self) -> watch::Receiver<Option<u64>> {
+        self.completed.clone()
+    }
+
+    /// Length of the file, waiting for the server to respond if needed
+    fn total(&self) -> Option<u64> {
+        let (_, condvar) = &*self.shared;
+        let mut buffer = lock(&self.shared);
+
+        while !buffer.opened && !buffer.finished && buffer.error.is_none() {
+            buffer = condvar.wait(buffer).unwrap_or_else(|e| e.into_inner());
+        }
+
+        buffer.total
+    }
+
+    /// Blocks until data past the current position is available, or the file ended
+    fn wait(&mut self) -> io::Result<MutexGuard<'_, Buffer>> {
+        let (_, condvar) = &*self.shared;
+        let mut buffer = lock(&self.shared);
+        let mut starved = false;
+
+        loop {
+            let available = buffer.data.len() as u64 > self.position;
+            let ready = self.started || buffer.data.len() >= self.prebuffer || buffer.finished;
+
+            if (available && ready) || buffer.finished {
+                break;
+            }
+
+            if let Some(e) = &buffer.error {
+                return Err(io::Error::other(e.clone()));
+            }
+
+            if self.started && !starved {
+                buffer.stats.starvations += 1;
+                starved = true;
+            }
+
+            buffer.waiting = true;
+            buffer = condvar.wait(buffer).unwrap_or_else(|e| e.into_inner());
+            buffer.waiting = false;
+        }
+
+        self.started = true;
+        Ok(buffer)
+    }
+}
+
+impl Read for StreamingReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let position = self.position as usize;
+        let buffer = self.wait()?;
+
+        let available = buffer.data.get(position..).unwrap_or_default();
+        let read = available.len().min(buf.len());
+        buf[..read].copy_from_slice(&available[..read]);
+
+        drop(buffer);
+        self.position += read as u64;
+
+        Ok(read)
+    }
+}
+
+impl Seek for StreamingReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(v) => Some(v),
+            SeekFrom::Current(v) => self.position.checked_add_signed(v),
+            SeekFrom::End(v) => {
+                let total = self.total().ok_or(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "Length of the stream is unknown",
+                ))?;
+                total.checked_add_signed(v)
+            }
+        };
+
+        self.position = position.ok_or(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Seeked before the start of the stream",
+        ))?;
+
+        Ok(self.position)
+    }
+}
+
+impl MediaSource for StreamingReader {
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        self.total()
+    }
+}
+
+impl Drop for StreamingReader {
+    fn drop(&mut self) {
+        lock(&self.shared).closed = true;
+    }
+}
+
+fn lock(shared: &Shared) -> MutexGuard<'_, Buffer> {
+    shared.0.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Downloads the file into the shared buffer, reconnecting until it's complete
+async fn fetch<T: Transport>(
+    transport: T,
+    shared: Shared,
+    complete: watch::Sender<Option<u64>>,
+    prebuffer: u64,
+) {
+    let (_, condvar) = &*shared;
+    let mut failures = 0;
+
+    let result = loop {
+        let offset = lock(&shared).data.len() as u64;
+
+        let error = match receive(&transport, &shared, offset, prebuffer).await {
+            Ok(()) => break Ok(()),
+            Err(e) => e,
+        };
+
+        // The guard can't be held across the sleep
+        let wait = {
+            let mut buffer = lock(&shared);
+            if buffer.closed {
+                return;
+            }
+
+            // Only count failures in a row, a long stream may drop now and then
+            if buffer.data.len() as u64 > offset {
+                failures = 0;
+            }
+            failures += 1;
+
+            if failures >= MAX_ATTEMPTS {
+                break Err(format!(
+                    "Streaming failed after {failures} attempts: {error:?}"
+                ));
+            }
+
+            match error {
+                FetchError::Fatal(e) => break Err(e),
+                FetchError::NotFound => break Err("File not found".into()),
+                FetchError::Throttled(retry_after) => {
+                    buffer.stats.throttled += 1;
+                    retry_after.unwrap_or(DEFAULT_RETRY_AFTER)
+                }
+                FetchError::Dropped(_) => {
+                    buffer.stats.reconnects += 1;
+                    backoff(failures)
+                }
+            }
+        };
+
+        tokio::time::sleep(wait).await;
+    };
+
+    let mut buffer = lock(&shared);
+    match result {
+        Ok(()) => {
+            buffer.finished = true;
+
+            // Receiving stops early once the reader is dropped
+            if !buffer.closed {
+                complete.send_replace(Some(buffer.data.len() as u64));
+            }
+        }
+        Err(e) => buffer.error = Some(e),
+    }
+    condvar.notify_all();
+}
+
+/// Receives one response into the buffer. Returns once the file is complete or the request failed.
+async fn receive<T: Transport>(
+    transport: &T,
+    shared: &Shared,
+    offset: u64,
+    prebuffer: u64,
+) -> Result<(), FetchError> {
+    let (_, condvar) = &**shared;
+
+    let mut response = transport.open(offset).await?;
+    let start = Instant::now();
+    let mut received = 0;
+    // Only the part of the prebuffer that's still missing arrives at full speed
+    let burst = prebuffer.saturating_sub(offset);
+
+    // The response starts at the beginning of the file, so skip what's already buffered
+    let mut skip = if response.partial { 0 } else { offset };
+
+    {
+        let mut buffer = lock(shared);
+        buffer.total = response.total;
+        buffer.opened = true;
+        condvar.notify_all();
+    }
+
+    while let Some(chunk) = response.body.chunk().await? {
+        let chunk = {
+            let skipped = (skip as usize).min(chunk.len());
+            skip -= skipped as u64;
+            &chunk[skipped..]
+        };
+
+        {
+            let mut buffer = lock(shared);
+            if buffer.closed {
+                return Ok(());
+            }
+
+            buffer.data.extend_from_slice(chunk);
+            buffer.stats.buffered = buffer.data.len() as u64;
+            condvar.notify_all();
+        }
+
+        received += chunk.len() as u64;
+        if let Some(rate) = response.max_rate {
+            throttle(start, received.saturating_sub(burst), rate).await;
+        }
+    }
+
+    let buffer = lock(shared);
+    match buffer.total {
+        Some(total) if (buffer.data.len() as u64) < total => {
+            Err(FetchError::Dropped("Connection closed early".into()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Waits until `received` bytes since `start` are within `rate` bytes per second.
+/// A rate of 0 doesn't limit anything.
+async fn throttle(start: Instant, received: u64, rate: u64) {
+    if rate == 0 {
+        return;
+    }
+
+    tokio::time::sleep_until(start + Duration::from_secs_f64(received as f64 / rate as f64)).await;
+}
+
+/// Fetches songs from a remote source over HTTP, using range requests to resume
+pub struct HttpTransport {
+    client: Client,
+    url: String,
+    credentials: Option<(String, String)>,
+    /// Sent as a POST request if set
+    body: Option<Vec<u8>>,
+    /// Bytes per second files the server didn't transcode are received at, at most
+    max_rate: Option<u64>,
+}
+
+pub struct HttpBody(reqwest::Response);
+
+impl HttpTransport {
+    /// Transport for any file, sending credentials as basic auth if there are any
+    pub fn new(client: Client, url: String, credentials: Option<(String, String)>) -> Self {
+        HttpTransport {
+            client,
+            url,
+            credentials,
+            body: None,
+            max_rate: None,
+        }
+    }
+
+    /// Transport for the response to a POST request. Servers can't be expected to
+    /// support ranges for these, so resuming usually means receiving it again.
+    pub fn post(
+        client: Client,
+        url: String,
+        credentials: Option<(String, String)>,
+        body: Vec<u8>,
+    ) -> Self {
+        HttpTransport {
+            body: Some(body),
+            ..HttpTransport::new(client, url, credentials)
+        }
+    }
+
+    /// Transport for a remote song, limited to the source's `max_bitrate`
+    pub fn for_song(song: &library::Model) -> Result<Self> {
+        let config = Config::read_config()?;
+
+        let source = config
+            .sources
+            .iter()
+            .find(|v| i32::from(v.id) == song.source_id)
+            .ok_or(miette!("Source {} does not exist", song.source_id))?;
+
+        // The lower limit wins, 0 is the same as no limit
+        let metered_bitrate = is_metered().then_some(config.metered_bitrate);
+        let max_bitrate = [source.settings.max_bitrate, metered_bitrate]
+            .into_iter()
+            .flatten()
+            .filter(|&v| v > 0)
+            .min();
+        let (url, credentials) = audio_location(source, song, max_bitrate)?;
+
+        Ok(HttpTransport {
+            max_rate: match source.source {
+                // Subsonic servers always transcode when asked to
+                SourceKind::Subsonic { .. } => None,
+                _ => max_bitrate.map(|kbps| u64::from(kbps) * 1000 / 8),
+            },
+            ..HttpTransport::new(http_client(&config, Some(source))?, url, credentials)
+        })
+    }
+}
+
+impl Transport for HttpTransport {
+    type Body = HttpBody;
+
+    async fn open(&self, offset: u64) -> Result<Response<HttpBody>, FetchError> {
+        let mut request = match &self.body {
+            Some(body) => self.client.post(&self.url).body(body.clone()),
+            None => self.client.get(&self.url),
+        };
+
+        if let Some((username, password)) = &self.credentials {
+            request = request.basic_auth(username, Some(password));
+        }
+
+        if offset > 0 {
+            request = request.header(RANGE, format!("bytes={offset}-"));
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| FetchError::Dropped(e.to_string()))?;
+
+        let status = response.status();
+
+        if matches!(
+            status,
+            StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+        ) {
+            let retry_after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse().ok())
+                .map(Duration::from_secs);
+
+            return Err(FetchError::Throttled(retry_after));
+        }
+
+        if status.is_server_error() {
+            return Err(FetchError::Dropped(status.to_string()));
+        }
+
+        if status == StatusCode::NOT_FOUND {
+            return Err(FetchError::NotFound);
+        }
+
+        if !status.is_success() {
+            return Err(FetchError::Fatal(status.to_string()));
+        }
+
+        let partial = status == StatusCode::PARTIAL_CONTENT;
+        let total = response
+            .content_length()
+            .map(|v| if partial { v + offset } else { v });
+        let max_rate = self
+            .max_rate
+            .filter(|_| !response.headers().contains_key(TRANSCODED));
+
+        Ok(Response {
+            body: HttpBody(response),
+            total,
+            partial,
+            max_rate,
+        })
+    }
+}
+
+impl Body for HttpBody {
+    async fn chunk(&mut self) -> Result<Option<Vec<u8>>, FetchError> {
+        self.0
+            .chunk()
+            .await
+            .map(|v| v.map(|v| v.to_vec()))
+            .map_err(|e| FetchError::Dropped(e.to_string()))
+    }
+}
+
+/// Starts streaming a remote song. Once it was received completely, its size is remembered,
+/// see `availability::availability`.
+pub fn stream_song(song: &library::Model, db: &DatabaseConnection) -> Result<StreamingReader> {
+    let reader = StreamingReader::new(HttpTransport::for_song(song)?, PREBUFFER);
+
+    track_completion(song.hash, reader.completed(), db.clone());
+
+    Ok(reader)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use tokio::{
+        sync::{oneshot, Notify},
+        time::Instant,
+    };
+
+    const SIZE: usize = 200_000;
+    const CHUNK: usize = 4096;
+
+    /// A server with configurable misbehavior. Randomness is seeded, so runs are repeatable.
+    struct Simulated {
+        data: Arc<Vec<u8>>,
+        latency: Duration,
+        jitter: Duration,
+        /// Offsets at which the connection drops, each only once
+        drops: Arc<Mutex<Vec<u64>>>,
+        /// Requests answered with a throttling error before the server responds
+        throttle: Arc<Mutex<u32>>,
+        retry_after: Option<Duration>,
+        ignore_ranges: bool,
+        /// Every request fails after this many bytes
+        fail_after: Option<u64>,
+        rng: Arc<Mutex<StdRng>>,
+        stall: Option<(u64, Arc<Notify>)>,
+        max_rate: Option<u64>,
+    }
+
+    struct SimulatedBody {
+        data: Arc<Vec<u8>>,
+        position: u64,
+        end: u64,
+        latency: Duration,
+        jitter: Duration,
+        rng: Arc<Mutex<StdRng>>,
+        /// Stops sending at this offset until notified
+        stall: Option<(u64, Arc<Notify>)>,
+    }
+
+    impl Simulated {
+        fn new() -> Self {
+            let mut rng = StdRng::seed_from_u64(0x454c454e);
+            let data = (0..SIZE).map(|_| rng.gen()).collect();
+
+            Simulated {
+                data: Arc::new(data),
+                latency: Duration::ZERO,
+                jitter: Duration::ZERO,
+                drops: Arc::default(),
+                throttle: Arc::default(),
+                retry_after: None,
+                ignore_ranges: false,
+                fail_after: None,
+                rng: Arc::new(Mutex::new(rng)),
+                stall: None,
+                max_rate: None,
+            }
+        }
+    }
+
+    impl Transport for Simulated {
+        type Body = SimulatedBody;
+
+        async fn open(&self, offset: u64) -> Result<Response<SimulatedBody>, FetchError> {
+            tokio::time::sleep(self.latency).await;
+
+            {
+                let mut throttle = self.throttle.lock().unwrap();
+                if *throttle > 0 {
+                    *throttle -= 1;
+                    return Err(FetchError::Throttled(self.retry_after));
+                }
+            }
+
+            let start = if self.ignore_ranges { 0 } else { offset };
+
+            let mut drops = self.drops.lock().unwrap();
+            let drop_at = drops.iter().position(|v| *v > start);
+            let end = match (drop_at, self.fail_after) {
+                (Some(i), _) => drops.remove(i),
+                (None, Some(limit)) => start + limit,
+                (None, None) => self.data.len() as u64,
+            };
+
+            Ok(Response {
+                body: SimulatedBody {
+                    data: self.data.clone(),
+                    position: start,
+                    end: end.min(self.data.len() as u64),
+                    latency: self.latency,
+                    jitter: self.jitter,
+                    rng: self.rng.clone(),
+                    stall: self.stall.clone(),
+                },
+                total: Some(self.data.len() as u64),
+                partial: !self.ignore_ranges,
+                max_rate: self.max_rate,
+            })
+        }
+    }
+
+    impl Body for SimulatedBody {
+        async fn chunk(&mut self) -> Result<Option<Vec<u8>>, FetchError> {
+            let jitter = if self.jitter.is_zero() {
+                Duration::ZERO
+            } else {
+                self.rng
+                    .lock()
+                    .unwrap()
+                    .gen_range(Duration::ZERO..self.jitter)
+            };
+            tokio::time::sleep(self.latency + jitter).await;
+
+            if let Some((offset, release)) = &self.stall {
+                if self.position >= *offset {
+                    release.notified().await;
+                    self.stall = None;
+                }
+            }
+
+            if self.position >= self.end {
+                return if self.end < self.data.len() as u64 {
+                    Err(FetchError::Dropped("Connection reset".into()))
+                } else {
+                    Ok(None)
+                };
+            }
+
+            let end = (self.position + CHUNK as u64).min(self.end);
+            let chunk = self.data[self.position as usize..end as usize].to_vec();
+            self.position = end;
+
+            Ok(Some(chunk))
+        }
+    }
+
+    /// Reads the whole stream on a separate thread, like a decoder would
+    async fn read_all(
+        transport: Simulated,
+        prebuffer: usize,
+    ) -> (io::Result<Vec<u8>>, StreamStats, Arc<Vec<u8>>) {
+        let expected = transport.data.clone();
+        let mut reader = StreamingReader::new(transport, prebuffer);
+        let (sender, receiver) = oneshot::channel();
+
+        // Not a blocking task, since those would keep paused time from advancing
+        std::thread::spawn(move || {
+            let mut data = vec![];
+            let result = reader.read_to_end(&mut data).map(|_| data);
+            let _ = sender.send((result, reader.stats()));
+        });
+
+        let (result, stats) = receiver.await.unwrap();
+        (result, stats, expected)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fast_server_never_starves() {
+        let (result, stats, expected) = read_all(Simulated::new(), PREBUFFER).await;
+
+        assert_eq!(result.unwrap(), *expected);
+        assert_eq!(stats.starvations, 0);
+        assert_eq!(stats.reconnects, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn first_read_waits_for_prebuffer() {
+        let mut transport = Simulated::new();
+        transport.latency = Duration::from_millis(10);
+
+        let mut reader = StreamingReader::new(transport, 64 * 1024);
+        let (sender, receiver) = oneshot::channel();
+
+        std::thread::spawn(move || {
+            let mut first = [0; 16];
+            reader.read_exact(&mut first).unwrap();
+            let _ = sender.send(reader.stats());
+        });
+
+        let stats = receiver.await.unwrap();
+        assert!(stats.buffered >= 64 * 1024);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stalled_server_starves_decoder() {
+        let release = Arc::new(Notify::new());
+
+        let mut transport = Simulated::new();
+        transport.latency = Duration::from_millis(50);
+        transport.jitter = Duration::from_millis(30);
+        transport.stall = Some((64 * 1024, release.clone()));
+
+        let expected = transport.data.clone();
+        let mut reader = StreamingReader::new(transport, 16 * 1024);
+        let shared = reader.shared.clone();
+        let (sender, receiver) = oneshot::channel();
+
+        std::thread::spawn(move || {
+            let mut data = vec![];
+            let result = reader.read_to_end(&mut data).map(|_| data);
+            let _ = sender.send((result, reader.stats()));
+        });
+
+        // The reader catches up with the stalled download and has to wait for it
+        loop {
+            let stalled = {
+                let buffer = lock(&shared);
+                buffer.waiting && buffer.data.len() == 64 * 1024
+            };
+
+            if stalled {
+                break;
+            }
+
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(lock(&shared).stats.starvations >= 1);
+
+        release.notify_one();
+
+        let (result, stats) = receiver.await.unwrap();
+        assert_eq!(result.unwrap(), *expected);
+        assert!(stats.starvations >= 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn resumes_after_dropped_connections() {
+        let transport = Simulated::new();
+        *transport.drops.lock().unwrap() = vec![10_000, 50_000, 150_000];
+
+        let (result, stats, expected) = read_all(transport, PREBUFFER).await;
+
+        assert_eq!(result.unwrap(), *expected);
+        assert_eq!(stats.reconnects, 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn resumes_when_server_ignores_ranges() {
+        let mut transport = Simulated::new();
+        transport.ignore_ranges = true;
+        *transport.drops.lock().unwrap() = vec![30_000, 120_000];
+
+        let (result, stats, expected) = read_all(transport, PREBUFFER).await;
+
+        assert_eq!(result.unwrap(), *expected);
+        assert_eq!(stats.reconnects, 2);
+        assert_eq!(stats.buffered, SIZE as u64);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn waits_while_throttled() {
+        let mut transport = Simulated::new();
+        transport.retry_after = Some(Duration::from_secs(3));
+        *transport.throttle.lock().unwrap() = 2;
+
+        let start = Instant::now();
+        let (result, stats, expected) = read_all(transport, PREBUFFER).await;
+
+        assert_eq!(result.unwrap(), *expected);
+        assert_eq!(stats.throttled, 2);
+        assert!(start.elapsed() >= Duration::from_secs(6));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn limits_rate_after_prebuffer() {
+        let mut transport = Simulated::new();
+        transport.max_rate = Some(10_000);
+
+        let start = Instant::now();
+        let (result, _, expected) = read_all(transport, 16 * 1024).await;
+
+        assert_eq!(result.unwrap(), *expected);
+        // Only what's beyond the prebuffer is limited
+        assert!(start.elapsed() >= Duration::from_secs(18));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_without_progress() {
+        let mut transport = Simulated::new();
+        transport.fail_after = Some(0);
+
+        let (result, stats, _) = read_all(transport, PREBUFFER).await;
+
+        assert!(result.is_err());
+        assert_eq!(stats.reconnects, MAX_ATTEMPTS - 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reports_completion_only_on_success() {
+        let reader = StreamingReader::new(Simulated::new(), PREBUFFER);
+        let mut completed = reader.completed();
+
+        completed.changed().await.unwrap();
+        assert_eq!(*completed.borrow(), Some(SIZE as u64));
+
+        let mut transport = Simulated::new();
+        transport.fail_after = Some(0);
+
+        let reader = StreamingReader::new(transport, PREBUFFER);
+        let mut completed = reader.completed();
+
+        assert!(completed.changed().await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keeps_going_while_making_progress() {
+        // Every request drops after a few chunks, but the stream still completes
+        let mut transport = Simulated::new();
+        transport.fail_after = Some(3 * CHUNK as u64);
+
+        let (result, stats, expected) = read_all(transport, PREBUFFER).await;
+
+        assert_eq!(result.unwrap(), *expected);
+        assert!(stats.reconnects >= (SIZE / (3 * CHUNK)) as u32);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn seeks_within_buffer() {
+        let mut reader = StreamingReader::new(Simulated::new(), PREBUFFER);
+        let expected = Simulated::new().data;
+        let (sender, receiver) = oneshot::channel();
+
+        std::thread::spawn(move || {
+            let mut start = [0; 8];
+            reader.seek(SeekFrom::End(-8)).unwrap();
+            reader.read_exact(&mut start).unwrap();
+
+            let mut again = [0; 8];
+            reader.seek(SeekFrom::Start(100)).unwrap();
+            reader.read_exact(&mut again).unwrap();
+
+            let _ = sender.send((start, again, reader.byte_len()));
+        });
+
+        let (end, again, len) = receiver.await.unwrap();
+        assert_eq!(end, expected[SIZE - 8..]);
+        assert_eq!(again, expected[100..108]);
+        assert_eq!(len, Some(SIZE as u64));
+    }
+}
@@ -0,0 +1,339 @@
+use std::path::Path;
+
+use super::{
+    channels::layout_name,
+    config::{Config, Source},
+    events::{publish, Event},
+    model::library,
+    sync::{sync_songs, SyncStats},
+    utils::get_auth_source,
+};
+use flate2::Crc;
+use md5::{Digest, Md5};
+use miette::{miette, IntoDiagnostic, Result};
+use rand::{distributions::Alphanumeric, Rng};
+use reqwest::{Client, Url};
+use sea_orm::DatabaseConnection;
+use serde::{de::DeserializeOwned, Deserialize};
+
+/// Oldest API version with everything used here
+const API_VERSION: &str = "1.16.1";
+
+/// Albums requested at a time, the most servers send
+const PAGE_SIZE: usize = 500;
+
+#[derive(Deserialize, Debug)]
+struct Envelope<T> {
+    #[serde(rename = "subsonic-response")]
+    response: ApiResponse<T>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ApiResponse<T> {
+    status: String,
+    error: Option<ApiError>,
+    #[serde(flatten)]
+    body: T,
+}
+
+#[derive(Deserialize, Debug)]
+struct ApiError {
+    code: i32,
+    message: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct Empty {}
+
+#[derive(Deserialize, Debug, Default)]
+struct AlbumList {
+    #[serde(rename = "albumList2", default)]
+    list: AlbumListEntries,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct AlbumListEntries {
+    #[serde(default)]
+    album: Vec<AlbumEntry>,
+}
+
+#[derive(Deserialize, Debug)]
+struct AlbumEntry {
+    id: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct AlbumResponse {
+    #[serde(default)]
+    album: Album,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct Album {
+    artist: Option<String>,
+    #[serde(default)]
+    song: Vec<Child>,
+}
+
+/// A song as the API describes it
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Child {
+    id: String,
+    title: Option<String>,
+    album: Option<String>,
+    artist: Option<String>,
+    track: Option<i32>,
+    year: Option<i32>,
+    genre: Option<String>,
+    /// In seconds
+    duration: Option<u32>,
+    disc_number: Option<i32>,
+    suffix: Option<String>,
+    path: Option<String>,
+    /// In kbps
+    bit_rate: Option<i32>,
+    /// Only sent by OpenSubsonic servers
+    replay_gain: Option<ReplayGain>,
+    /// Only sent by OpenSubsonic servers
+    sampling_rate: Option<i32>,
+    /// Only sent by OpenSubsonic servers
+    bit_depth: Option<i32>,
+    /// Only sent by OpenSubsonic servers
+    channel_count: Option<i32>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ReplayGain {
+    track_gain: Option<f32>,
+    track_peak: Option<f32>,
+    album_gain: Option<f32>,
+    album_peak: Option<f32>,
+}
+
+/// Checks that the server is reachable and accepts the source's credentials
+pub async fn ping(source: &Source, url: &str, client: &Client) -> Result<()> {
+    let credentials = get_auth_source(source.id)?;
+
+    call::<Empty>(client, url, "ping", &[], &credentials)
+        .await
+        .map(|_| ())
+}
+
+/// Brings the rows of a Subsonic source up to date by listing every album on the server.
+///
+/// Subsonic ids aren't content hashes, so songs are identified by a checksum of the server
+/// address and their id, see `song_hash`. The id itself is kept in the `path` column.
+pub async fn sync_subsonic(
+    source: &Source,
+    url: &str,
+    client: &Client,
+    config: &Config,
+    db: &DatabaseConnection,
+) -> Result<SyncStats> {
+    ping(source, url, client).await?;
+
+    let credentials = get_auth_source(source.id)?;
+    let mut songs = vec![];
+    let mut offset = 0;
+
+    loop {
+        let albums = call::<AlbumList>(
+            client,
+            url,
+            "getAlbumList2",
+            &[
+                ("type", "alphabeticalByName".into()),
+                ("size", PAGE_SIZE.to_string()),
+                ("offset", offset.to_string()),
+            ],
+            &credentials,
+        )
+        .await?
+        .list
+        .album;
+
+        if albums.is_empty() {
+            break;
+        }
+
+        offset += albums.len();
+
+        for entry in albums {
+            let album =
+                call::<AlbumResponse>(client, url, "getAlbum", &[("id", entry.id)], &credentials)
+                    .await?
+                    .album;
+
+            for child in album.song {
+                songs.push(to_model(child, album.artist.as_deref(), source, url));
+            }
+        }
+
+        publish(Event::IndexProgress {
+            source_id: source.id,
+            indexed: songs.len(),
+            total: songs.len(),
+        });
+    }
+
+    sync_songs(source, songs, config, db).await
+}
+
+/// Address a song's original file is streamed from, or a transcoded one if it's above
+/// `max_bitrate` kbit/s. Credentials are part of the URL, since Subsonic servers don't support basic auth.
+pub fn stream_url(
+    url: &str,
+    id: &str,
+    credentials: &(String, String),
+    max_bitrate: Option<u32>,
+) -> Result<String> {
+    let mut params = auth_params(credentials);
+    params.push(("id", id.to_string()));
+
+    match max_bitrate {
+        Some(kbps) => params.push(("maxBitRate", kbps.to_string())),
+        // Without this, servers may transcode
+        None => params.push(("format", "raw".into())),
+    }
+
+    Url::parse_with_params(&endpoint(url, "stream"), params)
+        .map(String::from)
+        .into_diagnostic()
+}
+
+/// Hash a song of a Subsonic server is stored under
+pub fn song_hash(url: &str, id: &str) -> i64 {
+    let mut crc = Crc::new();
+    crc.update(format!("{}/{id}", url.trim_end_matches('/')).as_bytes());
+    crc.sum().into()
+}
+
+async fn call<T: DeserializeOwned>(
+    client: &Client,
+    url: &str,
+    method: &str,
+    params: &[(&str, String)],
+    credentials: &(String, String),
+) -> Result<T> {
+    let envelope: Envelope<T> = client
+        .get(endpoint(url, method))
+        .query(&auth_params(credentials))
+        .query(params)
+        .send()
+        .await
+        .into_diagnostic()?
+        .error_for_status()
+        .into_diagnostic()?
+        .json()
+        .await
+        .into_diagnostic()?;
+
+    let response = envelope.response;
+
+    // Errors come with a successful status code
+    if let Some(error) = response.error {
+        return Err(miette!(
+            "Subsonic server refused {method}: {} (code {})",
+            error.message,
+            error.code
+        ));
+    }
+
+    miette::ensure!(
+        response.status == "ok",
+        "Subsonic server refused {method} with status {}",
+        response.status
+    );
+
+    Ok(response.body)
+}
+
+fn endpoint(url: &str, method: &str) -> String {
+    format!("{}/rest/{method}", url.trim_end_matches('/'))
+}
+
+/// Token authentication, which avoids sending the password itself
+fn auth_params((username, password): &(String, String)) -> Vec<(&'static str, String)> {
+    let salt: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(12)
+        .map(char::from)
+        .collect();
+
+    let token = Md5::digest(format!("{password}{salt}"))
+        .iter()
+        .map(|v| format!("{v:02x}"))
+        .collect();
+
+    vec![
+        ("u", username.clone()),
+        ("t", token),
+        ("s", salt),
+        ("v", API_VERSION.into()),
+        ("c", "eleanor".into()),
+        ("f", "json".into()),
+    ]
+}
+
+fn to_model(
+    child: Child,
+    album_artist: Option<&str>,
+    source: &Source,
+    url: &str,
+) -> library::Model {
+    let suffix = child.suffix.as_deref().unwrap_or("mp3");
+
+    // The extension is needed to decode the file
+    let filename = child
+        .path
+        .as_deref()
+        .and_then(|v| Path::new(v).file_name())
+        .and_then(|v| v.to_str())
+        .map(String::from)
+        .unwrap_or_else(|| format!("{}.{suffix}", child.id));
+
+    let gain = child.replay_gain.as_ref();
+
+    library::Model {
+        id: 0,
+        hash: song_hash(url, &child.id),
+        path: child.id,
+        filename,
+        source_id: source.id.into(),
+        artist: child.artist,
+        album_artist: album_artist.map(String::from),
+        name: child.title,
+        album: child.album,
+        duration: child.duration.unwrap_or(0).saturating_mul(1000),
+        genres: child.genre,
+        track: child.track,
+        year: child.year,
+        disc: child.disc_number,
+        rg_track_gain: gain.and_then(|v| v.track_gain),
+        rg_track_peak: gain.and_then(|v| v.track_peak),
+        rg_album_gain: gain.and_then(|v| v.album_gain),
+        rg_album_peak: gain.and_then(|v| v.album_peak),
+        compilation: false,
+        channel_layout: child
+            .channel_count
+            .and_then(|v| usize::try_from(v).ok())
+            .map(layout_name),
+        first_played: None,
+        last_played: None,
+        // The suffix is the closest thing to a codec the API has
+        codec: child.suffix,
+        bitrate: child.bit_rate,
+        sample_rate: child.sampling_rate,
+        bit_depth: child.bit_depth,
+        channels: child.channel_count,
+        composer: None,
+        comment: None,
+        extra_tags: None,
+        added_date: None,
+        file_size: None,
+        file_modified: None,
+        hash_bytes: None,
+    }
+}
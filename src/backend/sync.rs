@@ -0,0 +1,978 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::ErrorKind,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+    artists::link_song_artists,
+    config::{Config, Source},
+    diagnostics::record_rows,
+    downloads::move_download,
+    duplicates::claim,
+    genres::link_song,
+    hashes::{legacy_songs, remap},
+    model::{library, library::Column},
+    streaming::{
+        backoff, Body, FetchError, HttpTransport, Transport, DEFAULT_RETRY_AFTER, MAX_ATTEMPTS,
+    },
+    utils::basic_auth,
+};
+use miette::{bail, miette, IntoDiagnostic, Result};
+use reqwest::Client;
+use sea_orm::{
+    entity::prelude::Json, ColumnTrait, DatabaseConnection, DatabaseTransaction, EntityTrait,
+    QueryFilter, Set, TransactionTrait,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// Rows are inserted in batches, to stay under SQLite's limit on query parameters
+const BATCH_SIZE: usize = 100;
+
+/// A song in a remote source's manifest
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub hash: i64,
+    /// Changes whenever the song's metadata changes, see `checksum`
+    pub checksum: u32,
+}
+
+/// Number of rows changed by a sync
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SyncStats {
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+}
+
+/// What a sync would change, see `preview_remote`
+#[derive(Debug, Clone, Default)]
+pub struct SyncReport {
+    /// Songs new to the source
+    pub added: Vec<library::Model>,
+    /// Songs whose metadata changed on the server, as they are now and as they would be
+    pub updated: Vec<(library::Model, library::Model)>,
+    /// Songs no longer on the server
+    pub removed: Vec<library::Model>,
+}
+
+impl SyncReport {
+    pub fn stats(&self) -> SyncStats {
+        SyncStats {
+            added: self.added.len(),
+            updated: self.updated.len(),
+            removed: self.removed.len(),
+        }
+    }
+}
+
+/// Version of the fields `checksum` covers, increased whenever they change.
+/// It's part of the checksum, so songs from a server of another version are downloaded again.
+pub const CHECKSUM_VERSION: u32 = 1;
+
+/// The metadata `checksum` covers. Columns that differ between databases, like ids, play times
+/// and file details, are left out. New library columns are only covered once they're added here
+/// along with a new `CHECKSUM_VERSION`, so both ends keep agreeing until then.
+#[derive(Serialize)]
+struct ChecksumFields<'a> {
+    version: u32,
+    path: &'a str,
+    filename: &'a str,
+    hash: i64,
+    artist: Option<&'a str>,
+    album_artist: Option<&'a str>,
+    name: Option<&'a str>,
+    album: Option<&'a str>,
+    duration: u32,
+    genres: Option<&'a str>,
+    track: Option<i32>,
+    year: Option<i32>,
+    disc: Option<i32>,
+    rg_track_gain: Option<f32>,
+    rg_track_peak: Option<f32>,
+    rg_album_gain: Option<f32>,
+    rg_album_peak: Option<f32>,
+    compilation: bool,
+    channel_layout: Option<&'a str>,
+    codec: Option<&'a str>,
+    bitrate: Option<i32>,
+    sample_rate: Option<i32>,
+    bit_depth: Option<i32>,
+    channels: Option<i32>,
+    composer: Option<&'a str>,
+    comment: Option<&'a str>,
+    extra_tags: Option<&'a Json>,
+}
+
+/// Checksum of a song's metadata, see `ChecksumFields`.
+/// Servers report this in their manifest, so it has to be computed the same way on both ends.
+pub fn checksum(song: &library::Model) -> Result<u32> {
+    let fields = ChecksumFields {
+        version: CHECKSUM_VERSION,
+        path: &song.path,
+        filename: &song.filename,
+        hash: song.hash,
+        artist: song.artist.as_deref(),
+        album_artist: song.album_artist.as_deref(),
+        name: song.name.as_deref(),
+        album: song.album.as_deref(),
+        duration: song.duration,
+        genres: song.genres.as_deref(),
+        track: song.track,
+        year: song.year,
+        disc: song.disc,
+        rg_track_gain: song.rg_track_gain,
+        rg_track_peak: song.rg_track_peak,
+        rg_album_gain: song.rg_album_gain,
+        rg_album_peak: song.rg_album_peak,
+        compilation: song.compilation,
+        channel_layout: song.channel_layout.as_deref(),
+        codec: song.codec.as_deref(),
+        bitrate: song.bitrate,
+        sample_rate: song.sample_rate,
+        bit_depth: song.bit_depth,
+        channels: song.channels,
+        composer: song.composer.as_deref(),
+        comment: song.comment.as_deref(),
+        extra_tags: song.extra_tags.as_ref(),
+    };
+
+    Ok(adler::adler32_slice(
+        &rmp_serde::to_vec(&fields).into_diagnostic()?,
+    ))
+}
+
+/// Brings the rows of a remote source up to date with the server.
+/// Only songs that were added or changed are downloaded, and songs removed on the server are pruned.
+/// Servers without a manifest endpoint send their whole library instead.
+/// `progress` is called with the number of songs received so far and the number being sent.
+pub async fn sync_remote(
+    source: &Source,
+    address: &str,
+    client: &Client,
+    progress: impl FnMut(usize, usize) + Send,
+    config: &Config,
+    db: &DatabaseConnection,
+) -> Result<SyncStats> {
+    let (local, remote, songs) = fetch(source, address, client, progress, db).await?;
+
+    apply(source, local, remote, songs, config, db).await
+}
+
+/// Downloads what changed on the server like `sync_remote`, and reports what syncing
+/// would change without changing anything
+pub async fn preview_remote(
+    source: &Source,
+    address: &str,
+    client: &Client,
+    db: &DatabaseConnection,
+) -> Result<SyncReport> {
+    let (local, remote, songs) = fetch(source, address, client, |_, _| {}, db).await?;
+
+    preview(source, local, remote, songs, db).await
+}
+
+/// The rows of a source, the hashes of the songs on the server,
+/// and the songs that were added or changed there
+type Fetched = (
+    HashMap<i64, library::Model>,
+    HashSet<i64>,
+    Vec<library::Model>,
+);
+
+async fn fetch(
+    source: &Source,
+    address: &str,
+    client: &Client,
+    mut progress: impl FnMut(usize, usize) + Send,
+    db: &DatabaseConnection,
+) -> Result<Fetched> {
+    let credentials = basic_auth(source)?;
+
+    let local: HashMap<i64, library::Model> = library::Entity::find()
+        .filter(Column::SourceId.eq(source.id))
+        .all(db)
+        .await
+        .into_diagnostic()?
+        .into_iter()
+        .map(|v| (v.hash, v))
+        .collect();
+
+    let manifest = HttpTransport::new(
+        client.clone(),
+        format!("{address}/manifest"),
+        credentials.clone(),
+    );
+
+    let (remote, songs): (HashSet<i64>, Vec<library::Model>) =
+        match download::<_, ManifestEntry>(&manifest, |_, _| {}).await {
+            Err(FetchError::NotFound) => {
+                let index = HttpTransport::new(client.clone(), format!("{address}/"), credentials);
+                let songs: Vec<library::Model> = download(&index, &mut progress)
+                    .await
+                    .map_err(|e| miette!("Couldn't download the index: {:?}", e))?;
+
+                (songs.iter().map(|v| v.hash).collect(), songs)
+            }
+            manifest => {
+                let manifest =
+                    manifest.map_err(|e| miette!("Couldn't download the manifest: {:?}", e))?;
+
+                let mut wanted = vec![];
+                for entry in &manifest {
+                    let unchanged = match local.get(&entry.hash) {
+                        Some(song) => checksum(song)? == entry.checksum,
+                        None => false,
+                    };
+
+                    if !unchanged {
+                        wanted.push(entry.hash);
+                    }
+                }
+
+                let songs = if wanted.is_empty() {
+                    vec![]
+                } else {
+                    let request = HttpTransport::post(
+                        client.clone(),
+                        format!("{address}/songs"),
+                        credentials,
+                        rmp_serde::to_vec(&wanted).into_diagnostic()?,
+                    );
+
+                    download(&request, &mut progress)
+                        .await
+                        .map_err(|e| miette!("Couldn't download songs: {:?}", e))?
+                };
+
+                (manifest.iter().map(|v| v.hash).collect(), songs)
+            }
+        };
+
+    Ok((local, remote, songs))
+}
+
+/// Downloads a messagepack list, decoding entries as they arrive instead of buffering
+/// the whole response. Dropped connections are resumed where they stopped, like streamed songs.
+async fn download<T: Transport, E: DeserializeOwned>(
+    transport: &T,
+    mut progress: impl FnMut(usize, usize),
+) -> Result<Vec<E>, FetchError> {
+    let mut decoder = EntryDecoder::default();
+    let mut failures = 0;
+
+    loop {
+        let offset = decoder.received;
+
+        let error = match receive(transport, &mut decoder, &mut progress).await {
+            Ok(()) => return Ok(decoder.entries),
+            Err(e) => e,
+        };
+
+        // Only count failures in a row, like streaming does
+        if decoder.received > offset {
+            failures = 0;
+        }
+        failures += 1;
+
+        let wait = match error {
+            FetchError::Throttled(retry_after) if failures < MAX_ATTEMPTS => {
+                retry_after.unwrap_or(DEFAULT_RETRY_AFTER)
+            }
+            FetchError::Dropped(_) if failures < MAX_ATTEMPTS => backoff(failures),
+            e => return Err(e),
+        };
+
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// Receives one response into the decoder. Returns once the list is complete or the request failed.
+async fn receive<T: Transport, E: DeserializeOwned>(
+    transport: &T,
+    decoder: &mut EntryDecoder<E>,
+    progress: &mut impl FnMut(usize, usize),
+) -> Result<(), FetchError> {
+    let mut response = transport.open(decoder.received).await?;
+
+    // The response starts at the beginning, so skip what was already received
+    let mut skip = if response.partial {
+        0
+    } else {
+        decoder.received
+    };
+
+    while let Some(chunk) = response.body.chunk().await? {
+        let skipped = (skip as usize).min(chunk.len());
+        skip -= skipped as u64;
+
+        decoder
+            .push(&chunk[skipped..])
+            .map_err(|e| FetchError::Fatal(e.to_string()))?;
+
+        if let Some(total) = decoder.total {
+            progress(decoder.entries.len(), total);
+        }
+    }
+
+    if decoder.is_complete() {
+        Ok(())
+    } else {
+        Err(FetchError::Dropped("Connection closed early".into()))
+    }
+}
+
+/// Decodes the entries of a messagepack list as its bytes arrive
+struct EntryDecoder<E> {
+    /// Bytes of an entry that hasn't been received completely yet
+    pending: Vec<u8>,
+    /// Bytes received so far, including decoded ones
+    received: u64,
+    /// Length of the list, once its header was received
+    total: Option<usize>,
+    entries: Vec<E>,
+}
+
+impl<E> Default for EntryDecoder<E> {
+    fn default() -> Self {
+        EntryDecoder {
+            pending: vec![],
+            received: 0,
+            total: None,
+            entries: vec![],
+        }
+    }
+}
+
+impl<E: DeserializeOwned> EntryDecoder<E> {
+    fn push(&mut self, chunk: &[u8]) -> Result<()> {
+        self.pending.extend_from_slice(chunk);
+        self.received += chunk.len() as u64;
+
+        let mut input = &self.pending[..];
+
+        loop {
+            let total = match self.total {
+                Some(total) => total,
+                None => match list_len(&mut input)? {
+                    Some(total) => {
+                        self.total = Some(total);
+                        self.entries.reserve(total);
+                        total
+                    }
+                    None => break,
+                },
+            };
+
+            if self.entries.len() == total {
+                break;
+            }
+
+            // Decoding is retried from the start of the entry once more bytes arrived
+            let mut rest = input;
+            match E::deserialize(&mut rmp_serde::Deserializer::new(&mut rest)) {
+                Ok(entry) => {
+                    self.entries.push(entry);
+                    input = rest;
+                }
+                Err(e) if is_incomplete(&e) => break,
+                Err(e) => return Err(e).into_diagnostic(),
+            }
+        }
+
+        let decoded = self.pending.len() - input.len();
+        self.pending.drain(..decoded);
+
+        Ok(())
+    }
+
+    fn is_complete(&self) -> bool {
+        self.total == Some(self.entries.len())
+    }
+}
+
+/// Reads the header of a messagepack list, or returns `None` if it wasn't received completely
+fn list_len(input: &mut &[u8]) -> Result<Option<usize>> {
+    let Some(&marker) = input.first() else {
+        return Ok(None);
+    };
+
+    let (len, size) = match marker {
+        0x90..=0x9f => (usize::from(marker & 0x0f), 1),
+        0xdc if input.len() >= 3 => (u16::from_be_bytes([input[1], input[2]]).into(), 3),
+        0xdd if input.len() >= 5 => (
+            u32::from_be_bytes([input[1], input[2], input[3], input[4]]) as usize,
+            5,
+        ),
+        0xdc | 0xdd => return Ok(None),
+        _ => bail!("Expected a list, got marker {:#x}", marker),
+    };
+
+    *input = &input[size..];
+
+    Ok(Some(len))
+}
+
+fn is_incomplete(error: &rmp_serde::decode::Error) -> bool {
+    match error {
+        rmp_serde::decode::Error::InvalidMarkerRead(e)
+        | rmp_serde::decode::Error::InvalidDataRead(e) => e.kind() == ErrorKind::UnexpectedEof,
+        _ => false,
+    }
+}
+
+/// Replaces the rows of a source with a full listing of its songs,
+/// for servers that don't know about checksums
+pub async fn sync_songs(
+    source: &Source,
+    songs: Vec<library::Model>,
+    config: &Config,
+    db: &DatabaseConnection,
+) -> Result<SyncStats> {
+    let local = library::Entity::find()
+        .filter(Column::SourceId.eq(source.id))
+        .all(db)
+        .await
+        .into_diagnostic()?
+        .into_iter()
+        .map(|v| (v.hash, v))
+        .collect();
+
+    let remote = songs.iter().map(|v| v.hash).collect();
+
+    apply(source, local, remote, songs, config, db).await
+}
+
+/// Reports what `sync_songs` would change, without changing anything
+pub async fn preview_songs(
+    source: &Source,
+    songs: Vec<library::Model>,
+    db: &DatabaseConnection,
+) -> Result<SyncReport> {
+    let local = library::Entity::find()
+        .filter(Column::SourceId.eq(source.id))
+        .all(db)
+        .await
+        .into_diagnostic()?
+        .into_iter()
+        .map(|v| (v.hash, v))
+        .collect();
+
+    let remote = songs.iter().map(|v| v.hash).collect();
+
+    preview(source, local, remote, songs, db).await
+}
+
+/// What `apply` would do. Songs with a legacy hash are moved to their new one first,
+/// like in `apply`, then the transaction is rolled back.
+async fn preview(
+    source: &Source,
+    local: HashMap<i64, library::Model>,
+    remote: HashSet<i64>,
+    songs: Vec<library::Model>,
+    db: &DatabaseConnection,
+) -> Result<SyncReport> {
+    let txn = db.begin().await.into_diagnostic()?;
+    let local = rehash_legacy(source, local, &remote, &songs, &txn).await?;
+    txn.rollback().await.into_diagnostic()?;
+
+    plan(local, &remote, songs)
+}
+
+/// Sorts the songs of a sync into what's added, updated and removed
+fn plan(
+    local: HashMap<i64, library::Model>,
+    remote: &HashSet<i64>,
+    songs: Vec<library::Model>,
+) -> Result<SyncReport> {
+    let mut report = SyncReport::default();
+
+    for song in songs {
+        match local.get(&song.hash) {
+            Some(existing) if checksum(existing)? == checksum(&song)? => {}
+            Some(existing) => report.updated.push((existing.clone(), song)),
+            None => report.added.push(song),
+        }
+    }
+
+    report.removed = local
+        .into_values()
+        .filter(|v| !remote.contains(&v.hash))
+        .collect();
+
+    Ok(report)
+}
+
+/// Removes rows no longer in `remote`, then updates or adds the rows of `songs`.
+/// Everything happens in one transaction, so an interrupted sync leaves the source as it was.
+async fn apply(
+    source: &Source,
+    local: HashMap<i64, library::Model>,
+    remote: HashSet<i64>,
+    songs: Vec<library::Model>,
+    config: &Config,
+    db: &DatabaseConnection,
+) -> Result<SyncStats> {
+    let started = Instant::now();
+    let mut stats = SyncStats::default();
+
+    let txn = db.begin().await.into_diagnostic()?;
+
+    let local = rehash_legacy(source, local, &remote, &songs, &txn).await?;
+    let report = plan(local, &remote, songs)?;
+
+    let removed: Vec<i64> = report.removed.iter().map(|v| v.hash).collect();
+
+    for batch in removed.chunks(BATCH_SIZE) {
+        stats.removed += library::Entity::delete_many()
+            .filter(Column::SourceId.eq(source.id))
+            .filter(Column::Hash.is_in(batch.iter().copied()))
+            .exec(&txn)
+            .await
+            .into_diagnostic()?
+            .rows_affected as usize;
+    }
+
+    for (existing, song) in report.updated {
+        if existing.genres != song.genres {
+            link_song(song.hash, song.genres.as_deref(), &txn).await?;
+        }
+
+        if existing.artist != song.artist {
+            let separators = &config.artist_separators;
+            link_song_artists(song.hash, song.artist.as_deref(), separators, &txn).await?;
+        }
+
+        library::Entity::update(library::ActiveModel {
+            id: Set(existing.id),
+            ..to_active_model(song, source.id)
+        })
+        .exec(&txn)
+        .await
+        .into_diagnostic()?;
+
+        stats.updated += 1;
+    }
+
+    let added_date = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .into_diagnostic()?
+        .as_secs() as i64;
+
+    // Songs already in another source move here if this source comes first,
+    // otherwise they keep that source's row
+    for batch in report.added.chunks(BATCH_SIZE) {
+        let taken: HashSet<i64> = library::Entity::find()
+            .filter(Column::Hash.is_in(batch.iter().map(|v| v.hash)))
+            .filter(Column::SourceId.ne(source.id))
+            .all(&txn)
+            .await
+            .into_diagnostic()?
+            .into_iter()
+            .map(|v| v.hash)
+            .collect();
+
+        if taken.is_empty() {
+            continue;
+        }
+
+        for song in batch.iter().filter(|v| taken.contains(&v.hash)) {
+            let copy = to_active_model(song.clone(), source.id);
+            claim(&copy, song.hash, source.id, config, &txn).await?;
+        }
+    }
+
+    let mut added: Vec<_> = report
+        .added
+        .into_iter()
+        .map(|song| library::ActiveModel {
+            added_date: Set(Some(added_date)),
+            ..to_active_model(song, source.id)
+        })
+        .collect();
+
+    // Genres and artists of new songs, linked once their rows are in.
+    // Songs that stayed in another source are left alone, along with their links.
+    let mut unlinked = vec![];
+    while !added.is_empty() {
+        let batch: Vec<_> = added.drain(..added.len().min(BATCH_SIZE)).collect();
+
+        let hashes: Vec<i64> = batch.iter().map(|v| *v.hash.as_ref()).collect();
+        let taken: HashSet<i64> = library::Entity::find()
+            .filter(Column::Hash.is_in(hashes))
+            .all(&txn)
+            .await
+            .into_diagnostic()?
+            .into_iter()
+            .map(|v| v.hash)
+            .collect();
+        unlinked.extend(
+            batch
+                .iter()
+                .filter(|v| !taken.contains(v.hash.as_ref()))
+                .map(|v| {
+                    (
+                        *v.hash.as_ref(),
+                        v.genres.as_ref().clone(),
+                        v.artist.as_ref().clone(),
+                    )
+                }),
+        );
+
+        library::Entity::insert_many(batch)
+            .on_conflict(
+                sea_query::OnConflict::column(Column::Hash)
+                    .do_nothing()
+                    .to_owned(),
+            )
+            .exec(&txn)
+            .await
+            .into_diagnostic()?;
+    }
+
+    // Songs that stayed in another source weren't inserted, so they aren't counted
+    stats.added = unlinked.len();
+
+    for (hash, genres, artist) in unlinked {
+        link_song(hash, genres.as_deref(), &txn).await?;
+        link_song_artists(hash, artist.as_deref(), &config.artist_separators, &txn).await?;
+    }
+
+    txn.commit().await.into_diagnostic()?;
+
+    let rows = stats.added + stats.updated + stats.removed;
+    record_rows("sync", rows as u64, started.elapsed());
+
+    Ok(stats)
+}
+
+/// Moves songs still stored under the Adler-32 hash of their audio to the hash the server
+/// now sends for the same file, so their plays and playlist entries are kept
+async fn rehash_legacy(
+    source: &Source,
+    mut local: HashMap<i64, library::Model>,
+    remote: &HashSet<i64>,
+    songs: &[library::Model],
+    txn: &DatabaseTransaction,
+) -> Result<HashMap<i64, library::Model>> {
+    let legacy = legacy_songs(source.id, txn).await?;
+
+    for song in songs {
+        if local.contains_key(&song.hash) {
+            continue;
+        }
+
+        let Some(&old) = legacy.get(&(song.path.clone(), song.filename.clone())) else {
+            continue;
+        };
+
+        // Servers that weren't upgraded still send the old hash
+        if remote.contains(&old) {
+            continue;
+        }
+
+        if !remap(old, song.hash, txn).await? {
+            continue;
+        }
+
+        if let Some(mut existing) = local.remove(&old) {
+            move_download(&existing, song.hash)?;
+
+            existing.hash = song.hash;
+            local.insert(song.hash, existing);
+        }
+    }
+
+    Ok(local)
+}
+
+/// Uses all fields except for id, source_id and the play times, which come from the local history,
+/// the date the song was added, which is when it was first synced,
+/// and the size and modification time of the file, which only the server can check
+fn to_active_model(song: library::Model, source_id: u8) -> library::ActiveModel {
+    library::ActiveModel {
+        path: Set(song.path),
+        filename: Set(song.filename),
+        source_id: Set(source_id.into()), // Use local source id, not remote
+        hash: Set(song.hash),
+        artist: Set(song.artist),
+        album_artist: Set(song.album_artist),
+        name: Set(song.name),
+        album: Set(song.album),
+        genres: Set(song.genres),
+        track: Set(song.track),
+        year: Set(song.year),
+        duration: Set(song.duration),
+        disc: Set(song.disc),
+        rg_track_gain: Set(song.rg_track_gain),
+        rg_track_peak: Set(song.rg_track_peak),
+        rg_album_gain: Set(song.rg_album_gain),
+        rg_album_peak: Set(song.rg_album_peak),
+        compilation: Set(song.compilation),
+        channel_layout: Set(song.channel_layout),
+        codec: Set(song.codec),
+        bitrate: Set(song.bitrate),
+        sample_rate: Set(song.sample_rate),
+        bit_depth: Set(song.bit_depth),
+        channels: Set(song.channels),
+        composer: Set(song.composer),
+        comment: Set(song.comment),
+        extra_tags: Set(song.extra_tags),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{
+        config::SourceKind,
+        model::{legacy_hashes, song_genres},
+        test_util::{self, memory_db},
+    };
+    use sea_orm::{
+        ActiveValue::NotSet, ConnectionTrait, IntoActiveModel, PaginatorTrait, Statement,
+    };
+
+    fn source() -> Source {
+        Source {
+            id: 1,
+            name: "Server".into(),
+            source: SourceKind::Remote {
+                address: "http://localhost".into(),
+            },
+            proxy: None,
+            headers: HashMap::new(),
+            token: None,
+            tls: Default::default(),
+            settings: Default::default(),
+        }
+    }
+
+    fn song(hash: i64, genres: &str) -> library::Model {
+        library::Model {
+            genres: Some(genres.into()),
+            ..test_util::song(hash)
+        }
+    }
+
+    async fn genres_of(hash: i64, db: &DatabaseConnection) -> Option<String> {
+        library::Entity::find()
+            .filter(Column::Hash.eq(hash))
+            .one(db)
+            .await
+            .unwrap()
+            .and_then(|v| v.genres)
+    }
+
+    #[test]
+    fn checksum_only_covers_metadata() {
+        let song = song(1, "Rock");
+        let expected = checksum(&song).unwrap();
+
+        let elsewhere = library::Model {
+            id: 7,
+            source_id: 2,
+            first_played: Some(1),
+            added_date: Some(2),
+            file_size: Some(3),
+            ..song.clone()
+        };
+        assert_eq!(checksum(&elsewhere).unwrap(), expected);
+
+        let retagged = library::Model {
+            genres: Some("Jazz".into()),
+            ..song
+        };
+        assert_ne!(checksum(&retagged).unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn songs_kept_by_other_sources_are_not_added() {
+        let db = memory_db().await;
+
+        // Source 0 comes first, so it keeps its copy
+        let mut other = library::Model {
+            source_id: 0,
+            ..song(3, "Rock")
+        }
+        .into_active_model();
+        other.id = NotSet;
+        library::Entity::insert(other).exec(&db).await.unwrap();
+
+        let stats = sync_songs(
+            &source(),
+            vec![song(3, "Rock"), song(4, "Jazz")],
+            &Config::default(),
+            &db,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(stats.added, 1);
+        assert_eq!(library::Entity::find().count(&db).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn sync_applies_every_change() {
+        let db = memory_db().await;
+        let source = source();
+
+        sync_songs(
+            &source,
+            vec![song(1, "Rock"), song(2, "Jazz")],
+            &Config::default(),
+            &db,
+        )
+        .await
+        .unwrap();
+
+        let stats = sync_songs(&source, vec![song(2, "Blues")], &Config::default(), &db)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            stats,
+            SyncStats {
+                added: 0,
+                updated: 1,
+                removed: 1
+            }
+        );
+        assert_eq!(library::Entity::find().count(&db).await.unwrap(), 1);
+        assert_eq!(genres_of(2, &db).await.as_deref(), Some("Blues"));
+        assert_eq!(
+            song_genres::Entity::find()
+                .filter(song_genres::Column::SongHash.eq(2))
+                .count(&db)
+                .await
+                .unwrap(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn preview_changes_nothing() {
+        let db = memory_db().await;
+        let source = source();
+
+        sync_songs(
+            &source,
+            vec![song(1, "Rock"), song(2, "Jazz")],
+            &Config::default(),
+            &db,
+        )
+        .await
+        .unwrap();
+
+        let report = preview_songs(&source, vec![song(2, "Blues"), song(3, "Pop")], &db)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            report.stats(),
+            SyncStats {
+                added: 1,
+                updated: 1,
+                removed: 1
+            }
+        );
+        assert_eq!(report.removed[0].hash, 1);
+        assert_eq!(report.updated[0].0.genres.as_deref(), Some("Jazz"));
+        assert_eq!(library::Entity::find().count(&db).await.unwrap(), 2);
+        assert_eq!(genres_of(2, &db).await.as_deref(), Some("Jazz"));
+    }
+
+    #[tokio::test]
+    async fn failed_sync_is_rolled_back() {
+        let db = memory_db().await;
+        let source = source();
+
+        sync_songs(
+            &source,
+            vec![song(1, "Rock"), song(2, "Jazz")],
+            &Config::default(),
+            &db,
+        )
+        .await
+        .unwrap();
+
+        // Linking the changed genre fails after the removal already went through
+        db.execute(Statement::from_string(
+            db.get_database_backend(),
+            "DROP TABLE song_genres".into(),
+        ))
+        .await
+        .unwrap();
+
+        assert!(
+            sync_songs(&source, vec![song(2, "Blues")], &Config::default(), &db)
+                .await
+                .is_err()
+        );
+
+        assert_eq!(library::Entity::find().count(&db).await.unwrap(), 2);
+        assert_eq!(genres_of(2, &db).await.as_deref(), Some("Jazz"));
+    }
+
+    #[tokio::test]
+    async fn legacy_hashes_are_moved_to_new_ones() {
+        let db = memory_db().await;
+        let source = source();
+
+        sync_songs(&source, vec![song(1, "Rock")], &Config::default(), &db)
+            .await
+            .unwrap();
+        db.execute(Statement::from_string(
+            db.get_database_backend(),
+            "INSERT INTO legacy_hashes (song_hash) VALUES (1)".into(),
+        ))
+        .await
+        .unwrap();
+
+        // The server sends the same file under its new hash
+        let stats = sync_songs(
+            &source,
+            vec![library::Model {
+                hash: 1 << 40,
+                ..song(1, "Rock")
+            }],
+            &Config::default(),
+            &db,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(stats.removed, 0);
+        assert_eq!(library::Entity::find().count(&db).await.unwrap(), 1);
+        assert_eq!(genres_of(1 << 40, &db).await.as_deref(), Some("Rock"));
+        assert_eq!(
+            song_genres::Entity::find()
+                .filter(song_genres::Column::SongHash.eq(1_i64 << 40))
+                .count(&db)
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(legacy_hashes::Entity::find().count(&db).await.unwrap(), 0);
+    }
+
+    #[test]
+    fn entries_are_decoded_across_chunks() {
+        let songs: Vec<_> = (0..20).map(|v| song(v, "Rock")).collect();
+        let data = rmp_serde::to_vec(&songs).unwrap();
+
+        for size in [1, 7, 64, data.len()] {
+            let mut decoder = EntryDecoder::<library::Model>::default();
+
+            for chunk in data.chunks(size) {
+                assert!(!decoder.is_complete());
+                decoder.push(chunk).unwrap();
+            }
+
+            assert!(decoder.is_complete());
+            assert!(decoder.pending.is_empty());
+            assert_eq!(decoder.entries, songs);
+        }
+    }
+}
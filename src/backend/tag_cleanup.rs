@@ -0,0 +1,183 @@
+use super::{
+    config::{Config, TagCleanup},
+    model::{library, sea_orm_active_enums::TagField},
+    tagging::{write_many_tags, TagEdit},
+};
+use miette::{IntoDiagnostic, Result};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+
+/// Ways of writing "featuring", which are all written as "feat."
+const FEATURING: [&str; 5] = ["ft", "ft.", "feat", "feat.", "featuring"];
+/// Markers removed from titles and albums, compared case insensitively
+const EXPLICIT: [&str; 4] = [
+    "[explicit]",
+    "(explicit)",
+    "[explicit version]",
+    "(explicit version)",
+];
+/// Words that stay lower case in title case, unless they start or end a title
+const SMALL_WORDS: [&str; 14] = [
+    "a", "an", "and", "as", "at", "by", "for", "in", "of", "on", "or", "the", "to", "vs.",
+];
+
+/// Fixes the cleanup rules would make to a song's tags, e.g. to suggest them in the tag editor.
+/// The edit is empty if there's nothing to fix.
+pub fn suggest(song: &library::Model, rules: &TagCleanup) -> TagEdit {
+    let mut edit = TagEdit::default();
+
+    let fields = [
+        (TagField::Artist, &song.artist, &mut edit.artist),
+        (
+            TagField::AlbumArtist,
+            &song.album_artist,
+            &mut edit.album_artist,
+        ),
+        (TagField::Title, &song.name, &mut edit.title),
+        (TagField::Album, &song.album, &mut edit.album),
+        (TagField::Genre, &song.genres, &mut edit.genre),
+    ];
+
+    for (field, value, fixed) in fields {
+        let Some(value) = value else {
+            continue;
+        };
+
+        let cleaned = clean(value, field, rules);
+
+        if cleaned.is_empty() {
+            edit.clear.push(field);
+        } else if cleaned != *value {
+            *fixed = Some(cleaned);
+        }
+    }
+
+    edit
+}
+
+/// Songs in local sources whose tags the configured rules would change, along with the fixes,
+/// so they can be reviewed before running `apply`
+pub async fn preview(db: &DatabaseConnection) -> Result<Vec<(library::Model, TagEdit)>> {
+    let config = Config::read_config()?;
+
+    Ok(library::Entity::find()
+        .filter(library::Column::SourceId.is_in(config.local_source_ids()))
+        .all(db)
+        .await
+        .into_diagnostic()?
+        .into_iter()
+        .map(|song| {
+            let edit = suggest(&song, &config.tag_cleanup);
+            (song, edit)
+        })
+        .filter(|(_, edit)| !edit.is_empty())
+        .collect())
+}
+
+/// Fixes the tags of every song in local sources. Returns the batch of the edit log
+/// the fixes can be undone with, or `None` if there was nothing to fix.
+pub async fn apply(db: &DatabaseConnection) -> Result<Option<i32>> {
+    let edits: Vec<_> = preview(db)
+        .await?
+        .into_iter()
+        .map(|(song, edit)| (song.hash, edit))
+        .collect();
+
+    if edits.is_empty() {
+        return Ok(None);
+    }
+
+    write_many_tags(&edits, db).await.map(Some)
+}
+
+fn clean(value: &str, field: TagField, rules: &TagCleanup) -> String {
+    let mut value = value.to_string();
+
+    if rules.strip_explicit && matches!(field, TagField::Title | TagField::Album) {
+        value = strip_explicit(&value);
+    }
+
+    if rules.normalize_featuring
+        && matches!(
+            field,
+            TagField::Artist | TagField::AlbumArtist | TagField::Title
+        )
+    {
+        value = normalize_featuring(&value);
+    }
+
+    if rules.title_case && matches!(field, TagField::Title | TagField::Album) {
+        value = title_case(&value);
+    }
+
+    if rules.trim_whitespace {
+        value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+    }
+
+    value
+}
+
+fn strip_explicit(value: &str) -> String {
+    let mut value = value.to_string();
+
+    for marker in EXPLICIT {
+        // Lowercasing ASCII keeps byte offsets the same
+        while let Some(start) = value.to_ascii_lowercase().find(marker) {
+            value.replace_range(start..start + marker.len(), "");
+        }
+    }
+
+    value.trim_end().to_string()
+}
+
+fn normalize_featuring(value: &str) -> String {
+    value
+        .split(' ')
+        .map(|word| {
+            let rest = word.trim_start_matches(['(', '[']);
+            let prefix = &word[..word.len() - rest.len()];
+
+            if FEATURING.contains(&rest.to_lowercase().as_str()) {
+                format!("{prefix}feat.")
+            } else {
+                word.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Only changes values written entirely in lower or upper case,
+/// since anything else was probably capitalized on purpose, like "iTunes Originals"
+fn title_case(value: &str) -> String {
+    let has_lower = value.chars().any(char::is_lowercase);
+    let has_upper = value.chars().any(char::is_uppercase);
+
+    if has_lower == has_upper {
+        return value.to_string();
+    }
+
+    let words: Vec<&str> = value.split(' ').collect();
+    let last = words.len() - 1;
+
+    words
+        .iter()
+        .enumerate()
+        .map(|(index, word)| {
+            let word = word.to_lowercase();
+
+            if index != 0 && index != last && SMALL_WORDS.contains(&word.as_str()) {
+                return word;
+            }
+
+            // Skip punctuation like the opening bracket of "(live)"
+            match word.char_indices().find(|(_, c)| c.is_alphanumeric()) {
+                Some((start, c)) => {
+                    let end = start + c.len_utf8();
+                    format!("{}{}{}", &word[..start], c.to_uppercase(), &word[end..])
+                }
+                None => word,
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,431 @@
+use std::{
+    collections::BTreeMap,
+    io::Cursor,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+    artists::link_song_artists,
+    config::Config,
+    genres::link_song,
+    model::{library, sea_orm_active_enums::TagField, tag_edits},
+    utils::song_path,
+};
+use lofty::{read_from_path, Accessor, ItemKey, Picture, PictureType, Tag};
+use miette::{miette, IntoDiagnostic, Result};
+use paris::success;
+use sea_orm::{
+    ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, Set,
+    TransactionTrait,
+};
+
+/// Changes to a song's metadata. Fields left as `None` are not modified.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TagEdit {
+    pub artist: Option<String>,
+    pub album_artist: Option<String>,
+    pub title: Option<String>,
+    pub album: Option<String>,
+    pub track: Option<u32>,
+    pub year: Option<u32>,
+    pub genre: Option<String>,
+    /// Image data for the front cover
+    pub art: Option<Vec<u8>>,
+    /// Fields removed from the tag
+    pub clear: Vec<TagField>,
+}
+
+impl TagEdit {
+    /// Whether the edit changes anything stored in the library
+    fn changes_row(&self) -> bool {
+        self.artist.is_some()
+            || self.album_artist.is_some()
+            || self.title.is_some()
+            || self.album.is_some()
+            || self.track.is_some()
+            || self.year.is_some()
+            || self.genre.is_some()
+            || !self.clear.is_empty()
+    }
+
+    /// Whether the edit changes nothing at all
+    pub fn is_empty(&self) -> bool {
+        !self.changes_row() && self.art.is_none()
+    }
+
+    /// Whether the edit only makes sense for a single track
+    fn is_per_track(&self) -> bool {
+        self.title.is_some()
+            || self.track.is_some()
+            || self
+                .clear
+                .iter()
+                .any(|v| matches!(v, TagField::Title | TagField::Track))
+    }
+
+    /// Sets a field from its text form, as stored in the edit log
+    fn set(&mut self, field: TagField, value: Option<String>) {
+        let Some(value) = value else {
+            self.clear.push(field);
+            return;
+        };
+
+        match field {
+            TagField::Artist => self.artist = Some(value),
+            TagField::AlbumArtist => self.album_artist = Some(value),
+            TagField::Title => self.title = Some(value),
+            TagField::Album => self.album = Some(value),
+            TagField::Track => self.track = value.parse().ok(),
+            TagField::Year => self.year = value.parse().ok(),
+            TagField::Genre => self.genre = Some(value),
+        }
+    }
+
+    /// New value of every field the edit changes, `None` if it's removed
+    fn changes(&self) -> Vec<(TagField, Option<String>)> {
+        let mut changes: Vec<_> = [
+            (TagField::Artist, self.artist.clone()),
+            (TagField::AlbumArtist, self.album_artist.clone()),
+            (TagField::Title, self.title.clone()),
+            (TagField::Album, self.album.clone()),
+            (TagField::Track, self.track.map(|v| v.to_string())),
+            (TagField::Year, self.year.map(|v| v.to_string())),
+            (TagField::Genre, self.genre.clone()),
+        ]
+        .into_iter()
+        .filter_map(|(field, value)| Some((field, Some(value?))))
+        .collect();
+
+        changes.extend(self.clear.iter().map(|v| (*v, None)));
+        changes
+    }
+
+    fn apply(&self, tag: &mut Tag) -> Result<()> {
+        if let Some(artist) = &self.artist {
+            tag.set_artist(artist.clone());
+        }
+        if let Some(album_artist) = &self.album_artist {
+            tag.insert_text(lofty::ItemKey::AlbumArtist, album_artist.clone());
+        }
+        if let Some(title) = &self.title {
+            tag.set_title(title.clone());
+        }
+        if let Some(album) = &self.album {
+            tag.set_album(album.clone());
+        }
+        if let Some(track) = self.track {
+            tag.set_track(track);
+        }
+        if let Some(year) = self.year {
+            tag.set_year(year);
+        }
+        if let Some(genre) = &self.genre {
+            tag.set_genre(genre.clone());
+        }
+        if let Some(art) = &self.art {
+            let mut picture = Picture::from_reader(&mut Cursor::new(art)).into_diagnostic()?;
+            picture.set_pic_type(PictureType::CoverFront);
+
+            tag.remove_picture_type(PictureType::CoverFront);
+            tag.push_picture(picture);
+        }
+
+        for field in &self.clear {
+            tag.remove_key(&match field {
+                TagField::Artist => ItemKey::TrackArtist,
+                TagField::AlbumArtist => ItemKey::AlbumArtist,
+                TagField::Title => ItemKey::TrackTitle,
+                TagField::Album => ItemKey::AlbumTitle,
+                TagField::Track => ItemKey::TrackNumber,
+                TagField::Year => ItemKey::Year,
+                TagField::Genre => ItemKey::Genre,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn apply_to_row(&self, song: &library::Model) -> library::ActiveModel {
+        let mut row = library::ActiveModel {
+            id: Set(song.id),
+            ..Default::default()
+        };
+
+        if let Some(artist) = &self.artist {
+            row.artist = Set(Some(artist.clone()));
+        }
+        if let Some(album_artist) = &self.album_artist {
+            row.album_artist = Set(Some(album_artist.clone()));
+        }
+        if let Some(title) = &self.title {
+            row.name = Set(Some(title.clone()));
+        }
+        if let Some(album) = &self.album {
+            row.album = Set(Some(album.clone()));
+        }
+        if let Some(track) = self.track {
+            row.track = Set(Some(track as i32));
+        }
+        if let Some(year) = self.year {
+            row.year = Set(Some(year as i32));
+        }
+        if let Some(genre) = &self.genre {
+            row.genres = Set(Some(genre.clone()));
+        }
+
+        for field in &self.clear {
+            match field {
+                TagField::Artist => row.artist = Set(None),
+                TagField::AlbumArtist => row.album_artist = Set(None),
+                TagField::Title => row.name = Set(None),
+                TagField::Album => row.album = Set(None),
+                TagField::Track => row.track = Set(None),
+                TagField::Year => row.year = Set(None),
+                TagField::Genre => row.genres = Set(None),
+            }
+        }
+
+        row
+    }
+}
+
+/// Current value of a field in the library, in the same form as in the edit log
+fn current_value(song: &library::Model, field: TagField) -> Option<String> {
+    match field {
+        TagField::Artist => song.artist.clone(),
+        TagField::AlbumArtist => song.album_artist.clone(),
+        TagField::Title => song.name.clone(),
+        TagField::Album => song.album.clone(),
+        TagField::Track => song.track.map(|v| v.to_string()),
+        TagField::Year => song.year.map(|v| v.to_string()),
+        TagField::Genre => song.genres.clone(),
+    }
+}
+
+/// Writes new metadata to a song's file and updates its library row to match.
+/// The song hash only covers audio data, so it stays valid and nothing is re-analyzed.
+/// Returns the batch of the edit log the change can be undone with.
+pub async fn write_tags(hash: i64, edit: &TagEdit, db: &DatabaseConnection) -> Result<i32> {
+    let song = find_song(hash, db).await?;
+    let batch = next_batch(db).await?;
+
+    write_song_tags(
+        &song,
+        edit,
+        &Config::read_config()?.local_source_ids(),
+        Some(batch),
+        db,
+    )
+    .await?;
+
+    success!("Updated tags of {}", song.filename);
+    Ok(batch)
+}
+
+/// Writes different edits to several songs, e.g. suggested by `tag_cleanup`.
+/// They're logged as one batch, so they can be undone together.
+pub async fn write_many_tags(edits: &[(i64, TagEdit)], db: &DatabaseConnection) -> Result<i32> {
+    let sources = Config::read_config()?.local_source_ids();
+    let batch = next_batch(db).await?;
+
+    for (hash, edit) in edits {
+        write_song_tags(
+            &find_song(*hash, db).await?,
+            edit,
+            &sources,
+            Some(batch),
+            db,
+        )
+        .await?;
+    }
+
+    success!("Updated tags of {} songs", edits.len());
+    Ok(batch)
+}
+
+/// Applies the same edit to every song of an album, e.g. to fix its name or add cover art.
+/// Returns the batch of the edit log the change can be undone with.
+pub async fn write_album_tags(
+    album: &str,
+    album_artist: Option<&str>,
+    edit: &TagEdit,
+    db: &DatabaseConnection,
+) -> Result<i32> {
+    miette::ensure!(
+        !edit.is_per_track(),
+        "Titles and track numbers can't be set for a whole album"
+    );
+
+    let songs = library::Entity::find()
+        .filter(library::Column::Album.eq(album))
+        .filter(match album_artist {
+            Some(album_artist) => library::Column::AlbumArtist.eq(album_artist),
+            None => library::Column::AlbumArtist.is_null(),
+        })
+        .all(db)
+        .await
+        .into_diagnostic()?;
+
+    let sources = Config::read_config()?.local_source_ids();
+    let batch = next_batch(db).await?;
+
+    for song in &songs {
+        write_song_tags(song, edit, &sources, Some(batch), db).await?;
+    }
+
+    success!("Updated tags of {} songs in {album}", songs.len());
+    Ok(batch)
+}
+
+/// Reverts the tags changed in a batch of the edit log, and removes the batch from it.
+/// Cover art isn't logged, so it stays as it is.
+pub async fn undo(batch: i32, db: &DatabaseConnection) -> Result<()> {
+    let entries = tag_edits::Entity::find()
+        .filter(tag_edits::Column::Batch.eq(batch))
+        .order_by_desc(tag_edits::Column::Id)
+        .all(db)
+        .await
+        .into_diagnostic()?;
+
+    miette::ensure!(!entries.is_empty(), "Edit {} does not exist", batch);
+
+    let mut edits: BTreeMap<i64, TagEdit> = BTreeMap::new();
+    for entry in entries {
+        edits
+            .entry(entry.song_hash)
+            .or_default()
+            .set(entry.field, entry.old_value);
+    }
+
+    let sources = Config::read_config()?.local_source_ids();
+
+    for (hash, edit) in &edits {
+        write_song_tags(&find_song(*hash, db).await?, edit, &sources, None, db).await?;
+    }
+
+    tag_edits::Entity::delete_many()
+        .filter(tag_edits::Column::Batch.eq(batch))
+        .exec(db)
+        .await
+        .into_diagnostic()?;
+
+    success!("Undid changes to {} songs", edits.len());
+    Ok(())
+}
+
+/// The newest batch of the edit log, which `undo` reverts first
+pub async fn last_batch(db: &DatabaseConnection) -> Result<Option<i32>> {
+    Ok(tag_edits::Entity::find()
+        .order_by_desc(tag_edits::Column::Batch)
+        .one(db)
+        .await
+        .into_diagnostic()?
+        .map(|v| v.batch))
+}
+
+async fn next_batch(db: &DatabaseConnection) -> Result<i32> {
+    Ok(last_batch(db).await?.map_or(0, |v| v + 1))
+}
+
+async fn find_song(hash: i64, db: &DatabaseConnection) -> Result<library::Model> {
+    library::Entity::find()
+        .filter(library::Column::Hash.eq(hash))
+        .one(db)
+        .await
+        .into_diagnostic()?
+        .ok_or(miette!("No song with hash {}", hash))
+}
+
+/// Writes an edit to a song, logging the fields it changed under `batch`
+async fn write_song_tags(
+    song: &library::Model,
+    edit: &TagEdit,
+    local_sources: &[i32],
+    batch: Option<i32>,
+    db: &DatabaseConnection,
+) -> Result<()> {
+    miette::ensure!(
+        local_sources.contains(&song.source_id),
+        "{} belongs to a remote source and can't be edited",
+        song.filename
+    );
+
+    let path = song_path(song);
+
+    let mut file = read_from_path(&path, false).into_diagnostic()?;
+
+    if file.primary_tag().is_none() {
+        file.insert_tag(Tag::new(file.primary_tag_type()));
+    }
+
+    edit.apply(
+        file.primary_tag_mut()
+            .ok_or(miette!("Couldn't create a tag for {}", path.display()))?,
+    )?;
+
+    file.save_to_path(&path).into_diagnostic()?;
+
+    // The log, the row and its links change together
+    let txn = db.begin().await.into_diagnostic()?;
+
+    if let Some(batch) = batch {
+        log_changes(song, edit, batch, &txn).await?;
+    }
+
+    if edit.changes_row() {
+        library::Entity::update(edit.apply_to_row(song))
+            .exec(&txn)
+            .await
+            .into_diagnostic()?;
+    }
+
+    if edit.genre.is_some() || edit.clear.contains(&TagField::Genre) {
+        link_song(song.hash, edit.genre.as_deref(), &txn).await?;
+    }
+
+    if edit.artist.is_some() || edit.clear.contains(&TagField::Artist) {
+        let separators = Config::read_config()?.artist_separators;
+        link_song_artists(song.hash, edit.artist.as_deref(), &separators, &txn).await?;
+    }
+
+    txn.commit().await.into_diagnostic()
+}
+
+async fn log_changes(
+    song: &library::Model,
+    edit: &TagEdit,
+    batch: i32,
+    db: &impl ConnectionTrait,
+) -> Result<()> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .into_diagnostic()?
+        .as_secs() as i64;
+
+    let entries: Vec<_> = edit
+        .changes()
+        .into_iter()
+        .filter_map(|(field, new_value)| {
+            let old_value = current_value(song, field);
+
+            (old_value != new_value).then_some(tag_edits::ActiveModel {
+                batch: Set(batch),
+                song_hash: Set(song.hash),
+                field: Set(field),
+                old_value: Set(old_value),
+                new_value: Set(new_value),
+                edited_at: Set(now),
+                ..Default::default()
+            })
+        })
+        .collect();
+
+    if !entries.is_empty() {
+        tag_edits::Entity::insert_many(entries)
+            .exec(db)
+            .await
+            .into_diagnostic()?;
+    }
+
+    Ok(())
+}
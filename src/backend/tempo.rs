@@ -0,0 +1,199 @@
+use std::ops::Range;
+
+pub const MIN_SPEED: f32 = 0.5;
+pub const MAX_SPEED: f32 = 3.0;
+
+/// Length of the pieces of audio that are stitched together when keeping the pitch
+const SEQUENCE_MS: u32 = 40;
+/// Length of the crossfade between pieces
+const OVERLAP_MS: u32 = 10;
+/// How far ahead the best matching start of the next piece is looked for
+const SEEK_MS: u32 = 15;
+
+/// Clamps a speed to the supported range. Anything that isn't a number plays at normal speed.
+pub fn clamp_speed(speed: f32) -> f32 {
+    if speed.is_finite() {
+        speed.clamp(MIN_SPEED, MAX_SPEED)
+    } else {
+        1.0
+    }
+}
+
+/// Changes the speed of interleaved audio. With pitch correction, the audio is cut into short
+/// pieces that are overlapped more or less depending on the speed, each placed where it lines up
+/// best with the previous one (WSOLA), so voices keep their pitch. Without it, the audio is
+/// resampled like a record played faster or slower.
+pub struct Tempo {
+    channels: usize,
+    speed: f32,
+    pitch_correction: bool,
+    /// In frames
+    sequence: usize,
+    overlap: usize,
+    seek: usize,
+    /// Input that hasn't been used yet
+    input: Vec<f32>,
+    /// End of the last piece, crossfaded with the start of the next one
+    tail: Vec<f32>,
+    /// Fraction of an input frame already skipped, since speeds rarely divide pieces evenly
+    position: f64,
+}
+
+impl Tempo {
+    pub fn new(sample_rate: u32, channels: usize, speed: f32, pitch_correction: bool) -> Self {
+        let frames = |ms: u32| (sample_rate * ms / 1000).max(1) as usize;
+
+        Tempo {
+            channels: channels.max(1),
+            speed: clamp_speed(speed),
+            pitch_correction,
+            sequence: frames(SEQUENCE_MS),
+            overlap: frames(OVERLAP_MS),
+            seek: frames(SEEK_MS),
+            input: vec![],
+            tail: vec![],
+            position: 0.0,
+        }
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    /// Takes effect with the next block. Audio held back for the previous speed is kept.
+    pub fn set_speed(&mut self, speed: f32, pitch_correction: bool) {
+        self.speed = clamp_speed(speed);
+        self.pitch_correction = pitch_correction;
+    }
+
+    pub fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+        // Audio held back is let through once the speed is back to normal
+        if self.speed == 1.0 {
+            self.tail.clear();
+            self.position = 0.0;
+
+            let mut output = std::mem::take(&mut self.input);
+            output.extend_from_slice(samples);
+
+            return output;
+        }
+
+        self.input.extend_from_slice(samples);
+
+        if self.pitch_correction {
+            self.stretch()
+        } else {
+            self.resample()
+        }
+    }
+
+    /// Returns what's left once the input ended
+    pub fn finish(&mut self) -> Vec<f32> {
+        let mut output = std::mem::take(&mut self.tail);
+
+        // Less than a piece is left, which is too short to be noticeably off in speed
+        if self.pitch_correction || self.speed == 1.0 {
+            output.append(&mut self.input);
+        } else {
+            self.input.clear();
+        }
+
+        self.position = 0.0;
+        output
+    }
+
+    fn stretch(&mut self) -> Vec<f32> {
+        let mut output = vec![];
+        // Fast speeds skip past the end of the piece
+        let window = (self.seek + self.sequence + self.overlap)
+            .max((self.sequence as f64 * self.speed as f64).ceil() as usize + 1);
+
+        while self.frames() >= window {
+            let offset = if self.tail.is_empty() {
+                0
+            } else {
+                self.best_offset()
+            };
+
+            let start = offset;
+            let middle = offset + self.overlap.min(self.tail.len() / self.channels);
+            let end = offset + self.sequence;
+
+            // Crossfade from the end of the last piece
+            let fade = self.samples(start..middle);
+            let frames = middle - start;
+            for frame in 0..frames {
+                let weight = frame as f32 / frames as f32;
+
+                for channel in 0..self.channels {
+                    let i = frame * self.channels + channel;
+                    let next = self.input[fade.start + i];
+                    output.push(self.tail[i] * (1.0 - weight) + next * weight);
+                }
+            }
+
+            output.extend_from_slice(&self.input[self.samples(middle..end)]);
+            self.tail = self.input[self.samples(end..end + self.overlap)].to_vec();
+
+            // The input moves on by the length of a piece scaled by the speed
+            self.position += self.sequence as f64 * self.speed as f64;
+            let consumed = self.position.floor() as usize;
+            self.position -= consumed as f64;
+
+            self.input.drain(..consumed * self.channels);
+        }
+
+        output
+    }
+
+    /// Start of the next piece within the seek window that's most similar to the last tail
+    fn best_offset(&self) -> usize {
+        let compared = self.samples(0..self.overlap).len().min(self.tail.len());
+
+        (0..self.seek)
+            .map(|offset| {
+                let start = offset * self.channels;
+                let candidate = &self.input[start..start + compared];
+
+                let correlation: f32 = candidate.iter().zip(&self.tail).map(|(a, b)| a * b).sum();
+                let energy: f32 = candidate.iter().map(|v| v * v).sum();
+
+                (offset, correlation / energy.sqrt().max(f32::EPSILON))
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map_or(0, |v| v.0)
+    }
+
+    /// Linear interpolation between input frames, stepping by the speed
+    fn resample(&mut self) -> Vec<f32> {
+        let mut output = vec![];
+
+        while self.position + 1.0 < self.frames() as f64 {
+            let frame = self.position.floor() as usize;
+            let weight = (self.position - frame as f64) as f32;
+
+            for channel in 0..self.channels {
+                let a = self.input[frame * self.channels + channel];
+                let b = self.input[(frame + 1) * self.channels + channel];
+                output.push(a + (b - a) * weight);
+            }
+
+            self.position += self.speed as f64;
+        }
+
+        let consumed = (self.position.floor() as usize).min(self.frames());
+        self.position -= consumed as f64;
+        self.input.drain(..consumed * self.channels);
+
+        output
+    }
+
+    fn frames(&self) -> usize {
+        self.input.len() / self.channels
+    }
+
+    /// Range of the samples of a range of frames
+    fn samples(&self, frames: Range<usize>) -> Range<usize> {
+        frames.start * self.channels..frames.end * self.channels
+    }
+}
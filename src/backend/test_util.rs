@@ -0,0 +1,56 @@
+//! Fixtures shared by the backend's tests
+
+use super::{model::library, prepare_db};
+use sea_orm::{ConnectOptions, Database, DatabaseConnection};
+
+/// Song `Album/{hash}.flac` of source 1, a second long and without any tags
+pub fn song(hash: i64) -> library::Model {
+    library::Model {
+        id: 0,
+        path: "Album".into(),
+        filename: format!("{hash}.flac"),
+        source_id: 1,
+        hash,
+        artist: None,
+        album_artist: None,
+        name: None,
+        album: None,
+        duration: 1000,
+        genres: None,
+        track: None,
+        year: None,
+        disc: None,
+        rg_track_gain: None,
+        rg_track_peak: None,
+        rg_album_gain: None,
+        rg_album_peak: None,
+        compilation: false,
+        channel_layout: None,
+        first_played: None,
+        last_played: None,
+        codec: None,
+        bitrate: None,
+        sample_rate: None,
+        bit_depth: None,
+        channels: None,
+        composer: None,
+        comment: None,
+        extra_tags: None,
+        added_date: None,
+        file_size: None,
+        file_modified: None,
+        hash_bytes: None,
+    }
+}
+
+/// An empty, migrated library that only lives in memory.
+/// It's kept to a single connection, since each one would open a database of its own.
+pub async fn memory_db() -> DatabaseConnection {
+    let mut options = ConnectOptions::new("sqlite::memory:".into());
+    options.max_connections(1);
+
+    let db = Database::connect(options).await.unwrap();
+    prepare_db(&db).await.unwrap();
+
+    db
+}
@@ -0,0 +1,170 @@
+use std::{fs::File, io::Write};
+
+use super::{model::library, utils::config_dir};
+use miette::{miette, IntoDiagnostic, Result};
+use serde::{Deserialize, Serialize};
+
+/// Views of the library that remember their own sorting
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum View {
+    Library,
+    Album,
+    Playlist,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortColumn {
+    Artist,
+    AlbumArtist,
+    Name,
+    Album,
+    Duration,
+    Genres,
+    Track,
+    Year,
+    FirstPlayed,
+    LastPlayed,
+    Codec,
+    Bitrate,
+    SampleRate,
+    Composer,
+    AddedDate,
+}
+
+impl From<SortColumn> for library::Column {
+    fn from(column: SortColumn) -> Self {
+        match column {
+            SortColumn::Artist => library::Column::Artist,
+            SortColumn::AlbumArtist => library::Column::AlbumArtist,
+            SortColumn::Name => library::Column::Name,
+            SortColumn::Album => library::Column::Album,
+            SortColumn::Duration => library::Column::Duration,
+            SortColumn::Genres => library::Column::Genres,
+            SortColumn::Track => library::Column::Track,
+            SortColumn::Year => library::Column::Year,
+            SortColumn::FirstPlayed => library::Column::FirstPlayed,
+            SortColumn::LastPlayed => library::Column::LastPlayed,
+            SortColumn::Codec => library::Column::Codec,
+            SortColumn::Bitrate => library::Column::Bitrate,
+            SortColumn::SampleRate => library::Column::SampleRate,
+            SortColumn::Composer => library::Column::Composer,
+            SortColumn::AddedDate => library::Column::AddedDate,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortDirection {
+    Ascending,
+    Descending,
+}
+
+impl From<SortDirection> for sea_orm::Order {
+    fn from(direction: SortDirection) -> Self {
+        match direction {
+            SortDirection::Ascending => sea_orm::Order::Asc,
+            SortDirection::Descending => sea_orm::Order::Desc,
+        }
+    }
+}
+
+/// Determines which column rows are grouped under
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Grouping {
+    None,
+    Artist,
+    AlbumArtist,
+    Album,
+    Genre,
+    Year,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ViewState {
+    pub sort_column: SortColumn,
+    pub sort_direction: SortDirection,
+    pub grouping: Grouping,
+}
+
+/// State of the interface that is restored on startup.
+/// Unlike `Config`, this is written by the GUI itself and not meant to be edited by hand.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct UiState {
+    pub library: ViewState,
+    pub album: ViewState,
+    pub playlist: ViewState,
+    /// Name of the queue session that was active when the app was closed
+    pub session: String,
+}
+
+impl UiState {
+    /// Reads the saved interface state, falling back to defaults if none was saved yet
+    pub fn read_state() -> Result<Self> {
+        let file = config_dir()
+            .map(|v| v.join("ui_state.toml"))
+            .ok_or(miette!("Configuration directory not found"))?;
+
+        if !file.exists() {
+            return Ok(Default::default());
+        }
+
+        let contents = std::fs::read_to_string(file).into_diagnostic()?;
+
+        toml::from_str(&contents).into_diagnostic()
+    }
+
+    pub fn write_state(state: &UiState) -> Result<()> {
+        let contents = toml::to_string(state).into_diagnostic()?;
+
+        let path = config_dir()
+            .map(|v| v.join("ui_state.toml"))
+            .ok_or(miette!("Configuration directory not found"))?;
+
+        File::create(path)
+            .and_then(|mut v| v.write_all(contents.as_bytes()))
+            .into_diagnostic()
+    }
+
+    pub fn view(&self, view: View) -> &ViewState {
+        match view {
+            View::Library => &self.library,
+            View::Album => &self.album,
+            View::Playlist => &self.playlist,
+        }
+    }
+
+    /// Updates the state of a single view and saves it
+    pub fn set_view(&mut self, view: View, state: ViewState) -> Result<()> {
+        match view {
+            View::Library => self.library = state,
+            View::Album => self.album = state,
+            View::Playlist => self.playlist = state,
+        }
+
+        Self::write_state(self)
+    }
+}
+
+impl Default for UiState {
+    fn default() -> Self {
+        UiState {
+            library: ViewState {
+                sort_column: SortColumn::Artist,
+                sort_direction: SortDirection::Ascending,
+                grouping: Grouping::Album,
+            },
+            album: ViewState {
+                sort_column: SortColumn::Track,
+                sort_direction: SortDirection::Ascending,
+                grouping: Grouping::None,
+            },
+            playlist: ViewState {
+                sort_column: SortColumn::Artist,
+                sort_direction: SortDirection::Ascending,
+                grouping: Grouping::None,
+            },
+            session: "default".to_string(),
+        }
+    }
+}
@@ -0,0 +1,373 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+
+use super::{
+    history::{delete_entries, merge_duplicates, restore_entries},
+    model::{history, playlist_entries, playlists, song_stats},
+};
+use miette::{miette, IntoDiagnostic, Result};
+use sea_orm::{
+    sea_query::{Expr, OnConflict},
+    ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter, Set,
+    TransactionTrait,
+};
+
+/// Edits older than this many can't be undone anymore
+const MAX_EDITS: usize = 50;
+
+/// A change to the library that can be undone, with what's needed to revert it
+#[derive(Debug, Clone, PartialEq)]
+pub enum Edit {
+    PlaylistDeleted {
+        playlist: playlists::Model,
+        entries: Vec<playlist_entries::Model>,
+    },
+    EntriesRemoved(Vec<playlist_entries::Model>),
+    RatingChanged {
+        hash: i64,
+        before: Option<i32>,
+        after: Option<i32>,
+    },
+    /// Plays removed from the history, by hand or by merging duplicates.
+    /// `finished` are the plays that were marked finished by merging.
+    PlaysDeleted {
+        entries: Vec<history::Model>,
+        finished: Vec<i32>,
+    },
+}
+
+impl Edit {
+    /// Names the edit for menus, like "Undo Delete playlist"
+    pub fn description(&self) -> &'static str {
+        match self {
+            Edit::PlaylistDeleted { .. } => "Delete playlist",
+            Edit::EntriesRemoved(_) => "Remove from playlist",
+            Edit::RatingChanged { .. } => "Change rating",
+            Edit::PlaysDeleted { .. } => "Delete plays",
+        }
+    }
+}
+
+/// Edits that were made, and ones that were undone since, newest last.
+/// They're only kept in memory, so they can't be undone after a restart.
+struct Stack {
+    done: Vec<Edit>,
+    undone: Vec<Edit>,
+}
+
+static STACK: Mutex<Stack> = Mutex::new(Stack {
+    done: vec![],
+    undone: vec![],
+});
+
+fn stack() -> MutexGuard<'static, Stack> {
+    STACK.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// A new edit can't be redone over, so it clears what was undone
+fn record(edit: Edit) {
+    let mut stack = stack();
+
+    stack.undone.clear();
+    stack.done.push(edit);
+
+    if stack.done.len() > MAX_EDITS {
+        stack.done.remove(0);
+    }
+}
+
+/// The edit `undo` would revert
+pub fn next_undo() -> Option<Edit> {
+    stack().done.last().cloned()
+}
+
+/// The edit `redo` would make again
+pub fn next_redo() -> Option<Edit> {
+    stack().undone.last().cloned()
+}
+
+/// Reverts the newest edit, returning it. `None` if there's nothing to undo.
+pub async fn undo(db: &DatabaseConnection) -> Result<Option<Edit>> {
+    // Not held while the database is written to, the edit is put back if that fails
+    let Some(edit) = stack().done.pop() else {
+        return Ok(None);
+    };
+
+    if let Err(e) = revert(&edit, db).await {
+        stack().done.push(edit);
+        return Err(e);
+    }
+
+    stack().undone.push(edit.clone());
+    Ok(Some(edit))
+}
+
+/// Makes the newest undone edit again, returning it. `None` if there's nothing to redo.
+pub async fn redo(db: &DatabaseConnection) -> Result<Option<Edit>> {
+    let Some(edit) = stack().undone.pop() else {
+        return Ok(None);
+    };
+
+    if let Err(e) = apply(&edit, db).await {
+        stack().undone.push(edit);
+        return Err(e);
+    }
+
+    stack().done.push(edit.clone());
+    Ok(Some(edit))
+}
+
+/// Deletes a playlist and its entries
+pub async fn delete_playlist(playlist_id: i32, db: &DatabaseConnection) -> Result<()> {
+    let playlist = playlists::Entity::find_by_id(playlist_id)
+        .one(db)
+        .await
+        .into_diagnostic()?
+        .ok_or(miette!("Playlist {} does not exist", playlist_id))?;
+
+    let entries = playlist_entries::Entity::find()
+        .filter(playlist_entries::Column::PlaylistId.eq(playlist_id))
+        .all(db)
+        .await
+        .into_diagnostic()?;
+
+    let edit = Edit::PlaylistDeleted { playlist, entries };
+    apply(&edit, db).await?;
+    record(edit);
+
+    Ok(())
+}
+
+/// Removes entries from playlists, returning how many were removed
+pub async fn remove_playlist_entries(ids: &[i32], db: &DatabaseConnection) -> Result<usize> {
+    let entries = playlist_entries::Entity::find()
+        .filter(playlist_entries::Column::Id.is_in(ids.to_vec()))
+        .all(db)
+        .await
+        .into_diagnostic()?;
+
+    if entries.is_empty() {
+        return Ok(0);
+    }
+
+    let removed = entries.len();
+    let edit = Edit::EntriesRemoved(entries);
+    apply(&edit, db).await?;
+    record(edit);
+
+    Ok(removed)
+}
+
+/// Rates a song from 0 to 100, or removes its rating
+pub async fn set_rating(hash: i64, rating: Option<i32>, db: &DatabaseConnection) -> Result<()> {
+    let before = song_stats::Entity::find()
+        .filter(song_stats::Column::SongHash.eq(hash))
+        .one(db)
+        .await
+        .into_diagnostic()?
+        .and_then(|v| v.rating);
+
+    let after = rating.map(|v| v.clamp(0, 100));
+    if before == after {
+        return Ok(());
+    }
+
+    let edit = Edit::RatingChanged {
+        hash,
+        before,
+        after,
+    };
+    apply(&edit, db).await?;
+    record(edit);
+
+    Ok(())
+}
+
+/// Deletes plays from the history, see `history::delete_entries`
+pub async fn delete_plays(ids: &[i32], db: &DatabaseConnection) -> Result<u64> {
+    let entries = history::Entity::find()
+        .filter(history::Column::Id.is_in(ids.to_vec()))
+        .all(db)
+        .await
+        .into_diagnostic()?;
+
+    let deleted = delete_entries(ids, db).await?;
+
+    if !entries.is_empty() {
+        record(Edit::PlaysDeleted {
+            entries,
+            finished: vec![],
+        });
+    }
+
+    Ok(deleted)
+}
+
+/// Merges duplicate plays, see `history::merge_duplicates`
+pub async fn merge_duplicate_plays(window: Duration, db: &DatabaseConnection) -> Result<u64> {
+    let before = history::Entity::find().all(db).await.into_diagnostic()?;
+
+    let merged = merge_duplicates(window, db).await?;
+
+    let after: HashMap<i32, bool> = history::Entity::find()
+        .all(db)
+        .await
+        .into_diagnostic()?
+        .into_iter()
+        .map(|v| (v.id, v.finished))
+        .collect();
+
+    let finished = before
+        .iter()
+        .filter(|v| !v.finished && after.get(&v.id) == Some(&true))
+        .map(|v| v.id)
+        .collect();
+    let entries: Vec<_> = before
+        .into_iter()
+        .filter(|v| !after.contains_key(&v.id))
+        .collect();
+
+    if !entries.is_empty() {
+        record(Edit::PlaysDeleted { entries, finished });
+    }
+
+    Ok(merged)
+}
+
+/// Makes an edit
+async fn apply(edit: &Edit, db: &DatabaseConnection) -> Result<()> {
+    match edit {
+        Edit::PlaylistDeleted { playlist, .. } => {
+            let txn = db.begin().await.into_diagnostic()?;
+
+            playlist_entries::Entity::delete_many()
+                .filter(playlist_entries::Column::PlaylistId.eq(playlist.id))
+                .exec(&txn)
+                .await
+                .into_diagnostic()?;
+            playlists::Entity::delete_by_id(playlist.id)
+                .exec(&txn)
+                .await
+                .into_diagnostic()?;
+
+            txn.commit().await.into_diagnostic()
+        }
+        Edit::EntriesRemoved(entries) => {
+            let ids: Vec<i32> = entries.iter().map(|v| v.id).collect();
+
+            playlist_entries::Entity::delete_many()
+                .filter(playlist_entries::Column::Id.is_in(ids))
+                .exec(db)
+                .await
+                .into_diagnostic()?;
+
+            Ok(())
+        }
+        Edit::RatingChanged { hash, after, .. } => save_rating(*hash, *after, db).await,
+        Edit::PlaysDeleted { entries, finished } => {
+            mark_finished(finished, true, db).await?;
+
+            let ids: Vec<i32> = entries.iter().map(|v| v.id).collect();
+            // Keep the number of bound parameters low
+            for batch in ids.chunks(100) {
+                delete_entries(batch, db).await?;
+            }
+
+            Ok(())
+        }
+    }
+}
+
+/// Reverts an edit
+async fn revert(edit: &Edit, db: &DatabaseConnection) -> Result<()> {
+    match edit {
+        Edit::PlaylistDeleted { playlist, entries } => {
+            let txn = db.begin().await.into_diagnostic()?;
+
+            playlists::Entity::insert(playlists::ActiveModel {
+                id: Set(playlist.id),
+                name: Set(playlist.name.clone()),
+                sort_order: Set(playlist.sort_order.clone()),
+            })
+            .exec(&txn)
+            .await
+            .into_diagnostic()?;
+            insert_entries(entries, &txn).await?;
+
+            txn.commit().await.into_diagnostic()
+        }
+        Edit::EntriesRemoved(entries) => {
+            // Entries are inserted in batches, which have to come back together
+            let txn = db.begin().await.into_diagnostic()?;
+            insert_entries(entries, &txn).await?;
+            txn.commit().await.into_diagnostic()
+        }
+        Edit::RatingChanged { hash, before, .. } => save_rating(*hash, *before, db).await,
+        Edit::PlaysDeleted { entries, finished } => {
+            restore_entries(entries, db).await?;
+            mark_finished(finished, false, db).await
+        }
+    }
+}
+
+/// Adds playlist entries back with their ids, so redoing removes the same ones
+async fn insert_entries(
+    entries: &[playlist_entries::Model],
+    db: &impl ConnectionTrait,
+) -> Result<()> {
+    // Keep the number of bound parameters low
+    for batch in entries.chunks(100) {
+        playlist_entries::Entity::insert_many(batch.iter().map(|v| {
+            playlist_entries::ActiveModel {
+                id: Set(v.id),
+                playlist_id: Set(v.playlist_id),
+                song_hash: Set(v.song_hash),
+                ordinal: Set(v.ordinal),
+                added_date: Set(v.added_date),
+            }
+        }))
+        .exec(db)
+        .await
+        .into_diagnostic()?;
+    }
+
+    Ok(())
+}
+
+/// Sets the rating of a song, keeping its imported plays
+async fn save_rating(hash: i64, rating: Option<i32>, db: &DatabaseConnection) -> Result<()> {
+    song_stats::Entity::insert(song_stats::ActiveModel {
+        song_hash: Set(hash),
+        rating: Set(rating),
+        imported_plays: Set(0),
+        ..Default::default()
+    })
+    .on_conflict(
+        OnConflict::column(song_stats::Column::SongHash)
+            .update_column(song_stats::Column::Rating)
+            .to_owned(),
+    )
+    .exec(db)
+    .await
+    .into_diagnostic()?;
+
+    Ok(())
+}
+
+async fn mark_finished(ids: &[i32], finished: bool, db: &DatabaseConnection) -> Result<()> {
+    if ids.is_empty() {
+        return Ok(());
+    }
+
+    history::Entity::update_many()
+        .col_expr(history::Column::Finished, Expr::value(finished))
+        .filter(history::Column::Id.is_in(ids.to_vec()))
+        .exec(db)
+        .await
+        .into_diagnostic()?;
+
+    Ok(())
+}
@@ -0,0 +1,147 @@
+use super::{
+    channels::layout_name,
+    config::Config,
+    errors::{report, Category, Severity},
+    fetching::probe_codec,
+    model::library,
+    replaygain::{from_tag, track_gain, update_album_gain, write_back},
+    utils::song_path,
+};
+use lofty::{read_from_path, Accessor, AudioFile};
+use miette::{IntoDiagnostic, Result};
+use paris::success;
+use sea_orm::{
+    ColumnTrait, Condition, DatabaseConnection, EntityTrait, NotSet, QueryFilter, QueryOrder,
+    QuerySelect, Set,
+};
+
+/// Fills in analysis columns (ReplayGain, disc number, technical info) for songs indexed
+/// before they existed. Only rows missing them are touched, so no purge and rescan is needed.
+pub async fn backfill_analysis(db: &DatabaseConnection) -> Result<()> {
+    let config = Config::read_config()?;
+
+    // Remote songs are analyzed by the server
+    let sources = config.local_source_ids();
+
+    // Sources with analysis turned off only get the other columns filled in
+    let analyzed: Vec<i32> = config
+        .sources
+        .iter()
+        .filter(|v| v.settings.analyze_replaygain)
+        .map(|v| v.id.into())
+        .filter(|v| sources.contains(v))
+        .collect();
+
+    // Analyze as many songs at once as there are cores
+    let batch_size = std::thread::available_parallelism()
+        .map(|v| v.get())
+        .unwrap_or(4) as u64;
+
+    let mut last_id = 0;
+    let mut updated = 0;
+
+    loop {
+        // Rows that fail to be analyzed stay empty, so continue after the previous batch
+        let batch = library::Entity::find()
+            .filter(library::Column::SourceId.is_in(sources.clone()))
+            .filter(
+                Condition::any()
+                    .add(
+                        Condition::all()
+                            .add(library::Column::RgTrackGain.is_null())
+                            .add(library::Column::SourceId.is_in(analyzed.clone())),
+                    )
+                    .add(library::Column::ChannelLayout.is_null())
+                    .add(library::Column::Codec.is_null()),
+            )
+            .filter(library::Column::Id.gt(last_id))
+            .order_by_asc(library::Column::Id)
+            .limit(batch_size)
+            .all(db)
+            .await
+            .into_diagnostic()?;
+
+        let Some(last) = batch.last() else {
+            break;
+        };
+        last_id = last.id;
+
+        let handles: Vec<_> = batch
+            .into_iter()
+            .map(|song| {
+                let analyze = analyzed.contains(&song.source_id);
+                tokio::task::spawn_blocking(move || (song.id, analyze_song(&song, analyze)))
+            })
+            .collect();
+
+        for handle in handles {
+            let (id, result) = handle.await.into_diagnostic()?;
+
+            let analysis = match result {
+                Ok(v) => v,
+                Err(e) => {
+                    report(
+                        Severity::Warning,
+                        Category::Decoding,
+                        None,
+                        format!("Couldn't analyze song {id}: {e}"),
+                    );
+                    continue;
+                }
+            };
+
+            library::Entity::update(library::ActiveModel {
+                id: Set(id),
+                ..analysis
+            })
+            .exec(db)
+            .await
+            .into_diagnostic()?;
+
+            updated += 1;
+        }
+    }
+
+    if updated > 0 {
+        for source in sources {
+            update_album_gain(source, db).await?;
+
+            if config.write_replaygain {
+                write_back(source, false, db).await?;
+            }
+        }
+
+        success!("Backfilled analysis for {updated} songs");
+    }
+
+    Ok(())
+}
+
+/// Returns the analysis columns of a song, to be written to its row.
+/// Without `analyze`, ReplayGain values are only read from the tags.
+fn analyze_song(song: &library::Model, analyze: bool) -> Result<library::ActiveModel> {
+    let path = song_path(song);
+
+    let audio = read_from_path(&path, false).into_diagnostic()?;
+    let tags = audio.primary_tag().or(audio.first_tag());
+    let properties = audio.properties();
+
+    let gain = if analyze {
+        Some(track_gain(&path, tags)?)
+    } else {
+        tags.and_then(from_tag)
+    };
+
+    Ok(library::ActiveModel {
+        disc: Set(tags.and_then(|t| t.disk()).map(|v| v as i32)),
+        rg_track_gain: gain.map_or(NotSet, |v| Set(Some(v.gain))),
+        rg_track_peak: gain.map_or(NotSet, |v| Set(Some(v.peak))),
+        channel_layout: Set(properties.channels().map(|v| layout_name(v.into()))),
+        codec: Set(probe_codec(&path)?),
+        bitrate: Set(properties.audio_bitrate().map(|v| v as i32)),
+        sample_rate: Set(properties.sample_rate().map(|v| v as i32)),
+        bit_depth: Set(properties.bit_depth().map(i32::from)),
+        channels: Set(properties.channels().map(i32::from)),
+        ..Default::default()
+    })
+}
@@ -1,12 +1,89 @@
-use miette::{miette, IntoDiagnostic, Result};
-use std::{fs::File, io::Write, path::PathBuf};
+use miette::{ensure, miette, IntoDiagnostic, Result};
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION},
+    Certificate, Client, Proxy,
+};
+use std::{fs::File, io::Write, path::PathBuf, sync::OnceLock};
+
+use super::{
+    config::{Config, Source, SourceKind},
+    model::library,
+    paths::decode,
+    subsonic::stream_url,
+    vfs::Entry,
+    webdav::file_url,
+};
+
+/// Profile selected on startup, see `set_profile`
+static PROFILE: OnceLock<String> = OnceLock::new();
+
+/// Keeps the config, database and cache of a profile apart from the default ones, in directories
+/// named `eleanor-<name>`. Has to be called before anything reads those files, and only once,
+/// since they can't move while the app runs.
+pub fn set_profile(name: String) -> Result<()> {
+    ensure!(
+        is_valid_profile(&name),
+        "Profile names can only contain letters, numbers, '-' and '_'"
+    );
+
+    PROFILE
+        .set(name)
+        .map_err(|_| miette!("A profile was already selected"))
+}
+
+/// The selected profile, `None` for the default one
+pub fn profile() -> Option<&'static str> {
+    PROFILE.get().map(String::as_str)
+}
+
+pub fn is_valid_profile(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|v| v.is_alphanumeric() || v == '-' || v == '_')
+}
+
+/// Profiles that were used at least once, not including the default one
+pub fn profiles() -> Vec<String> {
+    let Some(Ok(entries)) = dirs::config_dir().map(std::fs::read_dir) else {
+        return vec![];
+    };
+
+    let mut profiles: Vec<String> = entries
+        .filter_map(|v| v.ok())
+        .filter(|v| v.path().is_dir())
+        .filter_map(|v| {
+            v.file_name()
+                .to_str()?
+                .strip_prefix("eleanor-")
+                .map(String::from)
+        })
+        .filter(|v| is_valid_profile(v))
+        .collect();
+
+    profiles.sort();
+
+    profiles
+}
+
+fn app_dir_name() -> String {
+    match profile() {
+        Some(name) => format!("eleanor-{name}"),
+        None => "eleanor".into(),
+    }
+}
 
 pub fn config_dir() -> Option<PathBuf> {
-    dirs::config_dir().map(|v| v.join("eleanor"))
+    dirs::config_dir().map(|v| v.join(app_dir_name()))
 }
 
 pub fn cache_dir() -> Option<PathBuf> {
-    dirs::cache_dir().map(|v| v.join("eleanor"))
+    dirs::cache_dir().map(|v| v.join(app_dir_name()))
+}
+
+/// Location of a local song's file
+pub fn song_path(song: &library::Model) -> PathBuf {
+    decode(&song.path).join(decode(&song.filename))
 }
 
 /// If no files have been created in the config directory, the app is running for the first time
@@ -41,3 +118,105 @@ pub fn get_auth_source(source: u8) -> Result<(String, String)> {
 
     Ok(contents)
 }
+
+/// Removes the stored credentials of a source, if it has any
+pub fn remove_auth_source(source: u8) -> Result<()> {
+    let path = cache_dir()
+        .ok_or(miette!("Cache directory does not exist"))?
+        .join(format!("{source}.auth"));
+
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e).into_diagnostic(),
+        _ => Ok(()),
+    }
+}
+
+/// Credentials to send as basic auth to a source. Sources with a token send that instead,
+/// see `http_client`, so they don't need any stored.
+pub fn basic_auth(source: &Source) -> Result<Option<(String, String)>> {
+    match source.token {
+        Some(_) => Ok(None),
+        None => get_auth_source(source.id).map(Some),
+    }
+}
+
+/// Where a remote song's audio is fetched from, with the credentials to send as basic auth.
+/// Subsonic servers get theirs in the URL instead. With `max_bitrate`, servers that can
+/// transcode are asked for the song at no more than that many kbit/s.
+pub fn audio_location(
+    source: &Source,
+    song: &library::Model,
+    max_bitrate: Option<u32>,
+) -> Result<(String, Option<(String, String)>)> {
+    match &source.source {
+        SourceKind::Remote { address } => {
+            let url = match max_bitrate {
+                Some(kbps) => format!("{address}/{}?max_bitrate={kbps}", song.hash),
+                None => format!("{address}/{}", song.hash),
+            };
+
+            Ok((url, basic_auth(source)?))
+        }
+        SourceKind::Subsonic { url } => Ok((
+            stream_url(url, &song.path, &get_auth_source(source.id)?, max_bitrate)?,
+            None,
+        )),
+        SourceKind::WebDav { share } => {
+            let entry = Entry {
+                dir: song.path.clone(),
+                filename: song.filename.clone(),
+                size: None,
+                modified: None,
+            };
+
+            Ok((file_url(share, &entry)?.into(), basic_auth(source)?))
+        }
+        SourceKind::Local { .. } => Err(miette!("{} is not a remote song", song.filename)),
+    }
+}
+
+/// Builds an HTTP client, routed through the source's proxy if it has one, or the global proxy.
+/// Requests made with it carry the source's custom headers and token,
+/// and check certificates as set in its TLS settings.
+pub fn http_client(config: &Config, source: Option<&Source>) -> Result<Client> {
+    let mut builder = Client::builder().user_agent(concat!("Eleanor/", env!("CARGO_PKG_VERSION")));
+
+    if let Some(source) = source {
+        let mut headers = HeaderMap::new();
+
+        for (name, value) in &source.headers {
+            headers.insert(
+                HeaderName::from_bytes(name.as_bytes()).into_diagnostic()?,
+                HeaderValue::from_str(value).into_diagnostic()?,
+            );
+        }
+
+        if let Some(token) = &source.token {
+            let mut value = HeaderValue::from_str(&format!("Bearer {token}")).into_diagnostic()?;
+            value.set_sensitive(true);
+
+            headers.insert(AUTHORIZATION, value);
+        }
+
+        // Overrides the default user agent if one is set
+        builder = builder.default_headers(headers);
+
+        if let Some(path) = &source.tls.ca_certificate {
+            let pem = std::fs::read(path)
+                .map_err(|e| miette!("Couldn't read the CA certificate {}: {}", path, e))?;
+
+            builder = builder.add_root_certificate(Certificate::from_pem(&pem).into_diagnostic()?);
+        }
+
+        builder = builder.danger_accept_invalid_certs(source.tls.insecure);
+    }
+
+    if let Some(proxy) = source
+        .and_then(|v| v.proxy.as_ref())
+        .or(config.proxy.as_ref())
+    {
+        builder = builder.proxy(Proxy::all(proxy).into_diagnostic()?);
+    }
+
+    builder.build().into_diagnostic()
+}
@@ -0,0 +1,178 @@
+use std::{
+    fmt,
+    fs::{create_dir_all, remove_file, File},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::UNIX_EPOCH,
+};
+
+use super::{
+    paths::{decode, encode, files},
+    utils::cache_dir,
+};
+use async_trait::async_trait;
+use miette::{miette, IntoDiagnostic, Result};
+use symphonia::core::io::MediaSource;
+
+static NEXT_COPY: AtomicU64 = AtomicU64::new(0);
+
+/// An audio file in a source. Local paths that aren't valid UTF-8 are stored as `paths::encode`
+/// makes them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Directory the file is in, which is stored as the song's `path`
+    pub dir: String,
+    pub filename: String,
+    /// Size in bytes, if the provider knows it without opening the file
+    pub size: Option<u64>,
+    /// When the file was last modified, in seconds since the Unix epoch, if the provider knows it
+    pub modified: Option<i64>,
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.dir.trim_end_matches('/'), self.filename)
+    }
+}
+
+/// Where the files of a source are stored, so they can be indexed the same way wherever that is
+#[async_trait]
+pub trait Provider: Send + Sync {
+    /// Every audio file in the source
+    async fn list(&self) -> Result<Vec<Entry>>;
+
+    /// Opens a file for reading. Remote files are streamed, so reads block until data arrives.
+    fn open(&self, entry: &Entry) -> Result<Box<dyn MediaSource>>;
+
+    /// Directory the listed entries are in, which exclusion rules are matched relative to
+    fn root(&self) -> String;
+
+    /// Path the file can be read from directly, if it's on this machine
+    fn local_path(&self, _entry: &Entry) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// Files in a directory on this machine
+pub struct LocalFiles {
+    root: PathBuf,
+}
+
+impl LocalFiles {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        LocalFiles { root: root.into() }
+    }
+}
+
+#[async_trait]
+impl Provider for LocalFiles {
+    async fn list(&self) -> Result<Vec<Entry>> {
+        files(&self.root)
+            .into_iter()
+            .filter(|e| is_audio(e.path()))
+            .map(|file| {
+                let metadata = file.metadata().ok();
+
+                Ok(Entry {
+                    dir: encode(
+                        file.path()
+                            .parent()
+                            .ok_or(miette!("Couldn't get path for file {:?}", file))?
+                            .as_os_str(),
+                    ),
+                    filename: encode(file.file_name()),
+                    size: metadata.as_ref().map(|v| v.len()),
+                    modified: metadata
+                        .and_then(|v| v.modified().ok())
+                        .and_then(|v| v.duration_since(UNIX_EPOCH).ok())
+                        .map(|v| v.as_secs() as i64),
+                })
+            })
+            .collect()
+    }
+
+    fn root(&self) -> String {
+        encode(self.root.as_os_str())
+    }
+
+    fn open(&self, entry: &Entry) -> Result<Box<dyn MediaSource>> {
+        let path = decode(&entry.dir).join(decode(&entry.filename));
+
+        Ok(Box::new(File::open(path).into_diagnostic()?))
+    }
+
+    fn local_path(&self, entry: &Entry) -> Option<PathBuf> {
+        Some(decode(&entry.dir).join(decode(&entry.filename)))
+    }
+}
+
+/// A file that can be read from disk. Remote files are copied to the cache,
+/// and the copy is removed when this is dropped.
+pub struct LocalCopy {
+    path: PathBuf,
+    temporary: bool,
+}
+
+impl LocalCopy {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for LocalCopy {
+    fn drop(&mut self) {
+        if self.temporary {
+            let _ = remove_file(&self.path);
+        }
+    }
+}
+
+/// Makes a file readable by everything that needs a path, like tag readers
+pub async fn local_copy(provider: &dyn Provider, entry: &Entry) -> Result<LocalCopy> {
+    if let Some(path) = provider.local_path(entry) {
+        return Ok(LocalCopy {
+            path,
+            temporary: false,
+        });
+    }
+
+    // The extension is kept, since it's used to tell the format
+    let ext = Path::new(&entry.filename)
+        .extension()
+        .and_then(|v| v.to_str())
+        .unwrap_or("");
+
+    let path = cache_dir()
+        .ok_or(miette!("Cache directory does not exist"))?
+        .join("indexing")
+        .join(format!(
+            "{}.{ext}",
+            NEXT_COPY.fetch_add(1, Ordering::Relaxed)
+        ));
+    create_dir_all(path.parent().unwrap_or(&path)).into_diagnostic()?;
+
+    let copy = LocalCopy {
+        path: path.clone(),
+        temporary: true,
+    };
+
+    let mut source = provider.open(entry)?;
+
+    tokio::task::spawn_blocking(move || {
+        let mut file = File::create(path)?;
+        std::io::copy(&mut source, &mut file)
+    })
+    .await
+    .into_diagnostic()?
+    .into_diagnostic()?;
+
+    Ok(copy)
+}
+
+/// Whether a file is audio, going by its extension
+pub fn is_audio(path: &Path) -> bool {
+    mime_guess::from_path(path)
+        .first()
+        .map(|v| v.type_() == mime::AUDIO)
+        .unwrap_or(false)
+}
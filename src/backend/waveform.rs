@@ -0,0 +1,305 @@
+use std::{
+    fs::{create_dir_all, File},
+    io::Write,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use super::{
+    chapters::{chapter_at, with_fallback, Chapter},
+    config::Config,
+    details::details,
+    downloads::playable_path,
+    lyrics::{LyricLine, Lyrics},
+    model::library,
+    network::is_metered,
+    streaming::stream_song,
+    utils::cache_dir,
+};
+use miette::{miette, IntoDiagnostic, Result};
+use paris::warn;
+use sea_orm::DatabaseConnection;
+use serde::{Deserialize, Serialize};
+use symphonia::{
+    core::{
+        audio::SampleBuffer,
+        codecs::{CodecParameters, Decoder},
+        formats::Packet,
+        io::{MediaSource, MediaSourceStream},
+        probe::Hint,
+    },
+    default::{get_codecs, get_probe},
+};
+
+/// Length of audio each peak covers
+const BUCKET_MS: u32 = 100;
+
+/// How far around the hovered position peaks are returned for a seek preview
+const PREVIEW_RADIUS: Duration = Duration::from_secs(5);
+
+/// Peak levels of a song, for drawing it on the seek bar
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Waveform {
+    /// Length of audio each peak covers, in milliseconds
+    pub bucket_ms: u32,
+    /// Highest sample level across all channels in each bucket, from 0 to 255
+    pub peaks: Vec<u8>,
+}
+
+impl Waveform {
+    /// Peaks within `radius` of a position
+    pub fn around(&self, position: Duration, radius: Duration) -> &[u8] {
+        let bucket = |time: Duration| {
+            ((time.as_millis() / self.bucket_ms.max(1) as u128) as usize).min(self.peaks.len())
+        };
+
+        let start = bucket(position.saturating_sub(radius));
+        let end = bucket(position + radius);
+
+        &self.peaks[start..end]
+    }
+
+    /// Peaks of the whole song reduced to `count` columns, e.g. one per pixel of the seek bar.
+    /// Each column keeps the highest peak it covers. Songs shorter than that get fewer columns.
+    pub fn columns(&self, count: usize) -> Vec<u8> {
+        if count == 0 || self.peaks.len() <= count {
+            return self.peaks.clone();
+        }
+
+        (0..count)
+            .map(|column| {
+                let start = column * self.peaks.len() / count;
+                let end = (column + 1) * self.peaks.len() / count;
+
+                self.peaks[start..end].iter().copied().max().unwrap_or(0)
+            })
+            .collect()
+    }
+}
+
+/// What to show in the bubble when hovering over a position on the seek bar
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SeekPreview {
+    pub position: Duration,
+    /// Peaks from `PREVIEW_RADIUS` before the position to `PREVIEW_RADIUS` after it.
+    /// Empty if the waveform isn't available, e.g. for remote songs on a metered connection.
+    pub peaks: Vec<u8>,
+    pub bucket_ms: u32,
+    /// The chapter at the position, including generated sections of long files
+    pub chapter: Option<Chapter>,
+    /// The synced lyric line being sung at the position
+    pub lyric: Option<LyricLine>,
+}
+
+/// Returns the waveform of a song, analyzing it the first time.
+/// Remote songs that aren't downloaded are streamed, unless the connection is metered.
+pub async fn waveform(song: &library::Model, db: &DatabaseConnection) -> Result<Waveform> {
+    if let Some(cached) = cached_waveform(song.hash) {
+        return Ok(cached);
+    }
+
+    let source: Box<dyn MediaSource> = match playable_path(song, db).await? {
+        Some(path) => Box::new(File::open(path).into_diagnostic()?),
+        None => {
+            miette::ensure!(
+                !is_metered(),
+                "Not streaming {} for its waveform on a metered connection",
+                song.filename
+            );
+            Box::new(stream_song(song, db)?)
+        }
+    };
+
+    let ext = Path::new(&song.filename)
+        .extension()
+        .and_then(|v| v.to_str())
+        .unwrap_or("")
+        .to_string();
+
+    let waveform = tokio::task::spawn_blocking(move || analyze(source, &ext))
+        .await
+        .into_diagnostic()??;
+
+    save_waveform(song.hash, &waveform)?;
+
+    Ok(waveform)
+}
+
+/// A waveform analyzed before, if there is one
+pub fn cached_waveform(hash: i64) -> Option<Waveform> {
+    let data = std::fs::read(waveform_path(hash)?).ok()?;
+    rmp_serde::from_slice(&data).ok()
+}
+
+/// Nearby peaks, the chapter and the lyric line at a position in a song
+pub async fn seek_preview(
+    song: &library::Model,
+    position: Duration,
+    db: &DatabaseConnection,
+) -> Result<SeekPreview> {
+    let config = Config::read_config()?;
+    let details = details(song).await?;
+
+    let (peaks, bucket_ms) = match waveform(song, db).await {
+        Ok(waveform) => (
+            waveform.around(position, PREVIEW_RADIUS).to_vec(),
+            waveform.bucket_ms,
+        ),
+        Err(e) => {
+            warn!("No waveform for {}: {e}", song.filename);
+            (vec![], BUCKET_MS)
+        }
+    };
+
+    let chapters = with_fallback(
+        details.chapters,
+        Duration::from_secs(song.duration as u64),
+        Duration::from_secs(config.chapter_interval_minutes * 60),
+    );
+    let chapter = chapter_at(&chapters, position).map(|v| chapters[v].clone());
+
+    let lyric = match &details.lyrics {
+        Some(lyrics @ Lyrics::Synced(lines)) => lyrics.line_at(position).map(|v| lines[v].clone()),
+        _ => None,
+    };
+
+    Ok(SeekPreview {
+        position,
+        peaks,
+        bucket_ms,
+        chapter,
+        lyric,
+    })
+}
+
+/// Decodes a whole song, keeping the highest sample level of every bucket
+fn analyze(source: Box<dyn MediaSource>, ext: &str) -> Result<Waveform> {
+    let source = MediaSourceStream::new(source, Default::default());
+    let mut format = get_probe()
+        .format(
+            Hint::new().with_extension(ext),
+            source,
+            &Default::default(),
+            &Default::default(),
+        )
+        .into_diagnostic()?
+        .format;
+
+    let track = format
+        .default_track()
+        .ok_or(miette!("No audio track found"))?;
+    let track_id = track.id;
+
+    let mut collector = PeakCollector::new(&track.codec_params)?;
+
+    while let Ok(packet) = format.next_packet() {
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        if collector.process(&packet).is_err() {
+            break;
+        }
+    }
+
+    collector.finish()
+}
+
+/// Builds a waveform from the packets of a track as they're read, so it can be done
+/// alongside other work that reads the whole file, like indexing
+pub struct PeakCollector {
+    decoder: Box<dyn Decoder>,
+    channels: usize,
+    bucket_frames: usize,
+    peaks: Vec<u8>,
+    /// Highest level in the current bucket so far
+    peak: f32,
+    /// Frames in the current bucket so far
+    frames: usize,
+    buffer: Option<SampleBuffer<f32>>,
+}
+
+impl PeakCollector {
+    pub fn new(params: &CodecParameters) -> Result<Self> {
+        let sample_rate = params.sample_rate.ok_or(miette!("Unknown sample rate"))?;
+        let channels = params
+            .channels
+            .ok_or(miette!("Unknown channel layout"))?
+            .count();
+
+        let decoder = get_codecs()
+            .make(params, &Default::default())
+            .into_diagnostic()?;
+
+        Ok(PeakCollector {
+            decoder,
+            channels,
+            bucket_frames: (sample_rate as usize * BUCKET_MS as usize / 1000).max(1),
+            peaks: vec![],
+            peak: 0.0,
+            frames: 0,
+            buffer: None,
+        })
+    }
+
+    /// Decodes a packet of the track
+    pub fn process(&mut self, packet: &Packet) -> Result<()> {
+        let decoded = match self.decoder.decode(packet) {
+            Ok(v) => v,
+            // Skip over corrupted packets
+            Err(symphonia::core::errors::Error::DecodeError(_)) => return Ok(()),
+            Err(e) => return Err(e).into_diagnostic(),
+        };
+
+        let buffer = self
+            .buffer
+            .get_or_insert_with(|| SampleBuffer::new(decoded.capacity() as u64, *decoded.spec()));
+        buffer.copy_interleaved_ref(decoded);
+
+        for frame in buffer.samples().chunks(self.channels) {
+            self.peak = frame.iter().fold(self.peak, |peak, v| peak.max(v.abs()));
+            self.frames += 1;
+
+            if self.frames == self.bucket_frames {
+                self.peaks.push(level(self.peak));
+                self.peak = 0.0;
+                self.frames = 0;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<Waveform> {
+        if self.frames > 0 {
+            self.peaks.push(level(self.peak));
+        }
+
+        miette::ensure!(!self.peaks.is_empty(), "No audio could be decoded");
+
+        Ok(Waveform {
+            bucket_ms: BUCKET_MS,
+            peaks: self.peaks,
+        })
+    }
+}
+
+fn level(peak: f32) -> u8 {
+    (peak.min(1.0) * u8::MAX as f32).round() as u8
+}
+
+/// Caches a waveform, e.g. one collected while indexing
+pub fn save_waveform(hash: i64, waveform: &Waveform) -> Result<()> {
+    let path = waveform_path(hash).ok_or(miette!("Cache directory does not exist"))?;
+    create_dir_all(path.parent().unwrap_or(&path)).into_diagnostic()?;
+
+    let data = rmp_serde::to_vec(waveform).into_diagnostic()?;
+
+    File::create(path)
+        .and_then(|mut v| v.write_all(&data))
+        .into_diagnostic()
+}
+
+fn waveform_path(hash: i64) -> Option<PathBuf> {
+    cache_dir().map(|v| v.join("waveforms").join(format!("{hash}.mp")))
+}
@@ -0,0 +1,226 @@
+use std::{path::Path, time::UNIX_EPOCH};
+
+use super::{
+    config::{Config, Source},
+    streaming::{HttpTransport, StreamingReader, PREBUFFER},
+    utils::{basic_auth, http_client},
+    vfs::{is_audio, Entry, Provider},
+};
+use async_trait::async_trait;
+use httpdate::parse_http_date;
+use miette::{miette, IntoDiagnostic, Result};
+use percent_encoding::percent_decode_str;
+use quick_xml::{events::Event, Reader};
+use reqwest::{header::CONTENT_TYPE, Client, Method, Url};
+use symphonia::core::io::MediaSource;
+
+/// Only asks whether each entry is a directory, how large files are and when they last changed
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<propfind xmlns="DAV:"><prop><resourcetype/><getcontentlength/><getlastmodified/></prop></propfind>"#;
+
+/// An entry of a `PROPFIND` response
+struct Resource {
+    href: String,
+    collection: bool,
+    size: Option<u64>,
+    /// In seconds since the Unix epoch
+    modified: Option<i64>,
+}
+
+/// Lists and streams the files of a WebDAV share, with credentials from the auth store
+/// unless the source has a token
+pub struct WebDav {
+    client: Client,
+    share: Url,
+    credentials: Option<(String, String)>,
+}
+
+impl WebDav {
+    pub fn new(source: &Source, share: &str, config: &Config) -> Result<Self> {
+        Ok(WebDav {
+            client: http_client(config, Some(source))?,
+            share: directory_url(Url::parse(share).into_diagnostic()?),
+            credentials: basic_auth(source)?,
+        })
+    }
+
+    /// Entries directly in a directory, along with whether they're directories themselves,
+    /// their size and when they last changed
+    async fn list_directory(&self, dir: &Url) -> Result<Vec<(Url, Resource)>> {
+        let mut request = self.client.request(
+            Method::from_bytes(b"PROPFIND").into_diagnostic()?,
+            dir.clone(),
+        );
+
+        if let Some((username, password)) = &self.credentials {
+            request = request.basic_auth(username, Some(password));
+        }
+
+        let body = request
+            .header("Depth", "1")
+            .header(CONTENT_TYPE, "application/xml")
+            .body(PROPFIND_BODY)
+            .send()
+            .await
+            .into_diagnostic()?
+            .error_for_status()
+            .into_diagnostic()?
+            .text()
+            .await
+            .into_diagnostic()?;
+
+        let mut entries = vec![];
+
+        for resource in parse_multistatus(&body)? {
+            let url = dir.join(&resource.href).into_diagnostic()?;
+
+            // The directory itself is part of the listing
+            if url.path().trim_end_matches('/') == dir.path().trim_end_matches('/') {
+                continue;
+            }
+
+            entries.push((url, resource));
+        }
+
+        Ok(entries)
+    }
+}
+
+#[async_trait]
+impl Provider for WebDav {
+    async fn list(&self) -> Result<Vec<Entry>> {
+        // Servers often refuse to list everything at once with `Depth: infinity`,
+        // so directories are listed one by one
+        let mut pending = vec![self.share.clone()];
+        let mut files = vec![];
+
+        while let Some(dir) = pending.pop() {
+            for (url, resource) in self.list_directory(&dir).await? {
+                if resource.collection {
+                    pending.push(directory_url(url));
+                } else {
+                    let entry = entry(&url, &resource)?;
+
+                    if is_audio(Path::new(&entry.filename)) {
+                        files.push(entry);
+                    }
+                }
+            }
+        }
+
+        Ok(files)
+    }
+
+    fn open(&self, entry: &Entry) -> Result<Box<dyn MediaSource>> {
+        let url = file_url(self.share.as_str(), entry)?;
+        let transport =
+            HttpTransport::new(self.client.clone(), url.into(), self.credentials.clone());
+
+        Ok(Box::new(StreamingReader::new(transport, PREBUFFER)))
+    }
+
+    fn root(&self) -> String {
+        percent_decode_str(self.share.path())
+            .decode_utf8_lossy()
+            .into_owned()
+    }
+}
+
+/// Address of a file on the share's server. Entries hold decoded paths, which are encoded again.
+pub fn file_url(share: &str, entry: &Entry) -> Result<Url> {
+    let mut url = Url::parse(share).into_diagnostic()?;
+
+    url.path_segments_mut()
+        .map_err(|_| miette!("{} can't be a WebDAV share", share))?
+        .clear()
+        .extend(entry.dir.split('/').filter(|v| !v.is_empty()))
+        .push(&entry.filename);
+
+    Ok(url)
+}
+
+/// Relative links only resolve inside a directory if its address ends with a slash
+fn directory_url(mut url: Url) -> Url {
+    if !url.path().ends_with('/') {
+        url.set_path(&format!("{}/", url.path()));
+    }
+
+    url
+}
+
+fn entry(url: &Url, resource: &Resource) -> Result<Entry> {
+    let path = percent_decode_str(url.path())
+        .decode_utf8()
+        .into_diagnostic()?;
+
+    let (dir, filename) = path
+        .rsplit_once('/')
+        .ok_or(miette!("Invalid file address {}", url))?;
+
+    Ok(Entry {
+        dir: if dir.is_empty() { "/" } else { dir }.to_string(),
+        filename: filename.to_string(),
+        size: resource.size,
+        modified: resource.modified,
+    })
+}
+
+/// Reads the links of a `PROPFIND` response, whether each of them is a directory,
+/// its size and when it last changed.
+/// Namespace prefixes differ between servers, so only local names are compared.
+fn parse_multistatus(body: &str) -> Result<Vec<Resource>> {
+    let mut reader = Reader::from_str(body);
+    reader.trim_text(true);
+
+    let mut entries = vec![];
+    let mut href = None;
+    let mut collection = false;
+    let mut size = None;
+    let mut modified = None;
+    let mut in_href = false;
+    let mut in_length = false;
+    let mut in_modified = false;
+
+    loop {
+        match reader.read_event().into_diagnostic()? {
+            Event::Start(e) if e.local_name().as_ref() == b"response" => {
+                href = None;
+                collection = false;
+                size = None;
+                modified = None;
+            }
+            Event::Start(e) if e.local_name().as_ref() == b"href" => in_href = true,
+            Event::Start(e) if e.local_name().as_ref() == b"getcontentlength" => in_length = true,
+            Event::Start(e) if e.local_name().as_ref() == b"getlastmodified" => in_modified = true,
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == b"collection" => {
+                collection = true
+            }
+            Event::Text(e) if in_href => href = Some(e.unescape().into_diagnostic()?.into_owned()),
+            Event::Text(e) if in_length => size = e.unescape().into_diagnostic()?.parse().ok(),
+            // Dates are in the format of HTTP headers, like "Sun, 06 Nov 1994 08:49:37 GMT"
+            Event::Text(e) if in_modified => {
+                modified = parse_http_date(&e.unescape().into_diagnostic()?)
+                    .ok()
+                    .and_then(|v| v.duration_since(UNIX_EPOCH).ok())
+                    .map(|v| v.as_secs() as i64)
+            }
+            Event::End(e) if e.local_name().as_ref() == b"href" => in_href = false,
+            Event::End(e) if e.local_name().as_ref() == b"getcontentlength" => in_length = false,
+            Event::End(e) if e.local_name().as_ref() == b"getlastmodified" => in_modified = false,
+            Event::End(e) if e.local_name().as_ref() == b"response" => {
+                if let Some(href) = href.take() {
+                    entries.push(Resource {
+                        href,
+                        collection,
+                        size,
+                        modified,
+                    });
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(entries)
+}
@@ -0,0 +1,223 @@
+use std::{collections::HashMap, path::Path};
+
+use super::settings::{SettingsPage, SourceForm};
+use crate::backend::{
+    cancellation::CancellationToken,
+    config::{Source, SourceKind},
+    errors::{report, Category, Severity},
+    events::Event,
+    fetching::index_initial,
+};
+use miette::{ensure, IntoDiagnostic, Result};
+use sea_orm::DatabaseConnection;
+use tokio::task::JoinHandle;
+
+/// Pages of the first run wizard, in order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// At least one source has to be added to continue
+    AddSources,
+    ReplayGain,
+    /// The sources are indexed for the first time
+    Indexing,
+    Finished,
+}
+
+/// How far indexing a source got
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    pub indexed: usize,
+    /// Songs found in the source, 0 until they've been listed
+    pub total: usize,
+    pub done: bool,
+}
+
+/// Shown instead of the library on first run: adds sources, asks whether to analyze
+/// the loudness of songs without ReplayGain tags, then indexes everything while showing progress.
+pub struct FirstRunWizard {
+    step: Step,
+    settings: SettingsPage,
+    analyze_replaygain: bool,
+    progress: HashMap<u8, Progress>,
+    cancel: CancellationToken,
+    indexing: Option<JoinHandle<Result<()>>>,
+}
+
+impl FirstRunWizard {
+    pub fn new() -> Result<Self> {
+        let mut settings = SettingsPage::load()?;
+
+        // The default config comes with an example source, which most likely doesn't exist
+        let missing: Vec<u8> = settings
+            .sources()
+            .iter()
+            .filter(|v| match &v.source {
+                SourceKind::Local { path } => !Path::new(path).is_dir(),
+                _ => false,
+            })
+            .map(|v| v.id)
+            .collect();
+
+        for id in missing {
+            settings.remove_source(id)?;
+        }
+
+        Ok(FirstRunWizard {
+            step: Step::AddSources,
+            settings,
+            // Analysis takes a while on large libraries, so it's opt-in
+            analyze_replaygain: false,
+            progress: HashMap::new(),
+            cancel: CancellationToken::new(),
+            indexing: None,
+        })
+    }
+
+    pub fn step(&self) -> Step {
+        self.step
+    }
+
+    pub fn sources(&self) -> &[Source] {
+        self.settings.sources()
+    }
+
+    /// Adds a source from the form on the first page, see `SettingsPage::add_source`
+    pub fn add_source(&mut self, form: &SourceForm) -> Result<u8> {
+        self.settings.add_source(form)
+    }
+
+    pub fn remove_source(&mut self, id: u8) -> Result<()> {
+        self.settings.remove_source(id)
+    }
+
+    pub fn analyze_replaygain(&self) -> bool {
+        self.analyze_replaygain
+    }
+
+    pub fn set_analyze_replaygain(&mut self, analyze: bool) {
+        self.analyze_replaygain = analyze;
+    }
+
+    /// Goes to the next page. Leaving the ReplayGain page saves the config and starts indexing.
+    pub async fn next(&mut self, db: &DatabaseConnection) -> Result<()> {
+        self.step = match self.step {
+            Step::AddSources => {
+                ensure!(
+                    !self.settings.sources().is_empty(),
+                    "Add a source to continue"
+                );
+
+                Step::ReplayGain
+            }
+            Step::ReplayGain => {
+                self.start_indexing(db).await?;
+
+                Step::Indexing
+            }
+            Step::Indexing | Step::Finished => Step::Finished,
+        };
+
+        Ok(())
+    }
+
+    /// Goes back a page. Indexing can't be undone, so it can only be cancelled.
+    pub fn back(&mut self) {
+        if self.step == Step::ReplayGain {
+            self.step = Step::AddSources;
+        }
+    }
+
+    /// Progress of every source, in the order they were added
+    pub fn progress(&self) -> Vec<(&Source, Progress)> {
+        self.settings
+            .sources()
+            .iter()
+            .map(|v| (v, self.progress.get(&v.id).copied().unwrap_or_default()))
+            .collect()
+    }
+
+    /// Follows indexing. Other events are ignored.
+    pub async fn handle_event(&mut self, event: &Event) -> Result<()> {
+        if self.step != Step::Indexing {
+            return Ok(());
+        }
+
+        match *event {
+            Event::IndexProgress {
+                source_id,
+                indexed,
+                total,
+            } => {
+                let progress = self.progress.entry(source_id).or_default();
+                progress.indexed = indexed;
+                progress.total = total;
+            }
+            Event::IndexFinished { source_id } | Event::IndexCancelled { source_id } => {
+                self.progress.entry(source_id).or_default().done = true;
+            }
+            _ => {}
+        }
+
+        let all_done = self
+            .settings
+            .sources()
+            .iter()
+            .all(|v| self.progress.get(&v.id).is_some_and(|v| v.done));
+
+        // A failed source stops indexing without an event of its own
+        if all_done || self.indexing.as_ref().is_some_and(JoinHandle::is_finished) {
+            if let Some(handle) = self.indexing.take() {
+                handle.await.into_diagnostic()??;
+            }
+
+            self.step = Step::Finished;
+        }
+
+        Ok(())
+    }
+
+    /// Stops indexing after the song it's on. Songs indexed until then are kept,
+    /// and the rest are picked up the next time the app starts.
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    async fn start_indexing(&mut self, db: &DatabaseConnection) -> Result<()> {
+        let changes: Vec<_> = self
+            .settings
+            .sources()
+            .iter()
+            .map(|v| {
+                let mut settings = v.settings.clone();
+                settings.analyze_replaygain = self.analyze_replaygain;
+                (v.id, settings)
+            })
+            .collect();
+
+        for (id, settings) in changes {
+            self.settings.set_source_settings(id, settings)?;
+        }
+
+        self.settings.save(db).await?;
+
+        let cancel = self.cancel.clone();
+        let db = db.clone();
+
+        self.indexing = Some(tokio::spawn(async move {
+            let result = index_initial(&cancel, &db).await;
+
+            if let Err(e) = &result {
+                report(
+                    Severity::Error,
+                    Category::Indexing,
+                    None,
+                    format!("Initial indexing failed: {e}"),
+                );
+            }
+
+            result
+        }));
+
+        Ok(())
+    }
+}
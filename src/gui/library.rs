@@ -0,0 +1,294 @@
+use crate::backend::{
+    artists::{artists_page, count_artists},
+    browse::{album_tracks, artist_albums, Album},
+    model::{artists, library},
+    queue::{Placement, Selection},
+};
+use miette::Result;
+use sea_orm::DatabaseConnection;
+
+/// Artists loaded at once. More are loaded as the list is scrolled towards its end.
+const PAGE_SIZE: u64 = 200;
+/// Rows moved by Page Up and Page Down
+const PAGE_JUMP: usize = 20;
+
+/// Keys the library browser responds to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Up,
+    Down,
+    Left,
+    Right,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Enter,
+    Back,
+}
+
+/// What the rest of the GUI should do after a key press
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    None,
+    /// Play the tracks of an album, starting at `start`
+    Play {
+        tracks: Vec<library::Model>,
+        start: usize,
+    },
+    /// Queue the selected album or track, picked from its context menu
+    Queue {
+        selection: Selection,
+        placement: Placement,
+    },
+}
+
+/// A list or grid of items with one of them selected
+#[derive(Debug, Clone)]
+pub struct Pane<T> {
+    items: Vec<T>,
+    /// Number of items, including ones that aren't loaded yet
+    total: usize,
+    selected: usize,
+    /// Items in a row, 1 for lists
+    columns: usize,
+}
+
+impl<T> Pane<T> {
+    fn new(items: Vec<T>, total: usize, columns: usize) -> Self {
+        Pane {
+            items,
+            total,
+            selected: 0,
+            columns,
+        }
+    }
+
+    /// Items loaded so far, in display order
+    pub fn items(&self) -> &[T] {
+        &self.items
+    }
+
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    pub fn selected(&self) -> Option<&T> {
+        self.items.get(self.selected)
+    }
+
+    pub fn selected_index(&self) -> usize {
+        self.selected
+    }
+
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    fn move_by(&mut self, offset: isize) {
+        let last = self.items.len().saturating_sub(1);
+
+        self.selected = self.selected.saturating_add_signed(offset).min(last);
+    }
+
+    /// Whether the selection is close enough to the end of the loaded items to load more
+    fn needs_more(&self) -> bool {
+        self.items.len() < self.total && self.selected + PAGE_JUMP >= self.items.len()
+    }
+}
+
+/// A level of the library browser
+#[derive(Debug, Clone)]
+pub enum Screen {
+    Artists(Pane<artists::Model>),
+    /// Grid of the albums of an artist
+    Albums {
+        artist: artists::Model,
+        albums: Pane<Album>,
+    },
+    Tracks {
+        album: Album,
+        tracks: Pane<library::Model>,
+    },
+}
+
+/// Browses the library from the artist list, to the albums of an artist, to the tracks of an album.
+/// The artist list is loaded page by page, so it opens quickly in large libraries.
+/// Going back returns to the previous screen as it was left.
+pub struct LibraryBrowser {
+    /// The artist list
+    root: Screen,
+    /// Screens opened from the artist list, the last one is shown
+    opened: Vec<Screen>,
+    /// Albums in a row of the album grid, set by the GUI to fit its width
+    grid_columns: usize,
+}
+
+impl LibraryBrowser {
+    pub async fn new(db: &DatabaseConnection) -> Result<Self> {
+        let artists = artists_page(0, PAGE_SIZE, db).await?;
+        let total = count_artists(db).await?;
+
+        Ok(LibraryBrowser {
+            root: Screen::Artists(Pane::new(artists, total, 1)),
+            opened: vec![],
+            grid_columns: 4,
+        })
+    }
+
+    /// The screen that's currently shown
+    pub fn screen(&self) -> &Screen {
+        self.opened.last().unwrap_or(&self.root)
+    }
+
+    /// Number of screens the user can go back through, for breadcrumbs
+    pub fn depth(&self) -> usize {
+        self.opened.len()
+    }
+
+    /// Changes the number of albums in a row, e.g. when the window is resized
+    pub fn set_grid_columns(&mut self, columns: usize) {
+        self.grid_columns = columns.max(1);
+
+        for screen in &mut self.opened {
+            if let Screen::Albums { albums, .. } = screen {
+                albums.columns = self.grid_columns;
+            }
+        }
+    }
+
+    pub async fn handle_key(&mut self, key: Key, db: &DatabaseConnection) -> Result<Action> {
+        match key {
+            Key::Enter => return self.open(db).await,
+            Key::Back => {
+                self.opened.pop();
+            }
+            key => self.navigate(key, db).await?,
+        }
+
+        Ok(Action::None)
+    }
+
+    /// Queues the selected album or track, see `Queue::queue`. Artists can't be queued.
+    pub fn queue_selected(&self, placement: Placement) -> Action {
+        let selection = match self.screen() {
+            Screen::Artists(_) => None,
+            Screen::Albums { albums, .. } => albums.selected().cloned().map(Selection::Album),
+            Screen::Tracks { tracks, .. } => {
+                tracks.selected().map(|v| Selection::Tracks(vec![v.hash]))
+            }
+        };
+
+        match selection {
+            Some(selection) => Action::Queue {
+                selection,
+                placement,
+            },
+            None => Action::None,
+        }
+    }
+
+    /// Reloads the artist list, e.g. after indexing finished. Open screens are closed.
+    pub async fn refresh(&mut self, db: &DatabaseConnection) -> Result<()> {
+        *self = LibraryBrowser {
+            grid_columns: self.grid_columns,
+            ..LibraryBrowser::new(db).await?
+        };
+
+        Ok(())
+    }
+
+    async fn navigate(&mut self, key: Key, db: &DatabaseConnection) -> Result<()> {
+        match self.opened.last_mut().unwrap_or(&mut self.root) {
+            Screen::Artists(artists) => {
+                // Jumping to the end needs every artist
+                if key == Key::End {
+                    let loaded = artists.items.len();
+                    let rest = artists_page(
+                        loaded as u64,
+                        artists.total.saturating_sub(loaded) as u64,
+                        db,
+                    )
+                    .await?;
+                    artists.items.extend(rest);
+                    artists.total = artists.items.len();
+                }
+
+                move_selection(artists, key);
+
+                if artists.needs_more() {
+                    let page = artists_page(artists.items.len() as u64, PAGE_SIZE, db).await?;
+
+                    // The library shrank since the count
+                    if page.is_empty() {
+                        artists.total = artists.items.len();
+                    }
+
+                    artists.items.extend(page);
+                }
+            }
+            Screen::Albums { albums, .. } => move_selection(albums, key),
+            Screen::Tracks { tracks, .. } => move_selection(tracks, key),
+        }
+
+        Ok(())
+    }
+
+    async fn open(&mut self, db: &DatabaseConnection) -> Result<Action> {
+        let next = match self.screen() {
+            Screen::Artists(artists) => {
+                let Some(artist) = artists.selected() else {
+                    return Ok(Action::None);
+                };
+
+                let albums = artist_albums(artist, db).await?;
+                let total = albums.len();
+
+                Screen::Albums {
+                    artist: artist.clone(),
+                    albums: Pane::new(albums, total, self.grid_columns),
+                }
+            }
+            Screen::Albums { albums, .. } => {
+                let Some(album) = albums.selected() else {
+                    return Ok(Action::None);
+                };
+
+                let tracks = album_tracks(album, db).await?;
+                let total = tracks.len();
+
+                Screen::Tracks {
+                    album: album.clone(),
+                    tracks: Pane::new(tracks, total, 1),
+                }
+            }
+            Screen::Tracks { tracks, .. } => {
+                return Ok(Action::Play {
+                    tracks: tracks.items.clone(),
+                    start: tracks.selected,
+                });
+            }
+        };
+
+        self.opened.push(next);
+        Ok(Action::None)
+    }
+}
+
+/// Moves the selection of a pane. Left and right only move it in grids.
+fn move_selection<T>(pane: &mut Pane<T>, key: Key) {
+    let row = pane.columns as isize;
+    let page = (PAGE_JUMP as isize / row).max(1) * row;
+
+    match key {
+        Key::Up => pane.move_by(-row),
+        Key::Down => pane.move_by(row),
+        Key::Left if pane.columns > 1 => pane.move_by(-1),
+        Key::Right if pane.columns > 1 => pane.move_by(1),
+        Key::PageUp => pane.move_by(-page),
+        Key::PageDown => pane.move_by(page),
+        Key::Home => pane.selected = 0,
+        Key::End => pane.selected = pane.items.len().saturating_sub(1),
+        _ => {}
+    }
+}
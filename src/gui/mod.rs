@@ -0,0 +1,6 @@
+pub mod first_run;
+pub mod library;
+pub mod now_playing;
+pub mod profiles;
+pub mod settings;
+pub mod toasts;
@@ -0,0 +1,201 @@
+use std::time::Duration;
+
+use crate::backend::{
+    artwork::{album_art, Artwork},
+    config::Config,
+    events::Event,
+    model::library,
+    playback::{MediaCommand, PlaybackState},
+    position,
+    waveform::{cached_waveform, waveform, Waveform},
+};
+use miette::{miette, IntoDiagnostic, Result};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Buttons of the now playing bar
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    /// Play or pause, depending on the state
+    PlayPause,
+    Next,
+    Previous,
+}
+
+/// The bar at the bottom of the window: the current track with its album art,
+/// a seek bar drawn as the track's waveform, a volume slider and playback buttons. It follows the player through
+/// backend events and controls it with the same commands as media keys.
+pub struct NowPlayingBar {
+    commands: UnboundedSender<MediaCommand>,
+    song: Option<library::Model>,
+    art: Option<Artwork>,
+    /// Shown once it's available, the seek bar is plain until then
+    waveform: Option<Waveform>,
+    state: PlaybackState,
+    position: Duration,
+    /// Position under the pointer while the seek bar is dragged.
+    /// Positions reported by the player are ignored until it's released.
+    dragging: Option<Duration>,
+    volume: f32,
+}
+
+impl NowPlayingBar {
+    pub fn new(commands: UnboundedSender<MediaCommand>) -> Result<Self> {
+        Ok(NowPlayingBar {
+            commands,
+            song: None,
+            art: None,
+            waveform: None,
+            state: PlaybackState::Stopped,
+            position: Duration::ZERO,
+            dragging: None,
+            volume: Config::read_config()?.volume,
+        })
+    }
+
+    pub fn song(&self) -> Option<&library::Model> {
+        self.song.as_ref()
+    }
+
+    pub fn art(&self) -> Option<&Artwork> {
+        self.art.as_ref()
+    }
+
+    /// Peaks to draw the seek bar with, one per column, see `Waveform::columns`
+    pub fn waveform(&self, columns: usize) -> Option<Vec<u8>> {
+        self.waveform.as_ref().map(|v| v.columns(columns))
+    }
+
+    /// Analyzes the waveform of the current track if it wasn't cached. Kept out of
+    /// `handle_event`, since decoding a whole track takes a while.
+    pub async fn load_waveform(&mut self, db: &DatabaseConnection) -> Result<()> {
+        let Some(song) = &self.song else {
+            return Ok(());
+        };
+
+        if self.waveform.is_none() {
+            self.waveform = Some(waveform(song, db).await?);
+        }
+
+        Ok(())
+    }
+
+    pub fn state(&self) -> PlaybackState {
+        self.state
+    }
+
+    /// Position to show, which follows the pointer while the seek bar is dragged.
+    /// Between position events, it moves on with the player, so the seek bar doesn't jump.
+    pub fn position(&self) -> Duration {
+        if let Some(dragging) = self.dragging {
+            return dragging;
+        }
+
+        match (position::current(), &self.song) {
+            (Some(current), Some(song)) if current.hash == song.hash => current.position,
+            _ => self.position,
+        }
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.song
+            .as_ref()
+            .map_or(Duration::ZERO, |v| Duration::from_millis(v.duration.into()))
+    }
+
+    /// How far the seek bar is filled, from 0 to 1
+    pub fn progress(&self) -> f32 {
+        let duration = self.duration();
+
+        if duration.is_zero() {
+            return 0.0;
+        }
+
+        (self.position().as_secs_f32() / duration.as_secs_f32()).clamp(0.0, 1.0)
+    }
+
+    pub fn volume(&self) -> f32 {
+        self.volume
+    }
+
+    /// Follows the player. Other events are ignored.
+    pub async fn handle_event(&mut self, event: &Event, db: &DatabaseConnection) -> Result<()> {
+        match event {
+            Event::TrackStarted { hash } => {
+                let song = library::Entity::find()
+                    .filter(library::Column::Hash.eq(*hash))
+                    .one(db)
+                    .await
+                    .into_diagnostic()?;
+
+                // A missing cover shouldn't hide the rest of the track
+                self.art = song.as_ref().and_then(|v| album_art(v).ok());
+                self.waveform = cached_waveform(*hash);
+                self.song = song;
+                self.state = PlaybackState::Playing;
+                self.position = Duration::ZERO;
+            }
+            Event::Position { position, state } => {
+                self.state = *state;
+                self.position = *position;
+            }
+            Event::TrackEnded { .. } => {
+                self.state = PlaybackState::Stopped;
+            }
+            // The volume may have been changed elsewhere, e.g. through the HTTP API
+            Event::ConfigChanged => {
+                self.volume = Config::read_config()?.volume;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    pub fn press(&self, control: Control) -> Result<()> {
+        let command = match control {
+            Control::PlayPause => MediaCommand::Toggle,
+            Control::Next => MediaCommand::Next,
+            Control::Previous => MediaCommand::Previous,
+        };
+
+        self.send(command)
+    }
+
+    /// Moves the seek bar while it's dragged, `fraction` being from 0 to 1.
+    /// Nothing is sent to the player until `release_seek_bar`.
+    pub fn drag_seek_bar(&mut self, fraction: f32) {
+        self.dragging = Some(self.duration().mul_f32(fraction.clamp(0.0, 1.0)));
+    }
+
+    /// Moves playback to where the seek bar was let go
+    pub fn release_seek_bar(&mut self) -> Result<()> {
+        let Some(position) = self.dragging.take() else {
+            return Ok(());
+        };
+
+        self.position = position;
+        self.send(MediaCommand::SetPosition(position))
+    }
+
+    /// Changes the volume, from 0 to 1, and saves it to the config.
+    /// The player picks it up from there like any other config change.
+    pub fn set_volume(&mut self, volume: f32) -> Result<()> {
+        let volume = volume.clamp(0.0, 1.0);
+
+        if volume == self.volume {
+            return Ok(());
+        }
+        self.volume = volume;
+
+        let mut config = Config::read_config()?;
+        config.volume = volume;
+        Config::write_config(&config)
+    }
+
+    fn send(&self, command: MediaCommand) -> Result<()> {
+        self.commands
+            .send(command)
+            .map_err(|_| miette!("The player is not running"))
+    }
+}
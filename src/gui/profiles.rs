@@ -0,0 +1,82 @@
+use std::process::Command;
+
+use crate::backend::{
+    shutdown,
+    utils::{is_valid_profile, profile, profiles},
+};
+use miette::{ensure, IntoDiagnostic, Result};
+
+/// Lists profiles and switches between them. Every profile has its own settings, database
+/// and cache, so switching starts the app again with the other profile.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProfileSwitcher {
+    /// Profiles other than the default one
+    profiles: Vec<String>,
+}
+
+impl ProfileSwitcher {
+    pub fn load() -> Self {
+        let mut profiles = profiles();
+
+        // The current profile doesn't have a directory yet if it's still on the first run wizard
+        if let Some(current) = profile() {
+            if !profiles.iter().any(|v| v == current) {
+                profiles.push(current.to_string());
+                profiles.sort();
+            }
+        }
+
+        ProfileSwitcher { profiles }
+    }
+
+    /// The profile in use, `None` for the default one
+    pub fn current(&self) -> Option<&'static str> {
+        profile()
+    }
+
+    pub fn profiles(&self) -> &[String] {
+        &self.profiles
+    }
+
+    /// Adds a profile to the list. Its files are created once it's switched to.
+    pub fn create(&mut self, name: &str) -> Result<()> {
+        ensure!(
+            is_valid_profile(name),
+            "Profile names can only contain letters, numbers, '-' and '_'"
+        );
+        ensure!(
+            !self.profiles.iter().any(|v| v == name),
+            "Profile {} already exists",
+            name
+        );
+
+        self.profiles.push(name.to_string());
+        self.profiles.sort();
+
+        Ok(())
+    }
+
+    /// Starts the app with another profile, `None` being the default one, and quits this instance
+    pub fn switch(&self, name: Option<&str>) -> Result<()> {
+        if name == self.current() {
+            return Ok(());
+        }
+
+        let mut command = Command::new(std::env::current_exe().into_diagnostic()?);
+        command.env_remove("ELEANOR_PROFILE");
+
+        if let Some(name) = name {
+            ensure!(
+                is_valid_profile(name),
+                "Profile names can only contain letters, numbers, '-' and '_'"
+            );
+
+            command.args(["--profile", name]);
+        }
+
+        command.spawn().into_diagnostic()?;
+        shutdown::request();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,310 @@
+use std::{collections::HashMap, path::Path};
+
+use crate::backend::{
+    config::{Config, Source, SourceKind, SourceSettings},
+    loudness::Normalization,
+    model::library,
+    stats::{source_summary, SourceSummary},
+    utils::{get_auth_source, remove_auth_source, store_auth_source},
+};
+use miette::{ensure, miette, IntoDiagnostic, Result};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use url::Url;
+
+/// Kinds of sources that can be set up from the settings page
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SourceType {
+    #[default]
+    Local,
+    /// Another Eleanor instance
+    Remote,
+    Subsonic,
+    WebDav,
+}
+
+/// Fields of the dialog for adding or editing a source
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceForm {
+    pub name: String,
+    pub kind: SourceType,
+    /// Directory of a local source, or the address of any other
+    pub location: String,
+    pub username: String,
+    /// Left empty when editing a source to keep its stored password
+    pub password: String,
+}
+
+impl SourceForm {
+    /// Fills in the dialog for editing a source. The stored password isn't shown.
+    pub fn from_source(source: &Source) -> Self {
+        let (kind, location) = match &source.source {
+            SourceKind::Local { path } => (SourceType::Local, path),
+            SourceKind::Remote { address } => (SourceType::Remote, address),
+            SourceKind::Subsonic { url } => (SourceType::Subsonic, url),
+            SourceKind::WebDav { share } => (SourceType::WebDav, share),
+        };
+
+        SourceForm {
+            name: source.name.clone(),
+            kind,
+            location: location.clone(),
+            username: get_auth_source(source.id)
+                .map(|(username, _)| username)
+                .unwrap_or_default(),
+            password: String::new(),
+        }
+    }
+
+    fn source_kind(&self) -> Result<SourceKind> {
+        let location = self.location.trim();
+
+        if self.kind == SourceType::Local {
+            ensure!(
+                Path::new(location).is_dir(),
+                "{} is not a directory",
+                location
+            );
+        } else {
+            Url::parse(location).map_err(|e| miette!("Invalid address {}: {}", location, e))?;
+        }
+
+        let address = location.trim_end_matches('/').to_string();
+
+        Ok(match self.kind {
+            SourceType::Local => SourceKind::Local {
+                path: location.to_string(),
+            },
+            SourceType::Remote => SourceKind::Remote { address },
+            SourceType::Subsonic => SourceKind::Subsonic { url: address },
+            SourceType::WebDav => SourceKind::WebDav { share: address },
+        })
+    }
+}
+
+/// The settings page. Changes are made to a copy of the config and only written by `save`,
+/// so they can be discarded as a whole.
+pub struct SettingsPage {
+    config: Config,
+    /// Credentials entered for sources, stored once the changes are saved
+    credentials: HashMap<u8, (String, String)>,
+    /// Sources removed since the last save, whose songs and credentials are removed with it
+    removed: Vec<u8>,
+    changed: bool,
+    /// Counts shown next to each saved source, see `load_summaries`
+    summaries: Vec<SourceSummary>,
+}
+
+impl SettingsPage {
+    pub fn load() -> Result<Self> {
+        Ok(SettingsPage {
+            config: Config::read_config()?,
+            credentials: HashMap::new(),
+            removed: vec![],
+            changed: false,
+            summaries: vec![],
+        })
+    }
+
+    /// Settings as they are on the page, including unsaved changes
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn has_changes(&self) -> bool {
+        self.changed
+    }
+
+    pub fn set_crossfade(&mut self, crossfade: bool) {
+        self.edit(|v| v.crossfade = crossfade);
+    }
+
+    pub fn set_crossfade_duration(&mut self, seconds: u8) {
+        self.edit(|v| v.crossfade_duration = seconds);
+    }
+
+    pub fn set_crossfade_within_albums(&mut self, crossfade: bool) {
+        self.edit(|v| v.crossfade_within_albums = crossfade);
+    }
+
+    /// From 0 to 1
+    pub fn set_volume(&mut self, volume: f32) {
+        self.edit(|v| v.volume = volume.clamp(0.0, 1.0));
+    }
+
+    pub fn set_song_change_notification(&mut self, notify: bool) {
+        self.edit(|v| v.song_change_notification = notify);
+    }
+
+    pub fn set_normalization(&mut self, normalization: Normalization) {
+        self.edit(|v| v.playback.normalization = normalization);
+    }
+
+    pub fn set_prevent_clipping(&mut self, prevent: bool) {
+        self.edit(|v| v.playback.prevent_clipping = prevent);
+    }
+
+    /// 0 reads one file per CPU core
+    pub fn set_indexing_threads(&mut self, threads: usize) {
+        self.edit(|v| v.indexing.threads = threads);
+    }
+
+    pub fn set_low_priority_indexing(&mut self, low_priority: bool) {
+        self.edit(|v| v.indexing.low_priority = low_priority);
+    }
+
+    /// Loads the track, album and artist counts of the sources. Reading the size of every
+    /// local file takes a while in large libraries, so it's kept out of `load`.
+    pub async fn load_summaries(&mut self, db: &DatabaseConnection) -> Result<()> {
+        self.summaries = source_summary(db).await?;
+
+        Ok(())
+    }
+
+    /// Counts of a saved source, once `load_summaries` ran
+    pub fn summary(&self, id: u8) -> Option<&SourceSummary> {
+        self.summaries.iter().find(|v| v.source_id == id)
+    }
+
+    pub fn sources(&self) -> &[Source] {
+        &self.config.sources
+    }
+
+    /// Adds a source and returns its id. Sources other than local ones need credentials.
+    pub fn add_source(&mut self, form: &SourceForm) -> Result<u8> {
+        ensure!(!form.name.trim().is_empty(), "Sources need a name");
+
+        let source = form.source_kind()?;
+
+        let local = matches!(source, SourceKind::Local { .. });
+        ensure!(
+            local || !(form.username.is_empty() || form.password.is_empty()),
+            "Remote sources need a username and password"
+        );
+
+        // Ids of removed sources aren't reused until their songs are gone
+        let id = self
+            .config
+            .add_source(form.name.trim(), source, &self.removed)?;
+
+        if !local {
+            self.credentials
+                .insert(id, (form.username.clone(), form.password.clone()));
+        }
+
+        self.changed = true;
+        Ok(id)
+    }
+
+    /// Changes the name, location or credentials of a source, keeping the rest of its settings.
+    /// Songs indexed from a previous location stay until the source is reindexed.
+    pub fn edit_source(&mut self, id: u8, form: &SourceForm) -> Result<()> {
+        ensure!(!form.name.trim().is_empty(), "Sources need a name");
+
+        let kind = form.source_kind()?;
+
+        let local = matches!(kind, SourceKind::Local { .. });
+        // Sources with a token in the config don't use a username and password
+        let token = self
+            .config
+            .sources
+            .iter()
+            .any(|v| v.id == id && v.token.is_some());
+        ensure!(
+            local
+                || token
+                || !form.password.is_empty()
+                || self.credentials.contains_key(&id)
+                || get_auth_source(id).is_ok(),
+            "Remote sources need a username and password"
+        );
+
+        let source = self
+            .config
+            .sources
+            .iter_mut()
+            .find(|v| v.id == id)
+            .ok_or(miette!("Source {} does not exist", id))?;
+
+        source.name = form.name.trim().to_string();
+        source.source = kind;
+
+        // An empty password keeps the stored one
+        if !form.password.is_empty() {
+            self.credentials
+                .insert(id, (form.username.clone(), form.password.clone()));
+        }
+
+        self.changed = true;
+        Ok(())
+    }
+
+    /// Changes how a source is indexed, see `SourceSettings`
+    pub fn set_source_settings(&mut self, id: u8, settings: SourceSettings) -> Result<()> {
+        let source = self
+            .config
+            .sources
+            .iter_mut()
+            .find(|v| v.id == id)
+            .ok_or(miette!("Source {} does not exist", id))?;
+
+        source.settings = settings;
+
+        self.changed = true;
+        Ok(())
+    }
+
+    /// Removes a source. Its songs are removed from the library once the changes are saved.
+    pub fn remove_source(&mut self, id: u8) -> Result<()> {
+        let count = self.config.sources.len();
+        self.config.sources.retain(|v| v.id != id);
+
+        ensure!(
+            self.config.sources.len() < count,
+            "Source {} does not exist",
+            id
+        );
+
+        self.credentials.remove(&id);
+        self.removed.push(id);
+        self.changed = true;
+        Ok(())
+    }
+
+    /// Writes the changes to the config, and stores or removes the credentials of sources
+    pub async fn save(&mut self, db: &DatabaseConnection) -> Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+
+        Config::write_config(&self.config)?;
+
+        for (id, (username, password)) in self.credentials.drain() {
+            store_auth_source(username, password, id)?;
+        }
+
+        for id in self.removed.drain(..) {
+            remove_auth_source(id)?;
+
+            library::Entity::delete_many()
+                .filter(library::Column::SourceId.eq(id))
+                .exec(db)
+                .await
+                .into_diagnostic()?;
+        }
+
+        self.changed = false;
+        Ok(())
+    }
+
+    /// Throws away unsaved changes
+    pub fn discard(&mut self) -> Result<()> {
+        *self = SettingsPage::load()?;
+
+        Ok(())
+    }
+
+    fn edit(&mut self, change: impl FnOnce(&mut Config)) {
+        change(&mut self.config);
+        self.changed = true;
+    }
+}
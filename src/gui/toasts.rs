@@ -0,0 +1,51 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use crate::backend::{
+    errors::{Report, Severity},
+    events::Event,
+};
+
+/// How long a toast stays up unless it's dismissed
+const TOAST_DURATION: Duration = Duration::from_secs(6);
+/// Toasts shown at once, older ones make room for new ones
+const MAX_TOASTS: usize = 3;
+
+/// Short-lived popups for problems in the background. They don't block anything,
+/// and every report stays available on the error log page through `errors::recent`.
+#[derive(Debug, Default)]
+pub struct Toasts {
+    shown: VecDeque<(Report, Instant)>,
+}
+
+impl Toasts {
+    /// Shows warnings and errors as they're reported. Other events are ignored.
+    pub fn handle_event(&mut self, event: &Event) {
+        let Event::ErrorReported(report) = event else {
+            return;
+        };
+
+        if report.severity < Severity::Warning {
+            return;
+        }
+
+        if self.shown.len() == MAX_TOASTS {
+            self.shown.pop_front();
+        }
+        self.shown.push_back((report.clone(), Instant::now()));
+    }
+
+    /// Toasts to draw, oldest first. Expired ones are dropped.
+    pub fn visible(&mut self) -> impl Iterator<Item = &Report> {
+        self.shown
+            .retain(|(_, shown_at)| shown_at.elapsed() < TOAST_DURATION);
+
+        self.shown.iter().map(|(report, _)| report)
+    }
+
+    pub fn dismiss(&mut self, id: u64) {
+        self.shown.retain(|(report, _)| report.id != id);
+    }
+}
@@ -1,19 +1,59 @@
-use backend::{
-    create_app_data,
-    fetching::{index_initial, index_new},
-    prepare_db,
-    utils::{config_dir, is_first_run},
+use eleanor::backend::{
+    backup,
+    cancellation::CancellationToken,
+    config::Config,
+    create_app_data, diagnostics,
+    fetching::{index_initial, index_metadata, index_new, preview_sync},
+    health::album_health,
+    maintenance, network, prepare_db, scan_status, shutdown,
+    upgrade::backfill_analysis,
+    utils::{config_dir, is_first_run, set_profile},
 };
 use miette::{ensure, miette, IntoDiagnostic, Result};
 use paris::info;
 use sea_orm::{Database, DatabaseConnection};
 use sea_orm_migration::SchemaManager;
-
-mod backend;
-mod gui;
+use std::{path::Path, time::Duration};
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+
+    // `--profile <name>` or `ELEANOR_PROFILE` keeps a separate library, see `utils::set_profile`
+    let profile = match args.iter().position(|v| v == "--profile") {
+        Some(i) => {
+            ensure!(i + 1 < args.len(), "--profile needs the name of a profile");
+
+            let name = args.remove(i + 1);
+            args.remove(i);
+            Some(name)
+        }
+        None => std::env::var("ELEANOR_PROFILE")
+            .ok()
+            .filter(|v| !v.is_empty()),
+    };
+
+    if let Some(profile) = profile {
+        info!("Using profile {profile}");
+        set_profile(profile)?;
+    }
+
+    // `eleanor restore <file>` replaces the library with a backup before it's opened,
+    // then starts as usual. The restored library isn't indexed as if it was the first run.
+    // `eleanor restore-snapshot` puts the newest automatic snapshot back instead.
+    match args.as_slice() {
+        [command, path, ..] if command == "restore" => backup::restore(Path::new(path))?,
+        [command, ..] if command == "restore-snapshot" => {
+            let snapshots = backup::snapshots()?;
+            let newest = snapshots
+                .first()
+                .ok_or(miette!("There are no snapshots to restore"))?;
+
+            backup::restore_snapshot(newest)?;
+        }
+        _ => {}
+    }
+
     // First, make sure that the app's files exist
     let first_run = is_first_run()?;
     if first_run {
@@ -22,7 +62,7 @@ async fn main() -> Result<()> {
     }
 
     // Create a database connection
-    let db: DatabaseConnection = Database::connect(&format!(
+    let mut db: DatabaseConnection = Database::connect(format!(
         "sqlite://{}/eleanor.db?mode=rwc",
         config_dir()
             .ok_or(miette!("Configuration directory not found"))?
@@ -31,6 +71,11 @@ async fn main() -> Result<()> {
     .await
     .into_diagnostic()?;
 
+    diagnostics::install(&mut db);
+    if let Some(threshold) = Config::read_config()?.slow_query_ms {
+        diagnostics::set_enabled(true, Duration::from_millis(threshold));
+    }
+
     // Run migrations
     prepare_db(&db).await?;
 
@@ -44,12 +89,141 @@ async fn main() -> Result<()> {
         miette!("Running migrations failed")
     );
 
-    if first_run {
-        index_initial(&db).await?;
-    } else {
-        // Index only new songs
-        index_new(&db).await?;
+    // `eleanor doctor` checks the database and exits, `--fix` also repairs what it can.
+    // `eleanor backup <file>` writes a backup of the library and exits.
+    // `eleanor problems` lists the files indexing had trouble with and exits.
+    // `eleanor health` lists albums with missing tracks or inconsistent tags and exits.
+    // `eleanor sync --dry-run` lists what syncing remote sources would change and exits.
+    match args.as_slice() {
+        [command, rest @ ..] if command == "doctor" => {
+            let fix = rest.iter().any(|v| v == "--fix");
+            maintenance::doctor(fix, &db).await?;
+
+            return Ok(());
+        }
+        [command, path, ..] if command == "backup" => {
+            backup::export(Path::new(path), &db).await?;
+
+            return Ok(());
+        }
+        [command, ..] if command == "problems" => {
+            let issues = scan_status::issues(None, None, &db).await?;
+            if issues.is_empty() {
+                info!("Every file was indexed without problems");
+            }
+
+            for issue in issues {
+                info!(
+                    "Source {}: {}/{} ({:?}){}",
+                    issue.source_id,
+                    issue.path.trim_end_matches('/'),
+                    issue.filename,
+                    issue.status,
+                    issue.message.map(|v| format!(": {v}")).unwrap_or_default()
+                );
+            }
+
+            return Ok(());
+        }
+        [command, ..] if command == "health" => {
+            let albums = album_health(&db).await?;
+            if albums.is_empty() {
+                info!("Every album is complete and consistently tagged");
+            }
+
+            for album in albums {
+                let problems: Vec<String> = album.problems.iter().map(|v| v.to_string()).collect();
+
+                info!(
+                    "Source {}: {} ({}): {}",
+                    album.source_id,
+                    album.album,
+                    album.path.trim_end_matches('/'),
+                    problems.join("; ")
+                );
+            }
+
+            return Ok(());
+        }
+        [command, flag, ..] if command == "sync" && flag == "--dry-run" => {
+            for source in Config::read_config()?.sources {
+                let Some(report) = preview_sync(&source, &db).await? else {
+                    continue;
+                };
+
+                let stats = report.stats();
+                info!(
+                    "Source {}: {} to add, {} to update, {} to remove",
+                    source.id, stats.added, stats.updated, stats.removed
+                );
+
+                let changes = [
+                    ("+", report.added.iter().collect::<Vec<_>>()),
+                    ("~", report.updated.iter().map(|v| &v.1).collect()),
+                    ("-", report.removed.iter().collect()),
+                ];
+                for (sign, songs) in changes {
+                    for song in songs {
+                        info!(
+                            "{sign} {}/{}",
+                            song.path.trim_end_matches('/'),
+                            song.filename
+                        );
+                    }
+                }
+            }
+
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    // Keep track of whether the connection is metered
+    network::watch();
+
+    let cancel = CancellationToken::new();
+
+    // `eleanor refresh-tags` reads the tags of every song again instead of only indexing new ones.
+    // Files that didn't change since they were read aren't decoded again.
+    let refresh_tags = args.first().is_some_and(|v| v == "refresh-tags");
+
+    let startup = async {
+        if first_run {
+            index_initial(&cancel, &db).await?;
+        } else if refresh_tags {
+            index_metadata(&cancel, &db).await?;
+        } else {
+            // Index only new songs
+            index_new(&cancel, &db).await?;
+        }
+
+        // Songs indexed by older versions may be missing analysis data.
+        // This can be interrupted, since every song is written on its own.
+        tokio::select! {
+            result = backfill_analysis(&db) => result?,
+            _ = cancel.cancelled() => {}
+        }
+
+        // Covers found on the way are cached right away, so this can be interrupted too
+        #[cfg(feature = "online_artwork")]
+        eleanor::backend::online_artwork::fetch_missing(&cancel, &db).await?;
+
+        Ok::<_, miette::Report>(())
+    };
+    tokio::pin!(startup);
+
+    tokio::select! {
+        result = &mut startup => result?,
+        _ = shutdown::requested() => {
+            // Let indexing stop after the song it's on, so the partial run is recorded
+            cancel.cancel();
+            startup.await?;
+        }
+    }
+
+    if diagnostics::is_enabled() {
+        diagnostics::log_summary(10);
     }
 
-    Ok(())
+    shutdown::shutdown(&db, None, None).await
 }